@@ -0,0 +1,100 @@
+use std::{env, fmt::Display};
+
+use syscalls::{Sysno, syscall};
+
+use crate::socket::{Socket, SocketCreateError};
+
+// systemd passes sockets as consecutive descriptors starting right after stderr.
+pub const LISTEN_FDS_START: usize = 3;
+// The name systemd reports for sockets without a FileDescriptorName.
+const UNKNOWN_NAME: &str = "unknown";
+
+// A listening socket handed over by systemd, along with its FileDescriptorName.
+pub struct ActivatedSocket {
+    pub name: String,
+    pub socket: Socket,
+}
+
+#[derive(Debug)]
+pub enum ActivationError {
+    InvalidListenPid(String),
+    InvalidListenFds(String),
+    InvalidSocket(usize, SocketCreateError),
+}
+
+impl Display for ActivationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidListenPid(value) => write!(f, "Invalid LISTEN_PID: {}", value),
+            Self::InvalidListenFds(value) => write!(f, "Invalid LISTEN_FDS: {}", value),
+            Self::InvalidSocket(descriptor, err) => {
+                write!(
+                    f,
+                    "Descriptor {} is not a usable socket: {:?}",
+                    descriptor, err
+                )
+            }
+        }
+    }
+}
+
+// Adopts the sockets systemd passed to this process, in the order of the socket unit. Returns
+// nothing when the process wasn't socket activated, or when the variables were meant for another
+// process, such as a parent that exec'd this one without clearing them. If one of the descriptors
+// isn't a usable socket, all of them are closed.
+//
+// The variables are left in place, since changing the environment of a running program isn't
+// thread safe. Child processes ignore them anyway because LISTEN_PID won't match, and the
+// adopted descriptors are marked close-on-exec so they aren't leaked to them.
+pub fn activated_sockets() -> Result<Vec<ActivatedSocket>, ActivationError> {
+    let Ok(listen_pid) = env::var("LISTEN_PID") else {
+        return Ok(Vec::new());
+    };
+    let listen_pid: u32 = listen_pid
+        .trim()
+        .parse()
+        .map_err(|_| ActivationError::InvalidListenPid(listen_pid.clone()))?;
+    if listen_pid != std::process::id() {
+        return Ok(Vec::new());
+    }
+    let listen_fds = env::var("LISTEN_FDS").unwrap_or_default();
+    let count: usize = listen_fds
+        .trim()
+        .parse()
+        .map_err(|_| ActivationError::InvalidListenFds(listen_fds.clone()))?;
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut names: Vec<&str> = names.split(':').collect();
+    // A mismatched list can't be matched up with the descriptors, so it is ignored.
+    if names.len() != count {
+        names = vec![UNKNOWN_NAME; count];
+    }
+
+    let end = LISTEN_FDS_START + count;
+    let mut sockets = Vec::with_capacity(count);
+    for (descriptor, name) in (LISTEN_FDS_START..end).zip(names) {
+        match Socket::from_raw_descriptor(descriptor) {
+            Ok(socket) => sockets.push(ActivatedSocket {
+                name: name.to_string(),
+                socket,
+            }),
+            Err(err) => {
+                // Sockets already adopted close on drop, and the rest would otherwise stay open.
+                (descriptor + 1..end).for_each(close);
+                return Err(ActivationError::InvalidSocket(descriptor, err));
+            }
+        }
+    }
+    Ok(sockets)
+}
+
+// Picks the socket systemd passed under the given FileDescriptorName, if there is one.
+pub fn take_activated_socket(sockets: &mut Vec<ActivatedSocket>, name: &str) -> Option<Socket> {
+    let index = sockets.iter().position(|socket| socket.name == name)?;
+    Some(sockets.remove(index).socket)
+}
+
+fn close(descriptor: usize) {
+    unsafe {
+        let _ = syscall!(Sysno::close, descriptor);
+    }
+}
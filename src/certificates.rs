@@ -0,0 +1,413 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use libc::{SA_RESTART, c_int, sigaction, sigemptyset};
+use rustls::{
+    client::verify_server_name,
+    crypto::ring,
+    pki_types::{CertificateDer, DnsName, PrivateKeyDer, ServerName, pem::PemObject},
+    server::{ClientHello, ParsedCertificate, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use syscalls::Errno;
+use x509_parser::parse_x509_certificate;
+
+use crate::{
+    date::HttpDate,
+    logging::log,
+    timer::{Scheduler, TimerId},
+    tls::TlsConfigError,
+};
+
+// Bumped by the handler installed through reload_on_signal.
+static RELOAD_SIGNALS: AtomicU64 = AtomicU64::new(0);
+
+// The certificates of a TLS listener, picked by the server name clients ask for through SNI.
+// Clients asking for no name, or one without a certificate of its own, get the default. Shared
+// through an Arc, so certificates can be added or reloaded while the server runs; handshakes
+// already under way keep the certificate they started with.
+#[derive(Debug, Default)]
+pub struct CertificateStore {
+    certificates: RwLock<Certificates>,
+}
+
+#[derive(Debug, Default)]
+struct Certificates {
+    default: Option<Entry>,
+    by_name: HashMap<String, Entry>,
+}
+
+// Stops a store's watch when asked to. Dropping it leaves the watch running.
+pub struct CertificateWatch {
+    watch: Arc<Watch>,
+}
+
+struct Watch {
+    store: Arc<CertificateStore>,
+    scheduler: Scheduler,
+    interval: Duration,
+    // The next check, None until the first is scheduled or once stopped.
+    next: Mutex<Option<TimerId>>,
+    stopped: AtomicBool,
+    // Set while a reload runs on its own thread, so checks don't start another.
+    reloading: AtomicBool,
+    seen_signals: AtomicU64,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    certified_key: Arc<CertifiedKey>,
+    // Where to reload it from. Certificates added from memory stay as they are.
+    files: Option<PemFiles>,
+}
+
+#[derive(Debug, Clone)]
+struct PemFiles {
+    certificate_chain: PathBuf,
+    private_key: PathBuf,
+    // As of the last load, so changes can be noticed.
+    modified: [Option<SystemTime>; 2],
+}
+
+impl CertificateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Takes PEM data: the certificate followed by any intermediates, and its private key. A
+    // name of None sets the default certificate, and otherwise the certificate has to be valid
+    // for the name. Adding a certificate under a name that has one replaces it.
+    pub fn add_pem(
+        &self,
+        name: Option<&str>,
+        certificate_chain: &[u8],
+        private_key: &[u8],
+    ) -> Result<(), TlsConfigError> {
+        let entry = Entry {
+            certified_key: load_certified_key(name, certificate_chain, private_key)?,
+            files: None,
+        };
+        self.write().insert(name, entry);
+        Ok(())
+    }
+
+    // Like add_pem, but remembers the files so the certificate can be reloaded from them.
+    pub fn add_pem_files(
+        &self,
+        name: Option<&str>,
+        certificate_chain: impl AsRef<Path>,
+        private_key: impl AsRef<Path>,
+    ) -> Result<(), TlsConfigError> {
+        let mut files = PemFiles {
+            certificate_chain: certificate_chain.as_ref().to_path_buf(),
+            private_key: private_key.as_ref().to_path_buf(),
+            modified: [None; 2],
+        };
+        files.modified = files.get_modified();
+        let entry = Entry {
+            certified_key: files.load(name)?,
+            files: Some(files),
+        };
+        self.write().insert(name, entry);
+        Ok(())
+    }
+
+    // Drops the certificate for a name, or the default for None. Returns whether there was one.
+    pub fn remove(&self, name: Option<&str>) -> bool {
+        let mut certificates = self.write();
+        match name {
+            Some(name) => certificates
+                .by_name
+                .remove(&name.to_ascii_lowercase())
+                .is_some(),
+            None => certificates.default.take().is_some(),
+        }
+    }
+
+    // Names with a certificate of their own, in no particular order.
+    pub fn get_names(&self) -> Vec<String> {
+        self.read().by_name.keys().cloned().collect()
+    }
+
+    pub fn has_default(&self) -> bool {
+        self.read().default.is_some()
+    }
+
+    // Reads every certificate added from files again. One whose files fail to load stays as it
+    // was, while the others are still replaced. Returns how many were reloaded, or the first
+    // failure.
+    pub fn reload(&self) -> Result<usize, TlsConfigError> {
+        self.reload_where(|_| true)
+    }
+
+    // Reloads the certificates whose files changed since they were last loaded. A failed reload
+    // is tried again on the next call, so files that are still being written get picked up once
+    // they are complete.
+    pub fn reload_if_modified(&self) -> Result<usize, TlsConfigError> {
+        self.reload_where(|files| files.get_modified() != files.modified)
+    }
+
+    // Checks for changed files every `interval` on the server's event loop, and reloads all
+    // files once a signal set up with reload_on_signal arrives. The event loop only looks at
+    // modification times; files are read on a thread of their own, and only when there is
+    // something to reload. Failures are logged, and the store keeps serving the certificates it
+    // had.
+    pub fn watch(self: &Arc<Self>, scheduler: &Scheduler, interval: Duration) -> CertificateWatch {
+        let watch = Arc::new(Watch {
+            store: Arc::clone(self),
+            scheduler: scheduler.clone(),
+            interval,
+            next: Mutex::new(None),
+            stopped: AtomicBool::new(false),
+            reloading: AtomicBool::new(false),
+            seen_signals: AtomicU64::new(RELOAD_SIGNALS.load(Ordering::Relaxed)),
+        });
+        watch.schedule();
+        CertificateWatch { watch }
+    }
+
+    fn is_modified(&self) -> bool {
+        self.read().entries().any(|(_, entry)| {
+            entry
+                .files
+                .as_ref()
+                .is_some_and(|files| files.get_modified() != files.modified)
+        })
+    }
+
+    fn reload_where(&self, filter: impl Fn(&PemFiles) -> bool) -> Result<usize, TlsConfigError> {
+        let stale: Vec<(Option<String>, PemFiles)> = self
+            .read()
+            .entries()
+            .filter_map(|(name, entry)| {
+                let files = entry.files.as_ref().filter(|files| filter(files))?;
+                Some((name.map(str::to_string), files.clone()))
+            })
+            .collect();
+        let mut reloaded = Vec::with_capacity(stale.len());
+        let mut failure = None;
+        for (name, mut files) in stale {
+            files.modified = files.get_modified();
+            match files.load(name.as_deref()) {
+                Ok(certified_key) => reloaded.push((name, certified_key, files)),
+                Err(err) => {
+                    failure.get_or_insert(err);
+                }
+            }
+        }
+
+        // Only the files whose contents were taken count as loaded, so the others are tried again.
+        let mut certificates = self.write();
+        let count = reloaded.len();
+        for (name, certified_key, files) in reloaded {
+            let Some(entry) = certificates.get_mut(name.as_deref()) else {
+                continue;
+            };
+            entry.certified_key = certified_key;
+            entry.files = Some(files);
+        }
+        drop(certificates);
+        failure.map_or(Ok(count), Err)
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Certificates> {
+        self.certificates
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Certificates> {
+        self.certificates
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl CertificateWatch {
+    // No checks start after this returns, though a reload already under way finishes.
+    pub fn stop(&self) {
+        self.watch.stopped.store(true, Ordering::Relaxed);
+        let next = self.watch.lock_next().take();
+        if let Some(id) = next {
+            self.watch.scheduler.cancel(id);
+        }
+    }
+}
+
+impl Watch {
+    fn schedule(self: &Arc<Self>) {
+        let mut next = self.lock_next();
+        // Checked under the lock, so a stop either sees this check or prevents it.
+        if self.stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        let watch = Arc::clone(self);
+        *next = Some(
+            self.scheduler
+                .schedule_after(self.interval, move || watch.poll()),
+        );
+    }
+
+    fn poll(self: Arc<Self>) {
+        if !self.reloading.load(Ordering::Relaxed) {
+            let signals = RELOAD_SIGNALS.load(Ordering::Relaxed);
+            let signalled = self.seen_signals.swap(signals, Ordering::Relaxed) != signals;
+            if signalled || self.store.is_modified() {
+                self.reloading.store(true, Ordering::Relaxed);
+                let watch = Arc::clone(&self);
+                thread::spawn(move || watch.reload(signalled));
+            }
+        }
+        self.schedule();
+    }
+
+    fn reload(&self, everything: bool) {
+        let result = if everything {
+            self.store.reload()
+        } else {
+            self.store.reload_if_modified()
+        };
+        match result {
+            Ok(0) => {}
+            Ok(count) => log!("Reloaded {} TLS certificate(s).", count),
+            Err(err) => log!("Kept a TLS certificate that failed to reload: {}", err),
+        }
+        self.reloading.store(false, Ordering::Relaxed);
+    }
+
+    fn lock_next(&self) -> MutexGuard<'_, Option<TimerId>> {
+        self.next.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl ResolvesServerCert for CertificateStore {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let certificates = self.read();
+        client_hello
+            .server_name()
+            .and_then(|name| certificates.by_name.get(&name.to_ascii_lowercase()))
+            .or(certificates.default.as_ref())
+            .map(|entry| Arc::clone(&entry.certified_key))
+    }
+}
+
+impl Certificates {
+    fn insert(&mut self, name: Option<&str>, entry: Entry) {
+        match name {
+            Some(name) => {
+                self.by_name.insert(name.to_ascii_lowercase(), entry);
+            }
+            None => self.default = Some(entry),
+        }
+    }
+
+    fn get_mut(&mut self, name: Option<&str>) -> Option<&mut Entry> {
+        match name {
+            Some(name) => self.by_name.get_mut(name),
+            None => self.default.as_mut(),
+        }
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (Option<&str>, &Entry)> {
+        self.default.iter().map(|entry| (None, entry)).chain(
+            self.by_name
+                .iter()
+                .map(|(name, entry)| (Some(name.as_str()), entry)),
+        )
+    }
+}
+
+impl PemFiles {
+    fn load(&self, name: Option<&str>) -> Result<Arc<CertifiedKey>, TlsConfigError> {
+        let certificate_chain = fs::read(&self.certificate_chain)
+            .map_err(|err| TlsConfigError::ReadFailed(self.certificate_chain.clone(), err))?;
+        let private_key = fs::read(&self.private_key)
+            .map_err(|err| TlsConfigError::ReadFailed(self.private_key.clone(), err))?;
+        load_certified_key(name, &certificate_chain, &private_key)
+    }
+
+    // Missing files count as changed once they reappear.
+    fn get_modified(&self) -> [Option<SystemTime>; 2] {
+        [&self.certificate_chain, &self.private_key].map(|path| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+    }
+}
+
+// Makes the signal reload the certificates of every watched store. The handler only takes note
+// of the signal, so stores pick it up on their next check.
+pub fn reload_on_signal(signal: c_int) -> Result<(), Errno> {
+    unsafe {
+        let mut action: sigaction = std::mem::zeroed();
+        action.sa_sigaction = note_reload_signal as extern "C" fn(c_int) as usize;
+        action.sa_flags = SA_RESTART;
+        sigemptyset(&mut action.sa_mask);
+        if sigaction(signal, &action, std::ptr::null_mut()) == -1 {
+            return Err(Errno::new(
+                std::io::Error::last_os_error()
+                    .raw_os_error()
+                    .unwrap_or_default(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+extern "C" fn note_reload_signal(_signal: c_int) {
+    RELOAD_SIGNALS.fetch_add(1, Ordering::Relaxed);
+}
+
+// Parses and checks a certificate and key, so that mistakes show up when they are loaded rather
+// than as failed handshakes.
+pub(crate) fn load_certified_key(
+    name: Option<&str>,
+    certificate_chain: &[u8],
+    private_key: &[u8],
+) -> Result<Arc<CertifiedKey>, TlsConfigError> {
+    let chain = CertificateDer::pem_slice_iter(certificate_chain)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(TlsConfigError::InvalidCertificate)?;
+    let Some(leaf) = chain.first() else {
+        return Err(TlsConfigError::NoCertificates);
+    };
+    let parsed = ParsedCertificate::try_from(leaf).map_err(TlsConfigError::Rejected)?;
+    if let Some(name) = name {
+        let server_name = DnsName::try_from(name)
+            .map(|name| ServerName::DnsName(name.to_lowercase_owned()))
+            .map_err(|_| TlsConfigError::InvalidServerName(name.to_string()))?;
+        verify_server_name(&parsed, &server_name)
+            .map_err(|_| TlsConfigError::NameMismatch(name.to_string()))?;
+    }
+    let (_, certificate) =
+        parse_x509_certificate(leaf).map_err(|_| TlsConfigError::MalformedCertificate)?;
+    let validity = certificate.validity();
+    // Dates before the epoch only show up as the start of old certificates' validity.
+    let [not_before, not_after] = [&validity.not_before, &validity.not_after]
+        .map(|time| HttpDate::from_unix_seconds(u64::try_from(time.timestamp()).unwrap_or(0)));
+    let now = HttpDate::from(SystemTime::now());
+    if now < not_before {
+        return Err(TlsConfigError::NotYetValid(not_before));
+    }
+    if now > not_after {
+        return Err(TlsConfigError::Expired(not_after));
+    }
+
+    let key = PrivateKeyDer::from_pem_slice(private_key).map_err(TlsConfigError::InvalidKey)?;
+    let certified_key =
+        CertifiedKey::from_der(chain, key, &ring::default_provider()).map_err(|err| match err {
+            rustls::Error::InconsistentKeys(_) => TlsConfigError::KeyMismatch,
+            err => TlsConfigError::Rejected(err),
+        })?;
+    Ok(Arc::new(certified_key))
+}
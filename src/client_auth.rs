@@ -0,0 +1,289 @@
+use std::{
+    fmt::Write,
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+    sync::Arc,
+};
+
+use rustls::{
+    RootCertStore,
+    crypto::ring,
+    pki_types::{CertificateDer, pem::PemObject},
+    server::{WebPkiClientVerifier, danger::ClientCertVerifier},
+};
+use x509_parser::{
+    der_parser::{Oid, asn1_rs::ToDer, der::Tag},
+    extensions::GeneralName,
+    oid_registry::{
+        OID_DOMAIN_COMPONENT, OID_USERID, OID_X509_COMMON_NAME, OID_X509_COUNTRY_NAME,
+        OID_X509_LOCALITY_NAME, OID_X509_ORGANIZATION_NAME, OID_X509_ORGANIZATIONAL_UNIT,
+        OID_X509_STATE_OR_PROVINCE_NAME, OID_X509_STREET_ADDRESS,
+    },
+    parse_x509_certificate,
+    x509::{AttributeTypeAndValue, X509Name},
+};
+
+use crate::{
+    connection::Connection,
+    handler::Handler,
+    request::Request,
+    response::{Response, ResponseCode},
+    tls::TlsConfigError,
+};
+
+// The attribute names RFC 4514 gives for distinguished names. Others are written as OIDs.
+const ATTRIBUTE_NAMES: [(Oid<'static>, &str); 9] = [
+    (OID_X509_COMMON_NAME, "CN"),
+    (OID_X509_COUNTRY_NAME, "C"),
+    (OID_X509_LOCALITY_NAME, "L"),
+    (OID_X509_STATE_OR_PROVINCE_NAME, "ST"),
+    (OID_X509_STREET_ADDRESS, "STREET"),
+    (OID_X509_ORGANIZATION_NAME, "O"),
+    (OID_X509_ORGANIZATIONAL_UNIT, "OU"),
+    (OID_DOMAIN_COMPONENT, "DC"),
+    (OID_USERID, "UID"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuthMode {
+    // Clients without a certificate are still served, without an identity. Ones that send a
+    // certificate the CAs didn't issue are turned away.
+    Optional,
+    // The handshake fails unless the client proves a certificate the CAs issued.
+    Required,
+}
+
+// Asks clients for certificates issued by a set of CAs.
+#[derive(Clone)]
+pub struct ClientAuth {
+    roots: Arc<RootCertStore>,
+    mode: ClientAuthMode,
+}
+
+// Who a client proved to be with its certificate, after the certificate was verified.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientIdentity {
+    subject: String,
+    common_name: Option<String>,
+    dns_names: Vec<String>,
+    ip_addresses: Vec<IpAddr>,
+    email_addresses: Vec<String>,
+    uris: Vec<String>,
+}
+
+// Wraps a handler so that only clients whose verified identity passes `authorize` reach it.
+// Everyone else, including clients without a certificate, is answered 403 Forbidden.
+pub struct AuthorizeClient<H, F> {
+    handler: H,
+    authorize: F,
+}
+
+impl ClientAuth {
+    // Takes the CA certificates in PEM, any number of them.
+    pub fn from_pem(ca_bundle: &[u8], mode: ClientAuthMode) -> Result<Self, TlsConfigError> {
+        let certificates = CertificateDer::pem_slice_iter(ca_bundle)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(TlsConfigError::InvalidCertificate)?;
+        if certificates.is_empty() {
+            return Err(TlsConfigError::NoCertificates);
+        }
+        let mut roots = RootCertStore::empty();
+        for certificate in certificates {
+            roots.add(certificate).map_err(TlsConfigError::Rejected)?;
+        }
+        Ok(Self {
+            roots: Arc::new(roots),
+            mode,
+        })
+    }
+
+    pub fn from_pem_file(
+        ca_bundle: impl AsRef<Path>,
+        mode: ClientAuthMode,
+    ) -> Result<Self, TlsConfigError> {
+        let path = ca_bundle.as_ref();
+        Self::from_pem(
+            &fs::read(path).map_err(|err| TlsConfigError::ReadFailed(path.to_path_buf(), err))?,
+            mode,
+        )
+    }
+
+    pub const fn get_mode(&self) -> ClientAuthMode {
+        self.mode
+    }
+
+    pub(crate) fn verifier(&self) -> Result<Arc<dyn ClientCertVerifier>, TlsConfigError> {
+        let builder = WebPkiClientVerifier::builder_with_provider(
+            Arc::clone(&self.roots),
+            Arc::new(ring::default_provider()),
+        );
+        match self.mode {
+            ClientAuthMode::Optional => builder.allow_unauthenticated().build(),
+            ClientAuthMode::Required => builder.build(),
+        }
+        .map_err(TlsConfigError::InvalidClientAuth)
+    }
+}
+
+impl ClientIdentity {
+    // None when the certificate can't be read, which a verified certificate always can.
+    pub(crate) fn from_certificate(certificate: &CertificateDer) -> Option<Self> {
+        let (_, certificate) = parse_x509_certificate(certificate).ok()?;
+        let subject = certificate.subject();
+        let mut identity = Self {
+            subject: distinguished_name(subject),
+            // The last one is the most specific.
+            common_name: subject.iter_common_name().filter_map(string_value).last(),
+            ..Self::default()
+        };
+        let Some(alt_names) = certificate.subject_alternative_name().ok()? else {
+            return Some(identity);
+        };
+        for name in &alt_names.value.general_names {
+            match name {
+                GeneralName::DNSName(name) => identity.dns_names.push(name.to_string()),
+                GeneralName::RFC822Name(name) => identity.email_addresses.push(name.to_string()),
+                GeneralName::URI(uri) => identity.uris.push(uri.to_string()),
+                GeneralName::IPAddress(address) => {
+                    identity.ip_addresses.push(match address.len() {
+                        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(*address).ok()?)),
+                        16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(*address).ok()?)),
+                        _ => return None,
+                    })
+                }
+                GeneralName::Invalid(..) => return None,
+                // Directory names, registered IDs and the like aren't exposed.
+                _ => {}
+            }
+        }
+        Some(identity)
+    }
+
+    // The distinguished name in RFC 4514 form, such as "CN=billing,O=Example".
+    pub fn get_subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn get_common_name(&self) -> Option<&str> {
+        self.common_name.as_deref()
+    }
+
+    pub fn get_dns_names(&self) -> &[String] {
+        &self.dns_names
+    }
+
+    pub fn get_ip_addresses(&self) -> &[IpAddr] {
+        &self.ip_addresses
+    }
+
+    pub fn get_email_addresses(&self) -> &[String] {
+        &self.email_addresses
+    }
+
+    // Such as SPIFFE IDs.
+    pub fn get_uris(&self) -> &[String] {
+        &self.uris
+    }
+}
+
+impl<H, F> AuthorizeClient<H, F>
+where
+    H: Handler,
+    F: FnMut(&ClientIdentity, &Request) -> bool,
+{
+    pub const fn new(handler: H, authorize: F) -> Self {
+        Self { handler, authorize }
+    }
+}
+
+impl<H, F> Handler for AuthorizeClient<H, F>
+where
+    H: Handler,
+    F: FnMut(&ClientIdentity, &Request) -> bool,
+{
+    fn handle(&mut self, connection: &mut Connection, request: &Request) -> Response {
+        if connection
+            .get_client_identity()
+            .is_some_and(|identity| (self.authorize)(&identity, request))
+        {
+            self.handler.handle(connection, request)
+        } else {
+            Response::new(ResponseCode::Forbidden, request.get_protocol())
+        }
+    }
+
+    // The wrapped handler's representation isn't passed on, since preconditions are checked
+    // before `handle` and would answer clients that aren't authorized.
+}
+
+// The name as an RFC 4514 string, most specific attribute first.
+fn distinguished_name(name: &X509Name) -> String {
+    let mut relative_names: Vec<String> = name
+        .iter()
+        .map(|relative_name| {
+            relative_name
+                .iter()
+                .map(|attribute| {
+                    let oid = attribute.attr_type();
+                    let name = ATTRIBUTE_NAMES
+                        .iter()
+                        .find(|(known, _)| known == oid)
+                        .map_or_else(|| oid.to_id_string(), |(_, name)| name.to_string());
+                    format!("{}={}", name, attribute_value(attribute))
+                })
+                .collect::<Vec<_>>()
+                .join("+")
+        })
+        .collect();
+    relative_names.reverse();
+    relative_names.join(",")
+}
+
+fn string_value(attribute: &AttributeTypeAndValue) -> Option<String> {
+    let value = attribute.attr_value();
+    match value.tag() {
+        Tag::Utf8String | Tag::PrintableString | Tag::Ia5String => {
+            attribute.as_str().ok().map(str::to_string)
+        }
+        // Treated as Latin-1, which is what it holds in practice.
+        Tag::T61String => Some(value.data.iter().map(|&byte| byte as char).collect()),
+        Tag::BmpString if value.data.len().is_multiple_of(2) => String::from_utf16(
+            &value
+                .data
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect::<Vec<_>>(),
+        )
+        .ok(),
+        _ => None,
+    }
+}
+
+// Escapes a string value as RFC 4514 requires, or writes the whole encoded value as hex when it
+// isn't a string.
+fn attribute_value(attribute: &AttributeTypeAndValue) -> String {
+    let Some(value) = string_value(attribute) else {
+        let encoded = attribute.attr_value().to_der_vec().unwrap_or_default();
+        return encoded.iter().fold("#".to_string(), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        });
+    };
+    let last = value.chars().count().saturating_sub(1);
+    let mut escaped = String::with_capacity(value.len());
+    for (index, character) in value.chars().enumerate() {
+        match character {
+            '"' | '+' | ',' | ';' | '<' | '>' | '\\' => escaped.push('\\'),
+            '#' | ' ' if index == 0 => escaped.push('\\'),
+            ' ' if index == last => escaped.push('\\'),
+            '\0' => {
+                escaped.push_str("\\00");
+                continue;
+            }
+            _ => {}
+        }
+        escaped.push(character);
+    }
+    escaped
+}
@@ -0,0 +1,270 @@
+use std::io::{Read, Write};
+
+use flate2::{
+    Compression,
+    read::{self, MultiGzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+};
+
+use crate::{
+    conditional::EntityTag,
+    header::Header,
+    protocol::Protocol,
+    request::Request,
+    response::{Body, Response, ResponseCode},
+    typed_header::AcceptEncoding,
+};
+
+const DEFAULT_THRESHOLD: u64 = 1024;
+const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+const DEFAULT_LEVEL: u32 = 6;
+const DEFAULT_CONTENT_TYPES: [&str; 7] = [
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "image/svg+xml",
+    "application/problem+json",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentCoding {
+    Gzip,
+    Deflate,
+    Identity,
+}
+
+#[derive(Debug, Clone)]
+pub enum BodyDecodeError {
+    UnsupportedEncoding(String),
+    TooLarge(usize),
+    Corrupt(std::io::ErrorKind),
+}
+
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    threshold: u64,
+    max_size: u64,
+    level: u32,
+    content_types: Vec<String>,
+}
+
+impl ContentCoding {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+            Self::Identity => "identity",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Self::Gzip),
+            "deflate" => Some(Self::Deflate),
+            "identity" => Some(Self::Identity),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self, content: &[u8], level: u32) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+                encoder.write_all(content)?;
+                encoder.finish()
+            }
+            Self::Deflate => {
+                // The HTTP "deflate" coding is the zlib container, not a raw deflate stream.
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+                encoder.write_all(content)?;
+                encoder.finish()
+            }
+            Self::Identity => Ok(content.to_vec()),
+        }
+    }
+
+    // Wraps `source` so that reading from it gives the encoded bytes, as they are needed.
+    pub fn encoder(&self, source: impl Read + 'static, level: u32) -> Box<dyn Read> {
+        match self {
+            Self::Gzip => Box::new(read::GzEncoder::new(source, Compression::new(level))),
+            Self::Deflate => Box::new(read::ZlibEncoder::new(source, Compression::new(level))),
+            Self::Identity => Box::new(source),
+        }
+    }
+}
+
+impl BodyDecodeError {
+    pub const fn response_code(&self) -> ResponseCode {
+        match self {
+            Self::UnsupportedEncoding(_) => ResponseCode::UnsupportedMediaType,
+            Self::TooLarge(_) => ResponseCode::PayloadTooLarge,
+            Self::Corrupt(_) => ResponseCode::BadRequest,
+        }
+    }
+}
+
+fn read_limited(reader: impl Read, limit: usize) -> Result<Vec<u8>, BodyDecodeError> {
+    let mut decoded = Vec::new();
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|err| BodyDecodeError::Corrupt(err.kind()))?;
+    if decoded.len() > limit {
+        return Err(BodyDecodeError::TooLarge(limit));
+    }
+    Ok(decoded)
+}
+
+// Undoes a Content-Encoding value, whose codings are listed in the order they were applied.
+pub fn decode_content(
+    content_encoding: &str,
+    content: Vec<u8>,
+    limit: usize,
+) -> Result<Vec<u8>, BodyDecodeError> {
+    let codings = content_encoding
+        .split(',')
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty())
+        .map(|coding| {
+            ContentCoding::parse(coding)
+                .ok_or_else(|| BodyDecodeError::UnsupportedEncoding(coding.to_string()))
+        })
+        .collect::<Result<Vec<ContentCoding>, BodyDecodeError>>()?;
+    codings
+        .iter()
+        .rev()
+        .try_fold(content, |content, coding| match coding {
+            ContentCoding::Gzip => read_limited(MultiGzDecoder::new(content.as_slice()), limit),
+            ContentCoding::Deflate => read_limited(ZlibDecoder::new(content.as_slice()), limit),
+            ContentCoding::Identity => Ok(content),
+        })
+}
+
+// Picks the preferred compressed coding from an Accept-Encoding value, preferring gzip on ties.
+pub fn negotiate_coding(accept_encoding: &AcceptEncoding) -> Option<ContentCoding> {
+    accept_encoding
+        .preferred(&[
+            ContentCoding::Gzip.as_str(),
+            ContentCoding::Deflate.as_str(),
+        ])
+        .and_then(ContentCoding::parse)
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompressionConfig {
+    pub fn new() -> Self {
+        Self {
+            threshold: DEFAULT_THRESHOLD,
+            max_size: DEFAULT_MAX_SIZE,
+            level: DEFAULT_LEVEL,
+            content_types: DEFAULT_CONTENT_TYPES
+                .iter()
+                .map(|content_type| content_type.to_string())
+                .collect(),
+        }
+    }
+
+    pub const fn set_threshold(&mut self, threshold: u64) {
+        self.threshold = threshold;
+    }
+
+    pub const fn set_max_size(&mut self, max_size: u64) {
+        self.max_size = max_size;
+    }
+
+    pub fn set_level(&mut self, level: u32) {
+        self.level = level.min(9);
+    }
+
+    // Entries ending in `/` match a whole top-level type, anything else matches exactly.
+    pub fn set_content_types(&mut self, content_types: Vec<String>) {
+        self.content_types = content_types;
+    }
+
+    fn is_compressible_type(&self, content_type: &str) -> bool {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        self.content_types.iter().any(|allowed| {
+            if allowed.ends_with('/') {
+                mime.starts_with(allowed.as_str())
+            } else {
+                mime == *allowed
+            }
+        })
+    }
+
+    // Runs before preconditions are evaluated, so that they see the weak entity-tag an encoded
+    // response goes out with. A HEAD request gets the same headers as a GET, without the body.
+    pub fn apply(&self, request: &Request, mut response: Response) -> Response {
+        let headers = response.get_headers();
+        let eligible = matches!(response.get_code(), ResponseCode::Ok)
+            && !headers.contains_key(&Header::ContentEncoding)
+            && !headers.contains_key(&Header::ContentRange)
+            && headers
+                .get(&Header::ContentType)
+                .is_some_and(|content_type| self.is_compressible_type(content_type));
+        if !eligible {
+            return response;
+        }
+        if !headers.get_list(&Header::Vary).iter().any(|field| {
+            *field == "*" || field.eq_ignore_ascii_case(Header::AcceptEncoding.as_str())
+        }) {
+            response
+                .get_headers_mut()
+                .append(Header::Vary, Header::AcceptEncoding.as_str().to_string());
+        }
+
+        let Some(length) = response.get_body().and_then(Body::len) else {
+            return response;
+        };
+        // The encoded length isn't known until the body has gone out, so it needs chunked
+        // encoding, which HTTP/1.0 doesn't have. Ranges are cut from the unencoded body.
+        if length < self.threshold
+            || length > self.max_size
+            || matches!(
+                request.get_protocol(),
+                Protocol::Http1_0 | Protocol::Http0_9 | Protocol::Missing
+            )
+            || request.get_headers().contains_key(&Header::Range)
+        {
+            return response;
+        }
+        let Some(coding) = request
+            .get_headers()
+            .get_typed::<AcceptEncoding>()
+            .and_then(Result::ok)
+            .and_then(|accept_encoding| negotiate_coding(&accept_encoding))
+        else {
+            return response;
+        };
+
+        if let Some(etag) = response.get_etag()
+            && !etag.is_weak()
+            && let Ok(weak) = EntityTag::weak(etag.get_tag())
+        {
+            response.set_etag(&weak);
+        }
+        let headers = response.get_headers_mut();
+        headers.remove(&Header::ContentLength);
+        headers.remove(&Header::AcceptRanges);
+        headers.insert(Header::ContentEncoding, coding.as_str().to_string());
+        let body = response.take_body().map(|source| Body::Encoded {
+            source: Box::new(source),
+            coding,
+            level: self.level,
+        });
+        response.set_body(body);
+        response
+    }
+}
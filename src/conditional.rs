@@ -0,0 +1,252 @@
+use std::fmt::Display;
+
+use crate::{
+    date::HttpDate,
+    header::Header,
+    header_map::split_list,
+    request::{Method, Request},
+    response::{Response, ResponseCode},
+    typed_header::{IfModifiedSince, IfUnmodifiedSince},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EntityTag {
+    weak: bool,
+    tag: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntityTagParseError {
+    MissingQuotes(String),
+    InvalidCharacter(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    etag: Option<EntityTag>,
+    last_modified: Option<HttpDate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Representation {
+    Unknown,
+    Missing,
+    Present(Validators),
+}
+
+enum EntityTagCondition {
+    Any,
+    Tags(Vec<EntityTag>),
+}
+
+impl EntityTag {
+    pub fn strong(tag: &str) -> Result<Self, EntityTagParseError> {
+        Self::new(false, tag)
+    }
+
+    pub fn weak(tag: &str) -> Result<Self, EntityTagParseError> {
+        Self::new(true, tag)
+    }
+
+    fn new(weak: bool, tag: &str) -> Result<Self, EntityTagParseError> {
+        if !tag
+            .bytes()
+            .all(|byte| byte == 0x21 || (0x23..=0x7e).contains(&byte) || byte >= 0x80)
+        {
+            return Err(EntityTagParseError::InvalidCharacter(tag.to_string()));
+        }
+        Ok(Self {
+            weak,
+            tag: tag.to_string(),
+        })
+    }
+
+    pub fn parse(value: &str) -> Result<Self, EntityTagParseError> {
+        let value = value.trim();
+        let (weak, quoted) = value
+            .strip_prefix("W/")
+            .map_or((false, value), |quoted| (true, quoted));
+        let tag = quoted
+            .strip_prefix('"')
+            .and_then(|quoted| quoted.strip_suffix('"'))
+            .ok_or_else(|| EntityTagParseError::MissingQuotes(value.to_string()))?;
+        Self::new(weak, tag)
+    }
+
+    pub const fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    pub fn get_tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn strong_eq(&self, other: &Self) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    pub fn weak_eq(&self, other: &Self) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl Display for EntityTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.weak {
+            write!(f, "W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+impl EntityTagCondition {
+    fn parse(value: &str) -> Option<Self> {
+        if value.trim() == "*" {
+            return Some(Self::Any);
+        }
+        // Entity-tags may contain commas, so only those between tags separate them.
+        split_list(value)
+            .into_iter()
+            .map(|tag| EntityTag::parse(tag).ok())
+            .collect::<Option<Vec<EntityTag>>>()
+            .map(Self::Tags)
+    }
+
+    fn matches(&self, current: Option<&EntityTag>, exists: bool, weak: bool) -> bool {
+        match self {
+            Self::Any => exists,
+            Self::Tags(tags) => current.is_some_and(|current| {
+                tags.iter().any(|tag| {
+                    if weak {
+                        tag.weak_eq(current)
+                    } else {
+                        tag.strong_eq(current)
+                    }
+                })
+            }),
+        }
+    }
+}
+
+impl Validators {
+    pub const fn new(etag: Option<EntityTag>, last_modified: Option<HttpDate>) -> Self {
+        Self {
+            etag,
+            last_modified,
+        }
+    }
+
+    pub fn from_response(response: &Response) -> Self {
+        Self {
+            etag: response.get_etag(),
+            last_modified: response.get_last_modified(),
+        }
+    }
+
+    pub const fn get_etag(&self) -> Option<&EntityTag> {
+        self.etag.as_ref()
+    }
+
+    pub const fn get_last_modified(&self) -> Option<HttpDate> {
+        self.last_modified
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+// Evaluates the request's preconditions in the order given by RFC 9110 section 13.2.2.
+// `exists` is whether the target currently has a representation, which is what `*` matches.
+pub fn evaluate_preconditions(
+    request: &Request,
+    validators: &Validators,
+    exists: bool,
+) -> Option<ResponseCode> {
+    let headers = request.get_headers();
+    let safe = matches!(request.get_method(), Method::Get | Method::Head);
+
+    if let Some(if_match) = headers.get(&Header::IfMatch) {
+        let matched = EntityTagCondition::parse(if_match)
+            .is_some_and(|condition| condition.matches(validators.get_etag(), exists, false));
+        if !matched {
+            return Some(ResponseCode::PreconditionFailed);
+        }
+    } else if let Some(Ok(IfUnmodifiedSince(since))) = headers.get_typed::<IfUnmodifiedSince>()
+        && let Some(last_modified) = validators.get_last_modified()
+        && last_modified > since
+    {
+        return Some(ResponseCode::PreconditionFailed);
+    }
+
+    if let Some(if_none_match) = headers.get(&Header::IfNoneMatch) {
+        let matched = EntityTagCondition::parse(if_none_match)
+            .is_some_and(|condition| condition.matches(validators.get_etag(), exists, true));
+        if matched {
+            return Some(if safe {
+                ResponseCode::NotModified
+            } else {
+                ResponseCode::PreconditionFailed
+            });
+        }
+    } else if safe
+        && let Some(Ok(IfModifiedSince(since))) = headers.get_typed::<IfModifiedSince>()
+        && let Some(last_modified) = validators.get_last_modified()
+        && last_modified <= since
+    {
+        return Some(ResponseCode::NotModified);
+    }
+
+    None
+}
+
+impl Representation {
+    pub fn evaluate(&self, request: &Request) -> Option<Response> {
+        let (validators, exists) = match self {
+            Self::Unknown => return None,
+            Self::Missing => (&Validators::default(), false),
+            Self::Present(validators) => (validators, true),
+        };
+        evaluate_preconditions(request, validators, exists).map(|code| {
+            let mut response = Response::new(code, request.get_protocol());
+            if code == ResponseCode::NotModified {
+                if let Some(etag) = validators.get_etag() {
+                    response.set_etag(etag);
+                }
+                if let Some(last_modified) = validators.get_last_modified() {
+                    response.set_last_modified(last_modified);
+                }
+            }
+            response
+        })
+    }
+}
+
+fn not_modified(mut response: Response) -> Response {
+    response.set_code(ResponseCode::NotModified);
+    response.set_body(None);
+    for header in [
+        Header::ContentLength,
+        Header::ContentType,
+        Header::ContentEncoding,
+        Header::ContentLanguage,
+        Header::ContentRange,
+    ] {
+        response.get_headers_mut().remove(&header);
+    }
+    response
+}
+
+pub fn apply_conditional(request: &Request, response: Response) -> Response {
+    if response.get_code() != ResponseCode::Ok
+        || !matches!(request.get_method(), Method::Get | Method::Head)
+    {
+        return response;
+    }
+    let validators = Validators::from_response(&response);
+    match evaluate_preconditions(request, &validators, true) {
+        Some(ResponseCode::NotModified) => not_modified(response),
+        Some(code) => Response::new(code, response.get_protocol()),
+        None => response,
+    }
+}
@@ -1,20 +1,86 @@
-use libc::{EBADF, EDESTADDRREQ, EDQUOT, EFAULT, EFBIG, EINVAL, EIO, EISDIR, ENOSPC, EPERM, EPIPE};
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use libc::{
+    EBADF, EDESTADDRREQ, EDQUOT, EFAULT, EFBIG, EINVAL, EIO, EISDIR, ENOSPC, EPERM, EPIPE, EPROTO,
+    SHUT_WR,
+};
 use syscalls::{Errno, Sysno, syscall};
 
+#[cfg(feature = "tls")]
+use crate::{
+    client_auth::ClientIdentity,
+    tls::{TlsInfo, TlsStream},
+};
 use crate::{
+    compression::BodyDecodeError,
     error_utils::MaybeFatal,
-    request::{Request, RequestParseError},
-    response::Response,
+    header::Header,
+    http2::{ErrorCode, Http2Session, PREFACE},
+    limits::{LimitViolation, RequestLimits},
+    logging::log,
+    protocol::Protocol,
+    request::{ChunkedDecoder, Method, Request, RequestParseError, oversized_head_error},
+    response::{BODY_CHUNK_SIZE, BodyReader, Response, ResponseCode},
+    server::ListenerId,
+    socket::PeerCredentials,
+    sse::OpenEventStream,
+    timer::{Scheduler, Timeouts},
+    websocket::{CloseCode, WebSocketSession},
 };
 
 const BUFFER_SIZE: usize = 256;
+// How long a finished connection keeps draining input after its response, so that unread request
+// bytes don't make the kernel reset the connection before the client has read the response.
+const LINGER_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_MAX_DECODED_BODY_SIZE: usize = 8 * 1024 * 1024;
 
 pub struct Connection {
+    id: u64,
     descriptor: usize,
     buffer: [u8; BUFFER_SIZE],
     state: ConnectionStatus,
-    collector: String,
+    collector: Vec<u8>,
+    outgoing: Vec<u8>,
     write_index: usize,
+    max_decoded_body_size: usize,
+    limits: RequestLimits,
+    ready_since: Instant,
+    head_received_at: Option<Instant>,
+    lingering_since: Option<Instant>,
+    last_activity: Instant,
+    timeouts: Timeouts,
+    scheduler: Scheduler,
+    timer_deadline: Option<Instant>,
+    requests_served: u64,
+    keep_alive: bool,
+    request_protocol: Protocol,
+    head_only: bool,
+    peer_closed: bool,
+    pending_input: bool,
+    peer_address: Option<SocketAddr>,
+    local_address: Option<SocketAddr>,
+    peer_credentials: Option<PeerCredentials>,
+    listener: ListenerId,
+    refused: bool,
+    draining: bool,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsStream>,
+    // Set once the client turns out to speak HTTP/2.
+    http2: Option<Http2Session>,
+    // The HTTP/2 stream of the request being answered.
+    stream_id: u32,
+    // Set by a handler accepting a WebSocket handshake, and in charge once the 101 is out.
+    websocket: Option<WebSocketSession>,
+    // Event streams started by handlers. An HTTP/1 connection has at most one, sent in the
+    // Streaming state; over HTTP/2 each answers its own stream.
+    event_streams: Vec<OpenEventStream>,
+    // The rest of an HTTP/1 response body, read in as what came before it goes out.
+    response_body: Option<BodyReader>,
+    // How far the chunked body of the request being read has been decoded.
+    chunked_decoder: Option<ChunkedDecoder>,
 }
 
 #[derive(Clone, Debug)]
@@ -22,10 +88,24 @@ pub enum ConnectionReadError {
     ReadError(Errno),
     NotReadyToRead(ConnectionStatus),
     MalformedRequest(RequestParseError),
+    Incomplete,
+    IncompleteBody,
+    InvalidBody(BodyDecodeError),
+    HeaderTimeout,
+    BodyTimeout,
+    ReadTimeout,
+    #[cfg(feature = "tls")]
+    Tls(rustls::Error),
+    // The client broke the HTTP/2 protocol, and was sent a GOAWAY with this code.
+    Http2(ErrorCode),
+    // The client broke the WebSocket protocol, and was sent a close frame with this code.
+    WebSocket(CloseCode),
 }
 
+#[derive(Debug)]
 pub enum ConnectionResponseError {
     NotReadyToRespond(ConnectionStatus),
+    BodyUnavailable(std::io::Error),
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -34,11 +114,26 @@ pub enum ConnectionWriteError {
     NotReadyToWrite(ConnectionStatus),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ConnectionTimeout {
+    Idle,
+    KeepAlive,
+    Header,
+    Body,
+    Read,
+    Write,
+    Linger,
+    Ping,
+    EventKeepAlive,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum ConnectionStatus {
     Reading,
     AwaitingResponse,
     Writing,
+    Streaming,
+    Lingering,
     Dead,
 }
 
@@ -49,7 +144,39 @@ impl MaybeFatal for ConnectionReadError {
                 matches!(errno.into_raw(), EBADF | EFAULT | EINVAL | EIO | EISDIR)
             }
             Self::NotReadyToRead(_) => true,
-            Self::MalformedRequest(_) => false,
+            #[cfg(feature = "tls")]
+            Self::Tls(_) => true,
+            Self::Http2(_) | Self::WebSocket(_) => true,
+            Self::MalformedRequest(_)
+            | Self::Incomplete
+            | Self::IncompleteBody
+            | Self::InvalidBody(_)
+            | Self::HeaderTimeout
+            | Self::BodyTimeout
+            | Self::ReadTimeout => false,
+        }
+    }
+}
+
+impl ConnectionReadError {
+    // The status to answer with before giving up on the request, if it deserves an answer.
+    pub const fn response_code(&self) -> Option<ResponseCode> {
+        match self {
+            Self::InvalidBody(err) => Some(err.response_code()),
+            Self::MalformedRequest(err) => Some(err.response_code()),
+            Self::HeaderTimeout | Self::BodyTimeout | Self::ReadTimeout => {
+                Some(ResponseCode::RequestTimeout)
+            }
+            _ => None,
+        }
+    }
+
+    pub const fn limit_violation(&self) -> Option<LimitViolation> {
+        match self {
+            Self::MalformedRequest(err) => err.limit_violation(),
+            Self::HeaderTimeout => Some(LimitViolation::HeaderTimeout),
+            Self::BodyTimeout => Some(LimitViolation::BodyTimeout),
+            _ => None,
         }
     }
 }
@@ -70,6 +197,7 @@ impl MaybeFatal for ConnectionWriteError {
                         | ENOSPC
                         | EPERM
                         | EPIPE
+                        | EPROTO
                 )
             }
             Self::NotReadyToWrite(state) => matches!(state, ConnectionStatus::Reading),
@@ -78,17 +206,329 @@ impl MaybeFatal for ConnectionWriteError {
 }
 
 impl Connection {
-    pub(crate) const fn new(descriptor: usize) -> Self {
+    pub(crate) fn new(descriptor: usize, id: u64, scheduler: Scheduler) -> Self {
+        let now = Instant::now();
         Self {
+            id,
             descriptor,
             buffer: [0; BUFFER_SIZE],
             state: ConnectionStatus::Reading,
-            collector: String::new(),
+            collector: Vec::new(),
+            outgoing: Vec::new(),
             write_index: 0,
+            max_decoded_body_size: DEFAULT_MAX_DECODED_BODY_SIZE,
+            limits: RequestLimits::new(),
+            ready_since: now,
+            head_received_at: None,
+            lingering_since: None,
+            last_activity: now,
+            timeouts: Timeouts::new(),
+            scheduler,
+            timer_deadline: None,
+            requests_served: 0,
+            keep_alive: false,
+            request_protocol: Protocol::Http1_1,
+            head_only: false,
+            peer_closed: false,
+            pending_input: false,
+            peer_address: None,
+            local_address: None,
+            peer_credentials: None,
+            listener: ListenerId::default(),
+            draining: false,
+            #[cfg(feature = "tls")]
+            tls: None,
+            http2: None,
+            stream_id: 0,
+            websocket: None,
+            event_streams: Vec::new(),
+            response_body: None,
+            chunked_decoder: None,
+            refused: false,
         }
     }
 
-    fn read_once(&mut self) -> Result<usize, ConnectionReadError> {
+    pub const fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    // Lets a handler defer work onto the server's event loop.
+    pub const fn get_scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    pub const fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    pub const fn get_timeouts(&self) -> &Timeouts {
+        &self.timeouts
+    }
+
+    pub const fn get_requests_served(&self) -> u64 {
+        self.requests_served
+    }
+
+    pub(crate) const fn set_addresses(
+        &mut self,
+        peer_address: Option<SocketAddr>,
+        local_address: Option<SocketAddr>,
+    ) {
+        self.peer_address = peer_address;
+        self.local_address = local_address;
+    }
+
+    // The client's address, as seen at accept time. Proxies in front of the server show up here
+    // rather than the client behind them.
+    pub const fn get_peer_address(&self) -> Option<SocketAddr> {
+        self.peer_address
+    }
+
+    pub fn get_peer_ip(&self) -> Option<IpAddr> {
+        self.peer_address.map(|address| address.ip())
+    }
+
+    // The server address the client connected to.
+    pub const fn get_local_address(&self) -> Option<SocketAddr> {
+        self.local_address
+    }
+
+    pub(crate) const fn set_listener(&mut self, listener: ListenerId) {
+        self.listener = listener;
+    }
+
+    // The server socket this connection was accepted on.
+    pub const fn get_listener(&self) -> ListenerId {
+        self.listener
+    }
+
+    pub(crate) const fn set_peer_credentials(&mut self, peer_credentials: Option<PeerCredentials>) {
+        self.peer_credentials = peer_credentials;
+    }
+
+    // Only known for connections made over a Unix socket.
+    pub const fn get_peer_credentials(&self) -> Option<PeerCredentials> {
+        self.peer_credentials
+    }
+
+    // Answers a connection the server won't serve without waiting for its request.
+    pub(crate) fn refuse(&mut self, code: ResponseCode) {
+        self.refused = true;
+        self.state = ConnectionStatus::AwaitingResponse;
+        let mut response = Response::new(code, Protocol::Http1_1);
+        response
+            .get_headers_mut()
+            .insert(Header::Connection, "close".to_string());
+        response
+            .get_headers_mut()
+            .insert(Header::RetryAfter, "1".to_string());
+        self.prepare_response(&mut response);
+        let _ = self.begin_response(&response);
+    }
+
+    pub const fn is_refused(&self) -> bool {
+        self.refused
+    }
+
+    pub const fn set_request_limits(&mut self, limits: RequestLimits) {
+        self.limits = limits;
+    }
+
+    pub const fn get_request_limits(&self) -> &RequestLimits {
+        &self.limits
+    }
+
+    // Between requests on a persistent connection, only the keep-alive and idle timeouts apply.
+    // An HTTP/2 connection is between requests whenever none of its streams is open, and an
+    // upgraded one never is.
+    fn is_between_requests(&self) -> bool {
+        self.http2.as_ref().map_or_else(
+            || {
+                self.websocket.is_none()
+                    && self.requests_served > 0
+                    && self.collector.is_empty()
+                    && self.head_received_at.is_none()
+            },
+            Http2Session::is_idle,
+        )
+    }
+
+    // HTTP/2 streams and WebSocket messages aren't timed on their own, so only HTTP/1 requests
+    // are ever partial.
+    const fn has_partial_request(&self) -> bool {
+        let http1 = self.http2.is_none() && self.websocket.is_none();
+        http1 && (!self.collector.is_empty() || self.head_received_at.is_some())
+    }
+
+    fn deadlines(&self) -> Vec<(Instant, ConnectionTimeout)> {
+        let after = |start: Instant, timeout: Option<Duration>, kind: ConnectionTimeout| {
+            timeout.map(|timeout| (start + timeout, kind))
+        };
+        let mut deadlines = vec![after(
+            self.last_activity,
+            self.timeouts.get_idle(),
+            ConnectionTimeout::Idle,
+        )];
+        match self.state {
+            ConnectionStatus::Reading if self.websocket.is_some() => deadlines.push(
+                self.websocket
+                    .as_ref()
+                    .and_then(WebSocketSession::get_ping_deadline)
+                    .map(|deadline| (deadline, ConnectionTimeout::Ping)),
+            ),
+            ConnectionStatus::Reading if self.is_between_requests() => deadlines.push(after(
+                self.ready_since,
+                self.timeouts.get_keep_alive(),
+                ConnectionTimeout::KeepAlive,
+            )),
+            ConnectionStatus::Reading if self.http2.is_some() => {
+                deadlines.push(self.get_event_keep_alive_deadline());
+            }
+            ConnectionStatus::Reading => {
+                let (started, timeout, kind) = self.head_received_at.map_or_else(
+                    || {
+                        (
+                            self.ready_since,
+                            self.limits.get_header_read_timeout(),
+                            ConnectionTimeout::Header,
+                        )
+                    },
+                    |head_received_at| {
+                        (
+                            head_received_at,
+                            self.limits.get_body_read_timeout(),
+                            ConnectionTimeout::Body,
+                        )
+                    },
+                );
+                deadlines.push(after(started, timeout, kind));
+                if self.has_partial_request() {
+                    deadlines.push(after(
+                        self.last_activity,
+                        self.timeouts.get_read(),
+                        ConnectionTimeout::Read,
+                    ));
+                }
+            }
+            ConnectionStatus::Writing => deadlines.push(after(
+                self.last_activity,
+                self.timeouts.get_write(),
+                ConnectionTimeout::Write,
+            )),
+            ConnectionStatus::Streaming => {
+                deadlines.push(self.get_event_keep_alive_deadline());
+                if self.write_index < self.outgoing.len() {
+                    deadlines.push(after(
+                        self.last_activity,
+                        self.timeouts.get_write(),
+                        ConnectionTimeout::Write,
+                    ));
+                }
+            }
+            ConnectionStatus::Lingering => deadlines.push(after(
+                self.lingering_since.unwrap_or(self.last_activity),
+                Some(LINGER_TIMEOUT),
+                ConnectionTimeout::Linger,
+            )),
+            ConnectionStatus::AwaitingResponse | ConnectionStatus::Dead => return Vec::new(),
+        }
+        deadlines.into_iter().flatten().collect()
+    }
+
+    // When the quietest event stream is due a keep-alive comment.
+    fn get_event_keep_alive_deadline(&self) -> Option<(Instant, ConnectionTimeout)> {
+        self.event_streams
+            .iter()
+            .filter_map(OpenEventStream::get_keep_alive_deadline)
+            .min()
+            .map(|deadline| (deadline, ConnectionTimeout::EventKeepAlive))
+    }
+
+    // The instant the earliest applicable timeout expires, if any applies.
+    pub fn get_deadline(&self) -> Option<Instant> {
+        self.deadlines()
+            .into_iter()
+            .map(|(deadline, _)| deadline)
+            .min()
+    }
+
+    // Applies whichever timeouts have expired by `now`. A request that stalled part way through
+    // leaves the connection ready to send the RequestTimeout response returned as the error;
+    // every other timeout closes the connection silently.
+    pub fn check_timeouts(&mut self, now: Instant) -> Result<(), ConnectionReadError> {
+        let Some(expired) = self
+            .deadlines()
+            .into_iter()
+            .filter(|(deadline, _)| *deadline <= now)
+            .min_by_key(|(deadline, _)| *deadline)
+            .map(|(_, kind)| kind)
+        else {
+            return Ok(());
+        };
+        let err = match expired {
+            ConnectionTimeout::Header => ConnectionReadError::HeaderTimeout,
+            ConnectionTimeout::Body => ConnectionReadError::BodyTimeout,
+            ConnectionTimeout::Read => ConnectionReadError::ReadTimeout,
+            ConnectionTimeout::Idle if self.is_reading() && self.has_partial_request() => {
+                ConnectionReadError::ReadTimeout
+            }
+            ConnectionTimeout::Ping
+                if self
+                    .websocket
+                    .as_mut()
+                    .is_some_and(|session| session.ping(now)) =>
+            {
+                self.flush_session_output();
+                return Ok(());
+            }
+            // The comments go out with the rest of the stream's output.
+            ConnectionTimeout::EventKeepAlive => {
+                for stream in &self.event_streams {
+                    stream.keep_alive(now);
+                }
+                return Ok(());
+            }
+            ConnectionTimeout::Idle
+            | ConnectionTimeout::KeepAlive
+            | ConnectionTimeout::Write
+            | ConnectionTimeout::Linger
+            | ConnectionTimeout::Ping => {
+                log!(
+                    "Closing connection {} after {:?} timeout.",
+                    self.id,
+                    expired
+                );
+                self.kill();
+                return Ok(());
+            }
+        };
+        self.state = ConnectionStatus::AwaitingResponse;
+        self.keep_alive = false;
+        Err(err)
+    }
+
+    pub(crate) const fn get_timer_deadline(&self) -> Option<Instant> {
+        self.timer_deadline
+    }
+
+    pub(crate) const fn set_timer_deadline(&mut self, timer_deadline: Option<Instant>) {
+        self.timer_deadline = timer_deadline;
+    }
+
+    // Whether bytes of a pipelined request are already buffered and waiting to be parsed.
+    pub const fn has_pending_input(&self) -> bool {
+        self.pending_input
+    }
+
+    pub const fn set_max_decoded_body_size(&mut self, max_decoded_body_size: usize) {
+        self.max_decoded_body_size = max_decoded_body_size;
+    }
+
+    fn receive(&mut self) -> Result<usize, ConnectionReadError> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &mut self.tls {
+            return tls.read(self.descriptor, &mut self.buffer);
+        }
         unsafe {
             syscall!(
                 Sysno::read,
@@ -98,54 +538,397 @@ impl Connection {
             )
         }
         .map_err(ConnectionReadError::ReadError)
-        .inspect(|&count| {
-            self.collector
-                .push_str(&String::from_utf8_lossy(&self.buffer[0..count]));
+    }
+
+    fn read_once(&mut self) -> Result<usize, ConnectionReadError> {
+        self.receive().inspect(|&count| {
+            if count > 0 {
+                self.last_activity = Instant::now();
+                // The header timeout of a later request runs from its first byte.
+                if self.is_between_requests() {
+                    self.ready_since = self.last_activity;
+                }
+            }
+            self.collector.extend_from_slice(&self.buffer[0..count]);
         })
     }
 
-    fn parse_request(&self) -> Result<Request, ConnectionReadError> {
+    // The most read from a connection in one go, so a client that keeps sending can't hold up the
+    // others. It is as much as an HTTP/1 request may take.
+    const fn get_read_budget(&self) -> usize {
+        self.limits.get_max_header_bytes() + self.limits.get_max_body_size()
+    }
+
+    // A session that read its budget stops, and is read again without waiting on select.
+    const fn spent_read_budget(&mut self, received: usize) -> bool {
+        let spent = received > self.get_read_budget();
+        if spent {
+            self.pending_input = true;
+        }
+        spent
+    }
+
+    fn find_head_end(&self) -> Option<usize> {
         self.collector
-            .as_str()
-            .try_into()
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map(|index| index + 4)
+    }
+
+    fn parse_request(&self, head_end: usize) -> Result<Request, ConnectionReadError> {
+        std::str::from_utf8(&self.collector[..head_end])
+            .map_err(|_| RequestParseError::NotUtf8)
+            .and_then(|head| Request::parse(head, &self.limits))
             .map_err(ConnectionReadError::MalformedRequest)
     }
 
+    // Returns `None` while more bytes are needed to complete the request.
+    fn take_request(
+        &mut self,
+        end_of_stream: bool,
+    ) -> Option<Result<Request, ConnectionReadError>> {
+        let head_end = match self.find_head_end() {
+            Some(head_end) => head_end,
+            None if self.collector.len() > self.limits.get_max_header_bytes() => {
+                return Some(Err(ConnectionReadError::MalformedRequest(
+                    oversized_head_error(&self.collector, &self.limits),
+                )));
+            }
+            None => return None,
+        };
+        let mut request = match self.parse_request(head_end) {
+            Ok(request) => request,
+            Err(err) => return Some(Err(err)),
+        };
+        let framed = if request.is_chunked() {
+            let decoder = self
+                .chunked_decoder
+                .get_or_insert_with(|| ChunkedDecoder::new(head_end));
+            decoder
+                .decode(&self.collector[head_end..], &self.limits)
+                .map(|decoded| {
+                    decoded.map(|(body, length)| {
+                        request.replace_chunked_framing(body.len());
+                        (body, head_end + length)
+                    })
+                })
+        } else {
+            request.get_content_length().and_then(|body_length| {
+                if body_length > self.limits.get_max_body_size() {
+                    return Err(RequestParseError::BodyTooLarge(body_length));
+                }
+                let request_end = head_end.saturating_add(body_length);
+                Ok((self.collector.len() >= request_end)
+                    .then(|| (self.collector[head_end..request_end].to_vec(), request_end)))
+            })
+        };
+        let (body, request_end) = match framed {
+            Ok(Some(framed)) => framed,
+            Ok(None) => {
+                self.head_received_at.get_or_insert_with(Instant::now);
+                return end_of_stream.then_some(Err(ConnectionReadError::IncompleteBody));
+            }
+            Err(err) => {
+                self.chunked_decoder = None;
+                return Some(Err(ConnectionReadError::MalformedRequest(err)));
+            }
+        };
+        self.chunked_decoder = None;
+        self.collector.drain(..request_end);
+        self.head_only = *request.get_method() == Method::Head;
+        self.keep_alive = wants_keep_alive(&request) && !self.draining;
+        self.request_protocol = request.get_protocol();
+        Some(
+            request
+                .set_encoded_body(body, self.max_decoded_body_size)
+                .map(|()| request)
+                .map_err(ConnectionReadError::InvalidBody),
+        )
+    }
+
     pub fn read(&mut self) -> Result<Request, ConnectionReadError> {
         if !self.is_reading() {
             return Err(ConnectionReadError::NotReadyToRead(self.state));
         }
+        if self.http2.is_some() {
+            return self.read_http2();
+        }
+        if self.websocket.is_some() {
+            return self.read_websocket();
+        }
 
-        let mut read_result = self.read_once();
-        while let Ok(read_size) = read_result {
-            if read_size == 0 {
-                return self.parse_request();
+        self.pending_input = false;
+        let mut end_of_stream = false;
+        let read_error = loop {
+            match self.read_once() {
+                Ok(0) => {
+                    end_of_stream = true;
+                    self.peer_closed = true;
+                    break None;
+                }
+                // Stop pulling bytes once no acceptable request could be this large.
+                Ok(_) if self.collector.len() > self.get_read_budget() => {
+                    break None;
+                }
+                Ok(_) => continue,
+                Err(err) => break Some(err),
             }
-            read_result = self.read_once();
-        }
-        if read_result.as_ref().is_err_and(|err| err.is_fatal()) {
+        };
+        if let Some(err) = &read_error
+            && err.is_fatal()
+        {
             self.kill();
+            return Err(err.clone());
         }
-        if self.is_alive() && self.collector.ends_with("\r\n\r\n") {
-            self.state = ConnectionStatus::AwaitingResponse;
-            return self.parse_request();
+        // A client with prior knowledge of HTTP/2 opens with its preface, and one that agreed on
+        // h2 through ALPN has to.
+        if self.requests_served == 0 && self.head_received_at.is_none() {
+            if self.collector.starts_with(PREFACE) || self.negotiated_http2() {
+                self.http2 = Some(Http2Session::new(
+                    self.limits.clone(),
+                    self.max_decoded_body_size,
+                ));
+                return self.read_http2();
+            }
+            // The start of a preface would parse as an HTTP/1 request of its own.
+            if !end_of_stream && PREFACE.starts_with(&self.collector) {
+                return Err(read_error.unwrap_or(ConnectionReadError::Incomplete));
+            }
         }
-        match read_result {
-            Ok(_) => {
+        match self.take_request(end_of_stream) {
+            Some(Err(err)) if err.response_code().is_none() => {
+                self.kill();
+                Err(err)
+            }
+            Some(result) => {
                 self.state = ConnectionStatus::AwaitingResponse;
-                self.parse_request()
+                result
+            }
+            None if end_of_stream => {
+                self.kill();
+                Err(ConnectionReadError::Incomplete)
+            }
+            None => Err(read_error.unwrap_or(ConnectionReadError::Incomplete)),
+        }
+    }
+
+    // Reading an HTTP/2 connection also sends whatever its session has queued, such as replies
+    // to the client's frames. Each call hands out at most one of the requests that completed.
+    fn read_http2(&mut self) -> Result<Request, ConnectionReadError> {
+        self.pending_input = false;
+        let mut received = 0;
+        let read_error = loop {
+            if let Err(code) = self.receive_http2() {
+                break Some(ConnectionReadError::Http2(code));
+            }
+            if self.spent_read_budget(received) {
+                break None;
+            }
+            match self.read_once() {
+                Ok(0) => {
+                    self.peer_closed = true;
+                    break None;
+                }
+                Ok(count) => received += count,
+                Err(err) => break Some(err),
+            }
+        };
+        self.flush_session_output();
+        match read_error {
+            Some(ConnectionReadError::Http2(code)) => {
+                log!("Closing HTTP/2 connection {}: {:?}", self.id, code);
+            }
+            Some(err) if err.is_fatal() => {
+                self.kill();
+                return Err(err);
+            }
+            _ => {}
+        }
+        if let Some((stream_id, result)) = self.http2.as_mut().and_then(Http2Session::next_request)
+        {
+            self.stream_id = stream_id;
+            self.head_only = result
+                .as_ref()
+                .is_ok_and(|request| *request.get_method() == Method::Head);
+            self.request_protocol = Protocol::Http2;
+            self.state = ConnectionStatus::AwaitingResponse;
+            return result;
+        }
+        if self.peer_closed && self.is_between_requests() {
+            self.kill();
+        } else if self.is_alive()
+            && !self.has_unsent_session_output()
+            && self.http2.as_ref().is_some_and(Http2Session::is_finished)
+        {
+            self.linger();
+        }
+        Err(read_error.unwrap_or(ConnectionReadError::Incomplete))
+    }
+
+    fn receive_http2(&mut self) -> Result<(), ErrorCode> {
+        match &mut self.http2 {
+            Some(session) => session.receive(&mut self.collector),
+            None => Ok(()),
+        }
+    }
+
+    // Hands each complete frame to the WebSocket session, which answers through its own queue.
+    // Once a close frame has gone out and the client has had it, the server closes first.
+    fn read_websocket(&mut self) -> Result<Request, ConnectionReadError> {
+        self.pending_input = false;
+        let mut received = 0;
+        let read_error = loop {
+            if let Err(code) = self.receive_websocket() {
+                break Some(ConnectionReadError::WebSocket(code));
+            }
+            if self.spent_read_budget(received) {
+                break None;
+            }
+            match self.read_once() {
+                Ok(0) => {
+                    self.peer_closed = true;
+                    break None;
+                }
+                Ok(count) => received += count,
+                Err(err) => break Some(err),
+            }
+        };
+        self.flush_session_output();
+        match read_error {
+            Some(ConnectionReadError::WebSocket(code)) => {
+                log!("Closing WebSocket connection {}: {:?}", self.id, code);
+            }
+            Some(err) if err.is_fatal() => {
+                self.kill();
+                return Err(err);
+            }
+            _ => {}
+        }
+        if self.peer_closed {
+            self.kill();
+        } else if self.is_alive()
+            && !self.has_unsent_session_output()
+            && self
+                .websocket
+                .as_ref()
+                .is_some_and(WebSocketSession::is_closing)
+        {
+            self.linger();
+        }
+        Err(read_error.unwrap_or(ConnectionReadError::Incomplete))
+    }
+
+    fn receive_websocket(&mut self) -> Result<(), CloseCode> {
+        match &mut self.websocket {
+            Some(session) => session.receive(&mut self.collector),
+            None => Ok(()),
+        }
+    }
+
+    // Takes over a connection whose handshake response has just gone out.
+    pub(crate) fn upgrade_websocket(&mut self, session: WebSocketSession) {
+        if self.http2.is_none() {
+            self.websocket = Some(session);
+        }
+    }
+
+    fn start_websocket(&mut self) {
+        self.start_next_request();
+        if let Some(session) = &mut self.websocket {
+            session.open();
+            if self.draining {
+                session.close(CloseCode::GoingAway);
+            }
+        }
+    }
+
+    // Output queued by an HTTP/2 or WebSocket session rather than a single response.
+    fn take_session_output(&mut self) -> Vec<u8> {
+        if let Some(session) = &mut self.http2 {
+            // Event streams go out as DATA frames on the streams they answer, until either side
+            // ends them.
+            self.event_streams.retain(|stream| {
+                let id = stream.get_stream_id();
+                let done = stream.is_done();
+                let output = stream.take_output();
+                if !output.is_empty() && !session.push_data(id, &output) {
+                    return false;
+                }
+                if done {
+                    session.end_stream(id);
+                    return false;
+                }
+                session.is_open(id)
+            });
+            session.take_output()
+        } else if let Some(session) = &self.websocket {
+            session.take_output()
+        } else {
+            Vec::new()
+        }
+    }
+
+    // Writes out what the session has queued, as far as the descriptor takes it.
+    fn flush_session_output(&mut self) {
+        loop {
+            if self.write_index >= self.outgoing.len() {
+                self.outgoing = self.take_session_output();
+                self.write_index = 0;
+                if self.outgoing.is_empty() && !self.has_pending_tls_output() {
+                    break;
+                }
+            }
+            match self.write_once() {
+                Ok(count) if count > 0 => {
+                    self.write_index += count;
+                    self.last_activity = Instant::now();
+                }
+                Ok(_) => break,
+                Err(err) => {
+                    if err.is_fatal() {
+                        self.kill();
+                    }
+                    break;
+                }
             }
-            Err(err) => Err(err),
         }
     }
 
-    fn write_once(&self) -> Result<usize, ConnectionWriteError> {
+    fn has_unsent_session_output(&self) -> bool {
+        let queued = match (&self.http2, &self.websocket) {
+            (Some(session), _) => session.has_output() || self.has_event_output(),
+            (None, Some(session)) if self.is_reading() => session.has_output(),
+            _ => return false,
+        };
+        queued || self.write_index < self.outgoing.len()
+    }
+
+    // Whether an event stream has output queued, or has been closed and has its end to send.
+    fn has_event_output(&self) -> bool {
+        self.event_streams
+            .iter()
+            .any(|stream| stream.has_output() || stream.is_done())
+    }
+
+    // Called by an event source handler, whose response then stays open for the stream.
+    pub(crate) fn start_event_stream(&mut self, mut stream: OpenEventStream) {
+        if self.http2.is_some() {
+            stream.set_stream_id(self.stream_id);
+        }
+        self.event_streams.push(stream);
+    }
+
+    fn write_once(&mut self) -> Result<usize, ConnectionWriteError> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &mut self.tls {
+            return tls.write(self.descriptor, &self.outgoing[self.write_index..]);
+        }
         unsafe {
             syscall!(
                 Sysno::write,
                 self.descriptor,
-                self.collector[self.write_index..].as_ptr() as usize,
-                self.collector.len() - self.write_index
+                self.outgoing[self.write_index..].as_ptr() as usize,
+                self.outgoing.len() - self.write_index
             )
         }
         .map_err(ConnectionWriteError::WriteError)
@@ -155,13 +938,57 @@ impl Connection {
         if !self.is_awaiting_response() {
             return Err(ConnectionResponseError::NotReadyToRespond(self.state));
         }
-        self.collector = format!("{}", response);
+        // Over HTTP/2 the response goes out on its stream while the connection keeps reading.
+        // A handler that started an event stream may still have its answer replaced.
+        if response.get_code() != ResponseCode::Ok {
+            let stream_id = self.stream_id;
+            self.event_streams
+                .retain(|stream| stream.get_stream_id() != stream_id);
+        }
+        if let Some(session) = &mut self.http2 {
+            let open_ended = self
+                .event_streams
+                .iter()
+                .any(|stream| stream.get_stream_id() == self.stream_id);
+            session
+                .respond(self.stream_id, response, self.head_only, open_ended)
+                .map_err(ConnectionResponseError::BodyUnavailable)?;
+            self.requests_served += 1;
+            self.head_only = false;
+            self.state = ConnectionStatus::Reading;
+            self.pending_input |= session.has_requests();
+            if session.is_idle() {
+                self.ready_since = Instant::now();
+            }
+            self.flush_session_output();
+            return Ok(());
+        }
+        // A handler that accepted a WebSocket handshake may still have its answer replaced.
+        if response.get_code() != ResponseCode::SwitchingProtocols {
+            self.websocket = None;
+        }
+        self.outgoing = response.serialize_head();
+        self.response_body = None;
+        if let Some(body) = response.get_body().filter(|_| !self.head_only) {
+            let mut reader = body
+                .open()
+                .map_err(ConnectionResponseError::BodyUnavailable)?;
+            // A small body goes out together with the head.
+            let chunk = reader
+                .read_chunk(BODY_CHUNK_SIZE)
+                .map_err(ConnectionResponseError::BodyUnavailable)?;
+            self.outgoing.extend(frame_body_chunk(&reader, chunk));
+            self.response_body = Some(reader).filter(|reader| !reader.is_done());
+        }
         self.write_index = 0;
         self.state = ConnectionStatus::Writing;
         Ok(())
     }
 
     pub fn write(&mut self) -> Result<(), ConnectionWriteError> {
+        if self.is_streaming() {
+            return self.write_event_stream();
+        }
         if !self.is_writing() {
             return Err(ConnectionWriteError::NotReadyToWrite(self.state));
         }
@@ -171,19 +998,268 @@ impl Connection {
             && count > 0
         {
             self.write_index += count;
+            self.last_activity = Instant::now();
+            if !self.refill_body() {
+                self.kill();
+                return Ok(());
+            }
             write_result = self.write_once();
         }
-        if write_result.is_err_and(|err| err.is_fatal()) || self.write_index >= self.collector.len()
+        if write_result.is_err_and(|err| err.is_fatal()) {
+            self.kill();
+        } else if self.write_index >= self.outgoing.len()
+            && self.response_body.is_none()
+            && !self.has_pending_tls_output()
         {
+            if self.websocket.is_some() {
+                self.start_websocket();
+            } else if !self.event_streams.is_empty() {
+                self.outgoing.clear();
+                self.write_index = 0;
+                self.state = ConnectionStatus::Streaming;
+            } else {
+                self.finish_response();
+            }
+        }
+        write_result.map(|_| ())
+    }
+
+    // Reads the next piece of the response body once everything before it has gone out. False if
+    // the file can't be read, which leaves no way to finish the response.
+    fn refill_body(&mut self) -> bool {
+        if self.write_index < self.outgoing.len() {
+            return true;
+        }
+        let Some(reader) = &mut self.response_body else {
+            return true;
+        };
+        match reader.read_chunk(BODY_CHUNK_SIZE) {
+            Ok(chunk) => {
+                self.outgoing = frame_body_chunk(reader, chunk);
+                if reader.is_done() {
+                    self.response_body = None;
+                }
+                self.write_index = 0;
+                true
+            }
+            Err(err) => {
+                log!("Failed to read response body: {}", err);
+                false
+            }
+        }
+    }
+
+    // Sends what the event stream has queued as chunks, and the last chunk once it is closed.
+    // Nothing is read meanwhile, so a client that went away shows up as a failed write.
+    fn write_event_stream(&mut self) -> Result<(), ConnectionWriteError> {
+        let mut write_result = Ok(0);
+        loop {
+            if self.write_index >= self.outgoing.len() {
+                self.outgoing = self.take_event_chunks();
+                self.write_index = 0;
+                if self.outgoing.is_empty() && !self.has_pending_tls_output() {
+                    break;
+                }
+            }
+            write_result = self.write_once();
+            match write_result {
+                Ok(count) if count > 0 => {
+                    self.write_index += count;
+                    self.last_activity = Instant::now();
+                }
+                _ => break,
+            }
+        }
+        if write_result.is_err_and(|err| err.is_fatal()) {
             self.kill();
+        } else if self.event_streams.is_empty()
+            && self.write_index >= self.outgoing.len()
+            && !self.has_pending_tls_output()
+        {
+            self.finish_response();
         }
         write_result.map(|_| ())
     }
 
+    fn take_event_chunks(&mut self) -> Vec<u8> {
+        let Some(stream) = self.event_streams.first() else {
+            return Vec::new();
+        };
+        let done = stream.is_done();
+        let output = stream.take_output();
+        let mut chunks = Vec::new();
+        if !output.is_empty() {
+            chunks.extend_from_slice(format!("{:x}\r\n", output.len()).as_bytes());
+            chunks.extend_from_slice(&output);
+            chunks.extend_from_slice(b"\r\n");
+        }
+        if done {
+            chunks.extend_from_slice(b"0\r\n\r\n");
+            self.event_streams.clear();
+        }
+        chunks
+    }
+
+    fn finish_response(&mut self) {
+        if self.keep_alive && !self.peer_closed && !self.draining {
+            self.start_next_request();
+        } else {
+            self.linger();
+        }
+    }
+
+    // Marks the response as the last one on this connection when either side asked for that, and
+    // says so in its Connection header.
+    // HTTP/2 connections aren't managed per response, and leave out the Connection header. A
+    // WebSocket handshake response keeps the one it has.
+    pub fn prepare_response(&mut self, response: &mut Response) {
+        if self.http2.is_some()
+            || (self.websocket.is_some() && response.get_code() == ResponseCode::SwitchingProtocols)
+        {
+            return;
+        }
+        let headers = response.get_headers_mut();
+        if headers
+            .get_list(&Header::Connection)
+            .iter()
+            .any(|option| option.eq_ignore_ascii_case("close"))
+        {
+            self.keep_alive = false;
+        }
+        if !self.keep_alive {
+            headers.insert(Header::Connection, "close".to_string());
+        } else if matches!(self.request_protocol, Protocol::Http1_0) {
+            headers.insert(Header::Connection, "keep-alive".to_string());
+        }
+    }
+
+    fn start_next_request(&mut self) {
+        self.requests_served += 1;
+        self.outgoing.clear();
+        self.write_index = 0;
+        self.state = ConnectionStatus::Reading;
+        self.ready_since = Instant::now();
+        self.head_received_at = None;
+        self.head_only = false;
+        self.pending_input = !self.collector.is_empty();
+    }
+
+    // Lets the connection finish the request it is on, then close. One waiting between requests
+    // has nothing left to finish and closes at once. HTTP/2 clients are sent a GOAWAY, and get
+    // answers on the streams they already opened. WebSocket clients are sent a close frame, and
+    // event streams are ended.
+    pub(crate) fn drain(&mut self) {
+        self.draining = true;
+        for stream in &self.event_streams {
+            stream.close();
+        }
+        if let Some(session) = &mut self.http2 {
+            session.go_away(ErrorCode::NoError);
+        } else if let Some(session) = self.websocket.as_ref().filter(|_| self.is_reading()) {
+            session.close(CloseCode::GoingAway);
+        } else if self.is_reading() && self.is_between_requests() {
+            self.kill();
+        }
+    }
+
+    // Closes the sending side and waits for the client to close too, per RFC 9112 section 9.6.
+    fn linger(&mut self) {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &mut self.tls {
+            tls.close(self.descriptor);
+        }
+        if unsafe { syscall!(Sysno::shutdown, self.descriptor, SHUT_WR) }.is_err() {
+            self.kill();
+            return;
+        }
+        self.collector.clear();
+        self.state = ConnectionStatus::Lingering;
+        self.lingering_since = Some(Instant::now());
+    }
+
+    // Throws away whatever a lingering client still sends, closing once it finishes.
+    pub fn discard(&mut self) {
+        loop {
+            match self.read_once() {
+                Ok(0) => break self.kill(),
+                Ok(_) => self.collector.clear(),
+                Err(ConnectionReadError::ReadError(Errno::EAGAIN)) => break,
+                Err(_) => break self.kill(),
+            }
+        }
+    }
+
     pub const fn get_file_descriptor(&self) -> usize {
         self.descriptor
     }
 
+    // Whether the event loop should wait for the descriptor to become readable.
+    #[cfg_attr(not(feature = "tls"), allow(clippy::missing_const_for_fn))]
+    pub fn wants_read(&self) -> bool {
+        self.is_reading() || self.is_lingering() || self.is_handshaking()
+    }
+
+    // Whether the event loop should wait for the descriptor to become writable. A response can't
+    // go out until the TLS handshake is through, which takes reading.
+    pub fn wants_write(&self) -> bool {
+        self.has_pending_tls_output()
+            || self.has_unsent_session_output()
+            || (self.is_streaming()
+                && (self.write_index < self.outgoing.len() || self.has_event_output()))
+            || ((self.is_awaiting_response() || self.is_writing()) && !self.is_handshaking())
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) fn start_tls(&mut self, tls: TlsStream) {
+        self.tls = Some(tls);
+    }
+
+    // None for plaintext connections, and until the handshake is complete.
+    #[cfg(feature = "tls")]
+    pub fn get_tls_info(&self) -> Option<TlsInfo> {
+        self.tls
+            .as_ref()
+            .filter(|tls| !tls.is_handshaking())
+            .map(TlsStream::get_info)
+    }
+
+    // The verified identity of a client that authenticated with a certificate.
+    #[cfg(feature = "tls")]
+    pub fn get_client_identity(&self) -> Option<ClientIdentity> {
+        self.get_tls_info()
+            .and_then(|info| info.get_client_identity().cloned())
+    }
+
+    #[cfg(feature = "tls")]
+    fn negotiated_http2(&self) -> bool {
+        self.tls.as_ref().is_some_and(TlsStream::negotiated_http2)
+    }
+
+    #[cfg(not(feature = "tls"))]
+    const fn negotiated_http2(&self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "tls")]
+    fn is_handshaking(&self) -> bool {
+        self.tls.as_ref().is_some_and(TlsStream::is_handshaking)
+    }
+
+    #[cfg(not(feature = "tls"))]
+    const fn is_handshaking(&self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "tls")]
+    fn has_pending_tls_output(&self) -> bool {
+        self.tls.as_ref().is_some_and(TlsStream::wants_write)
+    }
+
+    #[cfg(not(feature = "tls"))]
+    const fn has_pending_tls_output(&self) -> bool {
+        false
+    }
+
     pub const fn is_alive(&self) -> bool {
         !matches!(self.state, ConnectionStatus::Dead)
     }
@@ -196,6 +1272,15 @@ impl Connection {
         matches!(self.state, ConnectionStatus::Writing)
     }
 
+    // Sending an event stream over HTTP/1, after its response head.
+    pub const fn is_streaming(&self) -> bool {
+        matches!(self.state, ConnectionStatus::Streaming)
+    }
+
+    pub const fn is_lingering(&self) -> bool {
+        matches!(self.state, ConnectionStatus::Lingering)
+    }
+
     pub const fn is_awaiting_response(&self) -> bool {
         matches!(self.state, ConnectionStatus::AwaitingResponse)
     }
@@ -206,6 +1291,9 @@ impl Connection {
 
     pub fn reset(&mut self) {
         self.collector.clear();
+        self.outgoing.clear();
+        self.ready_since = Instant::now();
+        self.head_received_at = None;
     }
 }
 
@@ -216,3 +1304,37 @@ impl Drop for Connection {
         }
     }
 }
+
+// HTTP/1.1 connections persist unless either side says otherwise, HTTP/1.0 ones only on request.
+fn wants_keep_alive(request: &Request) -> bool {
+    let options = request.get_headers().get_list(&Header::Connection);
+    let has_option = |name: &str| {
+        options
+            .iter()
+            .any(|option| option.eq_ignore_ascii_case(name))
+    };
+    match request.get_protocol() {
+        Protocol::Http1_1 => !has_option("close"),
+        Protocol::Http1_0 => has_option("keep-alive"),
+        Protocol::Http2 => true,
+        Protocol::Http0_9 | Protocol::Missing => false,
+    }
+}
+
+// A body whose length wasn't known up front goes out in chunked encoding, ending with the last
+// chunk once the reader is done.
+fn frame_body_chunk(reader: &BodyReader, chunk: Vec<u8>) -> Vec<u8> {
+    if reader.get_remaining().is_some() {
+        return chunk;
+    }
+    let mut framed = Vec::with_capacity(chunk.len() + 16);
+    if !chunk.is_empty() {
+        framed.extend(format!("{:x}\r\n", chunk.len()).into_bytes());
+        framed.extend(chunk);
+        framed.extend(b"\r\n");
+    }
+    if reader.is_done() {
+        framed.extend(b"0\r\n\r\n");
+    }
+    framed
+}
@@ -0,0 +1,154 @@
+use std::{
+    fmt::Display,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const DAY_NAMES: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HttpDate {
+    seconds: u64,
+}
+
+// Howard Hinnant's days-to-civil conversion, valid for all dates after the epoch.
+const fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let shifted = days + 719_468;
+    let era = shifted / 146_097;
+    let day_of_era = shifted - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+const fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn parse_month(name: &str) -> Option<u64> {
+    MONTH_NAMES
+        .iter()
+        .position(|month| *month == name)
+        .map(|index| index as u64 + 1)
+}
+
+const fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn parse_time(value: &str) -> Option<u64> {
+    let mut parts = value.split(':');
+    let hours: u64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: u64 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+impl HttpDate {
+    // Accepts IMF-fixdate as well as the obsolete RFC 850 and asctime formats.
+    pub fn parse(value: &str) -> Option<Self> {
+        let parts: Vec<&str> = value.split_whitespace().collect();
+        let (year, month, day, time) = match parts.as_slice() {
+            [_, day, month, year, time, "GMT"] => (
+                year.parse::<u64>().ok()?,
+                parse_month(month)?,
+                day.parse::<u64>().ok()?,
+                *time,
+            ),
+            [_, date, time, "GMT"] => {
+                let mut date_parts = date.split('-');
+                let day = date_parts.next()?.parse::<u64>().ok()?;
+                let month = parse_month(date_parts.next()?)?;
+                let year = date_parts.next()?.parse::<u64>().ok()?;
+                // Two-digit RFC 850 years are pivoted around the epoch.
+                (
+                    if year < 70 { 2000 + year } else { 1900 + year },
+                    month,
+                    day,
+                    *time,
+                )
+            }
+            [_, month, day, time, year] => (
+                year.parse::<u64>().ok()?,
+                parse_month(month)?,
+                day.parse::<u64>().ok()?,
+                *time,
+            ),
+            _ => return None,
+        };
+        if year < 1970 || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        Some(Self::from_unix_seconds(
+            days_from_civil(year, month, day) * 86_400 + parse_time(time)?,
+        ))
+    }
+
+    pub const fn from_unix_seconds(seconds: u64) -> Self {
+        Self { seconds }
+    }
+
+    pub const fn as_unix_seconds(&self) -> u64 {
+        self.seconds
+    }
+
+    pub fn as_system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.seconds)
+    }
+}
+
+impl From<SystemTime> for HttpDate {
+    fn from(value: SystemTime) -> Self {
+        Self::from_unix_seconds(
+            value
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+        )
+    }
+}
+
+impl Display for HttpDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let days = self.seconds / 86_400;
+        let seconds_of_day = self.seconds % 86_400;
+        let (year, month, day) = civil_from_days(days);
+        write!(
+            f,
+            "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            DAY_NAMES[(days % 7) as usize],
+            day,
+            MONTH_NAMES[(month - 1) as usize],
+            year,
+            seconds_of_day / 3600,
+            seconds_of_day % 3600 / 60,
+            seconds_of_day % 60
+        )
+    }
+}
@@ -1,7 +1,15 @@
-use crate::{connection::Connection, request::Request, response::Response};
+use crate::{
+    conditional::Representation, connection::Connection, request::Request, response::Response,
+};
 
 pub trait Handler {
     fn handle(&mut self, connection: &mut Connection, request: &Request) -> Response;
+
+    // Describes the target's current state so preconditions can be checked before `handle`
+    // runs, which is what keeps a failed `If-Match` from applying a state-changing request.
+    fn representation(&mut self, _request: &Request) -> Representation {
+        Representation::Unknown
+    }
 }
 
 pub struct ConstantHandler {
@@ -0,0 +1,237 @@
+use std::{
+    io::{self, Write},
+    mem,
+    os::{fd::AsRawFd, unix::net::UnixStream},
+    path::Path,
+};
+
+use libc::{
+    CMSG_DATA, CMSG_FIRSTHDR, CMSG_LEN, CMSG_SPACE, EAGAIN, MSG_CMSG_CLOEXEC, MSG_CTRUNC,
+    MSG_NOSIGNAL, SCM_RIGHTS, SOL_SOCKET, c_int, c_void, iovec, msghdr,
+};
+use syscalls::{Errno, Sysno, syscall};
+
+use crate::{
+    logging::log,
+    socket::{Socket, SocketCreateError, SocketListeningError},
+};
+
+// The kernel refuses to pass more descriptors than this in one message.
+const MAX_DESCRIPTORS: usize = 253;
+// Sent back by the new process once it owns the sockets.
+const ACKNOWLEDGEMENT: u8 = 1;
+
+#[derive(Debug)]
+pub enum HandoffError {
+    ControlSocketFailed(SocketCreateError),
+    ControlListeningFailed(SocketListeningError),
+    ConnectFailed(io::Error),
+    TooManyDescriptors(usize),
+    SendFailed(Errno),
+    ReceiveFailed(Errno),
+    // The message was cut short, or named more sockets than it carried.
+    MissingDescriptors(usize, usize),
+    InvalidSocket(SocketCreateError),
+    AcknowledgeFailed(io::Error),
+}
+
+// The running server's end of an upgrade: a Unix socket the new process connects to for the
+// listening sockets.
+pub(crate) struct HandoffControl {
+    socket: Socket,
+    // A new process that was sent the sockets but hasn't confirmed taking them yet.
+    successor: Option<usize>,
+}
+
+impl HandoffControl {
+    pub(crate) fn new(path: &Path) -> Result<Self, HandoffError> {
+        let mut socket =
+            Socket::new_unix(path, Some(0o600)).map_err(HandoffError::ControlSocketFailed)?;
+        socket
+            .start_listening()
+            .map_err(HandoffError::ControlListeningFailed)?;
+        Ok(Self {
+            socket,
+            successor: None,
+        })
+    }
+
+    // The descriptors to watch for the next step of the handoff.
+    pub(crate) fn get_file_descriptors(&self) -> Vec<usize> {
+        [Some(self.socket.get_file_descriptor()), self.successor]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    // Moves the handoff along as far as it can go without blocking. Returns true once a new
+    // process has confirmed it owns `listeners`, after which this one must stop accepting. A new
+    // process that goes away before confirming leaves this one serving as before.
+    pub(crate) fn poll(&mut self, listeners: &[usize]) -> bool {
+        if self.successor.is_none() {
+            let Ok(accepted) = self.socket.accept_connection() else {
+                return false;
+            };
+            match send_descriptors(accepted.descriptor, listeners) {
+                Ok(()) => self.successor = Some(accepted.descriptor),
+                Err(err) => {
+                    log!("Failed to hand off listeners: {:?}", err);
+                    close(accepted.descriptor);
+                    return false;
+                }
+            }
+        }
+        let Some(successor) = self.successor else {
+            return false;
+        };
+        let mut reply = 0u8;
+        let result = unsafe { syscall!(Sysno::read, successor, &mut reply as *mut u8 as usize, 1) };
+        if result.is_err_and(|errno| errno.into_raw() == EAGAIN) {
+            return false;
+        }
+        close(successor);
+        self.successor = None;
+        if result == Ok(1) && reply == ACKNOWLEDGEMENT {
+            true
+        } else {
+            log!("New process went away before taking over the listeners.");
+            false
+        }
+    }
+}
+
+impl Drop for HandoffControl {
+    fn drop(&mut self) {
+        if let Some(successor) = self.successor {
+            close(successor);
+        }
+    }
+}
+
+// Takes over the listening sockets of the server waiting at `path`, in the order that server
+// listed them. The old server stops accepting as soon as this returns, and connections arriving
+// before the new server runs wait in the sockets' backlogs.
+pub fn receive_sockets(path: impl AsRef<Path>) -> Result<Vec<Socket>, HandoffError> {
+    let mut stream = UnixStream::connect(path).map_err(HandoffError::ConnectFailed)?;
+    let descriptors = receive_descriptors(stream.as_raw_fd() as usize)?;
+    let mut sockets = Vec::with_capacity(descriptors.len());
+    for (index, &descriptor) in descriptors.iter().enumerate() {
+        match Socket::from_raw_descriptor(descriptor) {
+            Ok(socket) => sockets.push(socket),
+            Err(err) => {
+                descriptors[index + 1..]
+                    .iter()
+                    .for_each(|&rest| close(rest));
+                return Err(HandoffError::InvalidSocket(err));
+            }
+        }
+    }
+    stream
+        .write_all(&[ACKNOWLEDGEMENT])
+        .map_err(HandoffError::AcknowledgeFailed)?;
+    Ok(sockets)
+}
+
+// Sends the descriptors as SCM_RIGHTS ancillary data, with their count as the message body.
+fn send_descriptors(descriptor: usize, descriptors: &[usize]) -> Result<(), HandoffError> {
+    if descriptors.len() > MAX_DESCRIPTORS {
+        return Err(HandoffError::TooManyDescriptors(descriptors.len()));
+    }
+    let mut count = (descriptors.len() as u32).to_le_bytes();
+    let mut body = iovec {
+        iov_base: count.as_mut_ptr() as *mut c_void,
+        iov_len: count.len(),
+    };
+    let payload_length = (descriptors.len() * size_of::<c_int>()) as u32;
+    let mut control = control_buffer(payload_length);
+    let mut message: msghdr = unsafe { mem::zeroed() };
+    message.msg_iov = &mut body;
+    message.msg_iovlen = 1;
+    if !descriptors.is_empty() {
+        message.msg_control = control.as_mut_ptr() as *mut c_void;
+        message.msg_controllen = unsafe { CMSG_SPACE(payload_length) } as usize;
+        unsafe {
+            let header = CMSG_FIRSTHDR(&message);
+            (*header).cmsg_level = SOL_SOCKET;
+            (*header).cmsg_type = SCM_RIGHTS;
+            (*header).cmsg_len = CMSG_LEN(payload_length) as usize;
+            let data = CMSG_DATA(header) as *mut c_int;
+            for (index, &descriptor) in descriptors.iter().enumerate() {
+                data.add(index).write_unaligned(descriptor as c_int);
+            }
+        }
+    }
+    unsafe {
+        syscall!(
+            Sysno::sendmsg,
+            descriptor,
+            &message as *const msghdr as usize,
+            MSG_NOSIGNAL
+        )
+    }
+    .map(|_| ())
+    .map_err(HandoffError::SendFailed)
+}
+
+fn receive_descriptors(descriptor: usize) -> Result<Vec<usize>, HandoffError> {
+    let mut count = [0u8; 4];
+    let mut body = iovec {
+        iov_base: count.as_mut_ptr() as *mut c_void,
+        iov_len: count.len(),
+    };
+    let payload_length = (MAX_DESCRIPTORS * size_of::<c_int>()) as u32;
+    let mut control = control_buffer(payload_length);
+    let mut message: msghdr = unsafe { mem::zeroed() };
+    message.msg_iov = &mut body;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr() as *mut c_void;
+    message.msg_controllen = unsafe { CMSG_SPACE(payload_length) } as usize;
+    let received = unsafe {
+        syscall!(
+            Sysno::recvmsg,
+            descriptor,
+            &mut message as *mut msghdr as usize,
+            MSG_CMSG_CLOEXEC
+        )
+    }
+    .map_err(HandoffError::ReceiveFailed)?;
+
+    let mut descriptors = Vec::new();
+    unsafe {
+        let header = CMSG_FIRSTHDR(&message);
+        if !header.is_null()
+            && (*header).cmsg_level == SOL_SOCKET
+            && (*header).cmsg_type == SCM_RIGHTS
+        {
+            let data = CMSG_DATA(header) as *const c_int;
+            let length = (*header).cmsg_len - CMSG_LEN(0) as usize;
+            for index in 0..length / size_of::<c_int>() {
+                descriptors.push(data.add(index).read_unaligned() as usize);
+            }
+        }
+    }
+    let expected = u32::from_le_bytes(count) as usize;
+    if received != count.len()
+        || message.msg_flags & MSG_CTRUNC != 0
+        || descriptors.len() != expected
+    {
+        descriptors.iter().for_each(|&descriptor| close(descriptor));
+        return Err(HandoffError::MissingDescriptors(
+            expected,
+            descriptors.len(),
+        ));
+    }
+    Ok(descriptors)
+}
+
+// Ancillary data has to be aligned for its headers, which a plain byte buffer isn't.
+fn control_buffer(payload_length: u32) -> Vec<u64> {
+    let length = unsafe { CMSG_SPACE(payload_length) } as usize;
+    vec![0; length.div_ceil(size_of::<u64>())]
+}
+
+fn close(descriptor: usize) {
+    unsafe {
+        let _ = syscall!(Sysno::close, descriptor);
+    }
+}
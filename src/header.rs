@@ -1,34 +1,178 @@
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+use std::hash::{Hash, Hasher};
+
+// Field names are case-insensitive, so headers compare and hash by their lowercased name. `Other`
+// keeps the spelling it was created with for output, and equals a known variant of the same name.
+#[derive(Debug, Clone)]
 pub enum Header {
     ContentLength,
     ContentType,
     ContentEncoding,
     ContentLanguage,
     ContentLocation,
+    ContentRange,
+    ContentDisposition,
+
+    AccessControlAllowCredentials,
+    AccessControlAllowHeaders,
+    AccessControlAllowMethods,
+    AccessControlAllowOrigin,
+    AccessControlExposeHeaders,
+    AccessControlMaxAge,
+    AccessControlRequestHeaders,
+    AccessControlRequestMethod,
 
+    Accept,
+    AcceptCharset,
+    AcceptEncoding,
+    AcceptLanguage,
+    AcceptRanges,
+    Age,
+    Allow,
+    Authorization,
+    CacheControl,
+    Connection,
+    Cookie,
+    Date,
+    ETag,
+    Expect,
+    Expires,
+    Forwarded,
     From,
     Host,
+    IfMatch,
+    IfModifiedSince,
+    IfNoneMatch,
+    IfRange,
+    IfUnmodifiedSince,
+    KeepAlive,
+    LastEventId,
+    LastModified,
+    Link,
+    Location,
+    Origin,
+    Pragma,
+    ProxyAuthenticate,
+    ProxyAuthorization,
+    Range,
     Referer,
     ReferrerPolicy,
+    RetryAfter,
+    SecWebSocketAccept,
+    SecWebSocketKey,
+    SecWebSocketVersion,
+    Server,
+    SetCookie,
+    StrictTransportSecurity,
+    Te,
+    Trailer,
+    TransferEncoding,
+    Upgrade,
     UserAgent,
+    Vary,
+    Via,
+    WWWAuthenticate,
+    XForwardedFor,
+    XForwardedHost,
+    XForwardedProto,
 
     Other(String),
 }
 
+impl PartialEq for Header {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Other(_), _) | (_, Self::Other(_)) => {
+                self.as_str().eq_ignore_ascii_case(other.as_str())
+            }
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl Eq for Header {}
+
+impl Hash for Header {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for byte in self.as_str().bytes() {
+            state.write_u8(byte.to_ascii_lowercase());
+        }
+        state.write_u8(0xff);
+    }
+}
+
 impl From<&str> for Header {
     fn from(value: &str) -> Self {
-        match value.to_lowercase().as_str() {
+        match value.to_ascii_lowercase().as_str() {
             "content-length" => Self::ContentLength,
             "content-type" => Self::ContentType,
             "content-encoding" => Self::ContentEncoding,
             "content-language" => Self::ContentLanguage,
             "content-location" => Self::ContentLocation,
+            "content-range" => Self::ContentRange,
+            "content-disposition" => Self::ContentDisposition,
+
+            "access-control-allow-credentials" => Self::AccessControlAllowCredentials,
+            "access-control-allow-headers" => Self::AccessControlAllowHeaders,
+            "access-control-allow-methods" => Self::AccessControlAllowMethods,
+            "access-control-allow-origin" => Self::AccessControlAllowOrigin,
+            "access-control-expose-headers" => Self::AccessControlExposeHeaders,
+            "access-control-max-age" => Self::AccessControlMaxAge,
+            "access-control-request-headers" => Self::AccessControlRequestHeaders,
+            "access-control-request-method" => Self::AccessControlRequestMethod,
 
+            "accept" => Self::Accept,
+            "accept-charset" => Self::AcceptCharset,
+            "accept-encoding" => Self::AcceptEncoding,
+            "accept-language" => Self::AcceptLanguage,
+            "accept-ranges" => Self::AcceptRanges,
+            "age" => Self::Age,
+            "allow" => Self::Allow,
+            "authorization" => Self::Authorization,
+            "cache-control" => Self::CacheControl,
+            "connection" => Self::Connection,
+            "cookie" => Self::Cookie,
+            "date" => Self::Date,
+            "etag" => Self::ETag,
+            "expect" => Self::Expect,
+            "expires" => Self::Expires,
+            "forwarded" => Self::Forwarded,
             "from" => Self::From,
             "host" => Self::Host,
+            "if-match" => Self::IfMatch,
+            "if-modified-since" => Self::IfModifiedSince,
+            "if-none-match" => Self::IfNoneMatch,
+            "if-range" => Self::IfRange,
+            "if-unmodified-since" => Self::IfUnmodifiedSince,
+            "keep-alive" => Self::KeepAlive,
+            "last-event-id" => Self::LastEventId,
+            "last-modified" => Self::LastModified,
+            "link" => Self::Link,
+            "location" => Self::Location,
+            "origin" => Self::Origin,
+            "pragma" => Self::Pragma,
+            "proxy-authenticate" => Self::ProxyAuthenticate,
+            "proxy-authorization" => Self::ProxyAuthorization,
+            "range" => Self::Range,
             "referer" => Self::Referer,
             "referrer-policy" => Self::ReferrerPolicy,
+            "retry-after" => Self::RetryAfter,
+            "sec-websocket-accept" => Self::SecWebSocketAccept,
+            "sec-websocket-key" => Self::SecWebSocketKey,
+            "sec-websocket-version" => Self::SecWebSocketVersion,
+            "server" => Self::Server,
+            "set-cookie" => Self::SetCookie,
+            "strict-transport-security" => Self::StrictTransportSecurity,
+            "te" => Self::Te,
+            "trailer" => Self::Trailer,
+            "transfer-encoding" => Self::TransferEncoding,
+            "upgrade" => Self::Upgrade,
             "user-agent" => Self::UserAgent,
+            "vary" => Self::Vary,
+            "via" => Self::Via,
+            "www-authenticate" => Self::WWWAuthenticate,
+            "x-forwarded-for" => Self::XForwardedFor,
+            "x-forwarded-host" => Self::XForwardedHost,
+            "x-forwarded-proto" => Self::XForwardedProto,
 
             _ => Self::Other(value.to_string()),
         }
@@ -43,11 +187,69 @@ impl<'a> Header {
             Self::ContentEncoding => "Content-Encoding",
             Self::ContentLanguage => "Content-Language",
             Self::ContentLocation => "Content-Location",
+            Self::ContentRange => "Content-Range",
+            Self::ContentDisposition => "Content-Disposition",
+            Self::AccessControlAllowCredentials => "Access-Control-Allow-Credentials",
+            Self::AccessControlAllowHeaders => "Access-Control-Allow-Headers",
+            Self::AccessControlAllowMethods => "Access-Control-Allow-Methods",
+            Self::AccessControlAllowOrigin => "Access-Control-Allow-Origin",
+            Self::AccessControlExposeHeaders => "Access-Control-Expose-Headers",
+            Self::AccessControlMaxAge => "Access-Control-Max-Age",
+            Self::AccessControlRequestHeaders => "Access-Control-Request-Headers",
+            Self::AccessControlRequestMethod => "Access-Control-Request-Method",
+            Self::Accept => "Accept",
+            Self::AcceptCharset => "Accept-Charset",
+            Self::AcceptEncoding => "Accept-Encoding",
+            Self::AcceptLanguage => "Accept-Language",
+            Self::AcceptRanges => "Accept-Ranges",
+            Self::Age => "Age",
+            Self::Allow => "Allow",
+            Self::Authorization => "Authorization",
+            Self::CacheControl => "Cache-Control",
+            Self::Connection => "Connection",
+            Self::Cookie => "Cookie",
+            Self::Date => "Date",
+            Self::ETag => "ETag",
+            Self::Expect => "Expect",
+            Self::Expires => "Expires",
+            Self::Forwarded => "Forwarded",
             Self::From => "From",
             Self::Host => "Host",
+            Self::IfMatch => "If-Match",
+            Self::IfModifiedSince => "If-Modified-Since",
+            Self::IfNoneMatch => "If-None-Match",
+            Self::IfRange => "If-Range",
+            Self::IfUnmodifiedSince => "If-Unmodified-Since",
+            Self::KeepAlive => "Keep-Alive",
+            Self::LastEventId => "Last-Event-ID",
+            Self::LastModified => "Last-Modified",
+            Self::Link => "Link",
+            Self::Location => "Location",
+            Self::Origin => "Origin",
+            Self::Pragma => "Pragma",
+            Self::ProxyAuthenticate => "Proxy-Authenticate",
+            Self::ProxyAuthorization => "Proxy-Authorization",
+            Self::Range => "Range",
             Self::Referer => "Referer",
             Self::ReferrerPolicy => "Referrer-Policy",
+            Self::RetryAfter => "Retry-After",
+            Self::SecWebSocketAccept => "Sec-WebSocket-Accept",
+            Self::SecWebSocketKey => "Sec-WebSocket-Key",
+            Self::SecWebSocketVersion => "Sec-WebSocket-Version",
+            Self::Server => "Server",
+            Self::SetCookie => "Set-Cookie",
+            Self::StrictTransportSecurity => "Strict-Transport-Security",
+            Self::Te => "TE",
+            Self::Trailer => "Trailer",
+            Self::TransferEncoding => "Transfer-Encoding",
+            Self::Upgrade => "Upgrade",
             Self::UserAgent => "User-Agent",
+            Self::Vary => "Vary",
+            Self::Via => "Via",
+            Self::WWWAuthenticate => "WWW-Authenticate",
+            Self::XForwardedFor => "X-Forwarded-For",
+            Self::XForwardedHost => "X-Forwarded-Host",
+            Self::XForwardedProto => "X-Forwarded-Proto",
             Self::Other(header) => header.as_str(),
         }
     }
@@ -0,0 +1,143 @@
+use crate::header::Header;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<(Header, String)>,
+}
+
+// Splits a list-based field value on commas that are not inside a quoted string.
+pub(crate) fn split_list(value: &str) -> Vec<&str> {
+    let mut elements = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, character) in value.char_indices() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                elements.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    elements.push(&value[start..]);
+    elements
+        .into_iter()
+        .map(str::trim)
+        .filter(|element| !element.is_empty())
+        .collect()
+}
+
+impl HeaderMap {
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub const fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains_key(&self, header: &Header) -> bool {
+        self.entries.iter().any(|(name, _)| name == header)
+    }
+
+    pub fn get(&self, header: &Header) -> Option<&String> {
+        self.entries
+            .iter()
+            .find(|(name, _)| name == header)
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_all<'a>(&'a self, header: &'a Header) -> impl Iterator<Item = &'a String> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(name, _)| name == header)
+            .map(|(_, value)| value)
+    }
+
+    // Joins every line of a field as RFC 9110 section 5.3 allows. Set-Cookie is the one field
+    // that cannot be combined this way, so only its first line is returned.
+    pub fn get_combined(&self, header: &Header) -> Option<String> {
+        if *header == Header::SetCookie {
+            return self.get(header).cloned();
+        }
+        let values: Vec<&str> = self.get_all(header).map(String::as_str).collect();
+        (!values.is_empty()).then(|| values.join(", "))
+    }
+
+    pub fn get_list(&self, header: &Header) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(name, _)| name == header)
+            .flat_map(|(_, value)| split_list(value))
+            .collect()
+    }
+
+    // Replaces every existing line of the field, keeping the position of the first one.
+    pub fn insert(&mut self, header: Header, value: String) -> Option<String> {
+        let Some(index) = self.entries.iter().position(|(name, _)| *name == header) else {
+            self.entries.push((header, value));
+            return None;
+        };
+        let mut position = 0;
+        self.entries.retain(|(name, _)| {
+            position += 1;
+            position - 1 <= index || *name != header
+        });
+        Some(std::mem::replace(&mut self.entries[index], (header, value)).1)
+    }
+
+    pub fn append(&mut self, header: Header, value: String) {
+        self.entries.push((header, value));
+    }
+
+    pub fn remove(&mut self, header: &Header) -> Option<String> {
+        let mut removed = None;
+        self.entries.retain(|(name, value)| {
+            if name == header {
+                removed.get_or_insert_with(|| value.clone());
+                false
+            } else {
+                true
+            }
+        });
+        removed
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Header, &String)> {
+        self.entries.iter().map(|(name, value)| (name, value))
+    }
+}
+
+impl FromIterator<(Header, String)> for HeaderMap {
+    fn from_iter<T: IntoIterator<Item = (Header, String)>>(iter: T) -> Self {
+        Self {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a HeaderMap {
+    type Item = (&'a Header, &'a String);
+    type IntoIter = std::iter::Map<
+        std::slice::Iter<'a, (Header, String)>,
+        fn(&'a (Header, String)) -> (&'a Header, &'a String),
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter().map(|(name, value)| (name, value))
+    }
+}
@@ -0,0 +1,697 @@
+use std::collections::VecDeque;
+
+// HPACK, the header compression of HTTP/2 (RFC 7541). The decoder keeps the dynamic table the
+// peer's encoder fills; the encoder never adds to one, so the peer's table size doesn't matter to
+// it beyond acknowledging changes.
+
+// The size the dynamic table starts with, and the most a decoder here allows.
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+// Each table entry counts this much on top of its name and value.
+pub const ENTRY_OVERHEAD: usize = 32;
+
+// A name and value, as raw octets.
+pub type HeaderField = (Vec<u8>, Vec<u8>);
+
+// Appendix A of RFC 7541. Indices start at 1.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// Appendix B of RFC 7541: the code and its length in bits for each byte, then for end-of-string.
+// The code is canonical, so decoding only needs to know where each length starts.
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13),
+    (0x7fffd8, 23),
+    (0xfffffe2, 28),
+    (0xfffffe3, 28),
+    (0xfffffe4, 28),
+    (0xfffffe5, 28),
+    (0xfffffe6, 28),
+    (0xfffffe7, 28),
+    (0xfffffe8, 28),
+    (0xffffea, 24),
+    (0x3ffffffc, 30),
+    (0xfffffe9, 28),
+    (0xfffffea, 28),
+    (0x3ffffffd, 30),
+    (0xfffffeb, 28),
+    (0xfffffec, 28),
+    (0xfffffed, 28),
+    (0xfffffee, 28),
+    (0xfffffef, 28),
+    (0xffffff0, 28),
+    (0xffffff1, 28),
+    (0xffffff2, 28),
+    (0x3ffffffe, 30),
+    (0xffffff3, 28),
+    (0xffffff4, 28),
+    (0xffffff5, 28),
+    (0xffffff6, 28),
+    (0xffffff7, 28),
+    (0xffffff8, 28),
+    (0xffffff9, 28),
+    (0xffffffa, 28),
+    (0xffffffb, 28),
+    (0x14, 6),
+    (0x3f8, 10),
+    (0x3f9, 10),
+    (0xffa, 12),
+    (0x1ff9, 13),
+    (0x15, 6),
+    (0xf8, 8),
+    (0x7fa, 11),
+    (0x3fa, 10),
+    (0x3fb, 10),
+    (0xf9, 8),
+    (0x7fb, 11),
+    (0xfa, 8),
+    (0x16, 6),
+    (0x17, 6),
+    (0x18, 6),
+    (0x0, 5),
+    (0x1, 5),
+    (0x2, 5),
+    (0x19, 6),
+    (0x1a, 6),
+    (0x1b, 6),
+    (0x1c, 6),
+    (0x1d, 6),
+    (0x1e, 6),
+    (0x1f, 6),
+    (0x5c, 7),
+    (0xfb, 8),
+    (0x7ffc, 15),
+    (0x20, 6),
+    (0xffb, 12),
+    (0x3fc, 10),
+    (0x1ffa, 13),
+    (0x21, 6),
+    (0x5d, 7),
+    (0x5e, 7),
+    (0x5f, 7),
+    (0x60, 7),
+    (0x61, 7),
+    (0x62, 7),
+    (0x63, 7),
+    (0x64, 7),
+    (0x65, 7),
+    (0x66, 7),
+    (0x67, 7),
+    (0x68, 7),
+    (0x69, 7),
+    (0x6a, 7),
+    (0x6b, 7),
+    (0x6c, 7),
+    (0x6d, 7),
+    (0x6e, 7),
+    (0x6f, 7),
+    (0x70, 7),
+    (0x71, 7),
+    (0x72, 7),
+    (0xfc, 8),
+    (0x73, 7),
+    (0xfd, 8),
+    (0x1ffb, 13),
+    (0x7fff0, 19),
+    (0x1ffc, 13),
+    (0x3ffc, 14),
+    (0x22, 6),
+    (0x7ffd, 15),
+    (0x3, 5),
+    (0x23, 6),
+    (0x4, 5),
+    (0x24, 6),
+    (0x5, 5),
+    (0x25, 6),
+    (0x26, 6),
+    (0x27, 6),
+    (0x6, 5),
+    (0x74, 7),
+    (0x75, 7),
+    (0x28, 6),
+    (0x29, 6),
+    (0x2a, 6),
+    (0x7, 5),
+    (0x2b, 6),
+    (0x76, 7),
+    (0x2c, 6),
+    (0x8, 5),
+    (0x9, 5),
+    (0x2d, 6),
+    (0x77, 7),
+    (0x78, 7),
+    (0x79, 7),
+    (0x7a, 7),
+    (0x7b, 7),
+    (0x7ffe, 15),
+    (0x7fc, 11),
+    (0x3ffd, 14),
+    (0x1ffd, 13),
+    (0xffffffc, 28),
+    (0xfffe6, 20),
+    (0x3fffd2, 22),
+    (0xfffe7, 20),
+    (0xfffe8, 20),
+    (0x3fffd3, 22),
+    (0x3fffd4, 22),
+    (0x3fffd5, 22),
+    (0x7fffd9, 23),
+    (0x3fffd6, 22),
+    (0x7fffda, 23),
+    (0x7fffdb, 23),
+    (0x7fffdc, 23),
+    (0x7fffdd, 23),
+    (0x7fffde, 23),
+    (0xffffeb, 24),
+    (0x7fffdf, 23),
+    (0xffffec, 24),
+    (0xffffed, 24),
+    (0x3fffd7, 22),
+    (0x7fffe0, 23),
+    (0xffffee, 24),
+    (0x7fffe1, 23),
+    (0x7fffe2, 23),
+    (0x7fffe3, 23),
+    (0x7fffe4, 23),
+    (0x1fffdc, 21),
+    (0x3fffd8, 22),
+    (0x7fffe5, 23),
+    (0x3fffd9, 22),
+    (0x7fffe6, 23),
+    (0x7fffe7, 23),
+    (0xffffef, 24),
+    (0x3fffda, 22),
+    (0x1fffdd, 21),
+    (0xfffe9, 20),
+    (0x3fffdb, 22),
+    (0x3fffdc, 22),
+    (0x7fffe8, 23),
+    (0x7fffe9, 23),
+    (0x1fffde, 21),
+    (0x7fffea, 23),
+    (0x3fffdd, 22),
+    (0x3fffde, 22),
+    (0xfffff0, 24),
+    (0x1fffdf, 21),
+    (0x3fffdf, 22),
+    (0x7fffeb, 23),
+    (0x7fffec, 23),
+    (0x1fffe0, 21),
+    (0x1fffe1, 21),
+    (0x3fffe0, 22),
+    (0x1fffe2, 21),
+    (0x7fffed, 23),
+    (0x3fffe1, 22),
+    (0x7fffee, 23),
+    (0x7fffef, 23),
+    (0xfffea, 20),
+    (0x3fffe2, 22),
+    (0x3fffe3, 22),
+    (0x3fffe4, 22),
+    (0x7ffff0, 23),
+    (0x3fffe5, 22),
+    (0x3fffe6, 22),
+    (0x7ffff1, 23),
+    (0x3ffffe0, 26),
+    (0x3ffffe1, 26),
+    (0xfffeb, 20),
+    (0x7fff1, 19),
+    (0x3fffe7, 22),
+    (0x7ffff2, 23),
+    (0x3fffe8, 22),
+    (0x1ffffec, 25),
+    (0x3ffffe2, 26),
+    (0x3ffffe3, 26),
+    (0x3ffffe4, 26),
+    (0x7ffffde, 27),
+    (0x7ffffdf, 27),
+    (0x3ffffe5, 26),
+    (0xfffff1, 24),
+    (0x1ffffed, 25),
+    (0x7fff2, 19),
+    (0x1fffe3, 21),
+    (0x3ffffe6, 26),
+    (0x7ffffe0, 27),
+    (0x7ffffe1, 27),
+    (0x3ffffe7, 26),
+    (0x7ffffe2, 27),
+    (0xfffff2, 24),
+    (0x1fffe4, 21),
+    (0x1fffe5, 21),
+    (0x3ffffe8, 26),
+    (0x3ffffe9, 26),
+    (0xffffffd, 28),
+    (0x7ffffe3, 27),
+    (0x7ffffe4, 27),
+    (0x7ffffe5, 27),
+    (0xfffec, 20),
+    (0xfffff3, 24),
+    (0xfffed, 20),
+    (0x1fffe6, 21),
+    (0x3fffe9, 22),
+    (0x1fffe7, 21),
+    (0x1fffe8, 21),
+    (0x7ffff3, 23),
+    (0x3fffea, 22),
+    (0x3fffeb, 22),
+    (0x1ffffee, 25),
+    (0x1ffffef, 25),
+    (0xfffff4, 24),
+    (0xfffff5, 24),
+    (0x3ffffea, 26),
+    (0x7ffff4, 23),
+    (0x3ffffeb, 26),
+    (0x7ffffe6, 27),
+    (0x3ffffec, 26),
+    (0x3ffffed, 26),
+    (0x7ffffe7, 27),
+    (0x7ffffe8, 27),
+    (0x7ffffe9, 27),
+    (0x7ffffea, 27),
+    (0x7ffffeb, 27),
+    (0xffffffe, 28),
+    (0x7ffffec, 27),
+    (0x7ffffed, 27),
+    (0x7ffffee, 27),
+    (0x7ffffef, 27),
+    (0x7fffff0, 27),
+    (0x3ffffee, 26),
+    (0x3fffffff, 30),
+];
+const EOS: usize = 256;
+const HUFFMAN_DECODING: HuffmanDecoding = huffman_decoding();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HpackError {
+    // The block ended in the middle of a representation.
+    Truncated,
+    IntegerOverflow,
+    InvalidIndex(usize),
+    InvalidHuffman,
+    // A dynamic table size update past the limit the decoder allows.
+    TableSizeTooLarge(usize),
+    // A dynamic table size update after the first field of a block.
+    MisplacedTableSizeUpdate,
+}
+
+// Turns header blocks back into fields, keeping the dynamic table in step with the peer's
+// encoder. Every block on a connection has to go through the same decoder, in order.
+#[derive(Debug)]
+pub struct Decoder {
+    table: DynamicTable,
+    max_table_size: usize,
+}
+
+// Writes header blocks using the static table and literals, Huffman-coded when that is shorter.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    // A size the peer's decoder changed to, which the next block acknowledges.
+    table_size_update: Option<usize>,
+}
+
+#[derive(Debug)]
+struct DynamicTable {
+    // Newest first, which is the order indices count in.
+    entries: VecDeque<HeaderField>,
+    size: usize,
+    max_size: usize,
+}
+
+struct HuffmanDecoding {
+    // For each code length: the first code of that length, how many codes have it, and where
+    // its symbols start in `symbols`.
+    first_code: [u32; 31],
+    count: [u16; 31],
+    first_index: [u16; 31],
+    // Symbols ordered by code.
+    symbols: [u16; 257],
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self {
+            table: DynamicTable::new(DEFAULT_TABLE_SIZE),
+            max_table_size: DEFAULT_TABLE_SIZE,
+        }
+    }
+
+    // Passes each field to `field` as it is decoded. A failure leaves the table out of step
+    // with the peer's, so the decoder can't be used again.
+    pub fn decode_with(
+        &mut self,
+        block: &[u8],
+        mut field: impl FnMut(&[u8], &[u8]),
+    ) -> Result<(), HpackError> {
+        let mut input = block;
+        let mut fields_started = false;
+        while let Some(&first) = input.first() {
+            if first & 0x80 != 0 {
+                let index = decode_integer(&mut input, 7)?;
+                let (name, value) = self.get(index)?;
+                field(name, value);
+            } else if first & 0xe0 == 0x20 {
+                if fields_started {
+                    return Err(HpackError::MisplacedTableSizeUpdate);
+                }
+                let size = decode_integer(&mut input, 5)?;
+                if size > self.max_table_size {
+                    return Err(HpackError::TableSizeTooLarge(size));
+                }
+                self.table.set_max_size(size);
+                continue;
+            } else {
+                // With incremental indexing, without indexing, or never indexed.
+                let indexed = first & 0x40 != 0;
+                let index = decode_integer(&mut input, if indexed { 6 } else { 4 })?;
+                let name = match index {
+                    0 => decode_string(&mut input)?,
+                    index => self.get(index)?.0.to_vec(),
+                };
+                let value = decode_string(&mut input)?;
+                field(&name, &value);
+                if indexed {
+                    self.table.insert(name, value);
+                }
+            }
+            fields_started = true;
+        }
+        Ok(())
+    }
+
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<HeaderField>, HpackError> {
+        let mut fields = Vec::new();
+        self.decode_with(block, |name, value| {
+            fields.push((name.to_vec(), value.to_vec()));
+        })?;
+        Ok(fields)
+    }
+
+    fn get(&self, index: usize) -> Result<(&[u8], &[u8]), HpackError> {
+        match index {
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.as_bytes(), value.as_bytes()))
+            }
+            _ => index
+                .checked_sub(STATIC_TABLE.len() + 1)
+                .and_then(|index| self.table.entries.get(index))
+                .map(|(name, value)| (name.as_slice(), value.as_slice()))
+                .ok_or(HpackError::InvalidIndex(index)),
+        }
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Takes the peer's SETTINGS_HEADER_TABLE_SIZE.
+    pub const fn set_max_table_size(&mut self, size: usize) {
+        self.table_size_update = Some(size);
+    }
+
+    // Names must already be lowercase, as HTTP/2 requires.
+    pub fn encode<'a>(
+        &mut self,
+        fields: impl IntoIterator<Item = (&'a [u8], &'a [u8])>,
+    ) -> Vec<u8> {
+        let mut block = Vec::new();
+        // Nothing is ever added to the table, so any size the peer allows is fine to confirm.
+        if let Some(size) = self.table_size_update.take() {
+            encode_integer(&mut block, 0x20, 5, size);
+        }
+        for (name, value) in fields {
+            let static_name = STATIC_TABLE
+                .iter()
+                .position(|(static_name, _)| static_name.as_bytes() == name);
+            let static_field = STATIC_TABLE.iter().position(|(static_name, static_value)| {
+                static_name.as_bytes() == name && static_value.as_bytes() == value
+            });
+            if let Some(index) = static_field {
+                encode_integer(&mut block, 0x80, 7, index + 1);
+                continue;
+            }
+            match static_name {
+                Some(index) => encode_integer(&mut block, 0x00, 4, index + 1),
+                None => {
+                    block.push(0x00);
+                    encode_string(&mut block, name);
+                }
+            }
+            encode_string(&mut block, value);
+        }
+        block
+    }
+}
+
+impl DynamicTable {
+    const fn new(max_size: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict(0);
+    }
+
+    // An entry larger than the whole table empties it and isn't kept.
+    fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    // Drops the oldest entries until `room` more bytes fit.
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            let Some((name, value)) = self.entries.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+// Section 5.1: the value starts in the low `prefix_bits` of the first byte, and continues seven
+// bits at a time once those are all ones.
+fn decode_integer(input: &mut &[u8], prefix_bits: u32) -> Result<usize, HpackError> {
+    let (&first, mut rest) = input.split_first().ok_or(HpackError::Truncated)?;
+    let mask = (1 << prefix_bits) - 1;
+    let mut value = (first & mask) as usize;
+    if value == mask as usize {
+        let mut shift = 0;
+        loop {
+            let (&byte, remaining) = rest.split_first().ok_or(HpackError::Truncated)?;
+            rest = remaining;
+            if shift > 28 {
+                return Err(HpackError::IntegerOverflow);
+            }
+            value += ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+    }
+    *input = rest;
+    Ok(value)
+}
+
+fn encode_integer(output: &mut Vec<u8>, flags: u8, prefix_bits: u32, value: usize) {
+    let mask = (1 << prefix_bits) - 1;
+    if value < mask {
+        output.push(flags | value as u8);
+        return;
+    }
+    output.push(flags | mask as u8);
+    let mut value = value - mask;
+    while value >= 0x80 {
+        output.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+// Section 5.2: a length with the Huffman flag in its first bit, then the octets.
+fn decode_string(input: &mut &[u8]) -> Result<Vec<u8>, HpackError> {
+    let huffman = input.first().is_some_and(|first| first & 0x80 != 0);
+    let length = decode_integer(input, 7)?;
+    if length > input.len() {
+        return Err(HpackError::Truncated);
+    }
+    let (string, rest) = input.split_at(length);
+    *input = rest;
+    if huffman {
+        huffman_decode(string)
+    } else {
+        Ok(string.to_vec())
+    }
+}
+
+fn encode_string(output: &mut Vec<u8>, string: &[u8]) {
+    let huffman_bits: usize = string
+        .iter()
+        .map(|&byte| HUFFMAN_CODES[byte as usize].1 as usize)
+        .sum();
+    let huffman_length = huffman_bits.div_ceil(8);
+    if huffman_length < string.len() {
+        encode_integer(output, 0x80, 7, huffman_length);
+        huffman_encode(output, string);
+    } else {
+        encode_integer(output, 0x00, 7, string.len());
+        output.extend_from_slice(string);
+    }
+}
+
+fn huffman_decode(encoded: &[u8]) -> Result<Vec<u8>, HpackError> {
+    let decoding = &HUFFMAN_DECODING;
+    let mut decoded = Vec::with_capacity(encoded.len() * 8 / 5);
+    let mut code: u32 = 0;
+    let mut length = 0;
+    for byte in encoded {
+        for shift in (0..8).rev() {
+            code = (code << 1) | u32::from((byte >> shift) & 1);
+            length += 1;
+            if length >= decoding.first_code.len() {
+                return Err(HpackError::InvalidHuffman);
+            }
+            let offset = code.wrapping_sub(decoding.first_code[length]);
+            if offset < u32::from(decoding.count[length]) {
+                let symbol =
+                    decoding.symbols[(decoding.first_index[length] as u32 + offset) as usize];
+                if symbol as usize == EOS {
+                    return Err(HpackError::InvalidHuffman);
+                }
+                decoded.push(symbol as u8);
+                code = 0;
+                length = 0;
+            }
+        }
+    }
+    // Section 5.2: padding is shorter than a byte and made of the most significant bits of EOS,
+    // which are all ones.
+    if length > 7 || code != (1 << length) - 1 {
+        return Err(HpackError::InvalidHuffman);
+    }
+    Ok(decoded)
+}
+
+fn huffman_encode(output: &mut Vec<u8>, string: &[u8]) {
+    let mut bits: u64 = 0;
+    let mut length = 0;
+    for &byte in string {
+        let (code, code_length) = HUFFMAN_CODES[byte as usize];
+        bits = (bits << code_length) | u64::from(code);
+        length += code_length;
+        while length >= 8 {
+            length -= 8;
+            output.push((bits >> length) as u8);
+        }
+    }
+    if length > 0 {
+        output.push(((bits << (8 - length)) | (0xff >> length)) as u8);
+    }
+}
+
+const fn huffman_decoding() -> HuffmanDecoding {
+    let mut decoding = HuffmanDecoding {
+        first_code: [0; 31],
+        count: [0; 31],
+        first_index: [0; 31],
+        symbols: [0; 257],
+    };
+    let mut next = 0;
+    let mut length = 1;
+    while length < decoding.first_code.len() {
+        decoding.first_index[length] = next as u16;
+        let mut symbol = 0;
+        while symbol < HUFFMAN_CODES.len() {
+            let (code, code_length) = HUFFMAN_CODES[symbol];
+            if code_length as usize == length {
+                if decoding.count[length] == 0 {
+                    decoding.first_code[length] = code;
+                }
+                decoding.count[length] += 1;
+                decoding.symbols[next] = symbol as u16;
+                next += 1;
+            }
+            symbol += 1;
+        }
+        length += 1;
+    }
+    decoding
+}
@@ -0,0 +1,1020 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+};
+
+use crate::{
+    connection::ConnectionReadError,
+    header::Header,
+    header_map::HeaderMap,
+    hpack::{Decoder, ENTRY_OVERHEAD, Encoder, HpackError},
+    limits::RequestLimits,
+    protocol::Protocol,
+    request::{Method, Request, RequestParseError},
+    response::{BODY_CHUNK_SIZE, Body, BodyReader, Response},
+    typed_header::is_token,
+};
+
+// What a client opens an HTTP/2 connection with, before its first frame (RFC 9113 section 3.4).
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_HEADER_SIZE: usize = 9;
+// The frame size every peer has to accept, and the largest this server takes.
+const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+const MAX_FRAME_SIZE_LIMIT: usize = 16_777_215;
+const DEFAULT_WINDOW_SIZE: i64 = 65_535;
+const MAX_WINDOW_SIZE: i64 = (1 << 31) - 1;
+const MAX_CONCURRENT_STREAMS: usize = 100;
+// Response bodies stop being read in while this much output waits to be written.
+const OUTPUT_HIGH_WATER: usize = 2 * BODY_CHUNK_SIZE;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+// Fields that only mean something to a single HTTP/1 connection, which HTTP/2 forbids.
+const CONNECTION_SPECIFIC: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+// Why a stream or the whole connection was ended (RFC 9113 section 7).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NoError = 0x0,
+    ProtocolError = 0x1,
+    InternalError = 0x2,
+    FlowControlError = 0x3,
+    SettingsTimeout = 0x4,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    Cancel = 0x8,
+    CompressionError = 0x9,
+    ConnectError = 0xa,
+    EnhanceYourCalm = 0xb,
+    InadequateSecurity = 0xc,
+    Http11Required = 0xd,
+}
+
+// The HTTP/2 side of a connection. Bytes read from the client go in, requests and frames to
+// send come out; the connection does the reading and writing.
+pub(crate) struct Http2Session {
+    limits: RequestLimits,
+    max_decoded_body_size: usize,
+    decoder: Decoder,
+    encoder: Encoder,
+    streams: BTreeMap<u32, Stream>,
+    // The highest stream the client has opened. Lower ones not in `streams` are closed.
+    last_stream_id: u32,
+    // Requests that are complete, or failed in a way that deserves a response, oldest first.
+    ready: VecDeque<(u32, Result<Request, ConnectionReadError>)>,
+    // A header block waiting for its CONTINUATION frames.
+    header_block: Option<HeaderBlock>,
+    output: Vec<u8>,
+    preface_received: bool,
+    settings_received: bool,
+    send_window: i64,
+    initial_send_window: i64,
+    receive_window: i64,
+    // Request body bytes held for streams, whose credit goes back once they are handed on.
+    buffered: usize,
+    max_send_frame_size: usize,
+    going_away: bool,
+    peer_going_away: bool,
+}
+
+struct Stream {
+    // Whether the client has sent all of its request.
+    remote_closed: bool,
+    // Set once the request went to `ready`, early if it failed. Later DATA is dropped.
+    dispatched: bool,
+    head: Option<Request>,
+    body: Vec<u8>,
+    // How much of `body` counts towards the session's buffered bytes.
+    buffered: usize,
+    send_window: i64,
+    receive_window: i64,
+    // The part of the response body still to go out, once the response has started.
+    pending: Option<Vec<u8>>,
+    // The rest of the response body, read in as flow control lets it go out.
+    reader: Option<BodyReader>,
+    // Whether more of the body may follow what is pending, as for an event stream.
+    open_ended: bool,
+}
+
+impl Stream {
+    fn has_body_to_send(&self) -> bool {
+        self.pending
+            .as_ref()
+            .is_some_and(|pending| !pending.is_empty())
+            || self.reader.as_ref().is_some_and(|reader| !reader.is_done())
+    }
+}
+
+struct HeaderBlock {
+    stream_id: u32,
+    end_stream: bool,
+    fragment: Vec<u8>,
+}
+
+struct Frame<'a> {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: &'a [u8],
+}
+
+// Problems with a request head. Malformed ones reset the stream as RFC 9113 section 8.1.1 asks;
+// invalid ones are answered like their HTTP/1 equivalents.
+enum HeadError {
+    Malformed,
+    Invalid(RequestParseError),
+}
+
+impl Http2Session {
+    pub(crate) fn new(limits: RequestLimits, max_decoded_body_size: usize) -> Self {
+        let mut session = Self {
+            limits,
+            max_decoded_body_size,
+            decoder: Decoder::new(),
+            encoder: Encoder::new(),
+            streams: BTreeMap::new(),
+            last_stream_id: 0,
+            ready: VecDeque::new(),
+            header_block: None,
+            output: Vec::new(),
+            preface_received: false,
+            settings_received: false,
+            send_window: DEFAULT_WINDOW_SIZE,
+            initial_send_window: DEFAULT_WINDOW_SIZE,
+            receive_window: DEFAULT_WINDOW_SIZE,
+            buffered: 0,
+            max_send_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            going_away: false,
+            peer_going_away: false,
+        };
+        let mut settings = Vec::new();
+        for (id, value) in [
+            (
+                SETTINGS_MAX_CONCURRENT_STREAMS,
+                MAX_CONCURRENT_STREAMS as u32,
+            ),
+            (
+                SETTINGS_MAX_HEADER_LIST_SIZE,
+                u32::try_from(session.limits.get_max_header_bytes()).unwrap_or(u32::MAX),
+            ),
+        ] {
+            settings.extend(id.to_be_bytes());
+            settings.extend(value.to_be_bytes());
+        }
+        session.queue_frame(SETTINGS, 0, 0, &settings);
+        session.return_credit();
+        session
+    }
+
+    // Consumes every complete frame in `input`, leaving a partial one for later. On a connection
+    // error a GOAWAY is queued and the session takes nothing more.
+    pub(crate) fn receive(&mut self, input: &mut Vec<u8>) -> Result<(), ErrorCode> {
+        let result = self.process(input);
+        if let Err(code) = result {
+            input.clear();
+            self.go_away(code);
+        }
+        result
+    }
+
+    fn process(&mut self, input: &mut Vec<u8>) -> Result<(), ErrorCode> {
+        if !self.preface_received {
+            let length = input.len().min(PREFACE.len());
+            if input[..length] != PREFACE[..length] {
+                return Err(ErrorCode::ProtocolError);
+            }
+            if length < PREFACE.len() {
+                return Ok(());
+            }
+            input.drain(..PREFACE.len());
+            self.preface_received = true;
+        }
+        let mut consumed = 0;
+        let result = loop {
+            let available = &input[consumed..];
+            if available.len() < FRAME_HEADER_SIZE {
+                break Ok(());
+            }
+            let length = u32::from_be_bytes([0, available[0], available[1], available[2]]) as usize;
+            if length > DEFAULT_MAX_FRAME_SIZE {
+                break Err(ErrorCode::FrameSizeError);
+            }
+            if available.len() < FRAME_HEADER_SIZE + length {
+                break Ok(());
+            }
+            let frame = Frame {
+                kind: available[3],
+                flags: available[4],
+                stream_id: u32::from_be_bytes([
+                    available[5],
+                    available[6],
+                    available[7],
+                    available[8],
+                ]) & 0x7fff_ffff,
+                payload: &available[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + length],
+            };
+            if let Err(code) = self.handle_frame(&frame) {
+                break Err(code);
+            }
+            consumed += FRAME_HEADER_SIZE + length;
+        };
+        input.drain(..consumed);
+        result
+    }
+
+    fn handle_frame(&mut self, frame: &Frame) -> Result<(), ErrorCode> {
+        if !self.settings_received && (frame.kind != SETTINGS || frame.flags & ACK != 0) {
+            return Err(ErrorCode::ProtocolError);
+        }
+        if let Some(block) = &self.header_block
+            && (frame.kind != CONTINUATION || frame.stream_id != block.stream_id)
+        {
+            return Err(ErrorCode::ProtocolError);
+        }
+        match frame.kind {
+            DATA => self.handle_data(frame),
+            HEADERS => self.handle_headers(frame),
+            PRIORITY => self.handle_priority(frame),
+            RST_STREAM => self.handle_reset(frame),
+            SETTINGS => self.handle_settings(frame),
+            PING => self.handle_ping(frame),
+            GOAWAY => self.handle_go_away(frame),
+            WINDOW_UPDATE => self.handle_window_update(frame),
+            CONTINUATION => self.handle_continuation(frame),
+            // Clients can't push, and types this server doesn't know are to be ignored.
+            PUSH_PROMISE => Err(ErrorCode::ProtocolError),
+            _ => Ok(()),
+        }
+    }
+
+    fn handle_data(&mut self, frame: &Frame) -> Result<(), ErrorCode> {
+        if frame.stream_id == 0 || self.is_unopened(frame.stream_id) {
+            return Err(ErrorCode::ProtocolError);
+        }
+        // The whole payload counts against the windows, padding included.
+        let length = frame.payload.len();
+        if length as i64 > self.receive_window {
+            return Err(ErrorCode::FlowControlError);
+        }
+        self.receive_window -= length as i64;
+        let data = unpad(frame)?;
+        let body_limit = self.limits.get_max_body_size();
+        let Some(stream) = self
+            .streams
+            .get_mut(&frame.stream_id)
+            .filter(|stream| !stream.remote_closed)
+        else {
+            self.reset_stream(frame.stream_id, ErrorCode::StreamClosed);
+            self.return_credit();
+            return Ok(());
+        };
+        if length as i64 > stream.receive_window {
+            self.reset_stream(frame.stream_id, ErrorCode::FlowControlError);
+            self.return_credit();
+            return Ok(());
+        }
+        stream.receive_window -= length as i64;
+        let end_stream = frame.flags & END_STREAM != 0;
+        stream.remote_closed = end_stream;
+        // A stream whose request was already turned down gets no more room to send in.
+        let mut top_up = false;
+        if !stream.dispatched {
+            stream.body.extend_from_slice(data);
+            if stream.body.len() > body_limit {
+                let error = RequestParseError::BodyTooLarge(stream.body.len());
+                stream.dispatched = true;
+                stream.body = Vec::new();
+                self.buffered -= stream.buffered;
+                stream.buffered = 0;
+                self.ready.push_back((
+                    frame.stream_id,
+                    Err(ConnectionReadError::MalformedRequest(error)),
+                ));
+            } else {
+                stream.buffered += data.len();
+                self.buffered += data.len();
+                top_up = !end_stream;
+            }
+        }
+        if top_up {
+            stream.receive_window += length as i64;
+            self.queue_window_update(frame.stream_id, length);
+        }
+        if end_stream {
+            self.finish_request(frame.stream_id);
+        }
+        self.return_credit();
+        Ok(())
+    }
+
+    fn handle_headers(&mut self, frame: &Frame) -> Result<(), ErrorCode> {
+        if frame.stream_id == 0 {
+            return Err(ErrorCode::ProtocolError);
+        }
+        let mut fragment = unpad(frame)?;
+        // Streams are answered in the order their requests complete, so priorities go unused.
+        if frame.flags & PRIORITY_FLAG != 0 {
+            fragment = fragment.get(5..).ok_or(ErrorCode::FrameSizeError)?;
+        }
+        let block = HeaderBlock {
+            stream_id: frame.stream_id,
+            end_stream: frame.flags & END_STREAM != 0,
+            fragment: fragment.to_vec(),
+        };
+        if frame.flags & END_HEADERS == 0 {
+            self.header_block = Some(block);
+            Ok(())
+        } else {
+            self.handle_header_block(block)
+        }
+    }
+
+    fn handle_continuation(&mut self, frame: &Frame) -> Result<(), ErrorCode> {
+        let Some(mut block) = self.header_block.take() else {
+            return Err(ErrorCode::ProtocolError);
+        };
+        block.fragment.extend_from_slice(frame.payload);
+        // The whole block has to be kept to be decoded, so there is a limit on how much of it.
+        if block.fragment.len() > 2 * self.limits.get_max_header_bytes() {
+            return Err(ErrorCode::EnhanceYourCalm);
+        }
+        if frame.flags & END_HEADERS == 0 {
+            self.header_block = Some(block);
+            Ok(())
+        } else {
+            self.handle_header_block(block)
+        }
+    }
+
+    fn handle_header_block(&mut self, block: HeaderBlock) -> Result<(), ErrorCode> {
+        let id = block.stream_id;
+        // Every block goes through the decoder, even for streams that get refused, to keep its
+        // table in step with the client's.
+        let fields = self.decode_fields(&block.fragment)?;
+        if let Some(stream) = self.streams.get_mut(&id) {
+            // Trailers, which end the request. Nothing here uses them.
+            if stream.remote_closed {
+                self.reset_stream(id, ErrorCode::StreamClosed);
+            } else if !block.end_stream {
+                self.reset_stream(id, ErrorCode::ProtocolError);
+            } else {
+                stream.remote_closed = true;
+                self.finish_request(id);
+            }
+            return Ok(());
+        }
+        if id.is_multiple_of(2) || id <= self.last_stream_id {
+            return Err(if id.is_multiple_of(2) {
+                ErrorCode::ProtocolError
+            } else {
+                ErrorCode::StreamClosed
+            });
+        }
+        self.last_stream_id = id;
+        if self.going_away {
+            return Ok(());
+        }
+        if self.streams.len() >= MAX_CONCURRENT_STREAMS {
+            self.queue_reset(id, ErrorCode::RefusedStream);
+            return Ok(());
+        }
+        let mut stream = Stream {
+            remote_closed: block.end_stream,
+            dispatched: false,
+            head: None,
+            body: Vec::new(),
+            buffered: 0,
+            send_window: self.initial_send_window,
+            receive_window: DEFAULT_WINDOW_SIZE,
+            pending: None,
+            reader: None,
+            open_ended: false,
+        };
+        let head = fields.and_then(|fields| parse_head(fields, &self.limits));
+        match head {
+            Ok(request) => stream.head = Some(request),
+            Err(HeadError::Malformed) => {
+                self.queue_reset(id, ErrorCode::ProtocolError);
+                return Ok(());
+            }
+            Err(HeadError::Invalid(error)) => {
+                stream.dispatched = true;
+                self.ready
+                    .push_back((id, Err(ConnectionReadError::MalformedRequest(error))));
+            }
+        }
+        self.streams.insert(id, stream);
+        if block.end_stream {
+            self.finish_request(id);
+        }
+        Ok(())
+    }
+
+    // Fails the connection only when the block can't be decoded. A head that is too large still
+    // decodes, so the client can be told.
+    fn decode_fields(
+        &mut self,
+        block: &[u8],
+    ) -> Result<Result<Vec<(String, String)>, HeadError>, ErrorCode> {
+        let max_bytes = self.limits.get_max_header_bytes();
+        let max_count = self.limits.get_max_header_count();
+        let mut fields = Vec::new();
+        let mut size = 0;
+        let mut count = 0;
+        let mut not_utf8 = false;
+        self.decoder
+            .decode_with(block, |name, value| {
+                size += name.len() + value.len() + ENTRY_OVERHEAD;
+                if !name.starts_with(b":") {
+                    count += 1;
+                }
+                if size > max_bytes || count > max_count {
+                    return;
+                }
+                match (std::str::from_utf8(name), std::str::from_utf8(value)) {
+                    (Ok(name), Ok(value)) => fields.push((name.to_string(), value.to_string())),
+                    _ => not_utf8 = true,
+                }
+            })
+            .map_err(|_: HpackError| ErrorCode::CompressionError)?;
+        Ok(if size > max_bytes {
+            Err(HeadError::Invalid(RequestParseError::HeadTooLarge(size)))
+        } else if count > max_count {
+            Err(HeadError::Invalid(RequestParseError::TooManyHeaders(count)))
+        } else if not_utf8 {
+            Err(HeadError::Invalid(RequestParseError::NotUtf8))
+        } else {
+            Ok(fields)
+        })
+    }
+
+    // Hands the request on once all of it is in.
+    fn finish_request(&mut self, id: u32) {
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return;
+        };
+        if stream.dispatched {
+            return;
+        }
+        let Some(mut request) = stream.head.take() else {
+            return;
+        };
+        stream.dispatched = true;
+        let body = std::mem::take(&mut stream.body);
+        // A declared length the DATA frames don't add up to makes the request malformed.
+        if request.get_headers().contains_key(&Header::ContentLength)
+            && !request
+                .get_content_length()
+                .is_ok_and(|length| length == body.len())
+        {
+            self.reset_stream(id, ErrorCode::ProtocolError);
+            return;
+        }
+        let result = request
+            .set_encoded_body(body, self.max_decoded_body_size)
+            .map(|()| request)
+            .map_err(ConnectionReadError::InvalidBody);
+        self.ready.push_back((id, result));
+    }
+
+    fn handle_priority(&mut self, frame: &Frame) -> Result<(), ErrorCode> {
+        if frame.stream_id == 0 {
+            return Err(ErrorCode::ProtocolError);
+        }
+        if frame.payload.len() != 5 {
+            self.reset_stream(frame.stream_id, ErrorCode::FrameSizeError);
+        }
+        Ok(())
+    }
+
+    fn handle_reset(&mut self, frame: &Frame) -> Result<(), ErrorCode> {
+        if frame.stream_id == 0 || self.is_unopened(frame.stream_id) {
+            return Err(ErrorCode::ProtocolError);
+        }
+        if frame.payload.len() != 4 {
+            return Err(ErrorCode::FrameSizeError);
+        }
+        self.remove_stream(frame.stream_id);
+        Ok(())
+    }
+
+    fn handle_settings(&mut self, frame: &Frame) -> Result<(), ErrorCode> {
+        if frame.stream_id != 0 {
+            return Err(ErrorCode::ProtocolError);
+        }
+        if frame.flags & ACK != 0 {
+            return if frame.payload.is_empty() {
+                Ok(())
+            } else {
+                Err(ErrorCode::FrameSizeError)
+            };
+        }
+        if !frame.payload.len().is_multiple_of(6) {
+            return Err(ErrorCode::FrameSizeError);
+        }
+        self.settings_received = true;
+        for setting in frame.payload.chunks_exact(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match id {
+                SETTINGS_HEADER_TABLE_SIZE => self.encoder.set_max_table_size(value as usize),
+                SETTINGS_ENABLE_PUSH if value > 1 => return Err(ErrorCode::ProtocolError),
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let value = i64::from(value);
+                    if value > MAX_WINDOW_SIZE {
+                        return Err(ErrorCode::FlowControlError);
+                    }
+                    // Open streams' windows move by the difference, and may go negative.
+                    let delta = value - self.initial_send_window;
+                    self.initial_send_window = value;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                        if stream.send_window > MAX_WINDOW_SIZE {
+                            return Err(ErrorCode::FlowControlError);
+                        }
+                    }
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    let value = value as usize;
+                    if !(DEFAULT_MAX_FRAME_SIZE..=MAX_FRAME_SIZE_LIMIT).contains(&value) {
+                        return Err(ErrorCode::ProtocolError);
+                    }
+                    self.max_send_frame_size = value;
+                }
+                // Nothing is pushed and responses aren't limited, so the rest don't matter.
+                _ => {}
+            }
+        }
+        self.queue_frame(SETTINGS, ACK, 0, &[]);
+        self.send_data();
+        Ok(())
+    }
+
+    fn handle_ping(&mut self, frame: &Frame) -> Result<(), ErrorCode> {
+        if frame.stream_id != 0 {
+            return Err(ErrorCode::ProtocolError);
+        }
+        if frame.payload.len() != 8 {
+            return Err(ErrorCode::FrameSizeError);
+        }
+        if frame.flags & ACK == 0 {
+            self.queue_frame(PING, ACK, 0, frame.payload);
+        }
+        Ok(())
+    }
+
+    // The client won't open more streams. The ones it has still get their responses.
+    const fn handle_go_away(&mut self, frame: &Frame) -> Result<(), ErrorCode> {
+        if frame.stream_id != 0 {
+            return Err(ErrorCode::ProtocolError);
+        }
+        if frame.payload.len() < 8 {
+            return Err(ErrorCode::FrameSizeError);
+        }
+        self.peer_going_away = true;
+        Ok(())
+    }
+
+    fn handle_window_update(&mut self, frame: &Frame) -> Result<(), ErrorCode> {
+        if frame.payload.len() != 4 {
+            return Err(ErrorCode::FrameSizeError);
+        }
+        let increment = i64::from(
+            u32::from_be_bytes([
+                frame.payload[0],
+                frame.payload[1],
+                frame.payload[2],
+                frame.payload[3],
+            ]) & 0x7fff_ffff,
+        );
+        if frame.stream_id == 0 {
+            if increment == 0 {
+                return Err(ErrorCode::ProtocolError);
+            }
+            self.send_window += increment;
+            if self.send_window > MAX_WINDOW_SIZE {
+                return Err(ErrorCode::FlowControlError);
+            }
+        } else if self.is_unopened(frame.stream_id) {
+            return Err(ErrorCode::ProtocolError);
+        } else if increment == 0 {
+            self.reset_stream(frame.stream_id, ErrorCode::ProtocolError);
+        } else if let Some(stream) = self.streams.get_mut(&frame.stream_id) {
+            stream.send_window += increment;
+            if stream.send_window > MAX_WINDOW_SIZE {
+                self.reset_stream(frame.stream_id, ErrorCode::FlowControlError);
+            }
+        }
+        self.send_data();
+        Ok(())
+    }
+
+    // The next request to route, with the stream its response goes on.
+    pub(crate) fn next_request(&mut self) -> Option<(u32, Result<Request, ConnectionReadError>)> {
+        while let Some((id, result)) = self.ready.pop_front() {
+            // The client may have reset the stream while the request waited.
+            if let Some(stream) = self.streams.get_mut(&id) {
+                self.buffered -= std::mem::take(&mut stream.buffered);
+                self.return_credit();
+                return Some((id, result));
+            }
+        }
+        None
+    }
+
+    pub(crate) fn has_requests(&self) -> bool {
+        !self.ready.is_empty()
+    }
+
+    // Queues the response as HEADERS and DATA frames, as far as flow control lets the body go.
+    // A response for a stream the client has since reset is dropped. An open-ended one keeps its
+    // stream open for `push_data` until `end_stream`.
+    pub(crate) fn respond(
+        &mut self,
+        id: u32,
+        response: &Response,
+        head_only: bool,
+        open_ended: bool,
+    ) -> io::Result<()> {
+        if !self.streams.contains_key(&id) {
+            return Ok(());
+        }
+        let code = response.get_code();
+        let reader = match response.get_body() {
+            Some(body) if !head_only && code.allows_body() && !body.is_empty() => {
+                Some(body.open()?)
+            }
+            _ => None,
+        };
+        let status = (code as usize).to_string();
+        let mut fields: Vec<(String, &str)> = vec![(":status".to_string(), &status)];
+        for (header, value) in response.get_headers() {
+            let name = header.as_str().to_ascii_lowercase();
+            if !CONNECTION_SPECIFIC.contains(&name.as_str()) {
+                fields.push((name, value));
+            }
+        }
+        let length = response.get_body().map_or(Some(0), Body::len);
+        let length = length.map(|length| length.to_string());
+        if let Some(length) = &length
+            && !response.get_headers().contains_key(&Header::ContentLength)
+            && code.allows_body()
+            && !open_ended
+        {
+            fields.push(("content-length".to_string(), length));
+        }
+        let block = self.encoder.encode(
+            fields
+                .iter()
+                .map(|(name, value)| (name.as_bytes(), value.as_bytes())),
+        );
+        self.queue_header_block(id, &block, reader.is_none() && !open_ended);
+        if reader.is_none() && !open_ended {
+            self.close_stream(id);
+        } else if let Some(stream) = self.streams.get_mut(&id) {
+            stream.pending = Some(Vec::new());
+            stream.reader = reader;
+            stream.open_ended = open_ended;
+            self.send_data();
+        }
+        Ok(())
+    }
+
+    // Adds to the body of an open-ended response. False once the client has reset the stream.
+    pub(crate) fn push_data(&mut self, id: u32, data: &[u8]) -> bool {
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return false;
+        };
+        stream
+            .pending
+            .get_or_insert_default()
+            .extend_from_slice(data);
+        self.send_data();
+        true
+    }
+
+    // Ends an open-ended response once its pending data has gone out.
+    pub(crate) fn end_stream(&mut self, id: u32) {
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return;
+        };
+        stream.open_ended = false;
+        if stream.pending.as_ref().is_none_or(Vec::is_empty) {
+            self.queue_frame(DATA, END_STREAM, id, &[]);
+            self.close_stream(id);
+        } else {
+            self.send_data();
+        }
+    }
+
+    pub(crate) fn is_open(&self, id: u32) -> bool {
+        self.streams.contains_key(&id)
+    }
+
+    // Sends as much pending response data as the windows and OUTPUT_HIGH_WATER allow, stream by
+    // stream.
+    fn send_data(&mut self) {
+        let ids: Vec<u32> = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| stream.pending.is_some())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            if self.send_window <= 0 || self.output.len() >= OUTPUT_HIGH_WATER {
+                break;
+            }
+            let Some(stream) = self.streams.get_mut(&id) else {
+                continue;
+            };
+            let Some(mut pending) = stream.pending.take() else {
+                continue;
+            };
+            let mut offset = 0;
+            let mut unreadable = false;
+            while stream.send_window > 0 && self.send_window > 0 {
+                if offset == pending.len() {
+                    if self.output.len() >= OUTPUT_HIGH_WATER {
+                        break;
+                    }
+                    let Some(reader) = stream.reader.as_mut().filter(|reader| !reader.is_done())
+                    else {
+                        break;
+                    };
+                    let Ok(chunk) = reader.read_chunk(BODY_CHUNK_SIZE) else {
+                        unreadable = true;
+                        break;
+                    };
+                    pending = chunk;
+                    offset = 0;
+                }
+                let length = (pending.len() - offset)
+                    .min(self.max_send_frame_size)
+                    .min(stream.send_window as usize)
+                    .min(self.send_window as usize);
+                let end = offset + length;
+                let last = end == pending.len()
+                    && !stream.open_ended
+                    && stream.reader.as_ref().is_none_or(BodyReader::is_done);
+                let flags = if last { END_STREAM } else { 0 };
+                stream.send_window -= length as i64;
+                self.send_window -= length as i64;
+                queue_frame(&mut self.output, DATA, flags, id, &pending[offset..end]);
+                offset = end;
+                if last {
+                    break;
+                }
+            }
+            let finished = offset == pending.len()
+                && !stream.open_ended
+                && stream.reader.as_ref().is_none_or(BodyReader::is_done);
+            if unreadable {
+                // The response can't be finished, so the client is told it was cut short.
+                self.remove_stream(id);
+                self.queue_reset(id, ErrorCode::InternalError);
+            } else if finished {
+                self.close_stream(id);
+            } else {
+                pending.drain(..offset);
+                stream.pending = Some(pending);
+            }
+        }
+    }
+
+    // Our side of the stream is done. A client still sending its request is told to stop.
+    fn close_stream(&mut self, id: u32) {
+        if let Some(stream) = self.remove_stream(id)
+            && !stream.remote_closed
+        {
+            self.queue_reset(id, ErrorCode::NoError);
+        }
+    }
+
+    fn reset_stream(&mut self, id: u32, code: ErrorCode) {
+        self.remove_stream(id);
+        self.queue_reset(id, code);
+    }
+
+    fn remove_stream(&mut self, id: u32) -> Option<Stream> {
+        let stream = self.streams.remove(&id)?;
+        self.buffered -= stream.buffered;
+        Some(stream)
+    }
+
+    // Gives the client back as much connection window as buffered bodies leave room for. They may
+    // hold up to one full body and a window beyond it, however many streams that is spread over.
+    fn return_credit(&mut self) {
+        let budget = i64::try_from(self.limits.get_max_body_size())
+            .unwrap_or(MAX_WINDOW_SIZE)
+            .saturating_add(DEFAULT_WINDOW_SIZE)
+            .min(MAX_WINDOW_SIZE);
+        let target = budget - self.buffered as i64;
+        if target > self.receive_window {
+            self.queue_window_update(0, (target - self.receive_window) as usize);
+            self.receive_window = target;
+        }
+    }
+
+    // Refuses new streams from here on. Those already open are still answered.
+    pub(crate) fn go_away(&mut self, code: ErrorCode) {
+        if self.going_away {
+            return;
+        }
+        self.going_away = true;
+        let mut payload = self.last_stream_id.to_be_bytes().to_vec();
+        payload.extend((code as u32).to_be_bytes());
+        self.queue_frame(GOAWAY, 0, 0, &payload);
+        if code != ErrorCode::NoError {
+            self.streams.clear();
+            self.ready.clear();
+            self.buffered = 0;
+        }
+    }
+
+    // Response bodies held back by OUTPUT_HIGH_WATER are read in as the output is taken.
+    pub(crate) fn take_output(&mut self) -> Vec<u8> {
+        self.send_data();
+        std::mem::take(&mut self.output)
+    }
+
+    pub(crate) fn has_output(&self) -> bool {
+        !self.output.is_empty()
+            || (self.send_window > 0
+                && self
+                    .streams
+                    .values()
+                    .any(|stream| stream.send_window > 0 && stream.has_body_to_send()))
+    }
+
+    // No stream is waiting on a request or a response.
+    pub(crate) fn is_idle(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    // Either side is going away and every stream has been answered.
+    pub(crate) fn is_finished(&self) -> bool {
+        (self.going_away || self.peer_going_away) && self.streams.is_empty()
+    }
+
+    // A stream the client hasn't opened yet.
+    const fn is_unopened(&self, id: u32) -> bool {
+        id > self.last_stream_id
+    }
+
+    fn queue_header_block(&mut self, id: u32, block: &[u8], end_stream: bool) {
+        let mut chunks = block.chunks(self.max_send_frame_size).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { END_STREAM } else { 0 };
+        loop {
+            let chunk = chunks.next().unwrap_or_default();
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            queue_frame(&mut self.output, kind, flags, id, chunk);
+            if flags & END_HEADERS != 0 {
+                break;
+            }
+            kind = CONTINUATION;
+            flags = 0;
+        }
+    }
+
+    fn queue_reset(&mut self, id: u32, code: ErrorCode) {
+        self.queue_frame(RST_STREAM, 0, id, &(code as u32).to_be_bytes());
+    }
+
+    fn queue_window_update(&mut self, id: u32, increment: usize) {
+        if increment > 0 {
+            self.queue_frame(WINDOW_UPDATE, 0, id, &(increment as u32).to_be_bytes());
+        }
+    }
+
+    fn queue_frame(&mut self, kind: u8, flags: u8, id: u32, payload: &[u8]) {
+        queue_frame(&mut self.output, kind, flags, id, payload);
+    }
+}
+
+fn queue_frame(output: &mut Vec<u8>, kind: u8, flags: u8, id: u32, payload: &[u8]) {
+    output.extend(&(payload.len() as u32).to_be_bytes()[1..]);
+    output.push(kind);
+    output.push(flags);
+    output.extend(id.to_be_bytes());
+    output.extend(payload);
+}
+
+// The payload without the padding of a PADDED frame.
+fn unpad<'a>(frame: &Frame<'a>) -> Result<&'a [u8], ErrorCode> {
+    if frame.flags & PADDED == 0 {
+        return Ok(frame.payload);
+    }
+    let (&padding, rest) = frame
+        .payload
+        .split_first()
+        .ok_or(ErrorCode::FrameSizeError)?;
+    rest.len()
+        .checked_sub(padding as usize)
+        .map(|length| &rest[..length])
+        .ok_or(ErrorCode::ProtocolError)
+}
+
+// Turns the decoded fields into a request, checking the rules of RFC 9113 section 8.3.1.
+fn parse_head(fields: Vec<(String, String)>, limits: &RequestLimits) -> Result<Request, HeadError> {
+    let mut method = None;
+    let mut scheme = None;
+    let mut authority = None;
+    let mut path = None;
+    let mut header_fields = HeaderMap::new();
+    let mut cookies = Vec::new();
+    for (name, value) in fields {
+        if let Some(pseudo) = name.strip_prefix(':') {
+            if !header_fields.is_empty() || !cookies.is_empty() {
+                return Err(HeadError::Malformed);
+            }
+            let slot = match pseudo {
+                "method" => &mut method,
+                "scheme" => &mut scheme,
+                "authority" => &mut authority,
+                "path" => &mut path,
+                _ => return Err(HeadError::Malformed),
+            };
+            if slot.replace(value).is_some() {
+                return Err(HeadError::Malformed);
+            }
+            continue;
+        }
+        if !is_token(&name)
+            || name.bytes().any(|byte| byte.is_ascii_uppercase())
+            || CONNECTION_SPECIFIC.contains(&name.as_str())
+            || (name == "te" && value != "trailers")
+            || value.starts_with([' ', '\t'])
+            || value.ends_with([' ', '\t'])
+        {
+            return Err(HeadError::Malformed);
+        }
+        // Cookies may arrive split into separate fields, to compress better.
+        if name == "cookie" {
+            cookies.push(value);
+        } else {
+            header_fields.append(Header::from(name.as_str()), value);
+        }
+    }
+    if !cookies.is_empty() {
+        header_fields.append(Header::Cookie, cookies.join("; "));
+    }
+    let method = method.ok_or(HeadError::Malformed)?;
+    let target = if method == Method::Connect.as_str() {
+        if scheme.is_some() || path.is_some() {
+            return Err(HeadError::Malformed);
+        }
+        authority.clone().ok_or(HeadError::Malformed)?
+    } else {
+        if scheme.is_none() {
+            return Err(HeadError::Malformed);
+        }
+        path.filter(|path| !path.is_empty())
+            .ok_or(HeadError::Malformed)?
+    };
+    if target.len() > limits.get_max_request_line_length() {
+        return Err(HeadError::Invalid(RequestParseError::RequestLineTooLong(
+            target.len(),
+        )));
+    }
+    // Handlers that look at Host find the authority there, as they would over HTTP/1.1.
+    if let Some(authority) = authority
+        && !header_fields.contains_key(&Header::Host)
+    {
+        header_fields.insert(Header::Host, authority);
+    }
+    Request::from_parts(&method, &target, Protocol::Http2, header_fields)
+        .map_err(HeadError::Invalid)
+}
@@ -1,13 +1,36 @@
 #![warn(clippy::all, clippy::nursery)]
-#![feature(map_try_insert)]
 
+pub mod activation;
+#[cfg(feature = "tls")]
+pub mod certificates;
+#[cfg(feature = "tls")]
+pub mod client_auth;
+pub mod compression;
+pub mod conditional;
 pub mod connection;
+pub mod date;
 pub mod error_utils;
 pub mod handler;
+pub mod handoff;
 pub mod header;
+pub mod header_map;
+pub mod hpack;
+pub mod http2;
+pub mod limits;
+pub mod logging;
 pub mod protocol;
+pub mod range;
 pub mod request;
 pub mod response;
 pub mod router;
 pub mod server;
 pub mod socket;
+pub mod socket_builder;
+pub mod sse;
+pub mod static_files;
+pub mod timer;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod typed_header;
+pub mod uri;
+pub mod websocket;
@@ -0,0 +1,228 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+const DEFAULT_MAX_REQUEST_LINE_LENGTH: usize = 8 * 1024;
+const DEFAULT_MAX_HEADER_COUNT: usize = 100;
+const DEFAULT_MAX_HEADER_BYTES: usize = 64 * 1024;
+const DEFAULT_MAX_BODY_SIZE: usize = 8 * 1024 * 1024;
+const DEFAULT_HEADER_READ_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_BODY_READ_TIMEOUT: Duration = Duration::from_secs(30);
+// select() cannot watch descriptors past FD_SETSIZE, so the default stays well below it.
+const DEFAULT_MAX_CONNECTIONS: usize = 512;
+const DEFAULT_MAX_ACCEPTS_PER_WAKEUP: usize = 64;
+const DEFAULT_ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestLimits {
+    max_request_line_length: usize,
+    max_header_count: usize,
+    max_header_bytes: usize,
+    max_body_size: usize,
+    header_read_timeout: Option<Duration>,
+    body_read_timeout: Option<Duration>,
+}
+
+// What to do with new clients while `max_connections` are already open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverloadPolicy {
+    // Leave them in the listen backlog until a connection closes.
+    Pause,
+    // Accept them only to answer ServiceUnavailable and close.
+    Reject,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    overload_policy: OverloadPolicy,
+    max_accepts_per_wakeup: usize,
+    accept_error_backoff: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitViolation {
+    RequestLineTooLong,
+    TooManyHeaders,
+    HeadersTooLarge,
+    BodyTooLarge,
+    HeaderTimeout,
+    BodyTimeout,
+}
+
+// Counts rejected requests per limit. Shared behind an `Arc` so it can be read while the server
+// is running on another thread.
+#[derive(Debug, Default)]
+pub struct LimitMetrics {
+    counters: [AtomicU64; LimitViolation::ALL.len()],
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestLimits {
+    pub const fn new() -> Self {
+        Self {
+            max_request_line_length: DEFAULT_MAX_REQUEST_LINE_LENGTH,
+            max_header_count: DEFAULT_MAX_HEADER_COUNT,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            header_read_timeout: Some(DEFAULT_HEADER_READ_TIMEOUT),
+            body_read_timeout: Some(DEFAULT_BODY_READ_TIMEOUT),
+        }
+    }
+
+    pub const fn set_max_request_line_length(&mut self, max_request_line_length: usize) {
+        self.max_request_line_length = max_request_line_length;
+    }
+
+    pub const fn set_max_header_count(&mut self, max_header_count: usize) {
+        self.max_header_count = max_header_count;
+    }
+
+    // Covers the whole head, request line included, up to and including the empty line.
+    pub const fn set_max_header_bytes(&mut self, max_header_bytes: usize) {
+        self.max_header_bytes = max_header_bytes;
+    }
+
+    // Limits the body as sent, before any Content-Encoding is undone.
+    pub const fn set_max_body_size(&mut self, max_body_size: usize) {
+        self.max_body_size = max_body_size;
+    }
+
+    // Time allowed from the connection being ready for a request until its head is complete.
+    pub const fn set_header_read_timeout(&mut self, header_read_timeout: Option<Duration>) {
+        self.header_read_timeout = header_read_timeout;
+    }
+
+    // Time allowed from the head being complete until the whole body has arrived.
+    pub const fn set_body_read_timeout(&mut self, body_read_timeout: Option<Duration>) {
+        self.body_read_timeout = body_read_timeout;
+    }
+
+    pub const fn get_max_request_line_length(&self) -> usize {
+        self.max_request_line_length
+    }
+
+    pub const fn get_max_header_count(&self) -> usize {
+        self.max_header_count
+    }
+
+    pub const fn get_max_header_bytes(&self) -> usize {
+        self.max_header_bytes
+    }
+
+    pub const fn get_max_body_size(&self) -> usize {
+        self.max_body_size
+    }
+
+    pub const fn get_header_read_timeout(&self) -> Option<Duration> {
+        self.header_read_timeout
+    }
+
+    pub const fn get_body_read_timeout(&self) -> Option<Duration> {
+        self.body_read_timeout
+    }
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConnectionLimits {
+    pub const fn new() -> Self {
+        Self {
+            max_connections: Some(DEFAULT_MAX_CONNECTIONS),
+            max_connections_per_ip: None,
+            overload_policy: OverloadPolicy::Pause,
+            max_accepts_per_wakeup: DEFAULT_MAX_ACCEPTS_PER_WAKEUP,
+            accept_error_backoff: DEFAULT_ACCEPT_ERROR_BACKOFF,
+        }
+    }
+
+    pub const fn set_max_connections(&mut self, max_connections: Option<usize>) {
+        self.max_connections = max_connections;
+    }
+
+    // Clients over this cap are always answered with TooManyRequests, whatever the policy.
+    pub const fn set_max_connections_per_ip(&mut self, max_connections_per_ip: Option<usize>) {
+        self.max_connections_per_ip = max_connections_per_ip;
+    }
+
+    pub const fn set_overload_policy(&mut self, overload_policy: OverloadPolicy) {
+        self.overload_policy = overload_policy;
+    }
+
+    pub fn set_max_accepts_per_wakeup(&mut self, max_accepts_per_wakeup: usize) {
+        self.max_accepts_per_wakeup = max_accepts_per_wakeup.max(1);
+    }
+
+    // How long to stop accepting after running out of descriptors or memory, since the pending
+    // connection keeps the listener readable and retrying at once would just spin.
+    pub const fn set_accept_error_backoff(&mut self, accept_error_backoff: Duration) {
+        self.accept_error_backoff = accept_error_backoff;
+    }
+
+    pub const fn get_max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    pub const fn get_max_connections_per_ip(&self) -> Option<usize> {
+        self.max_connections_per_ip
+    }
+
+    pub const fn get_overload_policy(&self) -> OverloadPolicy {
+        self.overload_policy
+    }
+
+    pub const fn get_max_accepts_per_wakeup(&self) -> usize {
+        self.max_accepts_per_wakeup
+    }
+
+    pub const fn get_accept_error_backoff(&self) -> Duration {
+        self.accept_error_backoff
+    }
+}
+
+impl LimitViolation {
+    pub const ALL: [Self; 6] = [
+        Self::RequestLineTooLong,
+        Self::TooManyHeaders,
+        Self::HeadersTooLarge,
+        Self::BodyTooLarge,
+        Self::HeaderTimeout,
+        Self::BodyTimeout,
+    ];
+
+    const fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl LimitMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, violation: LimitViolation) -> u64 {
+        self.counters[violation.index()].load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> u64 {
+        LimitViolation::ALL
+            .iter()
+            .map(|&violation| self.get(violation))
+            .sum()
+    }
+
+    pub(crate) fn record(&self, violation: LimitViolation) {
+        self.counters[violation.index()].fetch_add(1, Ordering::Relaxed);
+    }
+}
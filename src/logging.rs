@@ -0,0 +1,66 @@
+use std::{
+    fmt::{self, Arguments, Display},
+    sync::{PoisonError, RwLock},
+};
+
+use crate::{header::Header, request::Request};
+
+// Takes each line the server logs.
+pub type Logger = Box<dyn Fn(&str) + Send + Sync>;
+
+static LOGGER: RwLock<Option<Logger>> = RwLock::new(None);
+
+// Header fields whose values are left out of logged requests.
+const CREDENTIALS: [Header; 3] = [
+    Header::Authorization,
+    Header::ProxyAuthorization,
+    Header::Cookie,
+];
+
+// Sends what the server logs to `logger`. Without one, as by default, nothing is logged.
+pub fn set_logger(logger: Option<Logger>) {
+    *LOGGER.write().unwrap_or_else(PoisonError::into_inner) = logger;
+}
+
+// The line is only formatted when there is a logger to take it.
+pub(crate) fn write(args: Arguments) {
+    if let Some(logger) = LOGGER
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+    {
+        logger(&fmt::format(args));
+    }
+}
+
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::logging::write(format_args!($($arg)*))
+    };
+}
+
+pub(crate) use log;
+
+// Shows a request head as it is logged, with credentials redacted.
+pub struct Redacted<'a>(pub &'a Request);
+
+impl Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let request = self.0;
+        write!(
+            f,
+            "{} {} {}",
+            request.get_method().as_str(),
+            request.get_target(),
+            request.get_protocol().as_str()
+        )?;
+        for (header, field) in request.get_headers().iter() {
+            if CREDENTIALS.contains(header) {
+                write!(f, "\n{}: [redacted]", header.as_str())?;
+            } else {
+                write!(f, "\n{}: {}", header.as_str(), field)?;
+            }
+        }
+        Ok(())
+    }
+}
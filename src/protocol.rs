@@ -1,5 +1,6 @@
 #[derive(Debug, Clone, Copy)]
 pub enum Protocol {
+    Http2,
     Http1_1,
     Http1_0,
     Http0_9,
@@ -12,6 +13,7 @@ impl<'a> TryFrom<Option<&'a str>> for Protocol {
     fn try_from(value: Option<&'a str>) -> Result<Self, Self::Error> {
         Ok(match value {
             Some(string) => match string {
+                "HTTP/2" => Self::Http2,
                 "HTTP/1.1" => Self::Http1_1,
                 "HTTP/1.0" => Self::Http1_0,
                 "HTTP/0.9" => Self::Http0_9,
@@ -25,6 +27,7 @@ impl<'a> TryFrom<Option<&'a str>> for Protocol {
 impl Protocol {
     pub const fn as_str(&self) -> &'static str {
         match self {
+            Self::Http2 => "HTTP/2",
             Self::Http1_1 => "HTTP/1.1",
             Self::Http1_0 => "HTTP/1.0",
             Self::Http0_9 => "HTTP/0.9",
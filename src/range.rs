@@ -0,0 +1,238 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    conditional::EntityTag,
+    date::HttpDate,
+    header::Header,
+    request::{Method, Request},
+    response::{Body, Response, ResponseCode},
+};
+
+const MAX_RANGES: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    FromTo(u64, u64),
+    From(u64),
+    Suffix(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeParseError {
+    UnsupportedUnit(String),
+    Malformed(String),
+    TooManyRanges,
+}
+
+impl ByteRange {
+    // Returns the inclusive bounds of this range within a body of `length` bytes, if any.
+    pub const fn resolve(&self, length: u64) -> Option<(u64, u64)> {
+        match *self {
+            Self::FromTo(start, end) if start < length => {
+                Some((start, if end < length { end } else { length - 1 }))
+            }
+            Self::From(start) if start < length => Some((start, length - 1)),
+            Self::Suffix(suffix) if suffix > 0 && length > 0 => {
+                Some((length.saturating_sub(suffix), length - 1))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn parse_position(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+pub fn parse_range_header(value: &str) -> Result<Vec<ByteRange>, RangeParseError> {
+    let (unit, ranges) = value
+        .trim()
+        .split_once('=')
+        .ok_or_else(|| RangeParseError::Malformed(value.to_string()))?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Err(RangeParseError::UnsupportedUnit(unit.to_string()));
+    }
+    let ranges = ranges
+        .split(',')
+        .map(str::trim)
+        .filter(|range| !range.is_empty())
+        .map(|range| {
+            let malformed = || RangeParseError::Malformed(range.to_string());
+            let (start, end) = range.split_once('-').ok_or_else(malformed)?;
+            match (start.trim(), end.trim()) {
+                ("", suffix) => parse_position(suffix)
+                    .map(ByteRange::Suffix)
+                    .ok_or_else(malformed),
+                (start, "") => parse_position(start)
+                    .map(ByteRange::From)
+                    .ok_or_else(malformed),
+                (start, end) => match (parse_position(start), parse_position(end)) {
+                    (Some(start), Some(end)) if start <= end => Ok(ByteRange::FromTo(start, end)),
+                    _ => Err(malformed()),
+                },
+            }
+        })
+        .collect::<Result<Vec<ByteRange>, RangeParseError>>()?;
+    if ranges.is_empty() {
+        return Err(RangeParseError::Malformed(value.to_string()));
+    }
+    if ranges.len() > MAX_RANGES {
+        return Err(RangeParseError::TooManyRanges);
+    }
+    Ok(ranges)
+}
+
+fn if_range_matches(if_range: &str, response: &Response) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        EntityTag::parse(if_range).is_ok_and(|tag| {
+            response
+                .get_etag()
+                .is_some_and(|current| tag.strong_eq(&current))
+        })
+    } else {
+        HttpDate::parse(if_range).is_some_and(|date| {
+            response
+                .get_last_modified()
+                .is_some_and(|last_modified| last_modified == date)
+        })
+    }
+}
+
+fn generate_boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos());
+    format!("byteranges_{:032x}", nanos)
+}
+
+// The parts of a multipart/byteranges body, each slice of `body` read only as it goes out.
+fn multipart_body(
+    body: &Body,
+    length: u64,
+    ranges: &[(u64, u64)],
+    content_type: Option<&String>,
+    boundary: &str,
+) -> Body {
+    let mut parts = Vec::new();
+    for &(start, end) in ranges {
+        let mut head = format!("\r\n--{}\r\n", boundary);
+        if let Some(content_type) = content_type {
+            head.push_str(&format!(
+                "{}: {}\r\n",
+                Header::ContentType.as_str(),
+                content_type
+            ));
+        }
+        head.push_str(&format!(
+            "{}: bytes {}-{}/{}\r\n\r\n",
+            Header::ContentRange.as_str(),
+            start,
+            end,
+            length
+        ));
+        parts.push(Body::Bytes(head.into_bytes()));
+        parts.push(body.slice(start, end - start + 1));
+    }
+    parts.push(Body::Bytes(
+        format!("\r\n--{}--\r\n", boundary).into_bytes(),
+    ));
+    Body::Parts(parts)
+}
+
+// Sorts the ranges and merges those that overlap or touch. None if they ask for more bytes than
+// the representation has, which only repeating parts of it could add up to.
+fn coalesce(mut ranges: Vec<(u64, u64)>, length: u64) -> Option<Vec<(u64, u64)>> {
+    let requested = ranges
+        .iter()
+        .map(|(start, end)| end - start + 1)
+        .fold(0u64, u64::saturating_add);
+    if requested > length {
+        return None;
+    }
+    ranges.sort_unstable();
+    let mut coalesced: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match coalesced.last_mut() {
+            Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                *last_end = (*last_end).max(end);
+            }
+            _ => coalesced.push((start, end)),
+        }
+    }
+    Some(coalesced)
+}
+
+pub fn apply_range(request: &Request, mut response: Response) -> Response {
+    if response.get_code() != ResponseCode::Ok {
+        return response;
+    }
+    // Only the length is needed until a range turns out satisfiable. An encoded body has none.
+    let Some(length) = response.get_body().and_then(Body::len) else {
+        return response;
+    };
+    response
+        .get_headers_mut()
+        .insert(Header::AcceptRanges, "bytes".to_string());
+    if *request.get_method() != Method::Get {
+        return response;
+    }
+    let Some(range_header) = request.get_headers().get(&Header::Range) else {
+        return response;
+    };
+    if let Some(if_range) = request.get_headers().get(&Header::IfRange)
+        && !if_range_matches(if_range, &response)
+    {
+        return response;
+    }
+    let Ok(ranges) = parse_range_header(range_header) else {
+        return response;
+    };
+
+    let resolved: Vec<(u64, u64)> = ranges
+        .iter()
+        .filter_map(|range| range.resolve(length))
+        .collect();
+    // Overlapping ranges that add up to more than the whole get the whole, as a plain 200.
+    let Some(resolved) = coalesce(resolved, length) else {
+        return response;
+    };
+    response.get_headers_mut().remove(&Header::ContentLength);
+    match resolved.as_slice() {
+        [] => {
+            response.set_code(ResponseCode::RangeNotSatisfiable);
+            response
+                .get_headers_mut()
+                .insert(Header::ContentRange, format!("bytes */{}", length));
+            response.set_body(None);
+        }
+        &[(start, end)] => {
+            response.set_code(ResponseCode::PartialContent);
+            response.get_headers_mut().insert(
+                Header::ContentRange,
+                format!("bytes {}-{}/{}", start, end, length),
+            );
+            let partial = response
+                .get_body()
+                .map(|body| body.slice(start, end - start + 1));
+            response.set_body(partial);
+        }
+        ranges => {
+            let boundary = generate_boundary();
+            let content_type = response.get_headers().get(&Header::ContentType).cloned();
+            let body = response
+                .get_body()
+                .map(|body| multipart_body(body, length, ranges, content_type.as_ref(), &boundary));
+            response.set_code(ResponseCode::PartialContent);
+            response.get_headers_mut().insert(
+                Header::ContentType,
+                format!("multipart/byteranges; boundary={}", boundary),
+            );
+            response.set_body(body);
+        }
+    }
+    response
+}
@@ -1,7 +1,17 @@
 use std::{collections::HashMap, fmt::Display};
 
-use crate::{header::Header, protocol::Protocol};
+use crate::{
+    compression::{BodyDecodeError, decode_content},
+    header::Header,
+    header_map::HeaderMap,
+    limits::{LimitViolation, RequestLimits},
+    protocol::Protocol,
+    response::ResponseCode,
+    typed_header::{ContentLength, HeaderParseError, is_token},
+    uri::parse_query,
+};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Method {
     Get,
     Head,
@@ -52,18 +62,334 @@ impl Method {
 #[derive(Debug, Clone)]
 pub enum RequestParseError {
     RequestLineMissing,
+    MalformedRequestLine(String),
     MethodMissing,
     UnknownMethod(String),
     TargetMissing,
+    InvalidTarget(String),
+    RequestLineTooLong(usize),
+    MalformedProtocol(String),
     UnknownProtocol(String),
+    MalformedHeaderLine(String),
+    ObsoleteLineFolding(String),
+    HeadTooLarge(usize),
+    TooManyHeaders(usize),
+    BodyTooLarge(usize),
+    NotUtf8,
+    MissingHost,
+    InvalidHeader(HeaderParseError),
+    UnsupportedTransferEncoding(String),
+    MalformedChunk(String),
 }
 
 pub struct Request {
     method: Method,
     target: String,
     protocol: Protocol,
-    header_fields: HashMap<Header, String>,
+    header_fields: HeaderMap,
     path_parameters: HashMap<String, String>,
+    query_parameters: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl RequestParseError {
+    pub const fn response_code(&self) -> ResponseCode {
+        match self {
+            Self::UnknownMethod(_) | Self::UnsupportedTransferEncoding(_) => {
+                ResponseCode::NotImplemented
+            }
+            Self::RequestLineTooLong(_) => ResponseCode::URITooLarge,
+            Self::UnknownProtocol(_) => ResponseCode::HTTPVersionNotSupported,
+            Self::HeadTooLarge(_) | Self::TooManyHeaders(_) => {
+                ResponseCode::RequestHeaderFieldsTooLarge
+            }
+            Self::BodyTooLarge(_) => ResponseCode::PayloadTooLarge,
+            Self::InvalidHeader(err) => err.response_code(),
+            Self::RequestLineMissing
+            | Self::MalformedRequestLine(_)
+            | Self::MethodMissing
+            | Self::TargetMissing
+            | Self::InvalidTarget(_)
+            | Self::MalformedProtocol(_)
+            | Self::MalformedHeaderLine(_)
+            | Self::ObsoleteLineFolding(_)
+            | Self::NotUtf8
+            | Self::MissingHost
+            | Self::MalformedChunk(_) => ResponseCode::BadRequest,
+        }
+    }
+
+    pub const fn limit_violation(&self) -> Option<LimitViolation> {
+        match self {
+            Self::RequestLineTooLong(_) => Some(LimitViolation::RequestLineTooLong),
+            Self::HeadTooLarge(_) => Some(LimitViolation::HeadersTooLarge),
+            Self::TooManyHeaders(_) => Some(LimitViolation::TooManyHeaders),
+            Self::BodyTooLarge(_) => Some(LimitViolation::BodyTooLarge),
+            _ => None,
+        }
+    }
+}
+
+// Classifies a head that outgrew the header byte limit, blaming the request line when it alone
+// is already too long.
+pub(crate) fn oversized_head_error(head: &[u8], limits: &RequestLimits) -> RequestParseError {
+    let request_line_length = head
+        .windows(2)
+        .position(|window| window == b"\r\n")
+        .unwrap_or(head.len());
+    if request_line_length > limits.get_max_request_line_length() {
+        RequestParseError::RequestLineTooLong(request_line_length)
+    } else {
+        RequestParseError::HeadTooLarge(head.len())
+    }
+}
+
+fn parse_method(method: &str) -> Result<Method, RequestParseError> {
+    if method.is_empty() {
+        return Err(RequestParseError::MethodMissing);
+    }
+    if !is_token(method) {
+        return Err(RequestParseError::MalformedRequestLine(method.to_string()));
+    }
+    method
+        .try_into()
+        .map_err(|err: &str| RequestParseError::UnknownMethod(err.to_string()))
+}
+
+// Accepts the four request-target forms of RFC 9112 section 3.2, each only where it is allowed.
+fn parse_target(target: &str, method: Method) -> Result<String, RequestParseError> {
+    if target.is_empty() {
+        return Err(RequestParseError::TargetMissing);
+    }
+    let visible = target.bytes().all(|byte| (0x21..=0x7e).contains(&byte));
+    let well_formed = match method {
+        Method::Connect => !target.starts_with('/') && target.contains(':'),
+        Method::Options if target == "*" => true,
+        _ => {
+            target.starts_with('/')
+                || target
+                    .split_once("://")
+                    .is_some_and(|(scheme, _)| is_token(scheme))
+        }
+    };
+    if visible && well_formed {
+        Ok(target.to_string())
+    } else {
+        Err(RequestParseError::InvalidTarget(target.to_string()))
+    }
+}
+
+// An absolute-form target names the host itself. Per RFC 9112 section 3.2.2 the server routes on
+// its path and takes its authority as the Host, in place of any Host field sent with it.
+fn split_absolute_form(target: &str) -> Result<Option<(String, String)>, RequestParseError> {
+    let Some((scheme, rest)) = target
+        .split_once("://")
+        .filter(|_| !target.starts_with('/'))
+    else {
+        return Ok(None);
+    };
+    let (authority, path) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+    if !(scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https"))
+        || authority.is_empty()
+        || authority.contains('@')
+    {
+        return Err(RequestParseError::InvalidTarget(target.to_string()));
+    }
+    let origin = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path)
+    };
+    Ok(Some((authority.to_string(), origin)))
+}
+
+fn parse_protocol(version: &str) -> Result<Protocol, RequestParseError> {
+    let Some((major, minor)) = version
+        .strip_prefix("HTTP/")
+        .and_then(|number| number.split_once('.'))
+        .filter(|(major, minor)| {
+            [major, minor]
+                .iter()
+                .all(|digit| digit.len() == 1 && digit.bytes().all(|byte| byte.is_ascii_digit()))
+        })
+    else {
+        return Err(RequestParseError::MalformedProtocol(version.to_string()));
+    };
+    // Later 1.x minor versions are compatible with HTTP/1.1, as RFC 9110 section 2.5 requires.
+    match (major, minor) {
+        ("1", "0") => Ok(Protocol::Http1_0),
+        ("1", _) => Ok(Protocol::Http1_1),
+        _ => Err(RequestParseError::UnknownProtocol(version.to_string())),
+    }
+}
+
+fn parse_field_line(line: &str) -> Result<(Header, String), RequestParseError> {
+    if line.starts_with([' ', '\t']) {
+        return Err(RequestParseError::ObsoleteLineFolding(line.to_string()));
+    }
+    let (name, value) = line
+        .split_once(':')
+        .filter(|(name, _)| is_token(name))
+        .ok_or_else(|| RequestParseError::MalformedHeaderLine(line.to_string()))?;
+    let header = Header::from(name);
+    let value = value.trim_matches([' ', '\t']);
+    if value
+        .bytes()
+        .any(|byte| (byte < 0x20 && byte != b'\t') || byte == 0x7f)
+    {
+        return Err(RequestParseError::InvalidHeader(HeaderParseError::new(
+            header,
+            &[value],
+        )));
+    }
+    Ok((header, value.to_string()))
+}
+
+// Rejects message framing this server cannot honour, since guessing would desynchronise the
+// connection. Chunked is the only transfer coding supported.
+fn check_framing(protocol: Protocol, headers: &HeaderMap) -> Result<(), RequestParseError> {
+    if matches!(protocol, Protocol::Http1_1) {
+        match headers.get_all(&Header::Host).count() {
+            0 => return Err(RequestParseError::MissingHost),
+            1 => {}
+            _ => {
+                let hosts: Vec<&str> = headers.get_all(&Header::Host).map(String::as_str).collect();
+                return Err(RequestParseError::InvalidHeader(HeaderParseError::new(
+                    Header::Host,
+                    &hosts,
+                )));
+            }
+        }
+    }
+    if !headers.contains_key(&Header::TransferEncoding) {
+        return Ok(());
+    }
+    let codings = headers.get_list(&Header::TransferEncoding);
+    let is_chunked = |coding: &str| coding.eq_ignore_ascii_case("chunked");
+    // Per RFC 9112 section 6.1, framing that comes with a Content-Length too or from an HTTP/1.0
+    // client can't be trusted, and per section 6.3 a body that isn't chunked last has no end.
+    if headers.contains_key(&Header::ContentLength)
+        || !matches!(protocol, Protocol::Http1_1)
+        || !codings.last().is_some_and(|coding| is_chunked(coding))
+        || codings.iter().filter(|coding| is_chunked(coding)).count() > 1
+    {
+        return Err(RequestParseError::InvalidHeader(HeaderParseError::new(
+            Header::TransferEncoding,
+            &codings,
+        )));
+    }
+    if codings.len() > 1 {
+        return Err(RequestParseError::UnsupportedTransferEncoding(
+            codings.join(", "),
+        ));
+    }
+    Ok(())
+}
+
+fn find_line_end(input: &[u8]) -> Option<usize> {
+    input.windows(2).position(|window| window == b"\r\n")
+}
+
+// Decodes a chunked body per RFC 9112 section 7.1 as it arrives, picking up where the last call
+// left off, so each byte is only looked at once. Chunk sizes, extensions and trailers count against
+// the header byte limit along with the head, so the whole request is no larger than the limits
+// allow. Trailer fields are checked, then discarded.
+pub(crate) struct ChunkedDecoder {
+    body: Vec<u8>,
+    // How much of the input has been decoded.
+    position: usize,
+    // The bytes of head and framing so far.
+    framing: usize,
+    // Set once a size line has been read, until its chunk has.
+    chunk_size: Option<usize>,
+    in_trailers: bool,
+}
+
+impl ChunkedDecoder {
+    pub(crate) const fn new(head_length: usize) -> Self {
+        Self {
+            body: Vec::new(),
+            position: 0,
+            framing: head_length,
+            chunk_size: None,
+            in_trailers: false,
+        }
+    }
+
+    // Takes everything after the head so far. Returns the body with the number of bytes it took
+    // up, or None while more are needed.
+    pub(crate) fn decode(
+        &mut self,
+        input: &[u8],
+        limits: &RequestLimits,
+    ) -> Result<Option<(Vec<u8>, usize)>, RequestParseError> {
+        loop {
+            if let Some(size) = self.chunk_size {
+                let Some(chunk) = input.get(self.position..self.position + size + 2) else {
+                    return Ok(None);
+                };
+                if !chunk.ends_with(b"\r\n") {
+                    return Err(RequestParseError::MalformedChunk(
+                        String::from_utf8_lossy(chunk).to_string(),
+                    ));
+                }
+                self.body.extend_from_slice(&chunk[..size]);
+                self.position += size + 2;
+                self.chunk_size = None;
+                continue;
+            }
+            let Some(line) = self.next_line(input, limits)? else {
+                return Ok(None);
+            };
+            if self.in_trailers {
+                if line.is_empty() {
+                    return Ok(Some((std::mem::take(&mut self.body), self.position)));
+                }
+                std::str::from_utf8(line)
+                    .map_err(|_| RequestParseError::NotUtf8)
+                    .and_then(parse_field_line)?;
+                continue;
+            }
+            let size = line
+                .split(|byte| *byte == b';')
+                .next()
+                .map(|size| size.trim_ascii_end())
+                .filter(|size| !size.is_empty() && size.iter().all(u8::is_ascii_hexdigit))
+                .and_then(|size| usize::from_str_radix(std::str::from_utf8(size).ok()?, 16).ok())
+                .ok_or_else(|| {
+                    RequestParseError::MalformedChunk(String::from_utf8_lossy(line).to_string())
+                })?;
+            if size == 0 {
+                self.in_trailers = true;
+            } else if self.body.len().saturating_add(size) > limits.get_max_body_size() {
+                return Err(RequestParseError::BodyTooLarge(
+                    self.body.len().saturating_add(size),
+                ));
+            } else {
+                self.chunk_size = Some(size);
+            }
+        }
+    }
+
+    // The next complete line, without its CRLF. A partial one already over the limit is an error.
+    fn next_line<'a>(
+        &mut self,
+        input: &'a [u8],
+        limits: &RequestLimits,
+    ) -> Result<Option<&'a [u8]>, RequestParseError> {
+        let rest = &input[self.position..];
+        let line_end = find_line_end(rest);
+        let length = line_end.map_or(rest.len(), |line_end| line_end + 2);
+        if self.framing + length > limits.get_max_header_bytes() {
+            return Err(RequestParseError::HeadTooLarge(self.framing + length));
+        }
+        let Some(line_end) = line_end else {
+            return Ok(None);
+        };
+        self.framing += length;
+        self.position += length;
+        Ok(Some(&rest[..line_end]))
+    }
 }
 
 impl Request {
@@ -71,6 +397,16 @@ impl Request {
         &self.target
     }
 
+    pub fn get_path(&self) -> &str {
+        self.target
+            .split_once('?')
+            .map_or(self.target.as_str(), |(path, _)| path)
+    }
+
+    pub const fn get_method(&self) -> &Method {
+        &self.method
+    }
+
     pub const fn get_protocol(&self) -> Protocol {
         self.protocol
     }
@@ -82,40 +418,175 @@ impl Request {
     pub const fn get_path_parameters_mut(&mut self) -> &mut HashMap<String, String> {
         &mut self.path_parameters
     }
+
+    pub const fn get_query_parameters(&self) -> &HashMap<String, String> {
+        &self.query_parameters
+    }
+
+    pub const fn get_headers(&self) -> &HeaderMap {
+        &self.header_fields
+    }
+
+    pub fn get_body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn get_content_length(&self) -> Result<usize, RequestParseError> {
+        match self.header_fields.get_typed::<ContentLength>() {
+            Some(Ok(ContentLength(length))) => usize::try_from(length).map_err(|_| {
+                RequestParseError::InvalidHeader(HeaderParseError::new(
+                    Header::ContentLength,
+                    &[&length.to_string()],
+                ))
+            }),
+            Some(Err(err)) => Err(RequestParseError::InvalidHeader(err)),
+            None => Ok(0),
+        }
+    }
+
+    pub(crate) fn is_chunked(&self) -> bool {
+        self.header_fields.contains_key(&Header::TransferEncoding)
+    }
+
+    // Once a chunked body is decoded, the request describes it by its length instead, as in
+    // RFC 9112 section 7.1.3.
+    pub(crate) fn replace_chunked_framing(&mut self, length: usize) {
+        self.header_fields.remove(&Header::TransferEncoding);
+        self.header_fields
+            .insert_typed(&ContentLength(length as u64));
+    }
+
+    pub(crate) fn set_encoded_body(
+        &mut self,
+        body: Vec<u8>,
+        max_decoded_size: usize,
+    ) -> Result<(), BodyDecodeError> {
+        self.body = match self.header_fields.remove(&Header::ContentEncoding) {
+            Some(content_encoding) => {
+                let decoded = decode_content(&content_encoding, body, max_decoded_size)?;
+                self.header_fields
+                    .insert_typed(&ContentLength(decoded.len() as u64));
+                decoded
+            }
+            None => body,
+        };
+        Ok(())
+    }
 }
 
 impl TryFrom<&str> for Request {
     type Error = RequestParseError;
 
     fn try_from(string: &str) -> Result<Self, RequestParseError> {
-        let mut lines = string.lines();
-        let mut request_line_parts = lines
-            .next()
-            .ok_or(RequestParseError::RequestLineMissing)?
-            .split_whitespace();
-        let method: Method = request_line_parts
-            .next()
-            .ok_or(RequestParseError::MethodMissing)?
-            .try_into()
-            .map_err(|err: &str| RequestParseError::UnknownMethod(err.to_string()))?;
-        let target: String = request_line_parts
-            .next()
-            .ok_or(RequestParseError::TargetMissing)?
-            .to_string();
-        let protocol: Protocol = request_line_parts
+        Self::parse(string, &RequestLimits::default())
+    }
+}
+
+impl Request {
+    // Parses a request head terminated by an empty line, following RFC 9112 strictly: lines end in
+    // CRLF, the request line has exactly three parts, and field lines may not be folded.
+    pub fn parse(string: &str, limits: &RequestLimits) -> Result<Self, RequestParseError> {
+        if string.len() > limits.get_max_header_bytes() {
+            return Err(oversized_head_error(string.as_bytes(), limits));
+        }
+        // RFC 9112 section 2.2 asks servers to ignore empty lines before the request line.
+        let mut lines = string.trim_start_matches("\r\n").split("\r\n");
+        let request_line = lines
             .next()
-            .try_into()
-            .map_err(|err: &str| RequestParseError::UnknownProtocol(err.to_string()))?;
-        let header_fields: HashMap<Header, String> =
-            HashMap::from_iter(lines.map_while(|line| line.split_once(':')).map(
-                |(raw_header, raw_field)| (raw_header.into(), raw_field.trim_start().to_string()),
+            .filter(|line| !line.is_empty())
+            .ok_or(RequestParseError::RequestLineMissing)?;
+        if request_line.len() > limits.get_max_request_line_length() {
+            return Err(RequestParseError::RequestLineTooLong(request_line.len()));
+        }
+        if request_line.contains(['\r', '\n']) {
+            return Err(RequestParseError::MalformedRequestLine(
+                request_line.to_string(),
+            ));
+        }
+        let mut request_line_parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) = (
+            request_line_parts.next(),
+            request_line_parts.next(),
+            request_line_parts.next(),
+            request_line_parts.next(),
+        ) else {
+            return Err(RequestParseError::MalformedRequestLine(
+                request_line.to_string(),
             ));
+        };
+        let method = parse_method(method)?;
+        let mut target = parse_target(target, method)?;
+        let protocol = parse_protocol(version)?;
+        let field_lines: Vec<&str> = lines.take_while(|line| !line.is_empty()).collect();
+        if field_lines.len() > limits.get_max_header_count() {
+            return Err(RequestParseError::TooManyHeaders(field_lines.len()));
+        }
+        let mut header_fields = field_lines
+            .into_iter()
+            .map(|line| {
+                if line.contains(['\r', '\n']) {
+                    Err(RequestParseError::MalformedHeaderLine(line.to_string()))
+                } else {
+                    parse_field_line(line)
+                }
+            })
+            .collect::<Result<HeaderMap, RequestParseError>>()?;
+        check_framing(protocol, &header_fields)?;
+        if method != Method::Connect
+            && let Some((authority, origin)) = split_absolute_form(&target)?
+        {
+            header_fields.insert(Header::Host, authority);
+            target = origin;
+        }
+        let query_parameters = target
+            .split_once('?')
+            .map(|(_, query)| parse_query(query))
+            .unwrap_or_default();
+        Ok(Self {
+            method,
+            target,
+            protocol,
+            header_fields,
+            path_parameters: HashMap::new(),
+            query_parameters,
+            body: Vec::new(),
+        })
+    }
+}
+
+impl Request {
+    // Builds a request from a head that arrived already split into its parts, as HTTP/2 sends it.
+    pub(crate) fn from_parts(
+        method: &str,
+        target: &str,
+        protocol: Protocol,
+        header_fields: HeaderMap,
+    ) -> Result<Self, RequestParseError> {
+        let method = parse_method(method)?;
+        let target = parse_target(target, method)?;
+        if let Some((header, value)) = header_fields.iter().find(|(_, value)| {
+            value
+                .bytes()
+                .any(|byte| (byte < 0x20 && byte != b'\t') || byte == 0x7f)
+        }) {
+            return Err(RequestParseError::InvalidHeader(HeaderParseError::new(
+                header.clone(),
+                &[value],
+            )));
+        }
+        check_framing(protocol, &header_fields)?;
+        let query_parameters = target
+            .split_once('?')
+            .map(|(_, query)| parse_query(query))
+            .unwrap_or_default();
         Ok(Self {
             method,
             target,
             protocol,
             header_fields,
             path_parameters: HashMap::new(),
+            query_parameters,
+            body: Vec::new(),
         })
     }
 }
@@ -1,8 +1,20 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    fs::File,
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::PathBuf,
+};
 
-use crate::{header::Header, protocol::Protocol};
+use crate::{
+    compression::ContentCoding, conditional::EntityTag, date::HttpDate, header::Header,
+    header_map::HeaderMap, protocol::Protocol, typed_header::LastModified,
+};
 
-#[derive(Debug, Clone, Copy)]
+// How much of a body is read into memory at a time while it is being sent.
+pub(crate) const BODY_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ResponseCode {
     Continue = 100,
     SwitchingProtocols = 101,
@@ -39,6 +51,8 @@ pub enum ResponseCode {
     RangeNotSatisfiable = 416,
     ExpectationFailed = 417,
     UpgradeRequired = 426,
+    TooManyRequests = 429,
+    RequestHeaderFieldsTooLarge = 431,
     InternalServerError = 500,
     NotImplemented = 501,
     BadGateway = 502,
@@ -48,6 +62,13 @@ pub enum ResponseCode {
 }
 
 impl ResponseCode {
+    pub const fn allows_body(&self) -> bool {
+        !matches!(
+            self,
+            Self::Continue | Self::SwitchingProtocols | Self::NoContent | Self::NotModified
+        )
+    }
+
     pub const fn as_phrase(&self) -> &'static str {
         match self {
             Self::Continue => "Continue",
@@ -85,6 +106,8 @@ impl ResponseCode {
             Self::RangeNotSatisfiable => "Range Not Satisfiable",
             Self::ExpectationFailed => "Expectation Failed",
             Self::UpgradeRequired => "Upgrade Required",
+            Self::TooManyRequests => "Too Many Requests",
+            Self::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
             Self::InternalServerError => "Internal Server Error",
             Self::NotImplemented => "Not Implemented",
             Self::BadGateway => "Bad Gateway",
@@ -95,53 +118,330 @@ impl ResponseCode {
     }
 }
 
+#[derive(Debug, Clone)]
+pub enum Body {
+    Bytes(Vec<u8>),
+    File {
+        path: PathBuf,
+        offset: u64,
+        length: u64,
+    },
+    // One body after another, each only opened once the one before it has been read.
+    Parts(Vec<Self>),
+    // Another body, compressed a chunk at a time as it goes out.
+    Encoded {
+        source: Box<Self>,
+        coding: ContentCoding,
+        level: u32,
+    },
+}
+
+impl Body {
+    // None for an encoded body, whose length is only known once all of it has gone out.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes.len() as u64),
+            Self::File { length, .. } => Some(*length),
+            Self::Parts(parts) => parts.iter().map(Self::len).sum(),
+            Self::Encoded { .. } => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    // An encoded body can't be sliced until it has been encoded, so it stays whole.
+    pub fn slice(&self, start: u64, length: u64) -> Self {
+        match self {
+            Self::Bytes(bytes) => {
+                let start = (start as usize).min(bytes.len());
+                let end = start.saturating_add(length as usize).min(bytes.len());
+                Self::Bytes(bytes[start..end].to_vec())
+            }
+            Self::File {
+                path,
+                offset,
+                length: file_length,
+            } => Self::File {
+                path: path.clone(),
+                offset: offset + start.min(*file_length),
+                length: length.min(file_length.saturating_sub(start)),
+            },
+            Self::Parts(parts) => {
+                let mut start = start;
+                let mut length = length;
+                let mut sliced = Vec::new();
+                for part in parts {
+                    let part_length = part.len().unwrap_or_default();
+                    if start >= part_length {
+                        start -= part_length;
+                        continue;
+                    }
+                    if length == 0 {
+                        break;
+                    }
+                    let taken = length.min(part_length - start);
+                    sliced.push(part.slice(start, taken));
+                    start = 0;
+                    length -= taken;
+                }
+                Self::Parts(sliced)
+            }
+            Self::Encoded { .. } => self.clone(),
+        }
+    }
+
+    pub fn open(&self) -> std::io::Result<BodyReader> {
+        let source: Box<dyn Read> = match self {
+            Self::Bytes(bytes) => Box::new(Cursor::new(bytes.clone())),
+            Self::File { path, offset, .. } => {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(*offset))?;
+                Box::new(file)
+            }
+            Self::Parts(parts) => Box::new(PartsReader {
+                parts: parts.iter().cloned().collect(),
+                current: None,
+            }),
+            Self::Encoded {
+                source,
+                coding,
+                level,
+            } => coding.encoder(source.open()?, *level),
+        };
+        Ok(BodyReader {
+            source,
+            remaining: self.len(),
+            finished: false,
+        })
+    }
+
+    pub fn read(&self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Bytes(bytes) => Ok(bytes.clone()),
+            _ => {
+                let mut contents = Vec::new();
+                self.open()?.read_to_end(&mut contents)?;
+                Ok(contents)
+            }
+        }
+    }
+}
+
+// Reads the parts of a body in turn, opening each as it is reached.
+struct PartsReader {
+    parts: VecDeque<Body>,
+    current: Option<BodyReader>,
+}
+
+impl Read for PartsReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some(current) = self.current.as_mut().filter(|current| !current.is_done()) {
+                return current.read(buf);
+            }
+            let Some(part) = self.parts.pop_front() else {
+                return Ok(0);
+            };
+            self.current = Some(part.open()?);
+        }
+    }
+}
+
+// Reads a body a piece at a time, so a large file is never held in memory whole.
+pub struct BodyReader {
+    source: Box<dyn Read>,
+    // None when the length isn't known up front, as for an encoded body.
+    remaining: Option<u64>,
+    // Whether a body of unknown length has reached its end.
+    finished: bool,
+}
+
+impl BodyReader {
+    pub const fn get_remaining(&self) -> Option<u64> {
+        self.remaining
+    }
+
+    pub const fn is_done(&self) -> bool {
+        match self.remaining {
+            Some(remaining) => remaining == 0,
+            None => self.finished,
+        }
+    }
+
+    // Reads up to `limit` more bytes, fewer only at the end of the body. A file that turns out
+    // shorter than it was is an error.
+    pub fn read_chunk(&mut self, limit: usize) -> std::io::Result<Vec<u8>> {
+        let limit = self
+            .remaining
+            .map_or(limit, |remaining| remaining.min(limit as u64) as usize);
+        let mut chunk = vec![0; limit];
+        let mut filled = 0;
+        while filled < limit {
+            match self.read(&mut chunk[filled..])? {
+                0 => break,
+                count => filled += count,
+            }
+        }
+        chunk.truncate(filled);
+        Ok(chunk)
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let limit = self.remaining.map_or(buf.len(), |remaining| {
+            remaining.min(buf.len() as u64) as usize
+        });
+        if limit == 0 {
+            return Ok(0);
+        }
+        let count = loop {
+            match self.source.read(&mut buf[..limit]) {
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
+        match self.remaining.as_mut() {
+            Some(_) if count == 0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Some(remaining) => *remaining -= count as u64,
+            None => self.finished = count == 0,
+        }
+        Ok(count)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Response {
     code: ResponseCode,
     protocol: Protocol,
-    header_fields: HashMap<Header, String>,
-    content: Option<String>,
+    header_fields: HeaderMap,
+    content: Option<Body>,
 }
 
 impl Response {
-    pub fn new(code: ResponseCode, protocol: Protocol) -> Self {
+    pub const fn new(code: ResponseCode, protocol: Protocol) -> Self {
         Self {
             code,
             protocol,
-            header_fields: HashMap::new(),
+            header_fields: HeaderMap::new(),
             content: None,
         }
     }
 
     pub fn set_content(&mut self, content: Option<String>) {
-        self.content = content;
+        self.content = content.map(|content| Body::Bytes(content.into_bytes()));
+    }
+
+    pub fn set_body(&mut self, body: Option<Body>) {
+        self.content = body;
+    }
+
+    pub const fn take_body(&mut self) -> Option<Body> {
+        self.content.take()
+    }
+
+    pub const fn get_body(&self) -> Option<&Body> {
+        self.content.as_ref()
+    }
+
+    pub const fn set_code(&mut self, code: ResponseCode) {
+        self.code = code;
+    }
+
+    pub const fn get_code(&self) -> ResponseCode {
+        self.code
     }
 
-    pub const fn get_headers(&self) -> &HashMap<Header, String> {
+    pub const fn get_protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub const fn get_headers(&self) -> &HeaderMap {
         &self.header_fields
     }
 
-    pub const fn get_headers_mut(&mut self) -> &mut HashMap<Header, String> {
+    pub const fn get_headers_mut(&mut self) -> &mut HeaderMap {
         &mut self.header_fields
     }
-}
 
-impl Display for Response {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(
+    pub fn set_etag(&mut self, etag: &EntityTag) {
+        self.header_fields.insert(Header::ETag, etag.to_string());
+    }
+
+    pub fn get_etag(&self) -> Option<EntityTag> {
+        self.header_fields
+            .get(&Header::ETag)
+            .and_then(|etag| EntityTag::parse(etag).ok())
+    }
+
+    pub fn set_last_modified(&mut self, last_modified: HttpDate) {
+        self.header_fields
+            .insert_typed(&LastModified(last_modified));
+    }
+
+    pub fn get_last_modified(&self) -> Option<HttpDate> {
+        self.header_fields
+            .get_typed::<LastModified>()
+            .and_then(Result::ok)
+            .map(|LastModified(last_modified)| last_modified)
+    }
+
+    fn write_head(&self, f: &mut impl std::fmt::Write) -> std::fmt::Result {
+        write!(
             f,
-            "{} {} {}",
+            "{} {} {}\r\n",
             self.protocol.as_str(),
             self.code as usize,
             self.code.as_phrase(),
         )?;
         for (header, field) in &self.header_fields {
-            writeln!(f, "{}: {}", header.as_str(), field)?;
+            write!(f, "{}: {}\r\n", header.as_str(), field)?;
         }
-        writeln!(f)?;
-        if let Some(content) = &self.content {
-            write!(f, "{}", content)?;
+        // Persistent connections rely on every response that may carry a body declaring its length,
+        // unless its body is chunked instead, as one whose length isn't known up front is.
+        if !self.header_fields.contains_key(&Header::ContentLength)
+            && !self.header_fields.contains_key(&Header::TransferEncoding)
+            && self.code.allows_body()
+        {
+            match self.content.as_ref().map_or(Some(0), Body::len) {
+                Some(length) => write!(f, "{}: {}\r\n", Header::ContentLength.as_str(), length)?,
+                None => write!(f, "{}: chunked\r\n", Header::TransferEncoding.as_str())?,
+            }
+        }
+        write!(f, "\r\n")
+    }
+
+    // The response to a HEAD request: every header the full response would have, but no body.
+    pub fn serialize_head(&self) -> Vec<u8> {
+        let mut head = String::new();
+        self.write_head(&mut head)
+            .expect("Formatting into a String cannot fail.");
+        head.into_bytes()
+    }
+
+    pub fn serialize(&self) -> std::io::Result<Vec<u8>> {
+        let mut head = String::new();
+        self.write_head(&mut head)
+            .expect("Formatting into a String cannot fail.");
+        let mut bytes = head.into_bytes();
+        if let Some(body) = &self.content {
+            bytes.extend(body.read()?);
+        }
+        Ok(bytes)
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_head(f)?;
+        match &self.content {
+            Some(Body::Bytes(bytes)) => write!(f, "{}", String::from_utf8_lossy(bytes)),
+            Some(Body::File { path, .. }) => write!(f, "<{}>", path.display()),
+            Some(Body::Parts(parts)) => write!(f, "<{} parts>", parts.len()),
+            Some(Body::Encoded { coding, .. }) => write!(f, "<{} encoded>", coding.as_str()),
+            None => Ok(()),
         }
-        Ok(())
     }
 }
@@ -3,6 +3,7 @@ use std::{collections::HashMap, fmt::Display, str::Split};
 use crate::{
     connection::Connection,
     handler::Handler,
+    logging::log,
     request::Request,
     response::{Response, ResponseCode},
 };
@@ -27,6 +28,16 @@ impl PathParameterRouter {
         &mut self.router
     }
 
+    fn consume_remaining_path<'a>(
+        &mut self,
+        next: &'a str,
+        path: &mut Split<'a, char>,
+        request: &mut Request,
+    ) -> &mut BaseRouter {
+        let remaining: Vec<&str> = std::iter::once(next).chain(path).collect();
+        self.consume_path_param(&remaining.join("/"), request)
+    }
+
     const fn get_router_mut(&mut self) -> &mut BaseRouter {
         &mut self.router
     }
@@ -36,6 +47,7 @@ pub struct BaseRouter {
     sub_routers: HashMap<String, Box<Self>>,
     handler: Option<Box<dyn Handler>>,
     wildcard: Option<Box<PathParameterRouter>>,
+    tail: Option<Box<PathParameterRouter>>,
 }
 
 impl Default for BaseRouter {
@@ -50,15 +62,13 @@ impl<'a> BaseRouter {
             sub_routers: HashMap::new(),
             handler: None,
             wildcard: None,
+            tail: None,
         }
     }
 
     pub fn route(&mut self, connection: &mut Connection, request: &mut Request) -> Response {
-        self.route_from_path(
-            connection,
-            request,
-            &mut request.get_target().clone().split('/'),
-        )
+        let path = request.get_path().to_string();
+        self.route_from_path(connection, request, &mut path.split('/'))
     }
 
     fn resolve_route_mut(
@@ -77,7 +87,16 @@ impl<'a> BaseRouter {
                             .consume_path_param(next, request)
                             .resolve_route_mut(path, request)
                     })
+                })
+                .or_else(|| {
+                    self.tail
+                        .as_mut()
+                        .map(|tail_router| tail_router.consume_remaining_path(next, path, request))
                 }),
+            None if self.handler.is_none() && self.tail.is_some() => self
+                .tail
+                .as_mut()
+                .map(|tail_router| tail_router.consume_path_param("", request)),
             None => Some(self),
         }
     }
@@ -91,13 +110,27 @@ impl<'a> BaseRouter {
 
     pub fn create_route(&mut self, path: &mut Split<'a, char>) -> &mut Self {
         if let Some(next) = path.next() {
-            if next.starts_with('{') && next.ends_with('}') {
+            if next.starts_with("{*") && next.ends_with('}') {
+                assert!(
+                    path.next().is_none(),
+                    "Tail path parameter must be the last path segment."
+                );
+                self.tail = Some(Box::new(PathParameterRouter::new(
+                    next.get(2..next.len() - 1)
+                        .expect("Tail parameter index invalid despite prefix check."),
+                )));
+                return self
+                    .tail
+                    .as_mut()
+                    .expect("Tail router missing despite just assigning one.")
+                    .get_router_mut();
+            } else if next.starts_with('{') && next.ends_with('}') {
                 assert!(next.len() >= 2);
                 self.wildcard = Some(Box::new(PathParameterRouter::new(
                     next.get(1..next.len() - 1)
                         .expect("Path parameter index invalid despite length check."),
                 )));
-                println!("Adding wildcard: {}", next);
+                log!("Adding wildcard: {}", next);
                 return self
                     .wildcard
                     .as_mut()
@@ -134,10 +167,12 @@ impl<'a> BaseRouter {
     ) -> Response {
         self.resolve_route_mut(path, request)
             .and_then(|router| {
-                router
-                    .handler
-                    .as_mut()
-                    .map(|handler| handler.handle(connection, request))
+                router.handler.as_mut().map(|handler| {
+                    handler
+                        .representation(request)
+                        .evaluate(request)
+                        .unwrap_or_else(|| handler.handle(connection, request))
+                })
             })
             .unwrap_or_else(|| Response::new(ResponseCode::NotFound, request.get_protocol()))
     }
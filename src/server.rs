@@ -1,25 +1,76 @@
-use std::mem::MaybeUninit;
+use std::{
+    collections::HashMap,
+    mem::MaybeUninit,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
 
-use libc::{FD_SET, FD_ZERO, fd_set};
+use libc::{
+    EAGAIN, EMFILE, ENFILE, ENOBUFS, ENOMEM, FD_SET, FD_SETSIZE, FD_ZERO, fd_set, suseconds_t,
+    time_t, timeval,
+};
 use syscalls::syscall;
 
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
 use crate::{
-    connection::Connection,
+    compression::CompressionConfig,
+    conditional::apply_conditional,
+    connection::{
+        Connection, ConnectionReadError, ConnectionResponseError, DEFAULT_MAX_DECODED_BODY_SIZE,
+    },
     error_utils::MaybeFatal,
+    handoff::{HandoffControl, HandoffError},
+    header::Header,
+    limits::{ConnectionLimits, LimitMetrics, OverloadPolicy, RequestLimits},
+    logging::{Redacted, log},
+    protocol::Protocol,
+    range::apply_range,
+    response::{Response, ResponseCode},
     router::BaseRouter,
-    socket::{Socket, SocketAcceptError, SocketListeningError},
+    socket::{PeerCredentials, Socket, SocketAcceptError, SocketListeningError},
+    timer::{Scheduler, Timeouts, TimerEvent, TimerQueue},
 };
 
 pub struct HTTPServer {
-    socket: Socket,
+    listeners: Vec<Listener>,
     connections: Vec<Connection>,
     router: BaseRouter,
+    compression: Option<CompressionConfig>,
+    max_decoded_body_size: usize,
+    limits: RequestLimits,
+    limit_metrics: Arc<LimitMetrics>,
+    timeouts: Timeouts,
+    timers: TimerQueue,
+    next_connection_id: u64,
+    connection_limits: ConnectionLimits,
+    accept_paused_until: Option<Instant>,
+    handoff: Option<HandoffControl>,
+    // Set once another process has taken over the listeners.
+    draining: bool,
+}
+
+// Identifies one of a server's listening sockets. The socket the server was created with is the
+// first, and the rest are numbered in the order they were added.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ListenerId(usize);
+
+struct Listener {
+    socket: Socket,
+    // Falls back to the server's router when unset.
+    router: Option<BaseRouter>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
 }
 
 #[derive(Debug)]
 pub enum HTTPServerRunError {
     SocketListeningError(SocketListeningError),
     SocketAcceptError(SocketAcceptError),
+    // Not a failure: the listeners were handed off and every connection has finished.
+    HandedOff,
 }
 
 impl MaybeFatal for HTTPServerRunError {
@@ -30,70 +81,428 @@ impl MaybeFatal for HTTPServerRunError {
                 SocketListeningError::ListeningFailed(_)
             ),
             Self::SocketAcceptError(socket_accept_error) => socket_accept_error.is_fatal(),
+            Self::HandedOff => false,
         }
     }
 }
 
+impl ListenerId {
+    pub const fn get_index(&self) -> usize {
+        self.0
+    }
+}
+
 impl HTTPServer {
-    pub const fn new(socket: Socket, router: BaseRouter) -> Self {
+    pub fn new(socket: Socket, router: BaseRouter) -> Self {
         Self {
-            socket,
+            listeners: vec![Listener {
+                socket,
+                router: None,
+                #[cfg(feature = "tls")]
+                tls: None,
+            }],
             connections: Vec::new(),
             router,
+            compression: None,
+            max_decoded_body_size: DEFAULT_MAX_DECODED_BODY_SIZE,
+            limits: RequestLimits::new(),
+            limit_metrics: Arc::new(LimitMetrics::new()),
+            timeouts: Timeouts::new(),
+            timers: TimerQueue::new(Scheduler::new()),
+            next_connection_id: 0,
+            connection_limits: ConnectionLimits::new(),
+            accept_paused_until: None,
+            handoff: None,
+            draining: false,
+        }
+    }
+
+    // Serves another socket from the same event loop, with its own router if one is given.
+    pub fn add_listener(&mut self, socket: Socket, router: Option<BaseRouter>) -> ListenerId {
+        self.listeners.push(Listener {
+            socket,
+            router,
+            #[cfg(feature = "tls")]
+            tls: None,
+        });
+        ListenerId(self.listeners.len() - 1)
+    }
+
+    // Connections accepted on the listener from then on speak TLS.
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, listener: ListenerId, tls: Option<TlsConfig>) {
+        if let Some(listener) = self.listeners.get_mut(listener.0) {
+            listener.tls = tls;
         }
     }
 
+    pub fn get_socket(&self, listener: ListenerId) -> Option<&Socket> {
+        self.listeners
+            .get(listener.0)
+            .map(|listener| &listener.socket)
+    }
+
+    // Waits for a new process to call `receive_sockets` with the same path. Once it has the
+    // listeners, this server stops accepting, finishes the requests it has, and returns from
+    // `run` with HandedOff.
+    pub fn enable_handoff(&mut self, path: impl AsRef<Path>) -> Result<(), HandoffError> {
+        self.handoff = Some(HandoffControl::new(path.as_ref())?);
+        Ok(())
+    }
+
+    pub const fn set_connection_limits(&mut self, connection_limits: ConnectionLimits) {
+        self.connection_limits = connection_limits;
+    }
+
+    pub const fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    // Work scheduled through the returned handle runs on the server's event loop.
+    pub fn get_scheduler(&self) -> Scheduler {
+        self.timers.get_scheduler().clone()
+    }
+
+    pub const fn set_request_limits(&mut self, limits: RequestLimits) {
+        self.limits = limits;
+    }
+
+    pub fn get_limit_metrics(&self) -> Arc<LimitMetrics> {
+        Arc::clone(&self.limit_metrics)
+    }
+
+    pub const fn set_max_decoded_body_size(&mut self, max_decoded_body_size: usize) {
+        self.max_decoded_body_size = max_decoded_body_size;
+    }
+
+    pub fn set_compression(&mut self, compression: Option<CompressionConfig>) {
+        self.compression = compression;
+    }
+
     pub fn run(&mut self) -> HTTPServerRunError {
-        if !self.socket.is_listening()
-            && let Err(err) = self.socket.start_listening()
-        {
-            return HTTPServerRunError::SocketListeningError(err);
+        for listener in &mut self.listeners {
+            if !listener.socket.is_listening()
+                && let Err(err) = listener.socket.start_listening()
+            {
+                return HTTPServerRunError::SocketListeningError(err);
+            }
         }
+        // Work scheduled before the server started signalled no wakeup, so it goes on the heap now.
+        self.timers.absorb_scheduled();
         loop {
             self.wait_for_event();
+            self.timers.get_scheduler().clear_wakeup();
             if let Err(err) = self.accept_connections()
                 && err.is_fatal()
             {
                 return err;
             }
+            self.poll_handoff();
+            self.run_timers(Instant::now());
             for connection in &mut self.connections {
                 if connection.is_reading() {
-                    if let Ok(mut request) = connection.read() {
-                        println!("Received request:\n{}", request);
+                    let read_result = connection.read();
+                    if let Err(err) = &read_result {
+                        Self::reject(connection, err, &self.limit_metrics);
+                    }
+                    if let Ok(mut request) = read_result {
+                        log!(
+                            "Received request from {}:\n{}",
+                            describe_client(
+                                connection.get_peer_address(),
+                                connection.get_peer_credentials()
+                            ),
+                            Redacted(&request)
+                        );
                         assert!(connection.is_awaiting_response());
-                        let response = self.router.route(connection, &mut request);
-                        let _ = connection.begin_response(&response);
+                        let router = self.listeners[connection.get_listener().0]
+                            .router
+                            .as_mut()
+                            .unwrap_or(&mut self.router);
+                        let mut response = router.route(connection, &mut request);
+                        if let Some(compression) = &self.compression {
+                            response = compression.apply(&request, response);
+                        }
+                        let mut response =
+                            apply_range(&request, apply_conditional(&request, response));
+                        connection.prepare_response(&mut response);
+                        if let Err(ConnectionResponseError::BodyUnavailable(err)) =
+                            connection.begin_response(&response)
+                        {
+                            log!("Failed to read response body: {}", err);
+                            let mut response = Response::new(
+                                ResponseCode::InternalServerError,
+                                request.get_protocol(),
+                            );
+                            connection.prepare_response(&mut response);
+                            let _ = connection.begin_response(&response);
+                        }
                     }
-                } else if connection.is_writing() {
+                } else if connection.is_writing() || connection.is_streaming() {
                     let _ = connection.write();
+                } else if connection.is_lingering() {
+                    connection.discard();
+                }
+            }
+            let timers = &mut self.timers;
+            self.connections.retain(|con| {
+                let alive = con.is_alive();
+                if !alive {
+                    timers.forget_connection(con.get_id());
+                }
+                alive
+            });
+            if self.draining && self.connections.is_empty() {
+                log!("All connections finished, exiting after handoff.");
+                return HTTPServerRunError::HandedOff;
+            }
+            self.schedule_connection_timers();
+            self.timers.absorb_scheduled();
+        }
+    }
+
+    fn poll_handoff(&mut self) {
+        let Some(handoff) = &mut self.handoff else {
+            return;
+        };
+        let listeners: Vec<usize> = self
+            .listeners
+            .iter()
+            .map(|listener| listener.socket.get_file_descriptor())
+            .collect();
+        if !handoff.poll(&listeners) {
+            return;
+        }
+        log!("Listeners handed off, draining connections.");
+        self.handoff = None;
+        self.draining = true;
+        for listener in &mut self.listeners {
+            listener.socket.release_unix_path();
+        }
+        for connection in &mut self.connections {
+            connection.drain();
+        }
+    }
+
+    // Runs deferred work that is due and enforces the timeouts of connections whose timers fired.
+    fn run_timers(&mut self, now: Instant) {
+        for event in self.timers.pop_expired(now) {
+            match event {
+                TimerEvent::Task(_, task) => task(),
+                TimerEvent::Connection(id) => {
+                    let Some(connection) = self
+                        .connections
+                        .iter_mut()
+                        .find(|connection| connection.get_id() == id)
+                    else {
+                        continue;
+                    };
+                    connection.set_timer_deadline(None);
+                    if let Err(err) = connection.check_timeouts(now) {
+                        Self::reject(connection, &err, &self.limit_metrics);
+                    }
                 }
             }
-            self.connections = self
-                .connections
-                .drain(..)
-                .filter(|con| con.is_alive())
-                .collect()
         }
     }
 
+    // Keeps one timer per connection, armed no later than its earliest deadline. Deadlines that
+    // move later are picked up when the earlier timer fires and finds nothing expired.
+    fn schedule_connection_timers(&mut self) {
+        for connection in &mut self.connections {
+            let Some(deadline) = connection.get_deadline() else {
+                continue;
+            };
+            if connection
+                .get_timer_deadline()
+                .is_none_or(|scheduled| deadline < scheduled)
+            {
+                self.timers
+                    .schedule(deadline, TimerEvent::Connection(connection.get_id()));
+                connection.set_timer_deadline(Some(deadline));
+            }
+        }
+    }
+
+    // Refused connections are on their way out and don't count against the limits.
+    fn active_connections(&self) -> impl Iterator<Item = &Connection> {
+        self.connections
+            .iter()
+            .filter(|connection| !connection.is_refused())
+    }
+
+    // Whether the listener should be polled at all. Under the Reject policy accepting continues
+    // past the limit, but refusals are capped too so a flood can't exhaust descriptors.
+    fn is_accepting(&self, now: Instant) -> bool {
+        if self.draining
+            || self
+                .accept_paused_until
+                .is_some_and(|paused_until| now < paused_until)
+        {
+            return false;
+        }
+        let Some(max_connections) = self.connection_limits.get_max_connections() else {
+            return true;
+        };
+        match self.connection_limits.get_overload_policy() {
+            OverloadPolicy::Pause => self.active_connections().count() < max_connections,
+            OverloadPolicy::Reject => {
+                self.connections.len()
+                    < max_connections + self.connection_limits.get_max_accepts_per_wakeup()
+            }
+        }
+    }
+
+    // Accepts every pending connection on each listener, up to the per-wakeup limit for each.
     pub fn accept_connections(&mut self) -> Result<(), HTTPServerRunError> {
-        match self.socket.accept_connection() {
-            Ok(descriptor) => {
-                self.connections.push(Connection::new(descriptor));
-                println!("Established new connection.");
-                Ok(())
+        let now = Instant::now();
+        let mut per_ip: HashMap<IpAddr, usize> = HashMap::new();
+        if self
+            .connection_limits
+            .get_max_connections_per_ip()
+            .is_some()
+        {
+            for peer_ip in self
+                .active_connections()
+                .filter_map(Connection::get_peer_ip)
+            {
+                *per_ip.entry(peer_ip).or_default() += 1;
+            }
+        }
+        for index in 0..self.listeners.len() {
+            self.accept_from(ListenerId(index), now, &mut per_ip)?;
+        }
+        Ok(())
+    }
+
+    fn accept_from(
+        &mut self,
+        listener: ListenerId,
+        now: Instant,
+        per_ip: &mut HashMap<IpAddr, usize>,
+    ) -> Result<(), HTTPServerRunError> {
+        for _ in 0..self.connection_limits.get_max_accepts_per_wakeup() {
+            if !self.is_accepting(now) {
+                break;
+            }
+            let accepted = match self.listeners[listener.0].socket.accept_connection() {
+                Ok(accepted) => accepted,
+                Err(SocketAcceptError::AcceptFailed(errno)) if errno.into_raw() == EAGAIN => break,
+                Err(SocketAcceptError::AcceptFailed(errno))
+                    if matches!(errno.into_raw(), EMFILE | ENFILE | ENOBUFS | ENOMEM) =>
+                {
+                    log!("Pausing accepts: {}", errno);
+                    self.accept_paused_until =
+                        Some(now + self.connection_limits.get_accept_error_backoff());
+                    break;
+                }
+                Err(err) => return Err(HTTPServerRunError::SocketAcceptError(err)),
+            };
+            let client = describe_client(accepted.peer_address, accepted.peer_credentials);
+            let mut connection = Connection::new(
+                accepted.descriptor,
+                self.next_connection_id,
+                self.timers.get_scheduler().clone(),
+            );
+            self.next_connection_id += 1;
+            if accepted.descriptor >= FD_SETSIZE {
+                // select() can't watch it, so there is no way to serve it.
+                log!(
+                    "Dropped connection from {}: descriptor out of range.",
+                    client
+                );
+                continue;
+            }
+            #[cfg(feature = "tls")]
+            if let Some(tls) = &self.listeners[listener.0].tls {
+                match tls.new_stream() {
+                    Ok(stream) => connection.start_tls(stream),
+                    Err(err) => {
+                        log!("Dropped connection from {}: {}", client, err);
+                        continue;
+                    }
+                }
             }
-            Err(err) => Err(HTTPServerRunError::SocketAcceptError(err)),
+            connection.set_listener(listener);
+            connection.set_addresses(accepted.peer_address, accepted.local_address);
+            connection.set_peer_credentials(accepted.peer_credentials);
+            connection.set_max_decoded_body_size(self.max_decoded_body_size);
+            connection.set_request_limits(self.limits.clone());
+            connection.set_timeouts(self.timeouts.clone());
+
+            let mut from_peer = accepted
+                .peer_address
+                .map(|address| per_ip.entry(address.ip()).or_default());
+            if let Some(max) = self.connection_limits.get_max_connections_per_ip()
+                && from_peer.as_ref().is_some_and(|count| **count >= max)
+            {
+                log!(
+                    "Refused connection from {}: too many from one client.",
+                    client
+                );
+                connection.refuse(ResponseCode::TooManyRequests);
+            } else if self
+                .connection_limits
+                .get_max_connections()
+                .is_some_and(|max| self.active_connections().count() >= max)
+            {
+                log!("Refused connection from {}: server at capacity.", client);
+                connection.refuse(ResponseCode::ServiceUnavailable);
+            } else {
+                if let Some(count) = from_peer.as_mut() {
+                    **count += 1;
+                }
+                log!("Established new connection from {}.", client);
+            }
+            self.connections.push(connection);
+        }
+        Ok(())
+    }
+
+    // Answers a request that cannot be served with the matching error status, after which the
+    // connection closes.
+    fn reject(connection: &mut Connection, err: &ConnectionReadError, metrics: &LimitMetrics) {
+        if let Some(violation) = err.limit_violation() {
+            metrics.record(violation);
+        }
+        if let Some(code) = err.response_code() {
+            log!("Rejected request: {:?}", err);
+            let mut response = Response::new(code, Protocol::Http1_1);
+            response
+                .get_headers_mut()
+                .insert(Header::Connection, "close".to_string());
+            connection.prepare_response(&mut response);
+            let _ = connection.begin_response(&response);
         }
     }
 
+    // Blocks until a descriptor is ready or the next timer is due. Buffered pipelined requests
+    // don't make their descriptor readable, so their presence turns this into a poll.
     fn wait_for_event(&self) {
+        let now = Instant::now();
+        let wakeup = self.timers.get_scheduler().get_wakeup_descriptor();
+        let next_deadline = if self.connections.iter().any(Connection::has_pending_input) {
+            Some(now)
+        } else {
+            [self.timers.next_deadline(), self.accept_paused_until]
+                .into_iter()
+                .flatten()
+                .min()
+        };
+        let mut timeout = next_deadline.map(|deadline| {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            timeval {
+                tv_sec: remaining.as_secs() as time_t,
+                tv_usec: remaining.subsec_micros() as suseconds_t,
+            }
+        });
+
         let mut write_file_descriptors = MaybeUninit::<fd_set>::uninit();
         let mut write_file_descriptors = unsafe {
             FD_ZERO(write_file_descriptors.as_mut_ptr());
             self.connections
                 .iter()
-                .filter(|con| con.is_awaiting_response() || con.is_writing())
+                .filter(|con| con.wants_write())
                 .for_each(|con| {
                     FD_SET(
                         con.get_file_descriptor()
@@ -110,7 +519,7 @@ impl HTTPServer {
             FD_ZERO(read_file_descriptors.as_mut_ptr());
             self.connections
                 .iter()
-                .filter(|con| con.is_reading())
+                .filter(|con| con.wants_read())
                 .for_each(|con| {
                     FD_SET(
                         con.get_file_descriptor()
@@ -119,13 +528,31 @@ impl HTTPServer {
                         read_file_descriptors.as_mut_ptr(),
                     );
                 });
-            FD_SET(
-                self.socket
-                    .get_file_descriptor()
-                    .try_into()
-                    .expect("File descriptor does not fit in an i32."),
-                read_file_descriptors.as_mut_ptr(),
-            );
+            for descriptor in self
+                .handoff
+                .iter()
+                .flat_map(HandoffControl::get_file_descriptors)
+                .chain(wakeup)
+            {
+                FD_SET(
+                    descriptor
+                        .try_into()
+                        .expect("File descriptor does not fit in an i32."),
+                    read_file_descriptors.as_mut_ptr(),
+                );
+            }
+            if self.is_accepting(now) {
+                for listener in &self.listeners {
+                    FD_SET(
+                        listener
+                            .socket
+                            .get_file_descriptor()
+                            .try_into()
+                            .expect("File descriptor does not fit in an i32."),
+                        read_file_descriptors.as_mut_ptr(),
+                    );
+                }
+            }
             read_file_descriptors.assume_init()
         };
 
@@ -133,9 +560,19 @@ impl HTTPServer {
             .connections
             .iter()
             .map(|con| con.get_file_descriptor())
+            .chain(
+                self.listeners
+                    .iter()
+                    .map(|listener| listener.socket.get_file_descriptor()),
+            )
+            .chain(
+                self.handoff
+                    .iter()
+                    .flat_map(HandoffControl::get_file_descriptors),
+            )
+            .chain(wakeup)
             .max()
             .unwrap_or(0)
-            .max(self.socket.get_file_descriptor())
             + 1)
         .try_into()
         .expect("Max file descriptor does not fit in an i32.");
@@ -147,8 +584,21 @@ impl HTTPServer {
                 &mut read_file_descriptors as *mut _ as usize,
                 &mut write_file_descriptors as *mut _ as usize,
                 0,
-                0
+                timeout
+                    .as_mut()
+                    .map_or(0, |timeout| timeout as *mut _ as usize)
             );
         }
     }
 }
+
+fn describe_client(
+    peer_address: Option<SocketAddr>,
+    peer_credentials: Option<PeerCredentials>,
+) -> String {
+    match (peer_address, peer_credentials) {
+        (Some(address), _) => address.to_string(),
+        (None, Some(credentials)) => format!("process {}", credentials.pid),
+        (None, None) => "an unknown address".to_string(),
+    }
+}
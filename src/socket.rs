@@ -1,23 +1,71 @@
-use std::net::Ipv4Addr;
+use std::{
+    fs::{self, Permissions},
+    io::{self, ErrorKind},
+    mem::{MaybeUninit, offset_of},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, PermissionsExt},
+        net::UnixStream,
+    },
+    path::{Path, PathBuf},
+};
 
 use libc::{
-    AF_INET, EBADF, EFAULT, EINVAL, ENOTSOCK, EOPNOTSUPP, SOCK_NONBLOCK, SOCK_STREAM, in_port_t,
-    sa_family_t, sockaddr_in,
+    AF_INET, AF_INET6, AF_UNIX, EADDRINUSE, EBADF, EFAULT, EINVAL, ENOTSOCK, EOPNOTSUPP, F_GETFL,
+    F_SETFD, F_SETFL, FD_CLOEXEC, O_NONBLOCK, SO_ACCEPTCONN, SO_PEERCRED, SO_TYPE, SOCK_CLOEXEC,
+    SOCK_NONBLOCK, SOCK_STREAM, SOL_SOCKET, c_char, c_int, gid_t, in_port_t, pid_t, sa_family_t,
+    sockaddr_in, sockaddr_in6, sockaddr_storage, sockaddr_un, socklen_t, ucred, uid_t,
 };
 use syscalls::{Errno, Sysno, syscall};
 
-use crate::error_utils::MaybeFatal;
+use crate::{error_utils::MaybeFatal, socket_builder::SocketBuilder};
 
 pub struct Socket {
     file_descriptor: usize,
-    address_descriptor: sockaddr_in,
+    address_descriptor: AddressDescriptor,
     listening: bool,
+    // Set for Unix sockets bound to a path, whose file is removed again when the socket closes.
+    unix_path: Option<PathBuf>,
+    options: SocketBuilder,
+}
+
+// The raw address a socket was bound to, in the form the kernel takes for its family.
+#[derive(Clone, Copy)]
+pub enum AddressDescriptor {
+    V4(sockaddr_in),
+    V6(sockaddr_in6),
+    // The length tells a path from an abstract name, which starts with a null byte instead.
+    Unix(sockaddr_un, socklen_t),
+}
+
+// Who is on the other end of a Unix socket connection, as recorded by the kernel when it
+// connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub pid: pid_t,
+    pub uid: uid_t,
+    pub gid: gid_t,
+}
+
+pub struct AcceptedConnection {
+    pub descriptor: usize,
+    pub peer_address: Option<SocketAddr>,
+    pub local_address: Option<SocketAddr>,
+    pub peer_credentials: Option<PeerCredentials>,
 }
 
 #[derive(Debug)]
 pub enum SocketCreateError {
     DescriptorCreationFailed(Errno),
+    SettingOptionFailed(Errno),
     BindingFailed(Errno),
+    PathTooLong(usize),
+    RemovingStaleSocketFailed(io::Error),
+    SettingPermissionsFailed(io::Error),
+    InspectingDescriptorFailed(Errno),
+    NotAStreamSocket,
+    UnsupportedFamily(c_int),
 }
 
 #[derive(Debug)]
@@ -46,57 +94,221 @@ impl MaybeFatal for SocketAcceptError {
 
 impl Socket {
     pub fn new(port: in_port_t, address: Ipv4Addr) -> Result<Self, SocketCreateError> {
-        let address_descriptor: sockaddr_in = sockaddr_in {
-            sin_family: AF_INET as sa_family_t,
-            sin_port: port.to_be(),
-            sin_addr: libc::in_addr {
-                s_addr: address.to_bits().to_be(),
-            },
-            sin_zero: [0; 8],
-        };
+        SocketBuilder::new().bind(SocketAddr::V4(SocketAddrV4::new(address, port)))
+    }
+
+    // With `v6_only` unset the unspecified address `::` also accepts IPv4 clients, which then
+    // show up as IPv4-mapped addresses such as `::ffff:127.0.0.1`.
+    pub fn new_v6(
+        port: in_port_t,
+        address: Ipv6Addr,
+        v6_only: bool,
+    ) -> Result<Self, SocketCreateError> {
+        let mut builder = SocketBuilder::new();
+        builder.set_v6_only(v6_only);
+        builder.bind(SocketAddr::V6(SocketAddrV6::new(address, port, 0, 0)))
+    }
+
+    pub fn from_socket_addr(address: SocketAddr) -> Result<Self, SocketCreateError> {
+        SocketBuilder::new().bind(address)
+    }
+
+    pub fn new_unix(path: impl AsRef<Path>, mode: Option<u32>) -> Result<Self, SocketCreateError> {
+        SocketBuilder::new().bind_unix(path, mode)
+    }
+
+    pub fn new_abstract(name: &[u8]) -> Result<Self, SocketCreateError> {
+        SocketBuilder::new().bind_abstract(name)
+    }
 
-        let file_descriptor: usize =
-            unsafe { syscall!(Sysno::socket, AF_INET, SOCK_STREAM | SOCK_NONBLOCK, 0) }
-                .map_err(SocketCreateError::DescriptorCreationFailed)?;
+    // Adopts a socket opened elsewhere, such as one inherited from systemd or a parent process.
+    // It may be bound already or listening already, and is switched to non-blocking mode and
+    // closed on exec, like a socket opened with the default options. The socket takes ownership
+    // of the descriptor and closes it on drop, or right away if it can't be adopted.
+    pub fn from_raw_descriptor(file_descriptor: usize) -> Result<Self, SocketCreateError> {
+        Self::adopt_descriptor(file_descriptor).inspect_err(|_| unsafe {
+            let _ = syscall!(Sysno::close, file_descriptor);
+        })
+    }
 
+    fn adopt_descriptor(file_descriptor: usize) -> Result<Self, SocketCreateError> {
+        if get_socket_option(file_descriptor, SOL_SOCKET, SO_TYPE)
+            .map_err(SocketCreateError::InspectingDescriptorFailed)?
+            != SOCK_STREAM
+        {
+            return Err(SocketCreateError::NotAStreamSocket);
+        }
+        let mut storage = MaybeUninit::<sockaddr_storage>::zeroed();
+        let mut length = size_of::<sockaddr_storage>() as socklen_t;
         unsafe {
             syscall!(
-                Sysno::bind,
+                Sysno::getsockname,
                 file_descriptor,
-                &address_descriptor as *const _ as usize,
-                size_of::<sockaddr_in>()
+                storage.as_mut_ptr() as usize,
+                &mut length as *mut _ as usize
             )
         }
-        .map_err(SocketCreateError::BindingFailed)?;
+        .map_err(SocketCreateError::InspectingDescriptorFailed)?;
+        let storage = unsafe { storage.assume_init_ref() };
+        let address_descriptor = AddressDescriptor::from_raw(storage, length)
+            .ok_or_else(|| SocketCreateError::UnsupportedFamily(c_int::from(storage.ss_family)))?;
+        let listening = get_socket_option(file_descriptor, SOL_SOCKET, SO_ACCEPTCONN)
+            .map_err(SocketCreateError::InspectingDescriptorFailed)?
+            != 0;
 
+        let flags = unsafe { syscall!(Sysno::fcntl, file_descriptor, F_GETFL) }
+            .map_err(SocketCreateError::InspectingDescriptorFailed)?;
+        unsafe {
+            syscall!(
+                Sysno::fcntl,
+                file_descriptor,
+                F_SETFL,
+                flags | O_NONBLOCK as usize
+            )
+        }
+        .map_err(SocketCreateError::SettingOptionFailed)?;
+        unsafe { syscall!(Sysno::fcntl, file_descriptor, F_SETFD, FD_CLOEXEC) }
+            .map_err(SocketCreateError::SettingOptionFailed)?;
+
+        // The socket file, if any, belongs to whoever created it, so it is not removed on drop.
         Ok(Self {
             file_descriptor,
             address_descriptor,
-            listening: false,
+            listening,
+            unix_path: None,
+            options: SocketBuilder::new(),
         })
     }
 
+    // A socket file left behind by a server that is gone is replaced. One a server still answers
+    // on makes this fail with EADDRINUSE, and so does any other kind of file at the path.
+    pub(crate) fn bind_unix(
+        path: &Path,
+        mode: Option<u32>,
+        options: SocketBuilder,
+    ) -> Result<Self, SocketCreateError> {
+        let address_descriptor = AddressDescriptor::from_unix_name(path.as_os_str().as_bytes())?;
+        remove_stale_socket(path)?;
+        let mut socket = Self::bind(address_descriptor, options)?;
+        socket.unix_path = Some(path.to_path_buf());
+        // The file is created with the process umask applied, so a client may connect in the
+        // moment before the mode is changed.
+        if let Some(mode) = mode {
+            fs::set_permissions(path, Permissions::from_mode(mode))
+                .map_err(SocketCreateError::SettingPermissionsFailed)?;
+        }
+        Ok(socket)
+    }
+
+    // Abstract sockets live outside the filesystem, so there is no file to clean up and no
+    // permissions to set, and they vanish with the last descriptor. Linux only.
+    pub(crate) fn bind_abstract(
+        name: &[u8],
+        options: SocketBuilder,
+    ) -> Result<Self, SocketCreateError> {
+        let mut abstract_name = Vec::with_capacity(name.len() + 1);
+        abstract_name.push(0);
+        abstract_name.extend_from_slice(name);
+        Self::bind(AddressDescriptor::from_unix_name(&abstract_name)?, options)
+    }
+
+    pub(crate) fn bind(
+        address_descriptor: AddressDescriptor,
+        options: SocketBuilder,
+    ) -> Result<Self, SocketCreateError> {
+        let close_on_exec = if options.get_inheritable() {
+            0
+        } else {
+            SOCK_CLOEXEC
+        };
+        let file_descriptor: usize = unsafe {
+            syscall!(
+                Sysno::socket,
+                address_descriptor.get_family(),
+                SOCK_STREAM | SOCK_NONBLOCK | close_on_exec,
+                0
+            )
+        }
+        .map_err(SocketCreateError::DescriptorCreationFailed)?;
+        // Owning the descriptor from here on closes it if any later step fails.
+        let socket = Self {
+            file_descriptor,
+            address_descriptor,
+            listening: false,
+            unix_path: None,
+            options,
+        };
+
+        socket
+            .options
+            .apply_to_listener(file_descriptor, socket.address_descriptor.get_family())
+            .map_err(SocketCreateError::SettingOptionFailed)?;
+
+        unsafe {
+            syscall!(
+                Sysno::bind,
+                socket.file_descriptor,
+                socket.address_descriptor.as_ptr(),
+                socket.address_descriptor.get_length()
+            )
+        }
+        .map_err(SocketCreateError::BindingFailed)?;
+
+        Ok(socket)
+    }
+
     pub fn start_listening(&mut self) -> Result<(), SocketListeningError> {
         if self.listening {
             return Err(SocketListeningError::AlreadyListening);
         }
 
-        let result = unsafe { syscall!(Sysno::listen, self.file_descriptor, 64) }
-            .map_err(SocketListeningError::ListeningFailed)
-            .map(|_| ());
+        let result = unsafe {
+            syscall!(
+                Sysno::listen,
+                self.file_descriptor,
+                self.options.get_backlog()
+            )
+        }
+        .map_err(SocketListeningError::ListeningFailed)
+        .map(|_| ());
         if result.is_ok() {
             self.listening = true;
         }
         result
     }
 
-    pub fn accept_connection(&mut self) -> Result<usize, SocketAcceptError> {
+    pub fn accept_connection(&mut self) -> Result<AcceptedConnection, SocketAcceptError> {
         if !self.listening {
             return Err(SocketAcceptError::NotListening);
         }
 
-        let result = unsafe { syscall!(Sysno::accept4, self.file_descriptor, 0, 0, SOCK_NONBLOCK) }
-            .map_err(SocketAcceptError::AcceptFailed);
+        let mut peer = MaybeUninit::<sockaddr_storage>::zeroed();
+        let mut peer_length = size_of::<sockaddr_storage>() as socklen_t;
+        let result = unsafe {
+            syscall!(
+                Sysno::accept4,
+                self.file_descriptor,
+                peer.as_mut_ptr() as usize,
+                &mut peer_length as *mut _ as usize,
+                SOCK_NONBLOCK | SOCK_CLOEXEC
+            )
+        }
+        .inspect(|&descriptor| {
+            if !matches!(self.address_descriptor, AddressDescriptor::Unix(..)) {
+                // A connection that can't take these options is still worth serving.
+                let _ = self.options.apply_to_connection(descriptor);
+            }
+        })
+        .map(|descriptor| AcceptedConnection {
+            descriptor,
+            peer_address: socket_addr_from_raw(unsafe { peer.assume_init_ref() }),
+            local_address: local_address_of(descriptor),
+            peer_credentials: match self.address_descriptor {
+                AddressDescriptor::Unix(..) => peer_credentials_of(descriptor),
+                _ => None,
+            },
+        })
+        .map_err(SocketAcceptError::AcceptFailed);
 
         if let Err(ref err) = result
             && err.is_fatal()
@@ -107,10 +319,28 @@ impl Socket {
         result
     }
 
-    pub const fn get_address_descriptor(&self) -> &sockaddr_in {
+    pub const fn get_address_descriptor(&self) -> &AddressDescriptor {
         &self.address_descriptor
     }
 
+    // Where the socket is actually bound, so binding port 0 reveals the port picked by the kernel.
+    pub fn get_local_address(&self) -> Option<SocketAddr> {
+        local_address_of(self.file_descriptor)
+    }
+
+    pub const fn get_options(&self) -> &SocketBuilder {
+        &self.options
+    }
+
+    pub fn get_unix_path(&self) -> Option<&Path> {
+        self.unix_path.as_deref()
+    }
+
+    // Leaves the socket file in place on drop, for when another process has taken over the socket.
+    pub(crate) fn release_unix_path(&mut self) {
+        self.unix_path = None;
+    }
+
     pub const fn is_listening(&self) -> bool {
         self.listening
     }
@@ -120,10 +350,243 @@ impl Socket {
     }
 }
 
+impl AddressDescriptor {
+    pub const fn get_family(&self) -> c_int {
+        match self {
+            Self::V4(_) => AF_INET,
+            Self::V6(_) => AF_INET6,
+            Self::Unix(..) => AF_UNIX,
+        }
+    }
+
+    pub const fn get_length(&self) -> socklen_t {
+        match self {
+            Self::V4(_) => size_of::<sockaddr_in>() as socklen_t,
+            Self::V6(_) => size_of::<sockaddr_in6>() as socklen_t,
+            Self::Unix(_, length) => *length,
+        }
+    }
+
+    pub const fn to_socket_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::V4(address) => Some(SocketAddr::V4(socket_addr_v4_from_raw(address))),
+            Self::V6(address) => Some(SocketAddr::V6(socket_addr_v6_from_raw(address))),
+            Self::Unix(..) => None,
+        }
+    }
+
+    // Copies an address filled in by the kernel, `length` bytes of which are meaningful.
+    pub(crate) fn from_raw(storage: &sockaddr_storage, length: socklen_t) -> Option<Self> {
+        let pointer = storage as *const sockaddr_storage;
+        match c_int::from(storage.ss_family) {
+            AF_INET => Some(Self::V4(unsafe { *(pointer as *const sockaddr_in) })),
+            AF_INET6 => Some(Self::V6(unsafe { *(pointer as *const sockaddr_in6) })),
+            AF_UNIX => Some(Self::Unix(
+                unsafe { *(pointer as *const sockaddr_un) },
+                length,
+            )),
+            _ => None,
+        }
+    }
+
+    // The raw `sun_path` bytes: a path, or a null byte followed by an abstract name.
+    fn from_unix_name(name: &[u8]) -> Result<Self, SocketCreateError> {
+        let mut address = sockaddr_un {
+            sun_family: AF_UNIX as sa_family_t,
+            sun_path: [0; 108],
+        };
+        let is_abstract = name.first() == Some(&0);
+        // Paths need room for their terminating null byte, abstract names don't.
+        let capacity = address.sun_path.len() - usize::from(!is_abstract);
+        if name.len() > capacity {
+            return Err(SocketCreateError::PathTooLong(name.len()));
+        }
+        for (target, &byte) in address.sun_path.iter_mut().zip(name) {
+            *target = byte as c_char;
+        }
+        let length = offset_of!(sockaddr_un, sun_path) + name.len() + usize::from(!is_abstract);
+        Ok(Self::Unix(address, length as socklen_t))
+    }
+
+    fn as_ptr(&self) -> usize {
+        match self {
+            Self::V4(address) => address as *const _ as usize,
+            Self::V6(address) => address as *const _ as usize,
+            Self::Unix(address, _) => address as *const _ as usize,
+        }
+    }
+}
+
+impl From<SocketAddrV4> for AddressDescriptor {
+    fn from(address: SocketAddrV4) -> Self {
+        Self::V4(sockaddr_in {
+            sin_family: AF_INET as sa_family_t,
+            sin_port: address.port().to_be(),
+            sin_addr: libc::in_addr {
+                s_addr: address.ip().to_bits().to_be(),
+            },
+            sin_zero: [0; 8],
+        })
+    }
+}
+
+impl From<SocketAddrV6> for AddressDescriptor {
+    fn from(address: SocketAddrV6) -> Self {
+        Self::V6(sockaddr_in6 {
+            sin6_family: AF_INET6 as sa_family_t,
+            sin6_port: address.port().to_be(),
+            sin6_flowinfo: address.flowinfo().to_be(),
+            sin6_addr: libc::in6_addr {
+                s6_addr: address.ip().octets(),
+            },
+            sin6_scope_id: address.scope_id(),
+        })
+    }
+}
+
+impl From<SocketAddr> for AddressDescriptor {
+    fn from(address: SocketAddr) -> Self {
+        match address {
+            SocketAddr::V4(address) => Self::from(address),
+            SocketAddr::V6(address) => Self::from(address),
+        }
+    }
+}
+
+const fn socket_addr_v4_from_raw(address: &sockaddr_in) -> SocketAddrV4 {
+    SocketAddrV4::new(
+        Ipv4Addr::from_bits(u32::from_be(address.sin_addr.s_addr)),
+        u16::from_be(address.sin_port),
+    )
+}
+
+const fn socket_addr_v6_from_raw(address: &sockaddr_in6) -> SocketAddrV6 {
+    SocketAddrV6::new(
+        Ipv6Addr::from_octets(address.sin6_addr.s6_addr),
+        u16::from_be(address.sin6_port),
+        u32::from_be(address.sin6_flowinfo),
+        address.sin6_scope_id,
+    )
+}
+
+// Reads an IPv4 or IPv6 address out of the storage filled in by the kernel. Other families, such
+// as Unix sockets, have no `SocketAddr` form.
+pub(crate) const fn socket_addr_from_raw(storage: &sockaddr_storage) -> Option<SocketAddr> {
+    match storage.ss_family as i32 {
+        AF_INET => {
+            let address = unsafe { &*(storage as *const sockaddr_storage as *const sockaddr_in) };
+            Some(SocketAddr::V4(socket_addr_v4_from_raw(address)))
+        }
+        AF_INET6 => {
+            let address = unsafe { &*(storage as *const sockaddr_storage as *const sockaddr_in6) };
+            Some(SocketAddr::V6(socket_addr_v6_from_raw(address)))
+        }
+        _ => None,
+    }
+}
+
+// The address a connected descriptor was reached on, which for a wildcard listener is the
+// specific interface the client connected to.
+pub(crate) fn local_address_of(descriptor: usize) -> Option<SocketAddr> {
+    let mut local = MaybeUninit::<sockaddr_storage>::zeroed();
+    let mut local_length = size_of::<sockaddr_storage>() as socklen_t;
+    unsafe {
+        syscall!(
+            Sysno::getsockname,
+            descriptor,
+            local.as_mut_ptr() as usize,
+            &mut local_length as *mut _ as usize
+        )
+    }
+    .ok()
+    .and_then(|_| socket_addr_from_raw(unsafe { local.assume_init_ref() }))
+}
+
+pub(crate) fn get_socket_option(
+    descriptor: usize,
+    level: c_int,
+    name: c_int,
+) -> Result<c_int, Errno> {
+    let mut value: c_int = 0;
+    let mut length = size_of::<c_int>() as socklen_t;
+    unsafe {
+        syscall!(
+            Sysno::getsockopt,
+            descriptor,
+            level,
+            name,
+            &mut value as *mut _ as usize,
+            &mut length as *mut _ as usize
+        )
+    }
+    .map(|_| value)
+}
+
+pub(crate) fn set_socket_option(
+    descriptor: usize,
+    level: c_int,
+    name: c_int,
+    value: c_int,
+) -> Result<(), Errno> {
+    unsafe {
+        syscall!(
+            Sysno::setsockopt,
+            descriptor,
+            level,
+            name,
+            &value as *const _ as usize,
+            size_of::<c_int>()
+        )
+    }
+    .map(|_| ())
+}
+
+fn remove_stale_socket(path: &Path) -> Result<(), SocketCreateError> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if !metadata.file_type().is_socket() {
+        return Ok(());
+    }
+    match UnixStream::connect(path) {
+        Ok(_) => Err(SocketCreateError::BindingFailed(Errno::new(EADDRINUSE))),
+        Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+            fs::remove_file(path).map_err(SocketCreateError::RemovingStaleSocketFailed)
+        }
+        // Anything else, such as a lack of permission, is left for bind to report.
+        Err(_) => Ok(()),
+    }
+}
+
+fn peer_credentials_of(descriptor: usize) -> Option<PeerCredentials> {
+    let mut credentials = MaybeUninit::<ucred>::zeroed();
+    let mut length = size_of::<ucred>() as socklen_t;
+    unsafe {
+        syscall!(
+            Sysno::getsockopt,
+            descriptor,
+            SOL_SOCKET,
+            SO_PEERCRED,
+            credentials.as_mut_ptr() as usize,
+            &mut length as *mut _ as usize
+        )
+    }
+    .ok()?;
+    let credentials = unsafe { credentials.assume_init() };
+    Some(PeerCredentials {
+        pid: credentials.pid,
+        uid: credentials.uid,
+        gid: credentials.gid,
+    })
+}
+
 impl Drop for Socket {
     fn drop(&mut self) {
         unsafe {
             let _ = syscall!(Sysno::close, self.file_descriptor);
         }
+        if let Some(path) = &self.unix_path {
+            let _ = fs::remove_file(path);
+        }
     }
 }
@@ -0,0 +1,294 @@
+use std::{net::SocketAddr, path::Path, time::Duration};
+
+use libc::{
+    AF_INET, AF_INET6, IPPROTO_IPV6, IPPROTO_TCP, IPV6_V6ONLY, SO_KEEPALIVE, SO_RCVBUF,
+    SO_REUSEADDR, SO_REUSEPORT, SO_SNDBUF, SOL_SOCKET, TCP_DEFER_ACCEPT, TCP_FASTOPEN, TCP_KEEPCNT,
+    TCP_KEEPIDLE, TCP_KEEPINTVL, TCP_NODELAY, c_int,
+};
+use syscalls::Errno;
+
+use crate::socket::{AddressDescriptor, Socket, SocketCreateError, set_socket_option};
+
+const DEFAULT_BACKLOG: u32 = 64;
+
+// TCP keepalive probing for idle connections: the first probe goes out after `idle`, then one
+// every `interval`, and the connection is dropped once `count` probes in a row go unanswered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepAlive {
+    idle: Duration,
+    interval: Duration,
+    count: u32,
+}
+
+// Socket options for a listening socket and the connections accepted on it. Options that only
+// make sense for TCP are skipped for Unix sockets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketBuilder {
+    backlog: u32,
+    reuse_address: bool,
+    reuse_port: bool,
+    v6_only: bool,
+    no_delay: bool,
+    defer_accept: Option<Duration>,
+    fast_open: Option<u32>,
+    keep_alive: Option<KeepAlive>,
+    send_buffer_size: Option<usize>,
+    receive_buffer_size: Option<usize>,
+    inheritable: bool,
+}
+
+impl KeepAlive {
+    pub const fn new(idle: Duration, interval: Duration, count: u32) -> Self {
+        Self {
+            idle,
+            interval,
+            count,
+        }
+    }
+
+    pub const fn get_idle(&self) -> Duration {
+        self.idle
+    }
+
+    pub const fn get_interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub const fn get_count(&self) -> u32 {
+        self.count
+    }
+}
+
+impl Default for SocketBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SocketBuilder {
+    pub const fn new() -> Self {
+        Self {
+            backlog: DEFAULT_BACKLOG,
+            reuse_address: true,
+            reuse_port: false,
+            v6_only: false,
+            no_delay: false,
+            defer_accept: None,
+            fast_open: None,
+            keep_alive: None,
+            send_buffer_size: None,
+            receive_buffer_size: None,
+            inheritable: false,
+        }
+    }
+
+    // The kernel silently caps this at `net.core.somaxconn`.
+    pub const fn set_backlog(&mut self, backlog: u32) {
+        self.backlog = backlog;
+    }
+
+    // Lets a restarted server bind its port while connections from the previous run are still in
+    // TIME_WAIT. On by default.
+    pub const fn set_reuse_address(&mut self, reuse_address: bool) {
+        self.reuse_address = reuse_address;
+    }
+
+    // Lets several sockets, possibly in different processes, listen on the same port, with the
+    // kernel spreading new connections between them.
+    pub const fn set_reuse_port(&mut self, reuse_port: bool) {
+        self.reuse_port = reuse_port;
+    }
+
+    // Only affects IPv6 addresses. Left off, IPv6 sockets are bound dual-stack rather than
+    // following the system's `bindv6only` setting, so the result is the same on every host.
+    pub const fn set_v6_only(&mut self, v6_only: bool) {
+        self.v6_only = v6_only;
+    }
+
+    pub const fn set_no_delay(&mut self, no_delay: bool) {
+        self.no_delay = no_delay;
+    }
+
+    // Holds connections back from accept until their first data arrives, giving up on that after
+    // roughly this long.
+    pub const fn set_defer_accept(&mut self, defer_accept: Option<Duration>) {
+        self.defer_accept = defer_accept;
+    }
+
+    // Accepts data in the SYN from clients holding a TCP Fast Open cookie, with at most this many
+    // such connections pending at once.
+    pub const fn set_fast_open(&mut self, fast_open: Option<u32>) {
+        self.fast_open = fast_open;
+    }
+
+    pub const fn set_keep_alive(&mut self, keep_alive: Option<KeepAlive>) {
+        self.keep_alive = keep_alive;
+    }
+
+    // The kernel doubles the requested sizes to leave room for its own bookkeeping.
+    pub const fn set_send_buffer_size(&mut self, send_buffer_size: Option<usize>) {
+        self.send_buffer_size = send_buffer_size;
+    }
+
+    pub const fn set_receive_buffer_size(&mut self, receive_buffer_size: Option<usize>) {
+        self.receive_buffer_size = receive_buffer_size;
+    }
+
+    // Leaves the listening socket open across exec, for a server that hands it to a program it
+    // starts. Accepted connections are never inherited.
+    pub const fn set_inheritable(&mut self, inheritable: bool) {
+        self.inheritable = inheritable;
+    }
+
+    pub const fn get_backlog(&self) -> u32 {
+        self.backlog
+    }
+
+    pub const fn get_reuse_address(&self) -> bool {
+        self.reuse_address
+    }
+
+    pub const fn get_reuse_port(&self) -> bool {
+        self.reuse_port
+    }
+
+    pub const fn get_v6_only(&self) -> bool {
+        self.v6_only
+    }
+
+    pub const fn get_no_delay(&self) -> bool {
+        self.no_delay
+    }
+
+    pub const fn get_defer_accept(&self) -> Option<Duration> {
+        self.defer_accept
+    }
+
+    pub const fn get_fast_open(&self) -> Option<u32> {
+        self.fast_open
+    }
+
+    pub const fn get_keep_alive(&self) -> Option<KeepAlive> {
+        self.keep_alive
+    }
+
+    pub const fn get_send_buffer_size(&self) -> Option<usize> {
+        self.send_buffer_size
+    }
+
+    pub const fn get_receive_buffer_size(&self) -> Option<usize> {
+        self.receive_buffer_size
+    }
+
+    pub const fn get_inheritable(&self) -> bool {
+        self.inheritable
+    }
+
+    pub fn bind(&self, address: SocketAddr) -> Result<Socket, SocketCreateError> {
+        Socket::bind(AddressDescriptor::from(address), self.clone())
+    }
+
+    pub fn bind_unix(
+        &self,
+        path: impl AsRef<Path>,
+        mode: Option<u32>,
+    ) -> Result<Socket, SocketCreateError> {
+        Socket::bind_unix(path.as_ref(), mode, self.clone())
+    }
+
+    pub fn bind_abstract(&self, name: &[u8]) -> Result<Socket, SocketCreateError> {
+        Socket::bind_abstract(name, self.clone())
+    }
+
+    // Runs between creating the listening socket and binding it, since the address reuse options
+    // only count at bind time.
+    pub(crate) fn apply_to_listener(&self, descriptor: usize, family: c_int) -> Result<(), Errno> {
+        self.apply_buffer_sizes(descriptor)?;
+        if family != AF_INET && family != AF_INET6 {
+            return Ok(());
+        }
+        set_socket_option(
+            descriptor,
+            SOL_SOCKET,
+            SO_REUSEADDR,
+            c_int::from(self.reuse_address),
+        )?;
+        if self.reuse_port {
+            set_socket_option(descriptor, SOL_SOCKET, SO_REUSEPORT, 1)?;
+        }
+        if family == AF_INET6 {
+            set_socket_option(
+                descriptor,
+                IPPROTO_IPV6,
+                IPV6_V6ONLY,
+                c_int::from(self.v6_only),
+            )?;
+        }
+        if let Some(defer_accept) = self.defer_accept {
+            set_socket_option(
+                descriptor,
+                IPPROTO_TCP,
+                TCP_DEFER_ACCEPT,
+                seconds(defer_accept),
+            )?;
+        }
+        if let Some(fast_open) = self.fast_open {
+            set_socket_option(descriptor, IPPROTO_TCP, TCP_FASTOPEN, saturate(fast_open))?;
+        }
+        self.apply_tcp_options(descriptor)
+    }
+
+    // Linux copies most options from the listener to the connections it accepts, but not all
+    // kernels do, so they are set again on each connection.
+    pub(crate) fn apply_to_connection(&self, descriptor: usize) -> Result<(), Errno> {
+        self.apply_buffer_sizes(descriptor)?;
+        self.apply_tcp_options(descriptor)
+    }
+
+    fn apply_tcp_options(&self, descriptor: usize) -> Result<(), Errno> {
+        if self.no_delay {
+            set_socket_option(descriptor, IPPROTO_TCP, TCP_NODELAY, 1)?;
+        }
+        if let Some(keep_alive) = self.keep_alive {
+            set_socket_option(descriptor, SOL_SOCKET, SO_KEEPALIVE, 1)?;
+            set_socket_option(
+                descriptor,
+                IPPROTO_TCP,
+                TCP_KEEPIDLE,
+                seconds(keep_alive.idle),
+            )?;
+            set_socket_option(
+                descriptor,
+                IPPROTO_TCP,
+                TCP_KEEPINTVL,
+                seconds(keep_alive.interval),
+            )?;
+            set_socket_option(
+                descriptor,
+                IPPROTO_TCP,
+                TCP_KEEPCNT,
+                saturate(keep_alive.count.max(1)),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn apply_buffer_sizes(&self, descriptor: usize) -> Result<(), Errno> {
+        if let Some(size) = self.send_buffer_size {
+            set_socket_option(descriptor, SOL_SOCKET, SO_SNDBUF, saturate(size))?;
+        }
+        if let Some(size) = self.receive_buffer_size {
+            set_socket_option(descriptor, SOL_SOCKET, SO_RCVBUF, saturate(size))?;
+        }
+        Ok(())
+    }
+}
+
+// The kernel takes these in whole seconds and rejects zero for the keepalive timings.
+fn seconds(duration: Duration) -> c_int {
+    saturate(duration.as_secs().max(1))
+}
+
+fn saturate(value: impl TryInto<c_int>) -> c_int {
+    value.try_into().unwrap_or(c_int::MAX)
+}
@@ -0,0 +1,439 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    connection::Connection,
+    handler::Handler,
+    header::Header,
+    protocol::Protocol,
+    request::{Method, Request},
+    response::{Response, ResponseCode},
+    timer::Scheduler,
+};
+
+const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+const DEFAULT_MAX_QUEUED_BYTES: usize = 1024 * 1024;
+
+type OpenCallback = Box<dyn FnMut(&Request, &EventStream) -> bool>;
+
+// One server-sent event. Ids and event names are single lines, so line breaks in them are
+// dropped; data may span lines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+// Sends events on an open text/event-stream response. Clones share the response, and can be kept
+// to push events from other threads long after the handler returned.
+#[derive(Clone)]
+pub struct EventStream {
+    shared: Arc<Mutex<Outbox>>,
+    scheduler: Scheduler,
+    last_event_id: Option<String>,
+}
+
+struct Outbox {
+    output: Vec<u8>,
+    max_queued_bytes: usize,
+    last_write: Instant,
+    // Set once the server ends the response.
+    closed: bool,
+    // Set once the connection is gone.
+    finished: bool,
+}
+
+// Fans events out to every stream subscribed to it, keeping the most recent ones so a client
+// reconnecting with Last-Event-ID gets what it missed.
+#[derive(Clone)]
+pub struct EventChannel {
+    shared: Arc<Mutex<ChannelState>>,
+}
+
+struct ChannelState {
+    subscribers: Vec<EventStream>,
+    history: VecDeque<Event>,
+    history_size: usize,
+    next_id: u64,
+}
+
+// Answers requests on its route with an open-ended text/event-stream response. The callback
+// gets each new stream, and returns false to turn the client away with 403.
+pub struct EventSource {
+    on_open: OpenCallback,
+    keep_alive_interval: Option<Duration>,
+    retry: Option<Duration>,
+    max_queued_bytes: usize,
+}
+
+// An event stream attached to a connection, on the HTTP/2 stream it answers if there is one.
+pub(crate) struct OpenEventStream {
+    stream: EventStream,
+    stream_id: u32,
+    keep_alive_interval: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn set_id(&mut self, id: Option<String>) {
+        self.id = id.map(|id| single_line(&id));
+    }
+
+    pub fn set_event(&mut self, event: Option<String>) {
+        self.event = event.map(|event| single_line(&event));
+    }
+
+    pub fn set_data(&mut self, data: impl Into<String>) {
+        self.data = data.into();
+    }
+
+    // How long the client should wait before reconnecting once the stream ends.
+    pub const fn set_retry(&mut self, retry: Option<Duration>) {
+        self.retry = retry;
+    }
+
+    pub fn get_id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn get_event(&self) -> Option<&str> {
+        self.event.as_deref()
+    }
+
+    pub fn get_data(&self) -> &str {
+        &self.data
+    }
+
+    pub const fn get_retry(&self) -> Option<Duration> {
+        self.retry
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut serialized = String::new();
+        if let Some(event) = &self.event {
+            serialized.push_str(&format!("event: {}\n", event));
+        }
+        if let Some(id) = &self.id {
+            serialized.push_str(&format!("id: {}\n", id));
+        }
+        if let Some(retry) = self.retry {
+            serialized.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.replace("\r\n", "\n").split(['\n', '\r']) {
+            serialized.push_str(&format!("data: {}\n", line));
+        }
+        serialized.push('\n');
+        serialized
+    }
+}
+
+impl EventStream {
+    fn new(scheduler: Scheduler, last_event_id: Option<String>, max_queued_bytes: usize) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Outbox {
+                output: Vec::new(),
+                max_queued_bytes,
+                last_write: Instant::now(),
+                closed: false,
+                finished: false,
+            })),
+            scheduler,
+            last_event_id,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Outbox> {
+        self.shared
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // False once the stream has ended, in which case the event is dropped. A client too far
+    // behind to take the event within the queue limit has its stream ended, and can catch up by
+    // reconnecting with Last-Event-ID.
+    pub fn send(&self, event: &Event) -> bool {
+        self.queue(event.serialize().as_bytes())
+    }
+
+    pub fn send_data(&self, data: impl Into<String>) -> bool {
+        self.send(&Event::new(data))
+    }
+
+    // Comments are ignored by clients, but keep proxies from timing the response out.
+    pub fn send_comment(&self, comment: &str) -> bool {
+        let mut serialized = String::new();
+        for line in comment.replace("\r\n", "\n").split(['\n', '\r']) {
+            serialized.push_str(&format!(":{}\n", line));
+        }
+        serialized.push('\n');
+        self.queue(serialized.as_bytes())
+    }
+
+    // Ends the response once what was already sent has gone out.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.scheduler.wake();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        let outbox = self.lock();
+        outbox.closed || outbox.finished
+    }
+
+    // The Last-Event-ID the client reconnected with, naming the last event it received.
+    pub fn get_last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    fn queue(&self, bytes: &[u8]) -> bool {
+        let mut outbox = self.lock();
+        if outbox.closed || outbox.finished {
+            return false;
+        }
+        if outbox.output.len() + bytes.len() > outbox.max_queued_bytes {
+            drop(outbox);
+            self.close();
+            return false;
+        }
+        outbox.output.extend_from_slice(bytes);
+        outbox.last_write = Instant::now();
+        drop(outbox);
+        // The event loop may be asleep if this comes from another thread.
+        self.scheduler.wake();
+        true
+    }
+
+    fn finish(&self) {
+        self.lock().finished = true;
+    }
+}
+
+impl EventChannel {
+    // Keeps up to `history_size` events for replay.
+    pub fn new(history_size: usize) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(ChannelState {
+                subscribers: Vec::new(),
+                history: VecDeque::new(),
+                history_size,
+                next_id: 1,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ChannelState> {
+        self.shared
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Sends the event to every open stream, numbering it first if it has no id. Streams that have
+    // ended, including those of clients too far behind to take it, are dropped. Returns the id.
+    pub fn publish(&self, mut event: Event) -> String {
+        let mut state = self.lock();
+        if event.id.is_none() {
+            event.id = Some(state.next_id.to_string());
+            state.next_id += 1;
+        }
+        state
+            .subscribers
+            .retain(|subscriber| subscriber.send(&event));
+        let id = event.id.clone().unwrap_or_default();
+        if state.history_size > 0 {
+            if state.history.len() == state.history_size {
+                state.history.pop_front();
+            }
+            state.history.push_back(event);
+        }
+        id
+    }
+
+    // Replays the events after the stream's Last-Event-ID, if that event is still in the history,
+    // then sends it everything published from here on.
+    pub fn subscribe(&self, stream: &EventStream) {
+        let mut state = self.lock();
+        if let Some(last_event_id) = stream.get_last_event_id()
+            && let Some(position) = state
+                .history
+                .iter()
+                .position(|event| event.get_id() == Some(last_event_id))
+        {
+            for event in state.history.iter().skip(position + 1) {
+                stream.send(event);
+            }
+        }
+        state.subscribers.push(stream.clone());
+    }
+
+    pub fn get_subscriber_count(&self) -> usize {
+        let mut state = self.lock();
+        state
+            .subscribers
+            .retain(|subscriber| !subscriber.is_closed());
+        state.subscribers.len()
+    }
+}
+
+impl EventSource {
+    pub fn new(on_open: impl FnMut(&Request, &EventStream) -> bool + 'static) -> Self {
+        Self {
+            on_open: Box::new(on_open),
+            keep_alive_interval: Some(DEFAULT_KEEP_ALIVE_INTERVAL),
+            retry: None,
+            max_queued_bytes: DEFAULT_MAX_QUEUED_BYTES,
+        }
+    }
+
+    // Subscribes every client to the channel.
+    pub fn from_channel(channel: EventChannel) -> Self {
+        Self::new(move |_, stream| {
+            channel.subscribe(stream);
+            true
+        })
+    }
+
+    // How long a stream may go without sending anything before a comment is sent to keep it
+    // open. This is also how long it takes to notice a client that went away.
+    pub const fn set_keep_alive_interval(&mut self, keep_alive_interval: Option<Duration>) {
+        self.keep_alive_interval = keep_alive_interval;
+    }
+
+    // Sent ahead of everything else, as the client's reconnection delay.
+    pub const fn set_retry(&mut self, retry: Option<Duration>) {
+        self.retry = retry;
+    }
+
+    // Most a stream holds of events the client has yet to read. Sending past it ends the stream,
+    // so a client that stops reading can't make the server buffer without end.
+    pub const fn set_max_queued_bytes(&mut self, max_queued_bytes: usize) {
+        self.max_queued_bytes = max_queued_bytes;
+    }
+
+    pub const fn get_keep_alive_interval(&self) -> Option<Duration> {
+        self.keep_alive_interval
+    }
+
+    pub const fn get_retry(&self) -> Option<Duration> {
+        self.retry
+    }
+
+    pub const fn get_max_queued_bytes(&self) -> usize {
+        self.max_queued_bytes
+    }
+}
+
+impl Handler for EventSource {
+    fn handle(&mut self, connection: &mut Connection, request: &Request) -> Response {
+        // An open-ended HTTP/1 response needs chunked encoding, which HTTP/1.0 doesn't have.
+        if matches!(
+            request.get_protocol(),
+            Protocol::Http1_0 | Protocol::Http0_9 | Protocol::Missing
+        ) {
+            return Response::new(
+                ResponseCode::HTTPVersionNotSupported,
+                request.get_protocol(),
+            );
+        }
+        if *request.get_method() != Method::Get {
+            let mut response = Response::new(ResponseCode::MethodNotAllowed, Protocol::Http1_1);
+            response
+                .get_headers_mut()
+                .insert(Header::Allow, "GET".to_string());
+            return response;
+        }
+        let last_event_id = request.get_headers().get(&Header::LastEventId).cloned();
+        let stream = EventStream::new(
+            connection.get_scheduler().clone(),
+            last_event_id,
+            self.max_queued_bytes,
+        );
+        if let Some(retry) = self.retry {
+            stream.queue(format!("retry: {}\n\n", retry.as_millis()).as_bytes());
+        }
+        if !(self.on_open)(request, &stream) {
+            stream.finish();
+            return Response::new(ResponseCode::Forbidden, Protocol::Http1_1);
+        }
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        let headers = response.get_headers_mut();
+        headers.insert(Header::ContentType, "text/event-stream".to_string());
+        headers.insert(Header::CacheControl, "no-cache".to_string());
+        headers.insert(Header::TransferEncoding, "chunked".to_string());
+        connection.start_event_stream(OpenEventStream {
+            stream,
+            stream_id: 0,
+            keep_alive_interval: self.keep_alive_interval,
+        });
+        response
+    }
+}
+
+impl OpenEventStream {
+    pub(crate) const fn get_stream_id(&self) -> u32 {
+        self.stream_id
+    }
+
+    pub(crate) const fn set_stream_id(&mut self, stream_id: u32) {
+        self.stream_id = stream_id;
+    }
+
+    pub(crate) fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut self.stream.lock().output)
+    }
+
+    pub(crate) fn has_output(&self) -> bool {
+        !self.stream.lock().output.is_empty()
+    }
+
+    // Closed by the server, with nothing left to send.
+    pub(crate) fn is_done(&self) -> bool {
+        let outbox = self.stream.lock();
+        outbox.closed && outbox.output.is_empty()
+    }
+
+    pub(crate) fn close(&self) {
+        self.stream.close();
+    }
+
+    // When the stream will have been quiet for a whole keep-alive interval.
+    pub(crate) fn get_keep_alive_deadline(&self) -> Option<Instant> {
+        let outbox = self.stream.lock();
+        let (closed, last_write) = (outbox.closed, outbox.last_write);
+        drop(outbox);
+        if closed {
+            return None;
+        }
+        self.keep_alive_interval
+            .map(|interval| last_write + interval)
+    }
+
+    pub(crate) fn keep_alive(&self, now: Instant) {
+        if self
+            .get_keep_alive_deadline()
+            .is_some_and(|deadline| deadline <= now)
+        {
+            self.stream.queue(b":\n\n");
+        }
+    }
+}
+
+impl Drop for OpenEventStream {
+    fn drop(&mut self) {
+        self.stream.finish();
+    }
+}
+
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], "")
+}
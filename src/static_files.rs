@@ -0,0 +1,461 @@
+use std::{
+    cmp::Ordering,
+    fmt::Write,
+    fs::{self, DirEntry},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    conditional::EntityTag,
+    connection::Connection,
+    date::HttpDate,
+    handler::Handler,
+    header::Header,
+    protocol::Protocol,
+    request::Request,
+    response::{Body, Response, ResponseCode},
+    typed_header::Accept,
+    uri::{percent_decode, percent_encode},
+};
+
+const DEFAULT_PATH_PARAMETER: &str = "path";
+
+pub struct StaticFileHandler {
+    root: PathBuf,
+    path_parameter: String,
+    index_files: Vec<String>,
+    listing: Option<DirectoryListing>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryListing {
+    show_hidden: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EntryKind {
+    Directory,
+    File,
+    Symlink,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Name,
+    Size,
+    Modified,
+    Kind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ListingFormat {
+    Html,
+    Json,
+}
+
+struct ListingEntry {
+    name: String,
+    size: u64,
+    modified: Option<HttpDate>,
+    kind: EntryKind,
+}
+
+impl EntryKind {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Directory => "directory",
+            Self::File => "file",
+            Self::Symlink => "symlink",
+            Self::Other => "other",
+        }
+    }
+}
+
+impl SortKey {
+    fn from_query(value: Option<&String>) -> Self {
+        match value.map(String::as_str) {
+            Some("size") => Self::Size,
+            Some("mtime") => Self::Modified,
+            Some("type") => Self::Kind,
+            _ => Self::Name,
+        }
+    }
+
+    fn compare(&self, left: &ListingEntry, right: &ListingEntry) -> Ordering {
+        let ordering = match self {
+            Self::Name => Ordering::Equal,
+            Self::Size => left.size.cmp(&right.size),
+            Self::Modified => left.modified.cmp(&right.modified),
+            Self::Kind => left.kind.cmp(&right.kind),
+        };
+        ordering.then_with(|| left.name.cmp(&right.name))
+    }
+}
+
+impl ListingEntry {
+    fn from_dir_entry(entry: &DirEntry) -> Option<Self> {
+        let name = entry.file_name().into_string().ok()?;
+        let file_type = entry.file_type().ok()?;
+        let kind = if file_type.is_dir() {
+            EntryKind::Directory
+        } else if file_type.is_file() {
+            EntryKind::File
+        } else if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else {
+            EntryKind::Other
+        };
+        let metadata = entry.metadata().ok();
+        Some(Self {
+            name,
+            size: metadata.as_ref().map_or(0, |metadata| metadata.len()),
+            modified: metadata
+                .and_then(|metadata| metadata.modified().ok())
+                .map(HttpDate::from),
+            kind,
+        })
+    }
+
+    fn href(&self) -> String {
+        let mut href = percent_encode(&self.name);
+        if self.kind == EntryKind::Directory {
+            href.push('/');
+        }
+        href
+    }
+}
+
+impl DirectoryListing {
+    pub const fn new() -> Self {
+        Self { show_hidden: false }
+    }
+
+    pub const fn set_show_hidden(&mut self, show_hidden: bool) {
+        self.show_hidden = show_hidden;
+    }
+
+    fn read_entries(&self, directory: &Path) -> std::io::Result<Vec<ListingEntry>> {
+        Ok(fs::read_dir(directory)?
+            .filter_map(Result::ok)
+            .filter_map(|entry| ListingEntry::from_dir_entry(&entry))
+            .filter(|entry| self.show_hidden || !entry.name.starts_with('.'))
+            .collect())
+    }
+
+    fn render(&self, directory: &Path, request: &Request) -> Response {
+        let mut entries = match self.read_entries(directory) {
+            Ok(entries) => entries,
+            Err(_) => return Response::new(ResponseCode::Forbidden, request.get_protocol()),
+        };
+        let query = request.get_query_parameters();
+        let sort_key = SortKey::from_query(query.get("sort"));
+        let descending = query.get("order").is_some_and(|order| order == "desc");
+        entries.sort_by(|left, right| {
+            let ordering = sort_key.compare(left, right);
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+
+        let format = match query.get("format").map(String::as_str) {
+            Some("json") => ListingFormat::Json,
+            Some(_) => ListingFormat::Html,
+            None if request
+                .get_headers()
+                .get_typed::<Accept>()
+                .and_then(Result::ok)
+                .is_some_and(|accept| {
+                    accept.preferred(&["text/html", "application/json"]) == Some("application/json")
+                }) =>
+            {
+                ListingFormat::Json
+            }
+            None => ListingFormat::Html,
+        };
+        let display_path =
+            percent_decode(request.get_path()).unwrap_or_else(|| request.get_path().to_string());
+        let (content, content_type) = match format {
+            ListingFormat::Html => (
+                render_html(&display_path, &entries, sort_key, descending),
+                "text/html; charset=utf-8",
+            ),
+            ListingFormat::Json => (
+                render_json(&display_path, &entries),
+                "application/json; charset=utf-8",
+            ),
+        };
+
+        let mut response = Response::new(ResponseCode::Ok, request.get_protocol());
+        response
+            .get_headers_mut()
+            .insert(Header::ContentType, content_type.to_string());
+        response.set_content(Some(content));
+        response
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            control if (control as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", control as u32);
+            }
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+fn render_html(
+    display_path: &str,
+    entries: &[ListingEntry],
+    sort_key: SortKey,
+    descending: bool,
+) -> String {
+    let title = escape_html(display_path);
+    let column_link = |key: SortKey, query_value: &str, label: &str| {
+        let order = if key == sort_key && !descending {
+            "desc"
+        } else {
+            "asc"
+        };
+        format!(
+            "<th><a href=\"?sort={}&amp;order={}\">{}</a></th>",
+            query_value, order, label
+        )
+    };
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<table>\n<tr>{}{}{}{}</tr>\n",
+        column_link(SortKey::Name, "name", "Name"),
+        column_link(SortKey::Size, "size", "Size"),
+        column_link(SortKey::Modified, "mtime", "Last Modified"),
+        column_link(SortKey::Kind, "type", "Type"),
+    );
+    if display_path != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let mut name = escape_html(&entry.name);
+        if entry.kind == EntryKind::Directory {
+            name.push('/');
+        }
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&entry.href()),
+            name,
+            if entry.kind == EntryKind::Directory {
+                "-".to_string()
+            } else {
+                entry.size.to_string()
+            },
+            entry
+                .modified
+                .map_or_else(String::new, |modified| modified.to_string()),
+            entry.kind.as_str(),
+        );
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn render_json(display_path: &str, entries: &[ListingEntry]) -> String {
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "{{\"name\":\"{}\",\"type\":\"{}\",\"size\":{},\"mtime\":{}}}",
+                escape_json(&entry.name),
+                entry.kind.as_str(),
+                entry.size,
+                entry.modified.map_or_else(
+                    || "null".to_string(),
+                    |modified| modified.as_unix_seconds().to_string()
+                ),
+            )
+        })
+        .collect();
+    format!(
+        "{{\"path\":\"{}\",\"entries\":[{}]}}",
+        escape_json(display_path),
+        entries.join(",")
+    )
+}
+
+pub fn content_type_for(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt" | "log" | "md") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("tar") => "application/x-tar",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("mp3") => "audio/mpeg",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+impl StaticFileHandler {
+    pub fn new<P: AsRef<Path>>(root: P) -> std::io::Result<Self> {
+        Ok(Self {
+            root: root.as_ref().canonicalize()?,
+            path_parameter: DEFAULT_PATH_PARAMETER.to_string(),
+            index_files: vec!["index.html".to_string()],
+            listing: None,
+        })
+    }
+
+    pub fn set_path_parameter(&mut self, path_parameter: &str) {
+        self.path_parameter = path_parameter.to_string();
+    }
+
+    pub fn set_index_files(&mut self, index_files: Vec<String>) {
+        self.index_files = index_files;
+    }
+
+    pub const fn set_directory_listing(&mut self, listing: Option<DirectoryListing>) {
+        self.listing = listing;
+    }
+
+    pub const fn get_root(&self) -> &PathBuf {
+        &self.root
+    }
+
+    pub fn resolve(&self, relative_path: &str) -> Result<PathBuf, ResponseCode> {
+        let decoded = percent_decode(relative_path).ok_or(ResponseCode::BadRequest)?;
+        let mut path = self.root.clone();
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => continue,
+                ".." => return Err(ResponseCode::Forbidden),
+                _ if segment.contains(['\0', '\\']) => return Err(ResponseCode::BadRequest),
+                _ => path.push(segment),
+            }
+        }
+        let canonical = path.canonicalize().map_err(|_| ResponseCode::NotFound)?;
+        if !canonical.starts_with(&self.root) {
+            return Err(ResponseCode::Forbidden);
+        }
+        Ok(canonical)
+    }
+
+    fn serve_file(&self, path: &Path, protocol: Protocol) -> Response {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => return Response::new(ResponseCode::NotFound, protocol),
+        };
+        let mut response = Response::new(ResponseCode::Ok, protocol);
+        response
+            .get_headers_mut()
+            .insert(Header::ContentType, content_type_for(path).to_string());
+        if let Ok(modified) = metadata.modified() {
+            let last_modified = HttpDate::from(modified);
+            response.set_last_modified(last_modified);
+        }
+        // A strong tag promises the same bytes, so it changes with any edit that keeps the size,
+        // down to the nanosecond, and with a file replaced by another.
+        if let Ok(etag) = EntityTag::strong(&format!(
+            "{:x}-{:x}-{:x}.{:x}-{:x}",
+            metadata.dev(),
+            metadata.ino(),
+            metadata.mtime(),
+            metadata.mtime_nsec(),
+            metadata.len()
+        )) {
+            response.set_etag(&etag);
+        }
+        response.set_body(Some(Body::File {
+            path: path.to_path_buf(),
+            offset: 0,
+            length: metadata.len(),
+        }));
+        response
+    }
+
+    fn serve_directory(&self, directory: &Path, request: &Request) -> Response {
+        let protocol = request.get_protocol();
+        if !request.get_path().ends_with('/') {
+            let mut response = Response::new(ResponseCode::MovedPermanently, protocol);
+            let location = match request.get_target().split_once('?') {
+                Some((path, query)) => format!("{}/?{}", path, query),
+                None => format!("{}/", request.get_target()),
+            };
+            response
+                .get_headers_mut()
+                .insert(Header::Location, location);
+            return response;
+        }
+        if let Some(index) = self
+            .index_files
+            .iter()
+            .map(|index| directory.join(index))
+            .find(|index| index.is_file())
+        {
+            return self.serve_file(&index, protocol);
+        }
+        self.listing.as_ref().map_or_else(
+            || Response::new(ResponseCode::Forbidden, protocol),
+            |listing| listing.render(directory, request),
+        )
+    }
+}
+
+impl Handler for StaticFileHandler {
+    fn handle(&mut self, _connection: &mut Connection, request: &Request) -> Response {
+        let relative_path = request
+            .get_path_parameters()
+            .get(&self.path_parameter)
+            .map_or_else(|| request.get_path(), String::as_str);
+        match self.resolve(relative_path) {
+            Ok(path) if path.is_dir() => self.serve_directory(&path, request),
+            Ok(path) => self.serve_file(&path, request.get_protocol()),
+            Err(code) => Response::new(code, request.get_protocol()),
+        }
+    }
+}
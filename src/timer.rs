@@ -0,0 +1,378 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use libc::{EFD_CLOEXEC, EFD_NONBLOCK};
+use syscalls::{Sysno, syscall};
+
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(15);
+const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type Task = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId(u64);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timeouts {
+    idle: Option<Duration>,
+    read: Option<Duration>,
+    write: Option<Duration>,
+    keep_alive: Option<Duration>,
+}
+
+// Queues deferred work for the server's event loop. Cloning shares the same queue, so a handler
+// can hold on to one and schedule work long after its request has been answered.
+#[derive(Clone, Default)]
+pub struct Scheduler {
+    shared: Arc<Mutex<SchedulerState>>,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    next_id: u64,
+    pending: Vec<(TimerId, Instant, Task)>,
+    // Work moved onto the event loop's heap that hasn't run yet, and which of it was cancelled.
+    scheduled: HashSet<TimerId>,
+    cancelled: HashSet<TimerId>,
+    wakeup: Option<Wakeup>,
+}
+
+// An eventfd the event loop waits on alongside its sockets, so it notices work handed to it from
+// other threads. Closed once the last handle to the scheduler is gone.
+struct Wakeup {
+    descriptor: usize,
+}
+
+pub(crate) enum TimerEvent {
+    Connection(u64),
+    Task(TimerId, Task),
+}
+
+struct TimerEntry {
+    deadline: Instant,
+    sequence: u64,
+    event: TimerEvent,
+}
+
+// A min-heap of deadlines driving the event loop's select timeout. Entries for cancelled work and
+// for connection timers that were replaced stay until they come up, unless there come to be more
+// of them than live entries, when the heap is rebuilt without them.
+pub(crate) struct TimerQueue {
+    heap: BinaryHeap<Reverse<TimerEntry>>,
+    next_sequence: u64,
+    scheduler: Scheduler,
+    // The sequence of each connection's current timer. Older entries for it are stale.
+    connection_timers: HashMap<u64, u64>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timeouts {
+    pub const fn new() -> Self {
+        Self {
+            idle: Some(DEFAULT_IDLE_TIMEOUT),
+            read: Some(DEFAULT_READ_TIMEOUT),
+            write: Some(DEFAULT_WRITE_TIMEOUT),
+            keep_alive: Some(DEFAULT_KEEP_ALIVE_TIMEOUT),
+        }
+    }
+
+    // Closes a connection that has neither sent nor received anything for this long.
+    pub const fn set_idle(&mut self, idle: Option<Duration>) {
+        self.idle = idle;
+    }
+
+    // Longest silence allowed between bytes of a request that has started arriving.
+    pub const fn set_read(&mut self, read: Option<Duration>) {
+        self.read = read;
+    }
+
+    // Longest wait allowed for the client to accept more of a response.
+    pub const fn set_write(&mut self, write: Option<Duration>) {
+        self.write = write;
+    }
+
+    // How long a persistent connection waits for its next request to start.
+    pub const fn set_keep_alive(&mut self, keep_alive: Option<Duration>) {
+        self.keep_alive = keep_alive;
+    }
+
+    pub const fn get_idle(&self) -> Option<Duration> {
+        self.idle
+    }
+
+    pub const fn get_read(&self) -> Option<Duration> {
+        self.read
+    }
+
+    pub const fn get_write(&self) -> Option<Duration> {
+        self.write
+    }
+
+    pub const fn get_keep_alive(&self) -> Option<Duration> {
+        self.keep_alive
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SchedulerState> {
+        self.shared
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Work scheduled from outside the event loop's thread wakes the loop, so it starts on time
+    // even while the server is otherwise idle.
+    pub fn schedule_at(&self, deadline: Instant, task: impl FnOnce() + Send + 'static) -> TimerId {
+        let mut state = self.lock();
+        let id = TimerId(state.next_id);
+        state.next_id += 1;
+        state.pending.push((id, deadline, Box::new(task)));
+        if let Some(wakeup) = &state.wakeup {
+            wakeup.signal();
+        }
+        drop(state);
+        id
+    }
+
+    pub fn schedule_after(&self, delay: Duration, task: impl FnOnce() + Send + 'static) -> TimerId {
+        self.schedule_at(Instant::now() + delay, task)
+    }
+
+    // Cancelling work that already ran, or was already cancelled, does nothing. Work already on
+    // the event loop's heap wakes the loop, which may then let go of it.
+    pub fn cancel(&self, id: TimerId) {
+        let mut state = self.lock();
+        let pending = state.pending.len();
+        state.pending.retain(|(pending_id, _, _)| *pending_id != id);
+        if state.pending.len() == pending
+            && state.scheduled.contains(&id)
+            && state.cancelled.insert(id)
+            && let Some(wakeup) = &state.wakeup
+        {
+            wakeup.signal();
+        }
+    }
+
+    // Makes the event loop go around once more, for work it finds without the timer heap.
+    pub(crate) fn wake(&self) {
+        if let Some(wakeup) = &self.lock().wakeup {
+            wakeup.signal();
+        }
+    }
+
+    // The descriptor for the event loop to wait on, created on first use. Without one, work
+    // scheduled from other threads waits for the loop to wake up for some other reason.
+    pub(crate) fn get_wakeup_descriptor(&self) -> Option<usize> {
+        let mut state = self.lock();
+        if state.wakeup.is_none() {
+            state.wakeup = unsafe { syscall!(Sysno::eventfd2, 0, EFD_NONBLOCK | EFD_CLOEXEC) }
+                .ok()
+                .map(|descriptor| Wakeup { descriptor });
+        }
+        state.wakeup.as_ref().map(|wakeup| wakeup.descriptor)
+    }
+
+    // Called by the event loop once it is awake, before it looks for work.
+    pub(crate) fn clear_wakeup(&self) {
+        if let Some(wakeup) = &self.lock().wakeup {
+            let mut count = 0u64;
+            unsafe {
+                let _ = syscall!(
+                    Sysno::read,
+                    wakeup.descriptor,
+                    &mut count as *mut _ as usize,
+                    size_of::<u64>()
+                );
+            }
+        }
+    }
+
+    fn take_pending(&self) -> Vec<(TimerId, Instant, Task)> {
+        let mut state = self.lock();
+        let pending = std::mem::take(&mut state.pending);
+        state.scheduled.extend(pending.iter().map(|(id, _, _)| *id));
+        pending
+    }
+
+    // Forgets work leaving the heap, saying whether it was cancelled while there.
+    fn take_cancelled(&self, id: TimerId) -> bool {
+        let mut state = self.lock();
+        state.scheduled.remove(&id);
+        state.cancelled.remove(&id)
+    }
+
+    // How much work on the heap is still to run, and how much was cancelled.
+    fn count_scheduled(&self) -> (usize, usize) {
+        let state = self.lock();
+        (
+            state.scheduled.len() - state.cancelled.len(),
+            state.cancelled.len(),
+        )
+    }
+
+    // Forgets all the cancelled work on the heap, for it to be dropped.
+    fn take_all_cancelled(&self) -> HashSet<TimerId> {
+        let mut state = self.lock();
+        let cancelled = std::mem::take(&mut state.cancelled);
+        state.scheduled.retain(|id| !cancelled.contains(id));
+        cancelled
+    }
+}
+
+impl Wakeup {
+    fn signal(&self) {
+        let count = 1u64;
+        unsafe {
+            let _ = syscall!(
+                Sysno::write,
+                self.descriptor,
+                &count as *const _ as usize,
+                size_of::<u64>()
+            );
+        }
+    }
+}
+
+impl Drop for Wakeup {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = syscall!(Sysno::close, self.descriptor);
+        }
+    }
+}
+
+impl PartialEq for TimerEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TimerEntry {}
+
+impl PartialOrd for TimerEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TimerEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.deadline, self.sequence).cmp(&(other.deadline, other.sequence))
+    }
+}
+
+impl TimerQueue {
+    pub(crate) fn new(scheduler: Scheduler) -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            next_sequence: 0,
+            scheduler,
+            connection_timers: HashMap::new(),
+        }
+    }
+
+    pub(crate) const fn get_scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    // A connection's timer replaces the one it had before.
+    pub(crate) fn schedule(&mut self, deadline: Instant, event: TimerEvent) {
+        if let TimerEvent::Connection(id) = event {
+            self.connection_timers.insert(id, self.next_sequence);
+        }
+        self.heap.push(Reverse(TimerEntry {
+            deadline,
+            sequence: self.next_sequence,
+            event,
+        }));
+        self.next_sequence += 1;
+    }
+
+    // Moves work queued through the scheduler onto the heap.
+    pub(crate) fn absorb_scheduled(&mut self) {
+        for (id, deadline, task) in self.scheduler.take_pending() {
+            self.schedule(deadline, TimerEvent::Task(id, task));
+        }
+        self.compact();
+    }
+
+    // The connection is gone, so its timer is stale.
+    pub(crate) fn forget_connection(&mut self, id: u64) {
+        self.connection_timers.remove(&id);
+    }
+
+    fn is_stale(&self, entry: &TimerEntry) -> bool {
+        match entry.event {
+            TimerEvent::Connection(id) => self.connection_timers.get(&id) != Some(&entry.sequence),
+            TimerEvent::Task(..) => false,
+        }
+    }
+
+    // Rebuilds the heap without its dead entries once they outnumber the live ones, so what
+    // cancelled work captured isn't held until its deadline.
+    fn compact(&mut self) {
+        let (live_tasks, cancelled) = self.scheduler.count_scheduled();
+        let live = live_tasks + self.connection_timers.len();
+        if self.heap.len() <= live * 2 {
+            return;
+        }
+        let cancelled = if cancelled > 0 {
+            self.scheduler.take_all_cancelled()
+        } else {
+            HashSet::new()
+        };
+        let entries = std::mem::take(&mut self.heap).into_vec();
+        self.heap = entries
+            .into_iter()
+            .filter(|Reverse(entry)| match &entry.event {
+                TimerEvent::Task(id, _) => !cancelled.contains(id),
+                TimerEvent::Connection(_) => !self.is_stale(entry),
+            })
+            .collect();
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.heap.peek().map(|Reverse(entry)| entry.deadline)
+    }
+
+    // Removes every event due by `now`, in deadline order, skipping cancelled work.
+    pub(crate) fn pop_expired(&mut self, now: Instant) -> Vec<TimerEvent> {
+        let mut expired = Vec::new();
+        while self
+            .heap
+            .peek()
+            .is_some_and(|Reverse(entry)| entry.deadline <= now)
+        {
+            let Some(Reverse(entry)) = self.heap.pop() else {
+                break;
+            };
+            if self.is_stale(&entry) {
+                continue;
+            }
+            match entry.event {
+                TimerEvent::Task(id, _) if self.scheduler.take_cancelled(id) => {}
+                TimerEvent::Connection(id) => {
+                    self.connection_timers.remove(&id);
+                    expired.push(TimerEvent::Connection(id));
+                }
+                event => expired.push(event),
+            }
+        }
+        expired
+    }
+}
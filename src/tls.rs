@@ -0,0 +1,347 @@
+use std::{
+    fmt::Display,
+    io::{self, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use libc::EIO;
+use rustls::{
+    CipherSuite, ProtocolVersion, ServerConfig, ServerConnection,
+    crypto::ring,
+    pki_types::{CertificateDer, pem},
+    server::{ResolvesServerCert, VerifierBuilderError},
+};
+use syscalls::{Errno, Sysno, syscall};
+
+use crate::{
+    certificates::CertificateStore,
+    client_auth::{ClientAuth, ClientIdentity},
+    connection::{ConnectionReadError, ConnectionWriteError},
+    date::HttpDate,
+};
+
+// Certificates and settings for a TLS listener. Cloning shares the same rustls configuration.
+#[derive(Clone)]
+pub struct TlsConfig {
+    config: Arc<ServerConfig>,
+    // Unset for configurations built by hand.
+    certificates: Option<Arc<CertificateStore>>,
+}
+
+#[derive(Debug)]
+pub enum TlsConfigError {
+    ReadFailed(PathBuf, io::Error),
+    InvalidCertificate(pem::Error),
+    NoCertificates,
+    MalformedCertificate,
+    InvalidKey(pem::Error),
+    // The private key isn't the one the certificate was issued for.
+    KeyMismatch,
+    InvalidServerName(String),
+    // The certificate isn't valid for the name it was added under.
+    NameMismatch(String),
+    Expired(HttpDate),
+    NotYetValid(HttpDate),
+    InvalidClientAuth(VerifierBuilderError),
+    Rejected(rustls::Error),
+}
+
+// What was agreed on during a connection's handshake.
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+    server_name: Option<String>,
+    alpn_protocol: Option<Vec<u8>>,
+    protocol_version: Option<ProtocolVersion>,
+    cipher_suite: Option<CipherSuite>,
+    peer_certificates: Vec<CertificateDer<'static>>,
+    client_identity: Option<ClientIdentity>,
+}
+
+// The TLS session of one connection, fed from and flushed to its non-blocking descriptor.
+pub(crate) struct TlsStream {
+    session: ServerConnection,
+}
+
+// Lets rustls read and write the descriptor directly.
+struct Descriptor(usize);
+
+impl TlsConfig {
+    // Serves the store's certificates, picking one per connection by SNI. HTTP/2 and HTTP/1.1
+    // are offered over ALPN, in that order.
+    pub fn from_store(certificates: Arc<CertificateStore>) -> Result<Self, TlsConfigError> {
+        let mut config = build_config(None, certificates.clone())?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(Self {
+            config: Arc::new(config),
+            certificates: Some(certificates),
+        })
+    }
+
+    // Takes PEM data: the server certificate followed by any intermediates, and its private key.
+    // It is served to every client, whatever name it asks for.
+    pub fn from_pem(certificate_chain: &[u8], private_key: &[u8]) -> Result<Self, TlsConfigError> {
+        let certificates = CertificateStore::new();
+        certificates.add_pem(None, certificate_chain, private_key)?;
+        Self::from_store(Arc::new(certificates))
+    }
+
+    pub fn from_pem_files(
+        certificate_chain: impl AsRef<Path>,
+        private_key: impl AsRef<Path>,
+    ) -> Result<Self, TlsConfigError> {
+        let certificates = CertificateStore::new();
+        certificates.add_pem_files(None, certificate_chain, private_key)?;
+        Self::from_store(Arc::new(certificates))
+    }
+
+    // For settings this type doesn't cover.
+    pub const fn from_server_config(config: Arc<ServerConfig>) -> Self {
+        Self {
+            config,
+            certificates: None,
+        }
+    }
+
+    // In order of preference. Clients offering none of them are turned away.
+    pub fn set_alpn_protocols(&mut self, protocols: &[&[u8]]) {
+        Arc::make_mut(&mut self.config).alpn_protocols =
+            protocols.iter().map(|protocol| protocol.to_vec()).collect();
+    }
+
+    // Asks clients for certificates, or stops asking for None. This replaces the rustls
+    // configuration: certificates and ALPN protocols carry over, but changes made to it directly
+    // don't.
+    pub fn set_client_auth(
+        &mut self,
+        client_auth: Option<&ClientAuth>,
+    ) -> Result<(), TlsConfigError> {
+        let mut config = build_config(client_auth, Arc::clone(&self.config.cert_resolver))?;
+        config.alpn_protocols = self.config.alpn_protocols.clone();
+        self.config = Arc::new(config);
+        Ok(())
+    }
+
+    pub const fn get_server_config(&self) -> &Arc<ServerConfig> {
+        &self.config
+    }
+
+    // For adding or reloading certificates while the server runs.
+    pub const fn get_certificate_store(&self) -> Option<&Arc<CertificateStore>> {
+        self.certificates.as_ref()
+    }
+
+    pub(crate) fn new_stream(&self) -> Result<TlsStream, rustls::Error> {
+        ServerConnection::new(Arc::clone(&self.config)).map(|session| TlsStream { session })
+    }
+}
+
+impl Display for TlsConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ReadFailed(path, err) => write!(f, "Failed to read {}: {}", path.display(), err),
+            Self::InvalidCertificate(err) => write!(f, "Invalid certificate PEM: {:?}", err),
+            Self::NoCertificates => write!(f, "No certificates found"),
+            Self::MalformedCertificate => write!(f, "Certificate could not be parsed"),
+            Self::InvalidKey(err) => write!(f, "Invalid private key PEM: {:?}", err),
+            Self::KeyMismatch => write!(f, "Private key does not match the certificate"),
+            Self::InvalidServerName(name) => write!(f, "Invalid server name: {}", name),
+            Self::NameMismatch(name) => write!(f, "Certificate is not valid for {}", name),
+            Self::Expired(not_after) => write!(f, "Certificate expired on {}", not_after),
+            Self::NotYetValid(not_before) => {
+                write!(f, "Certificate is not valid before {}", not_before)
+            }
+            Self::InvalidClientAuth(err) => write!(f, "Invalid client authentication: {}", err),
+            Self::Rejected(err) => write!(f, "Certificate rejected: {}", err),
+        }
+    }
+}
+
+impl TlsInfo {
+    // The host name the client asked for through SNI.
+    pub fn get_server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    pub fn get_alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref()
+    }
+
+    pub const fn get_protocol_version(&self) -> Option<ProtocolVersion> {
+        self.protocol_version
+    }
+
+    pub const fn get_cipher_suite(&self) -> Option<CipherSuite> {
+        self.cipher_suite
+    }
+
+    // The client's certificate chain, leaf first. Empty unless the listener asks clients for
+    // certificates and this one sent some.
+    pub fn get_peer_certificates(&self) -> &[CertificateDer<'static>] {
+        &self.peer_certificates
+    }
+
+    pub const fn get_client_identity(&self) -> Option<&ClientIdentity> {
+        self.client_identity.as_ref()
+    }
+}
+
+impl TlsStream {
+    pub(crate) fn is_handshaking(&self) -> bool {
+        self.session.is_handshaking()
+    }
+
+    pub(crate) fn negotiated_http2(&self) -> bool {
+        self.session.alpn_protocol() == Some(b"h2")
+    }
+
+    // Whether encrypted bytes are waiting for the descriptor to become writable.
+    pub(crate) fn wants_write(&self) -> bool {
+        self.session.wants_write()
+    }
+
+    pub(crate) fn get_info(&self) -> TlsInfo {
+        let peer_certificates = self
+            .session
+            .peer_certificates()
+            .map(<[CertificateDer<'static>]>::to_vec)
+            .unwrap_or_default();
+        TlsInfo {
+            server_name: self.session.server_name().map(str::to_string),
+            alpn_protocol: self.session.alpn_protocol().map(<[u8]>::to_vec),
+            protocol_version: self.session.protocol_version(),
+            cipher_suite: self
+                .session
+                .negotiated_cipher_suite()
+                .map(|suite| suite.suite()),
+            // rustls only reports certificates that passed verification.
+            client_identity: peer_certificates
+                .first()
+                .and_then(ClientIdentity::from_certificate),
+            peer_certificates,
+        }
+    }
+
+    // Decrypts into `buffer`, reading from the descriptor as needed. Handshake messages are
+    // answered along the way, so a connection completes its handshake just by being read.
+    pub(crate) fn read(
+        &mut self,
+        descriptor: usize,
+        buffer: &mut [u8],
+    ) -> Result<usize, ConnectionReadError> {
+        loop {
+            match self.session.reader().read(buffer) {
+                Ok(count) => return Ok(count),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                // The client closed without a close_notify, which HTTP/1.1 framing makes safe.
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(0),
+                Err(err) => return Err(ConnectionReadError::ReadError(errno_of(&err))),
+            }
+            if self.receive(descriptor)? == 0 {
+                return Ok(0);
+            }
+        }
+    }
+
+    // Encrypts as much of `data` as rustls will buffer and sends what the descriptor takes.
+    pub(crate) fn write(
+        &mut self,
+        descriptor: usize,
+        data: &[u8],
+    ) -> Result<usize, ConnectionWriteError> {
+        // A response queued before the handshake finished, such as a refusal, can't be sent
+        // until the client's side of the handshake has been read.
+        if self.session.is_handshaking() {
+            match self.receive(descriptor) {
+                Ok(_) | Err(ConnectionReadError::ReadError(Errno::EAGAIN)) => {}
+                Err(_) => return Err(ConnectionWriteError::WriteError(Errno::EPROTO)),
+            }
+        }
+        let accepted = self
+            .session
+            .writer()
+            .write(data)
+            .map_err(|err| ConnectionWriteError::WriteError(errno_of(&err)))?;
+        match self.flush(descriptor) {
+            Err(ConnectionWriteError::WriteError(Errno::EAGAIN)) if accepted > 0 => Ok(accepted),
+            Err(err) => Err(err),
+            Ok(()) if accepted == 0 && !data.is_empty() => {
+                Err(ConnectionWriteError::WriteError(Errno::EAGAIN))
+            }
+            Ok(()) => Ok(accepted),
+        }
+    }
+
+    pub(crate) fn flush(&mut self, descriptor: usize) -> Result<(), ConnectionWriteError> {
+        while self.session.wants_write() {
+            self.session
+                .write_tls(&mut Descriptor(descriptor))
+                .map_err(|err| ConnectionWriteError::WriteError(errno_of(&err)))?;
+        }
+        Ok(())
+    }
+
+    // Sends a close_notify if the descriptor will take it. The connection is closing either way.
+    pub(crate) fn close(&mut self, descriptor: usize) {
+        self.session.send_close_notify();
+        let _ = self.flush(descriptor);
+    }
+
+    // Feeds one read's worth of records to rustls and sends whatever it has to say in return.
+    // Returns zero at the end of the stream.
+    fn receive(&mut self, descriptor: usize) -> Result<usize, ConnectionReadError> {
+        let count = self
+            .session
+            .read_tls(&mut Descriptor(descriptor))
+            .map_err(|err| ConnectionReadError::ReadError(errno_of(&err)))?;
+        let processed = self.session.process_new_packets();
+        // Handshake replies, or the alert explaining a failure. Anything left over goes out once
+        // the descriptor is writable.
+        let _ = self.flush(descriptor);
+        processed.map_err(ConnectionReadError::Tls)?;
+        Ok(count)
+    }
+}
+
+impl Read for Descriptor {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        unsafe {
+            syscall!(
+                Sysno::read,
+                self.0,
+                buffer.as_mut_ptr() as usize,
+                buffer.len()
+            )
+        }
+        .map_err(|errno| io::Error::from_raw_os_error(errno.into_raw()))
+    }
+}
+
+impl Write for Descriptor {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        unsafe { syscall!(Sysno::write, self.0, buffer.as_ptr() as usize, buffer.len()) }
+            .map_err(|errno| io::Error::from_raw_os_error(errno.into_raw()))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn build_config(
+    client_auth: Option<&ClientAuth>,
+    certificates: Arc<dyn ResolvesServerCert>,
+) -> Result<ServerConfig, TlsConfigError> {
+    let builder = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(TlsConfigError::Rejected)?;
+    let builder = match client_auth {
+        Some(client_auth) => builder.with_client_cert_verifier(client_auth.verifier()?),
+        None => builder.with_no_client_auth(),
+    };
+    Ok(builder.with_cert_resolver(certificates))
+}
+
+fn errno_of(err: &io::Error) -> Errno {
+    Errno::new(err.raw_os_error().unwrap_or(EIO))
+}
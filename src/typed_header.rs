@@ -0,0 +1,520 @@
+use std::fmt::Display;
+
+use crate::{
+    conditional::EntityTag,
+    date::HttpDate,
+    header::Header,
+    header_map::HeaderMap,
+    protocol::Protocol,
+    response::{Response, ResponseCode},
+};
+
+pub trait TypedHeader: Sized {
+    fn header() -> Header;
+
+    // Receives every line of the field in order, so list-based fields can be merged.
+    fn parse(values: &[&str]) -> Result<Self, HeaderParseError>;
+
+    fn format(&self) -> String;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderParseError {
+    header: Header,
+    value: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ContentLength(pub u64);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    pub mime: String,
+    pub charset: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityItem {
+    pub value: String,
+    pub quality: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accept(pub Vec<QualityItem>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcceptEncoding(pub Vec<QualityItem>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcceptLanguage(pub Vec<QualityItem>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheDirective {
+    MaxAge(u64),
+    SMaxAge(u64),
+    MaxStale(Option<u64>),
+    MinFresh(u64),
+    StaleWhileRevalidate(u64),
+    StaleIfError(u64),
+    NoCache,
+    NoStore,
+    NoTransform,
+    OnlyIfCached,
+    MustRevalidate,
+    ProxyRevalidate,
+    MustUnderstand,
+    Public,
+    Private,
+    Immutable,
+    Extension(String, Option<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheControl(pub Vec<CacheDirective>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag(pub EntityTag);
+
+macro_rules! date_header {
+    ($name:ident, $header:ident) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(pub HttpDate);
+
+        impl TypedHeader for $name {
+            fn header() -> Header {
+                Header::$header
+            }
+
+            fn parse(values: &[&str]) -> Result<Self, HeaderParseError> {
+                match values {
+                    [value] => HttpDate::parse(value)
+                        .map(Self)
+                        .ok_or_else(|| HeaderParseError::new(Self::header(), values)),
+                    _ => Err(HeaderParseError::new(Self::header(), values)),
+                }
+            }
+
+            fn format(&self) -> String {
+                self.0.to_string()
+            }
+        }
+    };
+}
+
+date_header!(Date, Date);
+date_header!(Expires, Expires);
+date_header!(LastModified, LastModified);
+date_header!(IfModifiedSince, IfModifiedSince);
+date_header!(IfUnmodifiedSince, IfUnmodifiedSince);
+
+impl HeaderParseError {
+    pub fn new(header: Header, values: &[&str]) -> Self {
+        Self {
+            header,
+            value: values.join(", "),
+        }
+    }
+
+    pub const fn get_header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn get_value(&self) -> &str {
+        &self.value
+    }
+
+    pub const fn response_code(&self) -> ResponseCode {
+        ResponseCode::BadRequest
+    }
+
+    pub fn to_response(&self, protocol: Protocol) -> Response {
+        let mut response = Response::new(self.response_code(), protocol);
+        response
+            .get_headers_mut()
+            .insert(Header::ContentType, "text/plain; charset=utf-8".to_string());
+        response.set_content(Some(format!("{}\n", self)));
+        response
+    }
+}
+
+impl Display for HeaderParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid {} header: {:?}",
+            self.header.as_str(),
+            self.value
+        )
+    }
+}
+
+fn split_elements<'a>(values: &[&'a str]) -> Vec<&'a str> {
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|element| !element.is_empty())
+        .collect()
+}
+
+fn unquote(value: &str) -> String {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .map_or_else(|| value.to_string(), |value| value.replace("\\\"", "\""))
+}
+
+pub(crate) fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+fn parse_quality_items(
+    header: Header,
+    values: &[&str],
+) -> Result<Vec<QualityItem>, HeaderParseError> {
+    split_elements(values)
+        .into_iter()
+        .map(|element| {
+            let mut parameters = element.split(';').map(str::trim);
+            let value = parameters.next().unwrap_or_default();
+            let mut quality = 1.0;
+            let mut extensions = Vec::new();
+            for parameter in parameters {
+                match parameter.split_once('=') {
+                    Some((name, weight)) if name.trim().eq_ignore_ascii_case("q") => {
+                        quality = weight
+                            .trim()
+                            .parse::<f32>()
+                            .ok()
+                            .filter(|quality| (0.0..=1.0).contains(quality))
+                            .ok_or_else(|| HeaderParseError::new(header.clone(), values))?;
+                    }
+                    _ => extensions.push(parameter),
+                }
+            }
+            if value.is_empty() {
+                return Err(HeaderParseError::new(header.clone(), values));
+            }
+            let value = std::iter::once(value)
+                .chain(extensions)
+                .collect::<Vec<&str>>()
+                .join(";");
+            Ok(QualityItem { value, quality })
+        })
+        .collect()
+}
+
+fn format_quality_items(items: &[QualityItem]) -> String {
+    items
+        .iter()
+        .map(|item| {
+            if item.quality >= 1.0 {
+                item.value.clone()
+            } else {
+                format!(
+                    "{};q={}",
+                    item.value,
+                    (item.quality * 1000.0).round() / 1000.0
+                )
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl QualityItem {
+    pub fn new(value: &str, quality: f32) -> Self {
+        Self {
+            value: value.to_string(),
+            quality: quality.clamp(0.0, 1.0),
+        }
+    }
+}
+
+// Returns the highest quality assigned to `value`, letting exact matches beat `type/*`, which in
+// turn beats `*`. Values the list doesn't mention at all get a quality of zero.
+fn quality_of(items: &[QualityItem], value: &str) -> f32 {
+    let value = value.to_ascii_lowercase();
+    let base = |item: &QualityItem| {
+        item.value
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+    };
+    let find = |predicate: &dyn Fn(&str) -> bool| {
+        items
+            .iter()
+            .filter(|item| predicate(&base(item)))
+            .map(|item| item.quality)
+            .reduce(f32::max)
+    };
+    find(&|candidate| candidate == value)
+        .or_else(|| {
+            value
+                .split_once('/')
+                .and_then(|(kind, _)| find(&|candidate| candidate.strip_suffix("/*") == Some(kind)))
+        })
+        .or_else(|| find(&|candidate| candidate == "*" || candidate == "*/*"))
+        .unwrap_or(0.0)
+}
+
+macro_rules! quality_header {
+    ($name:ident, $header:ident) => {
+        impl TypedHeader for $name {
+            fn header() -> Header {
+                Header::$header
+            }
+
+            fn parse(values: &[&str]) -> Result<Self, HeaderParseError> {
+                parse_quality_items(Self::header(), values).map(Self)
+            }
+
+            fn format(&self) -> String {
+                format_quality_items(&self.0)
+            }
+        }
+
+        impl $name {
+            pub fn quality_of(&self, value: &str) -> f32 {
+                quality_of(&self.0, value)
+            }
+
+            // Picks the candidate with the highest non-zero quality, earlier candidates winning ties.
+            pub fn preferred<'a>(&self, candidates: &[&'a str]) -> Option<&'a str> {
+                candidates
+                    .iter()
+                    .map(|candidate| (*candidate, self.quality_of(candidate)))
+                    .filter(|(_, quality)| *quality > 0.0)
+                    .fold(
+                        None,
+                        |best: Option<(&str, f32)>, (candidate, quality)| match best {
+                            Some((_, best_quality)) if best_quality >= quality => best,
+                            _ => Some((candidate, quality)),
+                        },
+                    )
+                    .map(|(candidate, _)| candidate)
+            }
+        }
+    };
+}
+
+quality_header!(Accept, Accept);
+quality_header!(AcceptEncoding, AcceptEncoding);
+quality_header!(AcceptLanguage, AcceptLanguage);
+
+impl TypedHeader for ContentLength {
+    fn header() -> Header {
+        Header::ContentLength
+    }
+
+    // A repeated or list-valued Content-Length is only valid when every value agrees.
+    fn parse(values: &[&str]) -> Result<Self, HeaderParseError> {
+        let mut lengths = split_elements(values).into_iter().map(|value| {
+            if value.bytes().all(|byte| byte.is_ascii_digit()) {
+                value.parse::<u64>().ok()
+            } else {
+                None
+            }
+        });
+        let first = lengths
+            .next()
+            .flatten()
+            .ok_or_else(|| HeaderParseError::new(Self::header(), values))?;
+        if lengths.any(|length| length != Some(first)) {
+            return Err(HeaderParseError::new(Self::header(), values));
+        }
+        Ok(Self(first))
+    }
+
+    fn format(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl ContentType {
+    pub fn new(mime: &str, charset: Option<&str>) -> Self {
+        Self {
+            mime: mime.to_ascii_lowercase(),
+            charset: charset.map(str::to_string),
+        }
+    }
+}
+
+impl TypedHeader for ContentType {
+    fn header() -> Header {
+        Header::ContentType
+    }
+
+    fn parse(values: &[&str]) -> Result<Self, HeaderParseError> {
+        let error = || HeaderParseError::new(Self::header(), values);
+        let [value] = values else {
+            return Err(error());
+        };
+        let mut parameters = value.split(';').map(str::trim);
+        let mime = parameters.next().unwrap_or_default();
+        match mime.split_once('/') {
+            Some((kind, subtype)) if is_token(kind) && is_token(subtype) => {}
+            _ => return Err(error()),
+        }
+        let mut charset = None;
+        for parameter in parameters.filter(|parameter| !parameter.is_empty()) {
+            let (name, value) = parameter.split_once('=').ok_or_else(error)?;
+            if name.trim().eq_ignore_ascii_case("charset") {
+                charset = Some(unquote(value.trim()).to_ascii_lowercase());
+            }
+        }
+        Ok(Self {
+            mime: mime.to_ascii_lowercase(),
+            charset,
+        })
+    }
+
+    fn format(&self) -> String {
+        self.charset.as_ref().map_or_else(
+            || self.mime.clone(),
+            |charset| format!("{}; charset={}", self.mime, charset),
+        )
+    }
+}
+
+impl CacheDirective {
+    fn parse(directive: &str) -> Option<Self> {
+        let (name, argument) = match directive.split_once('=') {
+            Some((name, argument)) => (name.trim(), Some(unquote(argument.trim()))),
+            None => (directive.trim(), None),
+        };
+        if !is_token(name) {
+            return None;
+        }
+        let seconds = || {
+            argument
+                .as_deref()
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+        Some(match name.to_ascii_lowercase().as_str() {
+            "max-age" => Self::MaxAge(seconds()?),
+            "s-maxage" => Self::SMaxAge(seconds()?),
+            "max-stale" => Self::MaxStale(match &argument {
+                Some(_) => Some(seconds()?),
+                None => None,
+            }),
+            "min-fresh" => Self::MinFresh(seconds()?),
+            "stale-while-revalidate" => Self::StaleWhileRevalidate(seconds()?),
+            "stale-if-error" => Self::StaleIfError(seconds()?),
+            "no-cache" => Self::NoCache,
+            "no-store" => Self::NoStore,
+            "no-transform" => Self::NoTransform,
+            "only-if-cached" => Self::OnlyIfCached,
+            "must-revalidate" => Self::MustRevalidate,
+            "proxy-revalidate" => Self::ProxyRevalidate,
+            "must-understand" => Self::MustUnderstand,
+            "public" => Self::Public,
+            "private" => Self::Private,
+            "immutable" => Self::Immutable,
+            _ => Self::Extension(name.to_ascii_lowercase(), argument),
+        })
+    }
+}
+
+impl Display for CacheDirective {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MaxAge(seconds) => write!(f, "max-age={}", seconds),
+            Self::SMaxAge(seconds) => write!(f, "s-maxage={}", seconds),
+            Self::MaxStale(Some(seconds)) => write!(f, "max-stale={}", seconds),
+            Self::MaxStale(None) => write!(f, "max-stale"),
+            Self::MinFresh(seconds) => write!(f, "min-fresh={}", seconds),
+            Self::StaleWhileRevalidate(seconds) => write!(f, "stale-while-revalidate={}", seconds),
+            Self::StaleIfError(seconds) => write!(f, "stale-if-error={}", seconds),
+            Self::NoCache => write!(f, "no-cache"),
+            Self::NoStore => write!(f, "no-store"),
+            Self::NoTransform => write!(f, "no-transform"),
+            Self::OnlyIfCached => write!(f, "only-if-cached"),
+            Self::MustRevalidate => write!(f, "must-revalidate"),
+            Self::ProxyRevalidate => write!(f, "proxy-revalidate"),
+            Self::MustUnderstand => write!(f, "must-understand"),
+            Self::Public => write!(f, "public"),
+            Self::Private => write!(f, "private"),
+            Self::Immutable => write!(f, "immutable"),
+            Self::Extension(name, Some(argument)) if is_token(argument) => {
+                write!(f, "{}={}", name, argument)
+            }
+            Self::Extension(name, Some(argument)) => {
+                write!(f, "{}=\"{}\"", name, argument.replace('"', "\\\""))
+            }
+            Self::Extension(name, None) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl CacheControl {
+    pub fn contains(&self, directive: &CacheDirective) -> bool {
+        self.0.contains(directive)
+    }
+
+    pub fn max_age(&self) -> Option<u64> {
+        self.0.iter().find_map(|directive| match directive {
+            CacheDirective::MaxAge(seconds) => Some(*seconds),
+            _ => None,
+        })
+    }
+}
+
+impl TypedHeader for CacheControl {
+    fn header() -> Header {
+        Header::CacheControl
+    }
+
+    fn parse(values: &[&str]) -> Result<Self, HeaderParseError> {
+        split_elements(values)
+            .into_iter()
+            .map(CacheDirective::parse)
+            .collect::<Option<Vec<CacheDirective>>>()
+            .map(Self)
+            .ok_or_else(|| HeaderParseError::new(Self::header(), values))
+    }
+
+    fn format(&self) -> String {
+        self.0
+            .iter()
+            .map(CacheDirective::to_string)
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+impl TypedHeader for ETag {
+    fn header() -> Header {
+        Header::ETag
+    }
+
+    fn parse(values: &[&str]) -> Result<Self, HeaderParseError> {
+        match values {
+            [value] => EntityTag::parse(value)
+                .map(Self)
+                .map_err(|_| HeaderParseError::new(Self::header(), values)),
+            _ => Err(HeaderParseError::new(Self::header(), values)),
+        }
+    }
+
+    fn format(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl HeaderMap {
+    pub fn get_typed<T: TypedHeader>(&self) -> Option<Result<T, HeaderParseError>> {
+        let header = T::header();
+        let values: Vec<&str> = self.get_all(&header).map(String::as_str).collect();
+        (!values.is_empty()).then(|| T::parse(&values))
+    }
+
+    pub fn insert_typed<T: TypedHeader>(&mut self, value: &T) -> Option<String> {
+        self.insert(T::header(), value.format())
+    }
+}
@@ -0,0 +1,641 @@
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    connection::Connection,
+    handler::Handler,
+    header::Header,
+    protocol::Protocol,
+    request::{Method, Request},
+    response::{Response, ResponseCode},
+    timer::Scheduler,
+};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+const DEFAULT_MAX_QUEUED_BYTES: usize = 4 * 1024 * 1024;
+const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+type HandlerFactory = Box<dyn FnMut(&Request) -> Option<Box<dyn WebSocketHandler>>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+}
+
+// The status codes of RFC 6455 section 7.4.1. NoStatus and Abnormal are never sent, and only
+// describe how a connection ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseCode {
+    Normal = 1000,
+    GoingAway = 1001,
+    ProtocolError = 1002,
+    UnsupportedData = 1003,
+    NoStatus = 1005,
+    Abnormal = 1006,
+    InvalidPayload = 1007,
+    PolicyViolation = 1008,
+    MessageTooBig = 1009,
+    InternalError = 1011,
+}
+
+// Serves one WebSocket connection. Its methods run on the server's event loop, so they should
+// hand anything slow to another thread and push the result through the WebSocket later.
+pub trait WebSocketHandler {
+    fn on_open(&mut self, _socket: &WebSocket) {}
+
+    // Pings have already been answered by the time they get here.
+    fn on_message(&mut self, socket: &WebSocket, message: Message);
+
+    // Runs once, with the code from whichever side closed first: 1005 if the client's close
+    // carried none, 1006 if the connection dropped without one.
+    fn on_close(&mut self, _code: u16, _reason: &str) {}
+}
+
+// Sends on a WebSocket connection. Clones share the connection, and can be kept to push messages
+// from other threads long after the handler's last callback returned.
+#[derive(Clone)]
+pub struct WebSocket {
+    shared: Arc<Mutex<Outbox>>,
+    scheduler: Scheduler,
+}
+
+struct Outbox {
+    output: Vec<u8>,
+    max_queued_bytes: usize,
+    close_sent: Option<(u16, String)>,
+    // Set once the connection is gone.
+    finished: bool,
+}
+
+// Answers WebSocket handshakes on its route with 101 and hands the connection to a handler made
+// for it. The factory returns None to turn the client away with 403.
+pub struct WebSocketUpgrade {
+    factory: HandlerFactory,
+    max_message_size: usize,
+    max_queued_bytes: usize,
+    ping_interval: Option<Duration>,
+}
+
+// The receiving side of an upgraded connection, owned by its Connection.
+pub(crate) struct WebSocketSession {
+    handler: Box<dyn WebSocketHandler>,
+    socket: WebSocket,
+    max_message_size: usize,
+    ping_interval: Option<Duration>,
+    last_heard: Instant,
+    ping_sent: bool,
+    // The opcode and payload so far of a fragmented message.
+    message: Option<(u8, Vec<u8>)>,
+    close_received: bool,
+    close_reported: bool,
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+impl WebSocket {
+    fn new(scheduler: Scheduler, max_queued_bytes: usize) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Outbox {
+                output: Vec::new(),
+                max_queued_bytes,
+                close_sent: None,
+                finished: false,
+            })),
+            scheduler,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Outbox> {
+        self.shared
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // False once the connection is closing, in which case the message is dropped. Control
+    // frames carry at most 125 bytes, so longer pings and pongs are refused as well. A client
+    // too far behind to take the message within the queue limit is closed with 1008.
+    pub fn send(&self, message: Message) -> bool {
+        let (opcode, payload) = match message {
+            Message::Text(text) => (TEXT, text.into_bytes()),
+            Message::Binary(data) => (BINARY, data),
+            Message::Ping(data) => (PING, data),
+            Message::Pong(data) => (PONG, data),
+        };
+        if opcode >= CLOSE && payload.len() > MAX_CONTROL_PAYLOAD {
+            return false;
+        }
+        self.queue(opcode, &payload)
+    }
+
+    pub fn send_text(&self, text: impl Into<String>) -> bool {
+        self.send(Message::Text(text.into()))
+    }
+
+    // Starts the closing handshake. The reason is cut short to fit in a control frame.
+    pub fn close(&self, code: u16, reason: &str) -> bool {
+        let mut end = reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        let reason = &reason[..end];
+        let payload = [&code.to_be_bytes()[..], reason.as_bytes()].concat();
+        if !self.queue(CLOSE, &payload) {
+            return false;
+        }
+        self.lock().close_sent = Some((code, reason.to_string()));
+        true
+    }
+
+    pub fn is_closed(&self) -> bool {
+        let outbox = self.lock();
+        outbox.finished || outbox.close_sent.is_some()
+    }
+
+    fn queue(&self, opcode: u8, payload: &[u8]) -> bool {
+        let mut outbox = self.lock();
+        if outbox.finished || outbox.close_sent.is_some() {
+            return false;
+        }
+        // The close frame always goes in, so a client that stopped reading can still be closed.
+        if opcode != CLOSE && outbox.output.len() + payload.len() > outbox.max_queued_bytes {
+            drop(outbox);
+            self.close(CloseCode::PolicyViolation as u16, "Send queue full");
+            return false;
+        }
+        encode_frame(&mut outbox.output, opcode, payload);
+        drop(outbox);
+        // The event loop may be asleep if this comes from another thread.
+        self.scheduler.wake();
+        true
+    }
+}
+
+impl WebSocketUpgrade {
+    pub fn new<H: WebSocketHandler + 'static>(
+        mut factory: impl FnMut(&Request) -> Option<H> + 'static,
+    ) -> Self {
+        Self {
+            factory: Box::new(move |request| {
+                factory(request).map(|handler| Box::new(handler) as Box<dyn WebSocketHandler>)
+            }),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            max_queued_bytes: DEFAULT_MAX_QUEUED_BYTES,
+            ping_interval: Some(DEFAULT_PING_INTERVAL),
+        }
+    }
+
+    // Messages larger than this, fragmented or not, close the connection with 1009.
+    pub const fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    // Most a connection holds of messages the client has yet to read. Sending past it closes
+    // the connection with 1008, so a client that stops reading can't make the server buffer
+    // without end.
+    pub const fn set_max_queued_bytes(&mut self, max_queued_bytes: usize) {
+        self.max_queued_bytes = max_queued_bytes;
+    }
+
+    // How long a connection may go without hearing from the client before it is pinged. One
+    // that stays silent for another interval after that is dropped.
+    pub const fn set_ping_interval(&mut self, ping_interval: Option<Duration>) {
+        self.ping_interval = ping_interval;
+    }
+
+    pub const fn get_max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    pub const fn get_max_queued_bytes(&self) -> usize {
+        self.max_queued_bytes
+    }
+
+    pub const fn get_ping_interval(&self) -> Option<Duration> {
+        self.ping_interval
+    }
+
+    // The handshake response when the request isn't one that can be upgraded.
+    fn check_handshake(request: &Request) -> Option<Response> {
+        if !matches!(request.get_protocol(), Protocol::Http1_1) {
+            return Some(Response::new(
+                ResponseCode::HTTPVersionNotSupported,
+                request.get_protocol(),
+            ));
+        }
+        let headers = request.get_headers();
+        let has_token = |header: &Header, token: &str| {
+            headers
+                .get_list(header)
+                .iter()
+                .any(|value| value.eq_ignore_ascii_case(token))
+        };
+        if !has_token(&Header::Upgrade, "websocket") || !has_token(&Header::Connection, "upgrade") {
+            let mut response = Response::new(ResponseCode::UpgradeRequired, Protocol::Http1_1);
+            response
+                .get_headers_mut()
+                .insert(Header::Upgrade, "websocket".to_string());
+            response
+                .get_headers_mut()
+                .insert(Header::Connection, "Upgrade".to_string());
+            return Some(response);
+        }
+        if *request.get_method() != Method::Get {
+            let mut response = Response::new(ResponseCode::MethodNotAllowed, Protocol::Http1_1);
+            response
+                .get_headers_mut()
+                .insert(Header::Allow, "GET".to_string());
+            return Some(response);
+        }
+        if headers
+            .get(&Header::SecWebSocketVersion)
+            .is_none_or(|version| version.trim() != "13")
+        {
+            let mut response = Response::new(ResponseCode::UpgradeRequired, Protocol::Http1_1);
+            response
+                .get_headers_mut()
+                .insert(Header::SecWebSocketVersion, "13".to_string());
+            return Some(response);
+        }
+        if headers
+            .get(&Header::SecWebSocketKey)
+            .is_none_or(|key| !is_valid_key(key.trim()))
+        {
+            return Some(Response::new(ResponseCode::BadRequest, Protocol::Http1_1));
+        }
+        None
+    }
+}
+
+impl Handler for WebSocketUpgrade {
+    fn handle(&mut self, connection: &mut Connection, request: &Request) -> Response {
+        if let Some(response) = Self::check_handshake(request) {
+            return response;
+        }
+        let Some(handler) = (self.factory)(request) else {
+            return Response::new(ResponseCode::Forbidden, Protocol::Http1_1);
+        };
+        let key = request
+            .get_headers()
+            .get(&Header::SecWebSocketKey)
+            .map_or("", |key| key.trim());
+        let mut response = Response::new(ResponseCode::SwitchingProtocols, Protocol::Http1_1);
+        let headers = response.get_headers_mut();
+        headers.insert(Header::Upgrade, "websocket".to_string());
+        headers.insert(Header::Connection, "Upgrade".to_string());
+        headers.insert(Header::SecWebSocketAccept, accept_key(key));
+        connection.upgrade_websocket(WebSocketSession {
+            handler,
+            socket: WebSocket::new(connection.get_scheduler().clone(), self.max_queued_bytes),
+            max_message_size: self.max_message_size,
+            ping_interval: self.ping_interval,
+            last_heard: Instant::now(),
+            ping_sent: false,
+            message: None,
+            close_received: false,
+            close_reported: false,
+        });
+        response
+    }
+}
+
+impl WebSocketSession {
+    // Called once the 101 response is out and frames may flow.
+    pub(crate) fn open(&mut self) {
+        self.last_heard = Instant::now();
+        self.handler.on_open(&self.socket);
+    }
+
+    // Consumes every complete frame in `input`. A client that breaks the protocol is sent a close
+    // frame with the returned code, and nothing it sends after is read.
+    pub(crate) fn receive(&mut self, input: &mut Vec<u8>) -> Result<(), CloseCode> {
+        let mut consumed = 0;
+        let result = loop {
+            if self.is_closing() {
+                consumed = input.len();
+                break Ok(());
+            }
+            let (frame, length) = match parse_frame(&input[consumed..], self.max_message_size) {
+                Ok(Some(parsed)) => parsed,
+                Ok(None) => break Ok(()),
+                Err(code) => break Err(code),
+            };
+            consumed += length;
+            self.last_heard = Instant::now();
+            self.ping_sent = false;
+            if let Err(code) = self.handle_frame(frame) {
+                break Err(code);
+            }
+        };
+        match result {
+            Ok(()) => {
+                input.drain(..consumed);
+                Ok(())
+            }
+            Err(code) => Err(self.fail(code, input)),
+        }
+    }
+
+    fn fail(&self, code: CloseCode, input: &mut Vec<u8>) -> CloseCode {
+        input.clear();
+        self.socket.close(code as u16, "");
+        code
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), CloseCode> {
+        if frame.opcode >= CLOSE && (!frame.fin || frame.payload.len() > MAX_CONTROL_PAYLOAD) {
+            return Err(CloseCode::ProtocolError);
+        }
+        match frame.opcode {
+            CLOSE => self.handle_close(&frame.payload),
+            PING => {
+                self.socket.queue(PONG, &frame.payload);
+                self.handler
+                    .on_message(&self.socket, Message::Ping(frame.payload));
+                Ok(())
+            }
+            PONG => {
+                self.handler
+                    .on_message(&self.socket, Message::Pong(frame.payload));
+                Ok(())
+            }
+            CONTINUATION => {
+                let Some((_, data)) = &mut self.message else {
+                    return Err(CloseCode::ProtocolError);
+                };
+                if data.len() + frame.payload.len() > self.max_message_size {
+                    return Err(CloseCode::MessageTooBig);
+                }
+                data.extend(frame.payload);
+                if frame.fin {
+                    let (opcode, data) = self.message.take().unwrap_or_default();
+                    self.deliver(opcode, data)?;
+                }
+                Ok(())
+            }
+            TEXT | BINARY if self.message.is_some() => Err(CloseCode::ProtocolError),
+            TEXT | BINARY if frame.fin => self.deliver(frame.opcode, frame.payload),
+            TEXT | BINARY => {
+                self.message = Some((frame.opcode, frame.payload));
+                Ok(())
+            }
+            _ => Err(CloseCode::ProtocolError),
+        }
+    }
+
+    fn deliver(&mut self, opcode: u8, data: Vec<u8>) -> Result<(), CloseCode> {
+        let message = if opcode == TEXT {
+            Message::Text(String::from_utf8(data).map_err(|_| CloseCode::InvalidPayload)?)
+        } else {
+            Message::Binary(data)
+        };
+        self.handler.on_message(&self.socket, message);
+        Ok(())
+    }
+
+    // Answers a close the server didn't start by echoing its code, per RFC 6455 section 5.5.1.
+    fn handle_close(&mut self, payload: &[u8]) -> Result<(), CloseCode> {
+        let (code, reason) = match payload {
+            [] => (CloseCode::NoStatus as u16, ""),
+            [_] => return Err(CloseCode::ProtocolError),
+            [high, low, reason @ ..] => {
+                let code = u16::from_be_bytes([*high, *low]);
+                if !is_valid_close_code(code) {
+                    return Err(CloseCode::ProtocolError);
+                }
+                let reason = std::str::from_utf8(reason).map_err(|_| CloseCode::InvalidPayload)?;
+                (code, reason)
+            }
+        };
+        self.close_received = true;
+        if payload.is_empty() {
+            self.socket.queue(CLOSE, &[]);
+            self.socket.lock().close_sent = Some((code, String::new()));
+        } else {
+            self.socket.close(code, reason);
+        }
+        self.close_reported = true;
+        self.handler.on_close(code, reason);
+        Ok(())
+    }
+
+    pub(crate) fn close(&self, code: CloseCode) {
+        self.socket.close(code as u16, "");
+    }
+
+    // Once either side has sent a close frame, nothing more is read.
+    pub(crate) fn is_closing(&self) -> bool {
+        self.close_received || self.socket.is_closed()
+    }
+
+    pub(crate) fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut self.socket.lock().output)
+    }
+
+    pub(crate) fn has_output(&self) -> bool {
+        !self.socket.lock().output.is_empty()
+    }
+
+    // When the client is next due a ping, or due to have answered the last one.
+    pub(crate) fn get_ping_deadline(&self) -> Option<Instant> {
+        if self.is_closing() {
+            return None;
+        }
+        self.ping_interval
+            .map(|interval| self.last_heard + interval)
+    }
+
+    // Pings the client, unless it never answered the previous ping, in which case it's gone.
+    pub(crate) fn ping(&mut self, now: Instant) -> bool {
+        if self.ping_sent {
+            return false;
+        }
+        self.ping_sent = true;
+        self.last_heard = now;
+        self.socket.queue(PING, &[])
+    }
+}
+
+impl Drop for WebSocketSession {
+    fn drop(&mut self) {
+        let mut outbox = self.socket.lock();
+        outbox.finished = true;
+        let (code, reason) = outbox
+            .close_sent
+            .take()
+            .unwrap_or((CloseCode::Abnormal as u16, String::new()));
+        drop(outbox);
+        if !self.close_reported {
+            self.handler.on_close(code, &reason);
+        }
+    }
+}
+
+// The Sec-WebSocket-Accept value for a client's Sec-WebSocket-Key.
+pub fn accept_key(key: &str) -> String {
+    base64_encode(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+// A key is 16 random bytes in base64, which always comes out as 22 characters and "==". The last
+// character holds only the two leftover bits of the final byte, so its low four bits are zero.
+fn is_valid_key(key: &str) -> bool {
+    let bytes = key.as_bytes();
+    bytes.len() == 24
+        && key.ends_with("==")
+        && bytes[..22].iter().all(|byte| BASE64.contains(byte))
+        && matches!(bytes[21], b'A' | b'Q' | b'g' | b'w')
+}
+
+// Codes a close frame may carry: the ones RFC 6455 defines for use on the wire, plus the ranges
+// left to libraries and applications.
+const fn is_valid_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999)
+}
+
+// Reads the frame at the front of `input` along with its length, or returns None while it is
+// incomplete. Every frame from a client has to be masked.
+fn parse_frame(input: &[u8], max_message_size: usize) -> Result<Option<(Frame, usize)>, CloseCode> {
+    let [first, second, ..] = input[..] else {
+        return Ok(None);
+    };
+    if first & 0x70 != 0 || second & 0x80 == 0 {
+        return Err(CloseCode::ProtocolError);
+    }
+    let (length, offset): (u64, usize) = match second & 0x7f {
+        126 if input.len() >= 4 => (u64::from(u16::from_be_bytes([input[2], input[3]])), 4),
+        127 if input.len() >= 10 => (
+            u64::from_be_bytes(input[2..10].try_into().expect("Slice is 8 bytes long.")),
+            10,
+        ),
+        126 | 127 => return Ok(None),
+        length => (u64::from(length), 2),
+    };
+    let length = usize::try_from(length)
+        .ok()
+        .filter(|length| *length <= max_message_size)
+        .ok_or(CloseCode::MessageTooBig)?;
+    let Some(frame_end) = (offset + 4)
+        .checked_add(length)
+        .filter(|end| *end <= input.len())
+    else {
+        return Ok(None);
+    };
+    let mask = [
+        input[offset],
+        input[offset + 1],
+        input[offset + 2],
+        input[offset + 3],
+    ];
+    let payload = input[offset + 4..frame_end]
+        .iter()
+        .zip(mask.iter().cycle())
+        .map(|(byte, mask)| byte ^ mask)
+        .collect();
+    let frame = Frame {
+        fin: first & 0x80 != 0,
+        opcode: first & 0x0f,
+        payload,
+    };
+    Ok(Some((frame, frame_end)))
+}
+
+// Server frames go out whole and unmasked.
+fn encode_frame(output: &mut Vec<u8>, opcode: u8, payload: &[u8]) {
+    output.push(0x80 | opcode);
+    match payload.len() {
+        length @ 0..=125 => output.push(length as u8),
+        length @ 126..=0xffff => {
+            output.push(126);
+            output.extend((length as u16).to_be_bytes());
+        }
+        length => {
+            output.push(127);
+            output.extend((length as u64).to_be_bytes());
+        }
+    }
+    output.extend_from_slice(payload);
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().expect("Chunk is 4 bytes long."));
+        }
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..20 => ((b & c) | (!b & d), 0x5a827999),
+                20..40 => (b ^ c ^ d, 0x6ed9eba1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let next = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = next;
+        }
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(added);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, byte)| {
+            group | u32::from(*byte) << (16 - 8 * index)
+        });
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(BASE64[(group >> (18 - 6 * index)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
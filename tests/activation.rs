@@ -0,0 +1,191 @@
+use std::{
+    env,
+    fs::File,
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    os::{
+        fd::{AsRawFd, IntoRawFd},
+        unix::process::CommandExt,
+    },
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use http_server::{
+    activation::{activated_sockets, take_activated_socket},
+    connection::Connection,
+    handler::Handler,
+    protocol::Protocol,
+    request::Request,
+    response::{Response, ResponseCode},
+    router::BaseRouter,
+    server::HTTPServer,
+    socket::{Socket, SocketAcceptError},
+    socket_builder::SocketBuilder,
+};
+
+// Set in the child process the parent test starts.
+const CHILD_VARIABLE: &str = "HTTP_SERVER_ACTIVATION_CHILD";
+const FAILURE_CHILD_VARIABLE: &str = "HTTP_SERVER_ACTIVATION_FAILURE_CHILD";
+
+struct PidHandler {}
+
+impl Handler for PidHandler {
+    fn handle(&mut self, _connection: &mut Connection, _request: &Request) -> Response {
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response.set_content(Some(std::process::id().to_string()));
+        response
+    }
+}
+
+// Stops the child server however the test ends.
+struct ChildGuard(Child);
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn raw_descriptor() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let address = listener.local_addr().unwrap();
+    let mut socket = Socket::from_raw_descriptor(listener.into_raw_fd() as usize).unwrap();
+    assert!(socket.is_listening());
+    assert_eq!(
+        socket.get_address_descriptor().to_socket_addr(),
+        Some(address)
+    );
+
+    // The adopted listener is non-blocking, so an empty backlog doesn't hang.
+    assert!(matches!(
+        socket.accept_connection(),
+        Err(SocketAcceptError::AcceptFailed(_))
+    ));
+    let _client = TcpStream::connect(address).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(socket.accept_connection().is_ok());
+}
+
+// Runs the server the way systemd would, with the listener passed as descriptor 3.
+#[test]
+fn activation() {
+    // The listener may already be descriptor 3, where dup2 leaves it as it is.
+    let mut builder = SocketBuilder::new();
+    builder.set_inheritable(true);
+    let mut socket = builder
+        .bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .unwrap();
+    socket.start_listening().unwrap();
+    let address = socket.get_local_address().unwrap();
+    let descriptor = socket.get_file_descriptor() as i32;
+
+    // The shell sets LISTEN_PID to its own pid, which the test binary keeps through exec.
+    let child = ChildGuard(unsafe {
+        Command::new("sh")
+            .args([
+                "-c",
+                "LISTEN_PID=$$ exec \"$0\" \"$@\"",
+                env::current_exe().unwrap().to_str().unwrap(),
+                "--exact",
+                "activated_child",
+            ])
+            .env(CHILD_VARIABLE, "1")
+            .env("LISTEN_FDS", "1")
+            .env("LISTEN_FDNAMES", "http")
+            .stdout(Stdio::null())
+            .pre_exec(move || {
+                if libc::dup2(descriptor, 3) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            })
+            .spawn()
+            .unwrap()
+    });
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+        .write_all(b"GET /pid HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert!(response.ends_with(&format!("\r\n\r\n{}", child.0.id())));
+}
+
+#[test]
+fn activated_child() {
+    if env::var(CHILD_VARIABLE).is_err() {
+        assert!(activated_sockets().unwrap().is_empty());
+        return;
+    }
+    let mut sockets = activated_sockets().unwrap();
+    let socket = take_activated_socket(&mut sockets, "http").unwrap();
+    assert!(sockets.is_empty());
+    let mut router = BaseRouter::new();
+    router.register_handler_from_path(PidHandler {}, "/pid");
+    HTTPServer::new(socket, router).run();
+}
+
+// Passes a listener, a file and another listener as descriptors 3 to 5. The file can't be
+// adopted, so the child is left with none of them open.
+#[test]
+fn activation_failure() {
+    let first = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let file = File::open(env::current_exe().unwrap()).unwrap();
+    let second = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let descriptors = [first.as_raw_fd(), file.as_raw_fd(), second.as_raw_fd()];
+
+    let status = unsafe {
+        Command::new("sh")
+            .args([
+                "-c",
+                "LISTEN_PID=$$ exec \"$0\" \"$@\"",
+                env::current_exe().unwrap().to_str().unwrap(),
+                "--exact",
+                "activation_failure_child",
+            ])
+            .env(FAILURE_CHILD_VARIABLE, "1")
+            .env("LISTEN_FDS", "3")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .pre_exec(move || {
+                // Moved out of the way first, in case they already sit where they are going.
+                let mut moved = [0; 3];
+                for (moved, &descriptor) in moved.iter_mut().zip(&descriptors) {
+                    *moved = libc::fcntl(descriptor, libc::F_DUPFD, 100);
+                    if *moved == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                for (target, &descriptor) in (3..).zip(&moved) {
+                    if libc::dup2(descriptor, target) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    libc::close(descriptor);
+                }
+                Ok(())
+            })
+            .status()
+            .unwrap()
+    };
+    assert!(status.success());
+}
+
+#[test]
+fn activation_failure_child() {
+    if env::var(FAILURE_CHILD_VARIABLE).is_err() {
+        return;
+    }
+    assert!(activated_sockets().is_err());
+    for descriptor in 3..6 {
+        assert_eq!(unsafe { libc::fcntl(descriptor, libc::F_GETFD) }, -1);
+    }
+}
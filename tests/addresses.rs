@@ -0,0 +1,60 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, Shutdown},
+    thread,
+};
+
+use http_server::{
+    connection::Connection,
+    handler::Handler,
+    protocol::Protocol,
+    request::Request,
+    response::{Response, ResponseCode},
+    router::BaseRouter,
+    server::HTTPServer,
+    socket::Socket,
+};
+
+struct AddressHandler {}
+
+impl Handler for AddressHandler {
+    fn handle(&mut self, connection: &mut Connection, _request: &Request) -> Response {
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response.set_content(Some(format!(
+            "{} {}",
+            connection.get_peer_address().unwrap(),
+            connection.get_local_address().unwrap()
+        )));
+        response
+    }
+}
+
+#[test]
+fn addresses() {
+    thread::spawn(|| {
+        let mut router = BaseRouter::new();
+        router.register_handler_from_path(AddressHandler {}, "/whoami");
+        HTTPServer::new(
+            Socket::new(common::port(), Ipv4Addr::new(127, 0, 0, 1)).unwrap(),
+            router,
+        )
+        .run();
+    });
+
+    let mut stream = common::connect();
+    let expected = format!(
+        "{} {}",
+        stream.local_addr().unwrap(),
+        stream.peer_addr().unwrap()
+    );
+    stream
+        .write_all(b"GET /whoami HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let (_, body) = common::split_response(&response);
+    assert_eq!(String::from_utf8(body).unwrap(), expected);
+}
@@ -0,0 +1,364 @@
+#![cfg(feature = "tls")]
+
+use std::{
+    fs::{self, File},
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    path::PathBuf,
+    sync::{Arc, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use http_server::{
+    certificates::{CertificateStore, CertificateWatch, reload_on_signal},
+    router::BaseRouter,
+    server::{HTTPServer, ListenerId},
+    socket::Socket,
+    tls::{TlsConfig, TlsConfigError},
+};
+use rcgen::{CertificateParams, KeyPair, date_time_ymd};
+use rustls::{
+    ClientConfig, ClientConnection, RootCertStore, crypto::ring, pki_types::CertificateDer,
+};
+
+struct Certified {
+    certificate: CertificateDer<'static>,
+    certificate_pem: String,
+    key_pem: String,
+}
+
+fn certified(name: &str) -> Certified {
+    certified_with(CertificateParams::new(vec![name.to_string()]).unwrap())
+}
+
+fn certified_with(params: CertificateParams) -> Certified {
+    let key_pair = KeyPair::generate().unwrap();
+    let certificate = params.self_signed(&key_pair).unwrap();
+    Certified {
+        certificate: certificate.der().clone(),
+        certificate_pem: certificate.pem(),
+        key_pem: key_pair.serialize_pem(),
+    }
+}
+
+// Serves the store and returns the address to connect to, along with the watch if there is one.
+fn start_server(
+    store: Arc<CertificateStore>,
+    watch_interval: Option<Duration>,
+) -> (SocketAddr, Option<CertificateWatch>) {
+    let socket = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+    let address = socket.get_local_address().unwrap();
+    let (sender, watches) = mpsc::channel();
+    thread::spawn(move || {
+        let mut server = HTTPServer::new(socket, BaseRouter::new());
+        let watch = watch_interval.map(|interval| store.watch(&server.get_scheduler(), interval));
+        sender.send(watch).unwrap();
+        let tls = TlsConfig::from_store(store).unwrap();
+        server.set_tls(ListenerId::default(), Some(tls));
+        server.run();
+    });
+    (address, watches.recv().unwrap())
+}
+
+// Completes a handshake asking for `name`, and returns the certificate the server presented.
+fn presented_certificate(
+    address: SocketAddr,
+    name: &'static str,
+    trusted: &[&Certified],
+) -> CertificateDer<'static> {
+    let mut roots = RootCertStore::empty();
+    for certified in trusted {
+        roots.add(certified.certificate.clone()).unwrap();
+    }
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let mut session = ClientConnection::new(Arc::new(config), name.try_into().unwrap()).unwrap();
+    let mut stream = (0..50)
+        .find_map(|_| {
+            TcpStream::connect(address)
+                .inspect_err(|_| thread::sleep(Duration::from_millis(20)))
+                .ok()
+        })
+        .expect("Server never accepted a connection.");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    while session.is_handshaking() {
+        session.complete_io(&mut stream).unwrap();
+    }
+    session.peer_certificates().unwrap()[0].clone()
+}
+
+// Retries until the server presents `expected`, since reloads happen on the server's schedule.
+fn wait_for_certificate(address: SocketAddr, trusted: &[&Certified], expected: &Certified) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while presented_certificate(address, "localhost", trusted) != expected.certificate {
+        assert!(Instant::now() < deadline, "Certificate was never reloaded.");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn pem_files(test: &str, certified: &Certified) -> (PathBuf, PathBuf) {
+    let base = format!("/tmp/http_server_{}_{}", test, std::process::id());
+    let paths = (
+        PathBuf::from(format!("{}.crt", base)),
+        PathBuf::from(format!("{}.key", base)),
+    );
+    write_pem_files(&paths, certified);
+    paths
+}
+
+fn write_pem_files((certificate, key): &(PathBuf, PathBuf), certified: &Certified) {
+    fs::write(certificate, &certified.certificate_pem).unwrap();
+    fs::write(key, &certified.key_pem).unwrap();
+}
+
+#[test]
+fn sni() {
+    let default = certified("default.test");
+    let first = certified("first.test");
+    let second = certified("second.test");
+    let store = CertificateStore::new();
+    store
+        .add_pem(
+            None,
+            default.certificate_pem.as_bytes(),
+            default.key_pem.as_bytes(),
+        )
+        .unwrap();
+    store
+        .add_pem(
+            Some("first.test"),
+            first.certificate_pem.as_bytes(),
+            first.key_pem.as_bytes(),
+        )
+        .unwrap();
+    let paths = pem_files("sni", &second);
+    store
+        .add_pem_files(Some("Second.Test"), &paths.0, &paths.1)
+        .unwrap();
+    let mut names = store.get_names();
+    names.sort();
+    assert_eq!(names, ["first.test", "second.test"]);
+
+    let (address, _) = start_server(Arc::new(store), None);
+    let trusted = [&default, &first, &second];
+    assert_eq!(
+        presented_certificate(address, "first.test", &trusted),
+        first.certificate
+    );
+    assert_eq!(
+        presented_certificate(address, "second.test", &trusted),
+        second.certificate
+    );
+    // Names without a certificate of their own fall back to the default.
+    assert_eq!(
+        presented_certificate(address, "default.test", &trusted),
+        default.certificate
+    );
+    let _ = fs::remove_file(&paths.0);
+    let _ = fs::remove_file(&paths.1);
+}
+
+#[test]
+fn rejected_at_load() {
+    let store = CertificateStore::new();
+    let localhost = certified("localhost");
+
+    let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+    params.not_before = date_time_ymd(2000, 1, 1);
+    params.not_after = date_time_ymd(2001, 1, 1);
+    let expired = certified_with(params);
+    let err = store
+        .add_pem(
+            None,
+            expired.certificate_pem.as_bytes(),
+            expired.key_pem.as_bytes(),
+        )
+        .unwrap_err();
+    assert!(matches!(err, TlsConfigError::Expired(_)));
+    assert_eq!(
+        err.to_string(),
+        "Certificate expired on Mon, 01 Jan 2001 00:00:00 GMT"
+    );
+
+    let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+    params.not_before = date_time_ymd(2090, 1, 1);
+    params.not_after = date_time_ymd(2091, 1, 1);
+    let early = certified_with(params);
+    assert!(matches!(
+        store.add_pem(
+            None,
+            early.certificate_pem.as_bytes(),
+            early.key_pem.as_bytes()
+        ),
+        Err(TlsConfigError::NotYetValid(_))
+    ));
+
+    let other = certified("localhost");
+    assert!(matches!(
+        store.add_pem(
+            None,
+            localhost.certificate_pem.as_bytes(),
+            other.key_pem.as_bytes()
+        ),
+        Err(TlsConfigError::KeyMismatch)
+    ));
+    assert!(matches!(
+        TlsConfig::from_pem(
+            localhost.certificate_pem.as_bytes(),
+            other.key_pem.as_bytes()
+        ),
+        Err(TlsConfigError::KeyMismatch)
+    ));
+    assert!(matches!(
+        store.add_pem(
+            Some("example.test"),
+            localhost.certificate_pem.as_bytes(),
+            localhost.key_pem.as_bytes()
+        ),
+        Err(TlsConfigError::NameMismatch(_))
+    ));
+    assert!(matches!(
+        store.add_pem(
+            Some("not a name"),
+            localhost.certificate_pem.as_bytes(),
+            localhost.key_pem.as_bytes()
+        ),
+        Err(TlsConfigError::InvalidServerName(_))
+    ));
+    assert!(matches!(
+        store.add_pem_files(None, "/nonexistent.crt", "/nonexistent.key"),
+        Err(TlsConfigError::ReadFailed(_, _))
+    ));
+    assert!(!store.has_default());
+    assert!(store.get_names().is_empty());
+}
+
+#[test]
+fn reload_on_change() {
+    let original = certified("localhost");
+    let renewed = certified("localhost");
+    let paths = pem_files("reload", &original);
+    let store = Arc::new(CertificateStore::new());
+    store.add_pem_files(None, &paths.0, &paths.1).unwrap();
+    let (address, watch) = start_server(Arc::clone(&store), Some(Duration::from_millis(20)));
+    assert_eq!(
+        presented_certificate(address, "localhost", &[&original]),
+        original.certificate
+    );
+
+    write_pem_files(&paths, &renewed);
+    wait_for_certificate(address, &[&original, &renewed], &renewed);
+
+    // A key that doesn't match keeps the server on the certificate it had.
+    fs::write(&paths.1, &original.key_pem).unwrap();
+    assert!(matches!(store.reload(), Err(TlsConfigError::KeyMismatch)));
+    assert_eq!(
+        presented_certificate(address, "localhost", &[&renewed]),
+        renewed.certificate
+    );
+
+    // Once stopped, the watch lets go of the store and changes are left alone.
+    let watched = Arc::strong_count(&store);
+    watch.unwrap().stop();
+    write_pem_files(&paths, &original);
+    let stopped_at = Instant::now();
+    while Arc::strong_count(&store) >= watched {
+        assert!(stopped_at.elapsed() < Duration::from_secs(1));
+        thread::sleep(Duration::from_millis(5));
+    }
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(
+        presented_certificate(address, "localhost", &[&renewed]),
+        renewed.certificate
+    );
+    let _ = fs::remove_file(&paths.0);
+    let _ = fs::remove_file(&paths.1);
+}
+
+#[test]
+fn reload_with_broken_pair() {
+    let original = certified("localhost");
+    let renewed = certified("localhost");
+    let other = certified("other.test");
+    let paths = pem_files("reload_good", &original);
+    let other_paths = pem_files("reload_broken", &other);
+    let store = Arc::new(CertificateStore::new());
+    store.add_pem_files(None, &paths.0, &paths.1).unwrap();
+    store
+        .add_pem_files(Some("other.test"), &other_paths.0, &other_paths.1)
+        .unwrap();
+    let (address, _) = start_server(Arc::clone(&store), None);
+
+    // A renewal is taken even while another certificate's files are broken. The pause lets the
+    // rewritten files get new modification times.
+    thread::sleep(Duration::from_millis(20));
+    write_pem_files(&paths, &renewed);
+    fs::write(&other_paths.1, &original.key_pem).unwrap();
+    assert!(matches!(
+        store.reload_if_modified(),
+        Err(TlsConfigError::KeyMismatch)
+    ));
+    assert_eq!(
+        presented_certificate(address, "localhost", &[&renewed]),
+        renewed.certificate
+    );
+    assert_eq!(
+        presented_certificate(address, "other.test", &[&other]),
+        other.certificate
+    );
+
+    // The broken files are tried again until they load, while the renewed ones are done.
+    assert!(matches!(
+        store.reload_if_modified(),
+        Err(TlsConfigError::KeyMismatch)
+    ));
+    fs::write(&other_paths.1, &other.key_pem).unwrap();
+    assert_eq!(store.reload_if_modified().unwrap(), 1);
+    assert_eq!(store.reload_if_modified().unwrap(), 0);
+    for path in [&paths.0, &paths.1, &other_paths.0, &other_paths.1] {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[test]
+fn reload_on_signal_received() {
+    let original = certified("localhost");
+    let renewed = certified("localhost");
+    let paths = pem_files("signal", &original);
+    let store = Arc::new(CertificateStore::new());
+    store.add_pem_files(None, &paths.0, &paths.1).unwrap();
+    reload_on_signal(libc::SIGUSR1).unwrap();
+    let (address, _) = start_server(store, Some(Duration::from_millis(20)));
+
+    // The new files keep the old modification times, so only the signal can bring them in.
+    let staged = (
+        paths.0.with_extension("crt.new"),
+        paths.1.with_extension("key.new"),
+    );
+    write_pem_files(&staged, &renewed);
+    for (path, staged) in [(&paths.0, &staged.0), (&paths.1, &staged.1)] {
+        let modified = fs::metadata(path).unwrap().modified().unwrap();
+        File::options()
+            .write(true)
+            .open(staged)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        fs::rename(staged, path).unwrap();
+    }
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(
+        presented_certificate(address, "localhost", &[&original]),
+        original.certificate
+    );
+
+    unsafe { libc::raise(libc::SIGUSR1) };
+    wait_for_certificate(address, &[&original, &renewed], &renewed);
+    let _ = fs::remove_file(&paths.0);
+    let _ = fs::remove_file(&paths.1);
+}
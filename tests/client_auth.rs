@@ -0,0 +1,266 @@
+#![cfg(feature = "tls")]
+
+use std::{
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use http_server::{
+    client_auth::{AuthorizeClient, ClientAuth, ClientAuthMode, ClientIdentity},
+    connection::Connection,
+    handler::Handler,
+    protocol::Protocol,
+    request::Request,
+    response::{Response, ResponseCode},
+    router::BaseRouter,
+    server::{HTTPServer, ListenerId},
+    socket::Socket,
+    tls::{TlsConfig, TlsConfigError},
+};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, SanType,
+};
+use rustls::{
+    ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+
+struct Authority {
+    certificate: Certificate,
+    key_pair: KeyPair,
+}
+
+struct ClientCertificate {
+    certificate: CertificateDer<'static>,
+    key_pem: String,
+}
+
+// Describes the identity the client proved, if any.
+struct WhoAmIHandler {}
+
+impl Handler for WhoAmIHandler {
+    fn handle(&mut self, connection: &mut Connection, _request: &Request) -> Response {
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        let content = connection
+            .get_client_identity()
+            .map_or_else(|| "anonymous".to_string(), |identity| describe(&identity));
+        response.set_content(Some(content));
+        response
+    }
+}
+
+fn describe(identity: &ClientIdentity) -> String {
+    format!(
+        "{}|{}|{}|{:?}|{}|{}",
+        identity.get_subject(),
+        identity.get_common_name().unwrap_or("-"),
+        identity.get_dns_names().join(" "),
+        identity.get_ip_addresses(),
+        identity.get_email_addresses().join(" "),
+        identity.get_uris().join(" ")
+    )
+}
+
+fn new_authority(name: &str) -> Authority {
+    let mut params = CertificateParams::new(Vec::new()).unwrap();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.distinguished_name = DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, name);
+    let key_pair = KeyPair::generate().unwrap();
+    Authority {
+        certificate: params.self_signed(&key_pair).unwrap(),
+        key_pair,
+    }
+}
+
+fn client_certificate(authority: &Authority, name: &str) -> ClientCertificate {
+    let mut params = CertificateParams::new(vec![format!("{}.internal", name)]).unwrap();
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::OrganizationName, "Example, Inc.");
+    params.distinguished_name.push(DnType::CommonName, name);
+    params.subject_alt_names.extend([
+        SanType::URI(format!("spiffe://example/{}", name).try_into().unwrap()),
+        SanType::IpAddress(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7))),
+        SanType::Rfc822Name(format!("{}@example.test", name).try_into().unwrap()),
+    ]);
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let key_pair = KeyPair::generate().unwrap();
+    let certificate = params
+        .signed_by(&key_pair, &authority.certificate, &authority.key_pair)
+        .unwrap();
+    ClientCertificate {
+        certificate: certificate.der().clone(),
+        key_pem: key_pair.serialize_pem(),
+    }
+}
+
+// Serves /whoami openly and /billing only to the billing service.
+fn start_server(
+    authority: &Authority,
+    mode: ClientAuthMode,
+) -> (SocketAddr, CertificateDer<'static>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let server_certificate = certified.cert.der().clone();
+    let mut tls = TlsConfig::from_pem(
+        certified.cert.pem().as_bytes(),
+        certified.key_pair.serialize_pem().as_bytes(),
+    )
+    .unwrap();
+    let client_auth = ClientAuth::from_pem(authority.certificate.pem().as_bytes(), mode).unwrap();
+    assert_eq!(client_auth.get_mode(), mode);
+    tls.set_client_auth(Some(&client_auth)).unwrap();
+
+    let socket = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+    let address = socket.get_local_address().unwrap();
+    thread::spawn(move || {
+        let mut router = BaseRouter::new();
+        router.register_handler_from_path(WhoAmIHandler {}, "/whoami");
+        router.register_handler_from_path(
+            AuthorizeClient::new(
+                WhoAmIHandler {},
+                |identity: &ClientIdentity, _: &Request| {
+                    identity
+                        .get_uris()
+                        .iter()
+                        .any(|uri| uri == "spiffe://example/billing")
+                },
+            ),
+            "/billing",
+        );
+        let mut server = HTTPServer::new(socket, router);
+        server.set_tls(ListenerId::default(), Some(tls));
+        server.run();
+    });
+    (address, server_certificate)
+}
+
+// Returns the response, or nothing if the server ended the connection instead.
+fn request(
+    address: SocketAddr,
+    server_certificate: &CertificateDer<'static>,
+    client: Option<&ClientCertificate>,
+    path: &str,
+) -> Option<String> {
+    let mut roots = RootCertStore::empty();
+    roots.add(server_certificate.clone()).unwrap();
+    let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+    let config = match client {
+        Some(client) => builder
+            .with_client_auth_cert(
+                vec![client.certificate.clone()],
+                PrivateKeyDer::from_pem_slice(client.key_pem.as_bytes()).unwrap(),
+            )
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+    let session = ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+    let stream = (0..50)
+        .find_map(|_| {
+            TcpStream::connect(address)
+                .inspect_err(|_| thread::sleep(Duration::from_millis(20)))
+                .ok()
+        })
+        .expect("Server never accepted a connection.");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut stream = StreamOwned::new(session, stream);
+    stream
+        .write_all(
+            format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                path
+            )
+            .as_bytes(),
+        )
+        .ok()?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).ok()?;
+    (!response.is_empty()).then(|| String::from_utf8(response).unwrap())
+}
+
+fn body(response: &str) -> &str {
+    response.split_once("\r\n\r\n").unwrap().1
+}
+
+#[test]
+fn required() {
+    let authority = new_authority("Example Services CA");
+    let billing = client_certificate(&authority, "billing");
+    let (address, server_certificate) = start_server(&authority, ClientAuthMode::Required);
+
+    let response = request(address, &server_certificate, Some(&billing), "/whoami").unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert_eq!(
+        body(&response),
+        "CN=billing,O=Example\\, Inc.|billing|billing.internal|[10.0.0.7]|\
+         billing@example.test|spiffe://example/billing"
+    );
+    let response = request(address, &server_certificate, Some(&billing), "/billing").unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+    // Without a certificate, or with one from another CA, the handshake fails.
+    assert_eq!(request(address, &server_certificate, None, "/whoami"), None);
+    let stranger = client_certificate(&new_authority("Other CA"), "billing");
+    assert_eq!(
+        request(address, &server_certificate, Some(&stranger), "/whoami"),
+        None
+    );
+}
+
+#[test]
+fn optional() {
+    let authority = new_authority("Example Services CA");
+    let billing = client_certificate(&authority, "billing");
+    let reporting = client_certificate(&authority, "reporting");
+    let (address, server_certificate) = start_server(&authority, ClientAuthMode::Optional);
+
+    let response = request(address, &server_certificate, None, "/whoami").unwrap();
+    assert_eq!(body(&response), "anonymous");
+    let response = request(address, &server_certificate, None, "/billing").unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 403 Forbidden"),
+        "{}",
+        response
+    );
+
+    let response = request(address, &server_certificate, Some(&reporting), "/whoami").unwrap();
+    assert!(body(&response).starts_with("CN=reporting,"), "{}", response);
+    let response = request(address, &server_certificate, Some(&reporting), "/billing").unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 403 Forbidden"),
+        "{}",
+        response
+    );
+    let response = request(address, &server_certificate, Some(&billing), "/billing").unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+    // A certificate the CA didn't issue is still refused.
+    let stranger = client_certificate(&new_authority("Other CA"), "billing");
+    assert_eq!(
+        request(address, &server_certificate, Some(&stranger), "/whoami"),
+        None
+    );
+}
+
+#[test]
+fn invalid_ca_bundle() {
+    assert!(matches!(
+        ClientAuth::from_pem(b"", ClientAuthMode::Required),
+        Err(TlsConfigError::NoCertificates)
+    ));
+    assert!(matches!(
+        ClientAuth::from_pem_file("/nonexistent.pem", ClientAuthMode::Optional),
+        Err(TlsConfigError::ReadFailed(_, _))
+    ));
+}
@@ -0,0 +1,86 @@
+#![allow(dead_code)]
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, Shutdown, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use http_server::{
+    connection::Connection,
+    handler::Handler,
+    protocol::Protocol,
+    request::Request,
+    response::{Response, ResponseCode},
+    router::BaseRouter,
+    server::HTTPServer,
+    socket::Socket,
+};
+
+pub struct OkHandler {}
+
+impl Handler for OkHandler {
+    fn handle(&mut self, _connection: &mut Connection, _request: &Request) -> Response {
+        Response::new(ResponseCode::Ok, Protocol::Http1_1)
+    }
+}
+
+pub fn port() -> u16 {
+    20_000 + (std::process::id() % 20_000) as u16
+}
+
+// Runs a server on `port()` from a background thread, with `OkHandler` at `/ok` next to whatever
+// `routes` adds, once `configure` has set it up.
+pub fn serve(
+    routes: impl FnOnce(&mut BaseRouter) + Send + 'static,
+    configure: impl FnOnce(&mut HTTPServer) + Send + 'static,
+) {
+    thread::spawn(|| {
+        let mut router = BaseRouter::new();
+        router.register_handler_from_path(OkHandler {}, "/ok");
+        routes(&mut router);
+        let mut server = HTTPServer::new(
+            Socket::new(port(), Ipv4Addr::new(127, 0, 0, 1)).unwrap(),
+            router,
+        );
+        configure(&mut server);
+        server.run();
+    });
+}
+
+pub fn connect() -> TcpStream {
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect((Ipv4Addr::new(127, 0, 0, 1), port())) {
+            return stream;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("Server never accepted a connection.");
+}
+
+pub fn send(raw: &[u8]) -> Vec<u8> {
+    let mut stream = connect();
+    stream.write_all(raw).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    response
+}
+
+pub fn split_response(response: &[u8]) -> (String, Vec<u8>) {
+    let split = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .expect("Response has no header terminator.");
+    (
+        String::from_utf8_lossy(&response[..split + 2]).to_string(),
+        response[split + 4..].to_vec(),
+    )
+}
+
+// The status line of the response to `raw`.
+pub fn status(raw: &[u8]) -> String {
+    let response = String::from_utf8_lossy(&send(raw)).to_string();
+    response.lines().next().unwrap_or_default().to_string()
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::Shutdown,
+    thread,
+    time::Duration,
+};
+
+use http_server::limits::{ConnectionLimits, OverloadPolicy};
+
+#[test]
+fn connection_limits() {
+    common::serve(
+        |_| {},
+        |server| {
+            let mut limits = ConnectionLimits::new();
+            limits.set_max_connections(Some(2));
+            limits.set_overload_policy(OverloadPolicy::Reject);
+            server.set_connection_limits(limits);
+        },
+    );
+
+    let request = b"GET /ok HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    assert_eq!(common::status(request), "HTTP/1.1 200 OK");
+    thread::sleep(Duration::from_millis(100));
+
+    let mut first = common::connect();
+    let second = common::connect();
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(common::status(request), "HTTP/1.1 503 Service Unavailable");
+
+    first.write_all(request).unwrap();
+    first.shutdown(Shutdown::Write).unwrap();
+    let mut response = String::new();
+    first.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    drop(first);
+    drop(second);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(common::status(request), "HTTP/1.1 200 OK");
+}
@@ -0,0 +1,211 @@
+use std::{
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use http_server::{
+    connection::Connection,
+    handler::Handler,
+    handoff::receive_sockets,
+    protocol::Protocol,
+    request::Request,
+    response::{Response, ResponseCode},
+    router::BaseRouter,
+    server::{HTTPServer, HTTPServerRunError},
+    socket::Socket,
+};
+
+// Set in the child process the re-exec test starts, to the control socket's path.
+const CHILD_VARIABLE: &str = "HTTP_SERVER_HANDOFF_CHILD";
+// Printed by the child once it owns the listeners.
+const TAKEN_OVER: &str = "handoff child took over";
+
+struct NameHandler {
+    name: &'static str,
+}
+
+impl Handler for NameHandler {
+    fn handle(&mut self, _connection: &mut Connection, _request: &Request) -> Response {
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response.set_content(Some(self.name.to_string()));
+        response
+    }
+}
+
+struct PidHandler {}
+
+impl Handler for PidHandler {
+    fn handle(&mut self, _connection: &mut Connection, _request: &Request) -> Response {
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response.set_content(Some(std::process::id().to_string()));
+        response
+    }
+}
+
+// Stops the child server however the test ends.
+struct ChildGuard(Child);
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn router(name: &'static str) -> BaseRouter {
+    let mut router = BaseRouter::new();
+    router.register_handler_from_path(NameHandler { name }, "/name");
+    router
+}
+
+const REQUEST: &[u8] = b"GET /name HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+fn read_response(stream: &mut impl Read) -> String {
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    String::from_utf8(response).unwrap()
+}
+
+fn body(response: &str) -> &str {
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    response.rsplit("\r\n").next().unwrap()
+}
+
+fn request_tcp(address: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(REQUEST).unwrap();
+    body(&read_response(&mut stream)).to_string()
+}
+
+#[test]
+fn handoff() {
+    let id = std::process::id();
+    let control_path = PathBuf::from(format!("/tmp/http_server_handoff_{}.control", id));
+    let unix_path = PathBuf::from(format!("/tmp/http_server_handoff_{}.sock", id));
+    let tcp = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+    let address = tcp.get_local_address().unwrap();
+    let unix = Socket::new_unix(&unix_path, None).unwrap();
+
+    let (old_sender, old_result) = mpsc::channel();
+    let old_control_path = control_path.clone();
+    thread::spawn(move || {
+        let mut server = HTTPServer::new(tcp, router("old"));
+        server.add_listener(unix, None);
+        server.enable_handoff(&old_control_path).unwrap();
+        old_sender.send(server.run()).unwrap();
+    });
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(request_tcp(address), "old");
+
+    // A request that is still arriving when the handoff happens is finished by the old server.
+    let mut in_flight = TcpStream::connect(address).unwrap();
+    in_flight
+        .write_all(b"GET /name HTTP/1.1\r\nHost: localhost\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    let sockets = receive_sockets(&control_path).unwrap();
+    assert_eq!(sockets.len(), 2);
+    // Until the new server runs, connections wait in the backlog rather than being refused.
+    let mut queued = TcpStream::connect(address).unwrap();
+    queued.write_all(REQUEST).unwrap();
+    thread::spawn(move || {
+        let mut sockets = sockets.into_iter();
+        let mut server = HTTPServer::new(sockets.next().unwrap(), router("new"));
+        server.add_listener(sockets.next().unwrap(), None);
+        server.run();
+    });
+
+    in_flight.write_all(b"\r\n").unwrap();
+    let response = read_response(&mut in_flight);
+    drop(in_flight);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+    assert_eq!(body(&response), "old");
+    assert!(matches!(
+        old_result.recv_timeout(Duration::from_secs(5)),
+        Ok(HTTPServerRunError::HandedOff)
+    ));
+
+    assert_eq!(body(&read_response(&mut queued)), "new");
+    assert_eq!(request_tcp(address), "new");
+    // The old server leaves the socket file to its successor.
+    let mut stream = UnixStream::connect(&unix_path).unwrap();
+    stream.write_all(REQUEST).unwrap();
+    assert_eq!(body(&read_response(&mut stream)), "new");
+    assert!(!control_path.exists());
+    let _ = std::fs::remove_file(&unix_path);
+}
+
+// Upgrades to a new process, the way a real deployment would: the test binary runs again, and
+// only gets the listener through the control socket.
+#[test]
+fn handoff_exec() {
+    let control_path = PathBuf::from(format!(
+        "/tmp/http_server_handoff_exec_{}.control",
+        std::process::id()
+    ));
+    let tcp = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+    let address = tcp.get_local_address().unwrap();
+
+    let (old_sender, old_result) = mpsc::channel();
+    let old_control_path = control_path.clone();
+    thread::spawn(move || {
+        let mut server = HTTPServer::new(tcp, router("old"));
+        server.enable_handoff(&old_control_path).unwrap();
+        old_sender.send(server.run()).unwrap();
+    });
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(request_tcp(address), "old");
+
+    let mut in_flight = TcpStream::connect(address).unwrap();
+    in_flight
+        .write_all(b"GET /name HTTP/1.1\r\nHost: localhost\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    let mut child = ChildGuard(
+        Command::new(env::current_exe().unwrap())
+            .args(["--exact", "handoff_child", "--nocapture"])
+            .env(CHILD_VARIABLE, &control_path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    // The test harness prints the test's name on the same line.
+    let mut lines = BufReader::new(child.0.stdout.take().unwrap()).lines();
+    assert!(lines.any(|line| line.unwrap().ends_with(TAKEN_OVER)));
+
+    // The old process finishes what it had, then stops.
+    in_flight.write_all(b"\r\n").unwrap();
+    let response = read_response(&mut in_flight);
+    assert!(response.contains("Connection: close\r\n"), "{}", response);
+    assert_eq!(body(&response), "old");
+    assert!(matches!(
+        old_result.recv_timeout(Duration::from_secs(5)),
+        Ok(HTTPServerRunError::HandedOff)
+    ));
+
+    assert_eq!(request_tcp(address), child.0.id().to_string());
+    assert!(!control_path.exists());
+}
+
+#[test]
+fn handoff_child() {
+    let Some(control_path) = env::var_os(CHILD_VARIABLE) else {
+        return;
+    };
+    let mut sockets = receive_sockets(control_path).unwrap();
+    assert_eq!(sockets.len(), 1);
+    println!("{}", TAKEN_OVER);
+    let mut router = BaseRouter::new();
+    router.register_handler_from_path(PidHandler {}, "/name");
+    HTTPServer::new(sockets.remove(0), router).run();
+}
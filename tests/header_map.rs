@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+
+use http_server::{header::Header, header_map::HeaderMap};
+
+#[test]
+fn header_map() {
+    let mut headers = HeaderMap::new();
+    headers.append(Header::SetCookie, "a=1".to_string());
+    headers.append(Header::Other("X-Trace".to_string()), "one".to_string());
+    headers.append(Header::SetCookie, "b=2".to_string());
+    headers.append(Header::Accept, "text/html, \"a,b\"".to_string());
+    headers.append(Header::Accept, "application/json".to_string());
+
+    assert_eq!(
+        headers.get_all(&Header::SetCookie).collect::<Vec<_>>(),
+        ["a=1", "b=2"]
+    );
+    assert_eq!(
+        headers.get(&Header::Other("x-trace".to_string())),
+        Some(&"one".to_string())
+    );
+    assert_eq!(
+        headers.get_combined(&Header::Accept).unwrap(),
+        "text/html, \"a,b\", application/json"
+    );
+    assert_eq!(
+        headers.get_list(&Header::Accept),
+        ["text/html", "\"a,b\"", "application/json"]
+    );
+    assert_eq!(headers.get_combined(&Header::SetCookie).unwrap(), "a=1");
+
+    assert_eq!(
+        headers.insert(Header::SetCookie, "c=3".to_string()),
+        Some("a=1".to_string())
+    );
+    let order: Vec<(&str, &str)> = headers
+        .iter()
+        .map(|(header, value)| (header.as_str(), value.as_str()))
+        .collect();
+    assert_eq!(
+        order,
+        [
+            ("Set-Cookie", "c=3"),
+            ("X-Trace", "one"),
+            ("Accept", "text/html, \"a,b\""),
+            ("Accept", "application/json"),
+        ]
+    );
+
+    assert_eq!(
+        headers.remove(&Header::Accept),
+        Some("text/html, \"a,b\"".to_string())
+    );
+    assert_eq!(headers.len(), 2);
+}
+
+#[test]
+fn header_identity() {
+    let mixed = Header::from("X-Request-Id");
+    let lower = Header::from("x-request-id");
+    assert_eq!(mixed, lower);
+    assert_eq!(mixed.as_str(), "X-Request-Id");
+    assert_eq!(Header::from("WWW-authenticate"), Header::WWWAuthenticate);
+    assert_eq!(
+        Header::Other("cache-control".to_string()),
+        Header::CacheControl
+    );
+
+    let set: HashSet<Header> = [mixed, lower, Header::from("Transfer-Encoding")]
+        .into_iter()
+        .collect();
+    assert_eq!(set.len(), 2);
+    assert!(set.contains(&Header::TransferEncoding));
+}
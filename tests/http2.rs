@@ -0,0 +1,613 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use http_server::{
+    connection::Connection,
+    handler::Handler,
+    header::Header,
+    hpack::{Decoder, Encoder},
+    http2::{ErrorCode, PREFACE},
+    limits::RequestLimits,
+    protocol::Protocol,
+    request::Request,
+    response::{Response, ResponseCode},
+    router::BaseRouter,
+    server::HTTPServer,
+    socket::Socket,
+    sse::EventSource,
+    static_files::StaticFileHandler,
+};
+
+const LARGE_BODY_SIZE: usize = 200_000;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+
+// Describes the request as the handler saw it.
+struct EchoHandler {}
+
+impl Handler for EchoHandler {
+    fn handle(&mut self, _connection: &mut Connection, request: &Request) -> Response {
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response
+            .get_headers_mut()
+            .insert(Header::Connection, "keep-alive".to_string());
+        response.set_content(Some(format!(
+            "{} {} {} {} {} {}",
+            request.get_method().as_str(),
+            request.get_target(),
+            request.get_protocol().as_str(),
+            request.get_headers().get(&Header::Host).unwrap(),
+            request
+                .get_headers()
+                .get(&Header::Cookie)
+                .map_or("-", String::as_str),
+            String::from_utf8_lossy(request.get_body())
+        )));
+        response
+    }
+}
+
+struct LargeHandler {}
+
+impl Handler for LargeHandler {
+    fn handle(&mut self, _connection: &mut Connection, _request: &Request) -> Response {
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response.set_content(Some("x".repeat(LARGE_BODY_SIZE)));
+        response
+    }
+}
+
+// Takes its time, so the frames behind its request pile up unread.
+struct SlowHandler {}
+
+impl Handler for SlowHandler {
+    fn handle(&mut self, _connection: &mut Connection, _request: &Request) -> Response {
+        thread::sleep(Duration::from_millis(200));
+        Response::new(ResponseCode::Ok, Protocol::Http1_1)
+    }
+}
+
+#[derive(Debug)]
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct StreamResponse {
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    reset: Option<u32>,
+}
+
+// Just enough of an HTTP/2 client to drive the server frame by frame.
+struct Client {
+    stream: TcpStream,
+    encoder: Encoder,
+    decoder: Decoder,
+    // Whether DATA is acknowledged with WINDOW_UPDATEs as it arrives.
+    replenish: bool,
+    // The connection window the server has granted beyond the initial one.
+    credit: u64,
+}
+
+impl Client {
+    fn connect(address: SocketAddr, settings: &[(u16, u32)]) -> Self {
+        let stream = (0..50)
+            .find_map(|_| {
+                TcpStream::connect(address)
+                    .inspect_err(|_| thread::sleep(Duration::from_millis(20)))
+                    .ok()
+            })
+            .expect("Server never accepted a connection.");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut client = Self {
+            stream,
+            encoder: Encoder::new(),
+            decoder: Decoder::new(),
+            replenish: true,
+            credit: 0,
+        };
+        client.stream.write_all(PREFACE).unwrap();
+        let payload: Vec<u8> = settings
+            .iter()
+            .flat_map(|(id, value)| [&id.to_be_bytes()[..], &value.to_be_bytes()].concat())
+            .collect();
+        client.send(SETTINGS, 0, 0, &payload);
+        // The server speaks first with its own settings.
+        let settings = client.receive().unwrap();
+        assert_eq!((settings.kind, settings.flags), (SETTINGS, 0));
+        client
+    }
+
+    fn send(&mut self, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend([kind, flags]);
+        frame.extend(stream_id.to_be_bytes());
+        frame.extend(payload);
+        self.stream.write_all(&frame).unwrap();
+    }
+
+    fn send_headers(&mut self, stream_id: u32, fields: &[(&str, &str)], end_stream: bool) {
+        let block = self.encoder.encode(
+            fields
+                .iter()
+                .map(|(name, value)| (name.as_bytes(), value.as_bytes())),
+        );
+        let flags = END_HEADERS | if end_stream { END_STREAM } else { 0 };
+        self.send(HEADERS, flags, stream_id, &block);
+    }
+
+    fn get(&mut self, stream_id: u32, path: &str) {
+        self.send_headers(
+            stream_id,
+            &[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":authority", "localhost"),
+                (":path", path),
+            ],
+            true,
+        );
+    }
+
+    // None once the server has closed the connection.
+    fn receive(&mut self) -> Option<Frame> {
+        let mut header = [0; 9];
+        self.stream.read_exact(&mut header).ok()?;
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let mut payload = vec![0; length];
+        self.stream.read_exact(&mut payload).unwrap();
+        Some(Frame {
+            kind: header[3],
+            flags: header[4],
+            stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]]),
+            payload,
+        })
+    }
+
+    // Collects frames until each of the streams has ended, answering what the server asks of a
+    // client along the way. Returns the GOAWAY error code too, if the server sent one.
+    fn responses(&mut self, streams: &[u32]) -> (HashMap<u32, StreamResponse>, Option<u32>) {
+        let mut responses: HashMap<u32, StreamResponse> = HashMap::new();
+        let mut open: Vec<u32> = streams.to_vec();
+        let mut go_away = None;
+        while !open.is_empty() {
+            let Some(frame) = self.receive() else {
+                break;
+            };
+            let response = responses.entry(frame.stream_id).or_default();
+            match frame.kind {
+                HEADERS | CONTINUATION => {
+                    for (name, value) in self.decoder.decode(&frame.payload).unwrap() {
+                        response.headers.push((
+                            String::from_utf8(name).unwrap(),
+                            String::from_utf8(value).unwrap(),
+                        ));
+                    }
+                }
+                DATA => {
+                    response.body.extend(&frame.payload);
+                    if self.replenish && !frame.payload.is_empty() {
+                        let increment = (frame.payload.len() as u32).to_be_bytes();
+                        self.send(WINDOW_UPDATE, 0, 0, &increment);
+                        if frame.flags & END_STREAM == 0 {
+                            self.send(WINDOW_UPDATE, 0, frame.stream_id, &increment);
+                        }
+                    }
+                }
+                RST_STREAM => {
+                    response.reset =
+                        Some(u32::from_be_bytes(frame.payload[..4].try_into().unwrap()));
+                    open.retain(|id| *id != frame.stream_id);
+                }
+                SETTINGS if frame.flags & ACK == 0 => self.send(SETTINGS, ACK, 0, &[]),
+                WINDOW_UPDATE if frame.stream_id == 0 => {
+                    self.credit +=
+                        u64::from(u32::from_be_bytes(frame.payload[..4].try_into().unwrap()));
+                }
+                GOAWAY => {
+                    go_away = Some(u32::from_be_bytes(frame.payload[4..8].try_into().unwrap()));
+                }
+                _ => {}
+            }
+            if frame.flags & END_STREAM != 0 && matches!(frame.kind, HEADERS | DATA) {
+                open.retain(|id| *id != frame.stream_id);
+            }
+        }
+        (responses, go_away)
+    }
+}
+
+impl StreamResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn artifact() -> Vec<u8> {
+    (0..LARGE_BODY_SIZE as u32)
+        .map(|i| (i % 251) as u8)
+        .collect()
+}
+
+fn start_server() -> SocketAddr {
+    let socket = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+    let address = socket.get_local_address().unwrap();
+    thread::spawn(move || {
+        let mut router = BaseRouter::new();
+        router.register_handler_from_path(EchoHandler {}, "/echo");
+        router.register_handler_from_path(LargeHandler {}, "/large");
+        router.register_handler_from_path(SlowHandler {}, "/slow");
+        let root = std::env::temp_dir().join(format!("http_server_h2_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("artifact.bin"), artifact()).unwrap();
+        router.register_handler_from_path(StaticFileHandler::new(root).unwrap(), "/files/{*path}");
+        // Greets each client, then sends one more event from another thread and ends the stream.
+        let events = EventSource::new(|_, stream| {
+            stream.send_data("hello");
+            let stream = stream.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                stream.send_data("later");
+                stream.close();
+            });
+            true
+        });
+        router.register_handler_from_path(events, "/events");
+        let mut server = HTTPServer::new(socket, router);
+        let mut limits = RequestLimits::new();
+        limits.set_max_body_size(1024);
+        server.set_request_limits(limits);
+        server.run();
+    });
+    address
+}
+
+#[test]
+fn prior_knowledge() {
+    let address = start_server();
+    let mut client = Client::connect(address, &[]);
+    client.get(1, "/echo?a=1");
+    client.send_headers(
+        3,
+        &[
+            (":method", "POST"),
+            (":scheme", "http"),
+            (":authority", "example.test"),
+            (":path", "/echo"),
+            ("cookie", "a=1"),
+            ("cookie", "b=2"),
+            ("content-length", "5"),
+        ],
+        false,
+    );
+    client.send(DATA, 0, 3, b"hel");
+    client.send(DATA, END_STREAM, 3, b"lo");
+    client.send_headers(
+        5,
+        &[
+            (":method", "HEAD"),
+            (":scheme", "http"),
+            (":authority", "localhost"),
+            (":path", "/large"),
+        ],
+        true,
+    );
+    client.get(7, "/missing");
+    client.send(PING, 0, 0, b"12345678");
+
+    let (responses, go_away) = client.responses(&[1, 3, 5, 7]);
+    assert_eq!(go_away, None);
+    assert_eq!(responses[&1].header(":status"), Some("200"));
+    assert_eq!(
+        String::from_utf8_lossy(&responses[&1].body),
+        "GET /echo?a=1 HTTP/2 localhost - "
+    );
+    // Fields that only make sense on an HTTP/1 connection are left out.
+    assert_eq!(responses[&1].header("connection"), None);
+    assert_eq!(
+        String::from_utf8_lossy(&responses[&3].body),
+        "POST /echo HTTP/2 example.test a=1; b=2 hello"
+    );
+    assert_eq!(
+        responses[&5].header("content-length"),
+        Some(LARGE_BODY_SIZE.to_string().as_str())
+    );
+    assert!(responses[&5].body.is_empty());
+    assert_eq!(responses[&7].header(":status"), Some("404"));
+
+    // HTTP/1.1 clients are still served on the same listener.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(
+        response.ends_with("GET /echo HTTP/1.1 localhost - "),
+        "{}",
+        response
+    );
+}
+
+#[test]
+fn flow_control() {
+    let address = start_server();
+    // SETTINGS_INITIAL_WINDOW_SIZE of 1000 for every stream.
+    let mut client = Client::connect(address, &[(0x4, 1000)]);
+    client.replenish = false;
+    client.get(1, "/large");
+    client.get(3, "/echo");
+
+    // The other stream isn't held up by the one waiting for its window.
+    let (responses, _) = client.responses(&[3]);
+    assert_eq!(responses[&3].header(":status"), Some("200"));
+    assert!(
+        responses
+            .get(&1)
+            .is_none_or(|response| response.body.len() <= 1000)
+    );
+
+    client.replenish = true;
+    client.send(WINDOW_UPDATE, 0, 1, &(LARGE_BODY_SIZE as u32).to_be_bytes());
+    client.send(WINDOW_UPDATE, 0, 0, &(LARGE_BODY_SIZE as u32).to_be_bytes());
+    let (rest, _) = client.responses(&[1]);
+    let received =
+        responses.get(&1).map_or(0, |response| response.body.len()) + rest[&1].body.len();
+    assert_eq!(received, LARGE_BODY_SIZE);
+}
+
+#[test]
+fn open_windows() {
+    let address = start_server();
+    let mut client = Client::connect(address, &[(0x4, 0x7fff_ffff)]);
+    client.replenish = false;
+    client.send(
+        WINDOW_UPDATE,
+        0,
+        0,
+        &(0x7fff_ffff - 65_535u32).to_be_bytes(),
+    );
+    // With nothing to hold them back, the bodies are still read in a little at a time as the
+    // connection takes them.
+    client.get(1, "/files/artifact.bin");
+    client.get(3, "/files/artifact.bin");
+    let (responses, _) = client.responses(&[1, 3]);
+    assert!(responses[&1].body == artifact());
+    assert!(responses[&3].body == artifact());
+}
+
+#[test]
+fn receive_windows() {
+    let address = start_server();
+    let post = |client: &mut Client, stream_id| {
+        client.send_headers(
+            stream_id,
+            &[
+                (":method", "POST"),
+                (":scheme", "http"),
+                (":authority", "localhost"),
+                (":path", "/echo"),
+            ],
+            false,
+        );
+    };
+
+    // Connection credit for a body comes back once the request is handed on, not before.
+    let mut client = Client::connect(address, &[]);
+    post(&mut client, 1);
+    client.send(DATA, 0, 1, &[b'x'; 1000]);
+    client.get(3, "/echo");
+    let (responses, _) = client.responses(&[3]);
+    assert_eq!(responses[&3].header(":status"), Some("200"));
+    // The window is opened to one full body past the initial one.
+    assert_eq!(client.credit, 1024);
+    client.send(DATA, END_STREAM, 1, &[]);
+    let (responses, _) = client.responses(&[1]);
+    assert_eq!(responses[&1].header(":status"), Some("200"));
+    assert_eq!(client.credit, 2024);
+
+    // Unfinished bodies spread over many streams may not buffer past the connection window.
+    let mut client = Client::connect(address, &[]);
+    for stream_id in (1..=133).step_by(2) {
+        post(&mut client, stream_id);
+        client.send(DATA, 0, stream_id, &[b'x'; 1000]);
+    }
+    let (_, go_away) = client.responses(&[1]);
+    assert_eq!(go_away, Some(ErrorCode::FlowControlError as u32));
+    assert!(client.receive().is_none());
+
+    // A stream turned down gets no more window, so one that keeps sending is reset on its own.
+    let mut client = Client::connect(address, &[]);
+    client.get(1, "/slow");
+    client.send_headers(
+        3,
+        &[(":method", "BREW"), (":scheme", "http"), (":path", "/echo")],
+        false,
+    );
+    for _ in 0..4 {
+        client.send(DATA, 0, 3, &[b'x'; 16_384]);
+    }
+    client.get(5, "/echo");
+    let (responses, go_away) = client.responses(&[1, 3, 5]);
+    assert_eq!(go_away, None);
+    assert_eq!(responses[&1].header(":status"), Some("200"));
+    assert_eq!(
+        responses[&3].reset,
+        Some(ErrorCode::FlowControlError as u32)
+    );
+    assert_eq!(responses[&5].header(":status"), Some("200"));
+}
+
+#[test]
+fn stream_errors() {
+    let address = start_server();
+    let mut client = Client::connect(address, &[]);
+    // Uppercase names are malformed in HTTP/2.
+    client.send_headers(
+        1,
+        &[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/echo"),
+            ("X-Upper", "1"),
+        ],
+        true,
+    );
+    // A declared length the body doesn't match.
+    client.send_headers(
+        3,
+        &[
+            (":method", "POST"),
+            (":scheme", "http"),
+            (":path", "/echo"),
+            ("content-length", "10"),
+        ],
+        false,
+    );
+    client.send(DATA, END_STREAM, 3, b"short");
+    // Requests past the limits get the status HTTP/1 clients would.
+    client.send_headers(
+        5,
+        &[(":method", "POST"), (":scheme", "http"), (":path", "/echo")],
+        false,
+    );
+    client.send(DATA, END_STREAM, 5, &[b'x'; 2048]);
+    client.send_headers(
+        7,
+        &[(":method", "BREW"), (":scheme", "http"), (":path", "/echo")],
+        true,
+    );
+    client.get(9, "/echo");
+
+    let (responses, go_away) = client.responses(&[1, 3, 5, 7, 9]);
+    assert_eq!(go_away, None);
+    assert_eq!(responses[&1].reset, Some(ErrorCode::ProtocolError as u32));
+    assert_eq!(responses[&3].reset, Some(ErrorCode::ProtocolError as u32));
+    assert_eq!(responses[&5].header(":status"), Some("413"));
+    assert_eq!(responses[&7].header(":status"), Some("501"));
+    assert_eq!(responses[&9].header(":status"), Some("200"));
+}
+
+// Far more frames than one wakeup reads are all answered, over several.
+#[test]
+fn frame_flood() {
+    const PINGS: usize = 5000;
+    let address = start_server();
+    let mut client = Client::connect(address, &[]);
+    let mut pings = Vec::new();
+    for index in 0..PINGS as u64 {
+        pings.extend([0, 0, 8, PING, 0, 0, 0, 0, 0]);
+        pings.extend(index.to_be_bytes());
+    }
+    let mut writer = client.stream.try_clone().unwrap();
+    let sender = thread::spawn(move || writer.write_all(&pings).unwrap());
+
+    let mut answered = 0;
+    while answered < PINGS {
+        let frame = client.receive().unwrap();
+        if frame.kind == PING {
+            assert_eq!(frame.flags, ACK);
+            assert_eq!(frame.payload, (answered as u64).to_be_bytes());
+            answered += 1;
+        }
+    }
+    sender.join().unwrap();
+    client.get(1, "/echo");
+    let (responses, _) = client.responses(&[1]);
+    assert_eq!(responses[&1].header(":status"), Some("200"));
+}
+
+#[test]
+fn connection_errors() {
+    let address = start_server();
+    let mut client = Client::connect(address, &[]);
+    client.get(1, "/echo");
+    let (responses, _) = client.responses(&[1]);
+    assert_eq!(responses[&1].header(":status"), Some("200"));
+    // Streams the client opens have odd numbers that only go up.
+    client.get(1, "/echo");
+    let (_, go_away) = client.responses(&[3]);
+    assert_eq!(go_away, Some(ErrorCode::StreamClosed as u32));
+    assert!(client.receive().is_none());
+
+    let mut client = Client::connect(address, &[]);
+    client.send(DATA, 0, 0, b"data");
+    let (_, go_away) = client.responses(&[1]);
+    assert_eq!(go_away, Some(ErrorCode::ProtocolError as u32));
+
+    // Without the preface, the connection is HTTP/1 and PRI is just an unknown method.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"PRI * HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 501"), "{}", response);
+}
+
+#[test]
+fn event_stream() {
+    let address = start_server();
+    let mut client = Client::connect(address, &[]);
+    client.get(1, "/events");
+    client.get(3, "/echo");
+
+    // The event stream stays open while other streams are answered, until the server ends it.
+    let (responses, go_away) = client.responses(&[3]);
+    assert_eq!(go_away, None);
+    assert_eq!(responses[&3].header(":status"), Some("200"));
+    assert_eq!(responses[&1].header(":status"), Some("200"));
+    assert_eq!(
+        responses[&1].header("content-type"),
+        Some("text/event-stream")
+    );
+    assert_eq!(responses[&1].header("transfer-encoding"), None);
+    assert_eq!(responses[&1].header("content-length"), None);
+    assert_eq!(
+        String::from_utf8_lossy(&responses[&1].body),
+        "data: hello\n\n"
+    );
+
+    let (responses, _) = client.responses(&[1]);
+    assert_eq!(
+        String::from_utf8_lossy(&responses[&1].body),
+        "data: later\n\n"
+    );
+}
+
+#[test]
+fn file_body() {
+    let address = start_server();
+    let mut client = Client::connect(address, &[]);
+    client.get(1, "/files/artifact.bin");
+    let (responses, _) = client.responses(&[1]);
+    assert_eq!(responses[&1].header(":status"), Some("200"));
+    assert_eq!(
+        responses[&1].header("content-length"),
+        Some(LARGE_BODY_SIZE.to_string().as_str())
+    );
+    assert!(responses[&1].body == artifact());
+}
@@ -0,0 +1,87 @@
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use http_server::{
+    connection::Connection,
+    handler::Handler,
+    protocol::Protocol,
+    request::Request,
+    response::{Response, ResponseCode},
+    router::BaseRouter,
+    server::HTTPServer,
+    socket::Socket,
+};
+
+struct PeerHandler {}
+
+impl Handler for PeerHandler {
+    fn handle(&mut self, connection: &mut Connection, _request: &Request) -> Response {
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response.set_content(Some(connection.get_peer_ip().unwrap().to_string()));
+        response
+    }
+}
+
+// Starts a server on an already bound socket and returns the port the kernel picked for it.
+fn serve(socket: Socket) -> u16 {
+    let port = socket.get_local_address().unwrap().port();
+    thread::spawn(move || {
+        let mut router = BaseRouter::new();
+        router.register_handler_from_path(PeerHandler {}, "/peer");
+        HTTPServer::new(socket, router).run();
+    });
+    port
+}
+
+fn peer_seen_by_server(address: SocketAddr) -> String {
+    let mut stream = (0..50)
+        .find_map(|_| {
+            TcpStream::connect(address)
+                .inspect_err(|_| thread::sleep(Duration::from_millis(20)))
+                .ok()
+        })
+        .expect("Server never accepted a connection.");
+    stream
+        .write_all(b"GET /peer HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    response.rsplit("\r\n").next().unwrap().to_string()
+}
+
+#[test]
+fn ipv6() {
+    let socket = Socket::from_socket_addr("[::1]:0".parse().unwrap()).unwrap();
+    assert_eq!(socket.get_address_descriptor().get_family(), libc::AF_INET6);
+    let port = serve(socket);
+    assert_eq!(
+        peer_seen_by_server((Ipv6Addr::LOCALHOST, port).into()),
+        "::1"
+    );
+
+    // Dual-stack: IPv4 clients reach the IPv6 wildcard as mapped addresses.
+    let port = serve(Socket::new_v6(0, Ipv6Addr::UNSPECIFIED, false).unwrap());
+    assert_eq!(
+        peer_seen_by_server((Ipv6Addr::LOCALHOST, port).into()),
+        "::1"
+    );
+    assert_eq!(
+        peer_seen_by_server((Ipv4Addr::LOCALHOST, port).into()),
+        "::ffff:127.0.0.1"
+    );
+
+    // IPv6 only: the IPv4 side of the port stays free for another socket.
+    let v6_only = Socket::new_v6(0, Ipv6Addr::UNSPECIFIED, true).unwrap();
+    let port = v6_only.get_local_address().unwrap().port();
+    let mut v4 = Socket::new(port, Ipv4Addr::UNSPECIFIED).unwrap();
+    // Address reuse lets sockets share a port until one of them listens.
+    v4.start_listening().unwrap();
+    assert!(Socket::new_v6(port, Ipv6Addr::UNSPECIFIED, false).is_err());
+}
@@ -0,0 +1,97 @@
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use http_server::{
+    connection::Connection,
+    handler::Handler,
+    protocol::Protocol,
+    request::Request,
+    response::{Response, ResponseCode},
+    router::BaseRouter,
+    server::HTTPServer,
+    socket::Socket,
+};
+
+// Answers with the name of its router and the listener the connection came in on.
+struct ListenerHandler {
+    router: &'static str,
+}
+
+impl Handler for ListenerHandler {
+    fn handle(&mut self, connection: &mut Connection, _request: &Request) -> Response {
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response.set_content(Some(format!(
+            "{} {}",
+            self.router,
+            connection.get_listener().get_index()
+        )));
+        response
+    }
+}
+
+fn router(name: &'static str) -> BaseRouter {
+    let mut router = BaseRouter::new();
+    router.register_handler_from_path(ListenerHandler { router: name }, "/listener");
+    router
+}
+
+fn request<S: Read + Write>(connect: impl Fn() -> std::io::Result<S>) -> String {
+    let mut stream = (0..50)
+        .find_map(|_| {
+            connect()
+                .inspect_err(|_| thread::sleep(Duration::from_millis(20)))
+                .ok()
+        })
+        .expect("Server never accepted a connection.");
+    stream
+        .write_all(b"GET /listener HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    response.rsplit("\r\n").next().unwrap().to_string()
+}
+
+#[test]
+fn listeners() {
+    let public = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+    let admin = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+    let public_address = public.get_local_address().unwrap();
+    let admin_address = admin.get_local_address().unwrap();
+    let path = PathBuf::from(format!(
+        "/tmp/http_server_listeners_{}.sock",
+        std::process::id()
+    ));
+    let local = Socket::new_unix(&path, None).unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut server = HTTPServer::new(public, router("public"));
+        let admin_id = server.add_listener(admin, Some(router("admin")));
+        let local_id = server.add_listener(local, None);
+        let admin_socket_address = server.get_socket(admin_id).unwrap().get_local_address();
+        sender
+            .send((
+                admin_id.get_index(),
+                local_id.get_index(),
+                admin_socket_address,
+            ))
+            .unwrap();
+        server.run();
+    });
+    assert_eq!(receiver.recv().unwrap(), (1, 2, Some(admin_address)));
+
+    assert_eq!(request(|| TcpStream::connect(public_address)), "public 0");
+    assert_eq!(request(|| TcpStream::connect(admin_address)), "admin 1");
+    let local_response = request(|| UnixStream::connect(&path));
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(local_response, "public 2");
+}
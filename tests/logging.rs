@@ -0,0 +1,39 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use http_server::logging::set_logger;
+
+#[test]
+fn logging() {
+    common::serve(|_| {}, |_| {});
+    // Quiet by default.
+    assert_eq!(
+        common::status(b"GET /ok HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+        "HTTP/1.1 200 OK"
+    );
+
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let logged = lines.clone();
+    set_logger(Some(Box::new(move |line| {
+        logged.lock().unwrap().push(line.to_string());
+    })));
+    assert_eq!(
+        common::status(
+            b"GET /ok HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic c2VjcmV0\r\nCookie: session=secret\r\nX-Trace: visible\r\n\r\n"
+        ),
+        "HTTP/1.1 200 OK"
+    );
+    set_logger(None);
+
+    let lines = lines.lock().unwrap();
+    let request = lines
+        .iter()
+        .find(|line| line.starts_with("Received request"))
+        .expect("Request was not logged.");
+    assert!(request.contains("X-Trace: visible"), "{}", request);
+    assert!(request.contains("Authorization: [redacted]"), "{}", request);
+    assert!(request.contains("Cookie: [redacted]"), "{}", request);
+    assert!(!request.contains("secret"), "{}", request);
+    assert!(!request.contains("c2VjcmV0"), "{}", request);
+}
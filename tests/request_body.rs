@@ -0,0 +1,209 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::Ipv4Addr,
+    sync::Once,
+    thread,
+    time::Duration,
+};
+
+use flate2::{Compression, write::GzEncoder};
+use http_server::{
+    connection::Connection,
+    handler::Handler,
+    limits::RequestLimits,
+    protocol::Protocol,
+    request::Request,
+    response::{Body, Response, ResponseCode},
+    router::BaseRouter,
+    server::HTTPServer,
+    socket::Socket,
+};
+
+struct EchoBodyHandler {}
+
+impl Handler for EchoBodyHandler {
+    fn handle(&mut self, _connection: &mut Connection, request: &Request) -> Response {
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response.set_body(Some(Body::Bytes(request.get_body().to_vec())));
+        response
+    }
+}
+
+static SERVER: Once = Once::new();
+
+fn start_server() {
+    SERVER.call_once(|| {
+        thread::spawn(|| {
+            let mut router = BaseRouter::new();
+            router.register_handler_from_path(EchoBodyHandler {}, "/echo");
+            let mut server = HTTPServer::new(
+                Socket::new(common::port(), Ipv4Addr::new(127, 0, 0, 1)).unwrap(),
+                router,
+            );
+            server.set_max_decoded_body_size(4096);
+            let mut limits = RequestLimits::new();
+            limits.set_max_body_size(65_536);
+            server.set_request_limits(limits);
+            server.run();
+        });
+    });
+}
+
+fn gzip(content: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn post(body: &[u8], content_encoding: &str) -> (String, Vec<u8>) {
+    let mut raw = format!(
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Encoding: {}\r\nContent-Length: {}\r\n\r\n",
+        content_encoding,
+        body.len()
+    )
+    .into_bytes();
+    raw.extend_from_slice(body);
+    common::split_response(&common::send(&raw))
+}
+
+// Splits the body into chunks, with an extension on the first one and a trailer at the end.
+fn chunked(body: &[u8], chunk_size: usize) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for (index, chunk) in body.chunks(chunk_size).enumerate() {
+        let extension = if index == 0 { " ;name=value" } else { "" };
+        encoded.extend(format!("{:X}{}\r\n", chunk.len(), extension).into_bytes());
+        encoded.extend_from_slice(chunk);
+        encoded.extend_from_slice(b"\r\n");
+    }
+    encoded.extend_from_slice(b"0\r\nX-Checksum: 1234\r\n\r\n");
+    encoded
+}
+
+fn post_chunked(encoded: &[u8], headers: &str) -> (String, Vec<u8>) {
+    let mut raw = format!(
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n{}\r\n",
+        headers
+    )
+    .into_bytes();
+    raw.extend_from_slice(encoded);
+    common::split_response(&common::send(&raw))
+}
+
+#[test]
+fn request_body() {
+    start_server();
+
+    let payload = br#"{"message":"hello","values":[1,2,3]}"#;
+    let (head, body) = post(&gzip(payload), "gzip");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert_eq!(body, payload);
+
+    let (head, body) = post(payload, "identity");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert_eq!(body, payload);
+
+    let (head, _) = post(payload, "br");
+    assert!(head.starts_with("HTTP/1.1 415 Unsupported Media Type\r\n"));
+
+    let (head, _) = post(&gzip(&[0; 1 << 20]), "gzip");
+    assert!(head.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+    let (head, _) = post(b"not gzip", "gzip");
+    assert!(head.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+}
+
+#[test]
+fn invalid_content_length() {
+    start_server();
+
+    let (head, _) = common::split_response(&common::send(
+        b"POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 12abc\r\n\r\n",
+    ));
+    assert!(head.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+    let (head, _) = common::split_response(&common::send(
+        b"POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\nabcd",
+    ));
+    assert!(head.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+}
+
+#[test]
+fn chunked_body() {
+    start_server();
+
+    let payload = br#"{"message":"hello","values":[1,2,3]}"#;
+    let (head, body) = post_chunked(&chunked(payload, 10), "");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert_eq!(body, payload);
+
+    // Streaming clients send compressed uploads chunked, since they don't know the length.
+    let (head, body) = post_chunked(&chunked(&gzip(payload), 7), "Content-Encoding: gzip\r\n");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert_eq!(body, payload);
+
+    // The next request on the connection starts right after the trailer section.
+    let mut raw =
+        b"POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+    raw.extend(chunked(b"first", 2));
+    raw.extend_from_slice(b"GET /echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let mut stream = common::connect();
+    stream.write_all(&raw).unwrap();
+    let mut responses = String::new();
+    stream.read_to_string(&mut responses).unwrap();
+    assert_eq!(responses.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+    assert!(responses.contains("\r\n\r\nfirstHTTP/1.1 200 OK\r\n"));
+
+    // A body that trickles in splits sizes, extensions, data and trailers across reads.
+    let mut raw =
+        b"POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n"
+            .to_vec();
+    raw.extend(chunked(payload, 5));
+    let mut stream = common::connect();
+    stream.set_nodelay(true).unwrap();
+    for byte in &raw {
+        stream.write_all(&[*byte]).unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let (head, body) = common::split_response(&response);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert_eq!(body, payload);
+    // As does one near the limit, in many small chunks.
+    let large: Vec<u8> = (0..60_000u32).map(|i| b'a' + (i % 26) as u8).collect();
+    let (head, body) = post_chunked(&chunked(&large, 100), "");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert!(body == large);
+
+    // A chunk that would take the body over the limit is refused before it arrives.
+    let (head, _) = post_chunked(b"10001\r\n", "");
+    assert!(
+        head.starts_with("HTTP/1.1 413 Payload Too Large\r\n"),
+        "{}",
+        head
+    );
+    let (head, _) = post_chunked(&chunked(&[b'a'; 70_000], 8192), "");
+    assert!(
+        head.starts_with("HTTP/1.1 413 Payload Too Large\r\n"),
+        "{}",
+        head
+    );
+    // Trailers count against the header limit.
+    let trailers = format!(
+        "0\r\n{}\r\n",
+        "X-Filler: aaaaaaaaaaaaaaaa\r\n".repeat(5_000)
+    );
+    let (head, _) = post_chunked(trailers.as_bytes(), "");
+    assert!(head.starts_with("HTTP/1.1 431"), "{}", head);
+
+    for malformed in [
+        &b"zz\r\nab\r\n0\r\n\r\n"[..],
+        b"2\r\nabc\r\n0\r\n\r\n",
+        b"-1\r\n",
+    ] {
+        let (head, _) = post_chunked(malformed, "");
+        assert!(head.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", head);
+    }
+}
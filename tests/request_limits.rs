@@ -0,0 +1,96 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use http_server::limits::{LimitViolation, RequestLimits};
+
+// Sends `raw` and then stalls, returning the status line and how long the server took to answer.
+fn stall(raw: &[u8]) -> (String, Duration) {
+    let mut stream = common::connect();
+    let started = Instant::now();
+    stream.write_all(raw).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8_lossy(&response).to_string();
+    (
+        response.lines().next().unwrap_or_default().to_string(),
+        started.elapsed(),
+    )
+}
+
+#[test]
+fn request_limits() {
+    let (sender, metrics) = mpsc::channel();
+    common::serve(
+        |_| {},
+        move |server| {
+            let mut limits = RequestLimits::new();
+            limits.set_max_request_line_length(64);
+            limits.set_max_header_count(4);
+            limits.set_max_header_bytes(512);
+            limits.set_max_body_size(16);
+            limits.set_header_read_timeout(Some(Duration::from_millis(200)));
+            limits.set_body_read_timeout(Some(Duration::from_millis(200)));
+            server.set_request_limits(limits);
+            sender.send(server.get_limit_metrics()).unwrap();
+        },
+    );
+    let metrics = metrics.recv().unwrap();
+
+    assert_eq!(
+        common::status(
+            b"POST /ok HTTP/1.1\r\nHost: localhost\r\nContent-Length: 16\r\n\r\n0123456789abcdef"
+        ),
+        "HTTP/1.1 200 OK"
+    );
+
+    let long_line = format!(
+        "GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        "a".repeat(64)
+    );
+    assert_eq!(
+        common::status(long_line.as_bytes()),
+        "HTTP/1.1 414 URI Too Long"
+    );
+
+    let many_headers = format!(
+        "GET /ok HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+        "X-Extra: 1\r\n".repeat(4)
+    );
+    assert_eq!(
+        common::status(many_headers.as_bytes()),
+        "HTTP/1.1 431 Request Header Fields Too Large"
+    );
+
+    let unterminated = format!(
+        "GET /ok HTTP/1.1\r\nHost: localhost\r\nX-Large: {}",
+        "a".repeat(1024)
+    );
+    assert_eq!(
+        common::status(unterminated.as_bytes()),
+        "HTTP/1.1 431 Request Header Fields Too Large"
+    );
+
+    assert_eq!(
+        common::status(b"POST /ok HTTP/1.1\r\nHost: localhost\r\nContent-Length: 17\r\n\r\n"),
+        "HTTP/1.1 413 Payload Too Large"
+    );
+
+    let (head_timeout, elapsed) = stall(b"GET /ok HTTP/1.1\r\nHost: loc");
+    assert_eq!(head_timeout, "HTTP/1.1 408 Request Timeout");
+    assert!(elapsed >= Duration::from_millis(200));
+    assert!(elapsed < Duration::from_secs(5));
+
+    let (body_timeout, _) =
+        stall(b"POST /ok HTTP/1.1\r\nHost: localhost\r\nContent-Length: 8\r\n\r\nabc");
+    assert_eq!(body_timeout, "HTTP/1.1 408 Request Timeout");
+
+    for violation in LimitViolation::ALL {
+        assert_eq!(metrics.get(violation), 1, "{:?}", violation);
+    }
+    assert_eq!(metrics.total(), 6);
+}
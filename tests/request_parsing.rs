@@ -0,0 +1,127 @@
+mod common;
+
+use http_server::{
+    connection::Connection,
+    handler::Handler,
+    header::Header,
+    protocol::Protocol,
+    request::Request,
+    response::{Response, ResponseCode},
+};
+
+// Answers with the Host the request was routed under, and its target.
+struct HostHandler {}
+
+impl Handler for HostHandler {
+    fn handle(&mut self, _connection: &mut Connection, request: &Request) -> Response {
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response.set_content(Some(format!(
+            "{} {}",
+            request.get_headers().get(&Header::Host).unwrap(),
+            request.get_target()
+        )));
+        response
+    }
+}
+
+#[test]
+fn request_parsing() {
+    common::serve(
+        |router| router.register_handler_from_path(HostHandler {}, "/host"),
+        |_| {},
+    );
+
+    assert_eq!(
+        common::status(b"\r\nGET /ok HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+        "HTTP/1.1 200 OK"
+    );
+    assert_eq!(
+        common::status(b"GET /ok HTTP/1.0\r\n\r\n"),
+        "HTTP/1.1 200 OK"
+    );
+
+    for malformed in [
+        &b"GET  /ok HTTP/1.1\r\nHost: localhost\r\n\r\n"[..],
+        b"GET /ok HTTP/1.1 \r\nHost: localhost\r\n\r\n",
+        b"GET /ok\r\nHost: localhost\r\n\r\n",
+        b"GET ok HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        b"GET /ok HTTP/1.1\r\n\r\n",
+        b"GET /ok HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n",
+        b"GET /ok HTTP/1.1\r\nHost : localhost\r\n\r\n",
+        b"GET /ok HTTP/1.1\r\nHost: localhost\r\nX-Folded: a\r\n b\r\n\r\n",
+        b"GET /ok HTTP/1.1\r\nHost: localhost\r\nno colon\r\n\r\n",
+        b"GET /ok HTTP/1.1\r\nHost: localhost\nX-Bare: lf\r\n\r\n",
+        b"GET /ok HTTP/1.1\r\nHost: localhost\r\nX-Bytes: \xff\r\n\r\n",
+        b"GET /ok HTTP/one\r\nHost: localhost\r\n\r\n",
+        b"POST /ok HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nContent-Length: 1\r\n\r\nx",
+        // Without chunked last there is no telling where the body ends.
+        b"POST /ok HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip\r\n\r\nx",
+        b"POST /ok HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked, chunked\r\n\r\n",
+        b"POST /ok HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        b"GET ftp://example.com/ok HTTP/1.1\r\nHost: example.com\r\n\r\n",
+        b"GET http:///ok HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        b"GET http://user@example.com/ok HTTP/1.1\r\nHost: example.com\r\n\r\n",
+    ] {
+        assert_eq!(
+            common::status(malformed),
+            "HTTP/1.1 400 Bad Request",
+            "{}",
+            String::from_utf8_lossy(malformed)
+        );
+    }
+
+    assert_eq!(
+        common::status(b"GET /ok HTTP/2.0\r\nHost: localhost\r\n\r\n"),
+        "HTTP/1.1 505 HTTP Version Not Supported"
+    );
+    assert_eq!(
+        common::status(b"BREW /ok HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+        "HTTP/1.1 501 Not Implemented"
+    );
+    assert_eq!(
+        common::status(
+            b"POST /ok HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n"
+        ),
+        "HTTP/1.1 501 Not Implemented"
+    );
+    assert_eq!(
+        common::status(
+            b"POST /ok HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: Chunked\r\n\r\n0\r\n\r\n"
+        ),
+        "HTTP/1.1 200 OK"
+    );
+
+    // An absolute-form target is routed on its path, under the host it names.
+    let (head, body) = common::split_response(&common::send(
+        b"GET http://example.com:8080/host?a=1 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    ));
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert_eq!(body, b"example.com:8080 /host?a=1");
+    // With no path, the target is the root.
+    assert_eq!(
+        common::status(b"GET HTTPS://example.com?a=1 HTTP/1.1\r\nHost: example.com\r\n\r\n"),
+        "HTTP/1.1 404 Not Found"
+    );
+    assert_eq!(
+        common::status(b"GET http://example.com/host HTTP/1.0\r\n\r\n"),
+        "HTTP/1.1 200 OK"
+    );
+
+    let long_target = format!(
+        "GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        "a".repeat(10_000)
+    );
+    assert_eq!(
+        common::status(long_target.as_bytes()),
+        "HTTP/1.1 414 URI Too Long"
+    );
+
+    let large_head = format!(
+        "GET /ok HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+        "X-Filler: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n".repeat(2_000)
+    );
+    assert_eq!(
+        common::status(large_head.as_bytes()),
+        "HTTP/1.1 431 Request Header Fields Too Large"
+    );
+}
@@ -0,0 +1,146 @@
+use std::{
+    mem::size_of,
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    os::fd::IntoRawFd,
+    process::Command,
+    thread,
+    time::Duration,
+};
+
+use http_server::{
+    socket::{Socket, SocketAcceptError},
+    socket_builder::{KeepAlive, SocketBuilder},
+};
+use libc::{
+    IPPROTO_TCP, SO_KEEPALIVE, SO_RCVBUF, SO_REUSEADDR, SO_REUSEPORT, SOL_SOCKET, TCP_DEFER_ACCEPT,
+    TCP_KEEPCNT, TCP_KEEPIDLE, TCP_KEEPINTVL, TCP_NODELAY, c_int, socklen_t,
+};
+
+fn get_option(descriptor: usize, level: c_int, name: c_int) -> c_int {
+    let mut value: c_int = 0;
+    let mut length = size_of::<c_int>() as socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            descriptor as c_int,
+            level,
+            name,
+            &mut value as *mut c_int as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    assert_eq!(result, 0);
+    value
+}
+
+#[test]
+fn socket_builder() {
+    let mut builder = SocketBuilder::new();
+    builder.set_backlog(256);
+    builder.set_reuse_port(true);
+    builder.set_no_delay(true);
+    builder.set_defer_accept(Some(Duration::from_secs(5)));
+    builder.set_keep_alive(Some(KeepAlive::new(
+        Duration::from_secs(30),
+        Duration::from_secs(10),
+        4,
+    )));
+    builder.set_receive_buffer_size(Some(64 * 1024));
+
+    let mut socket = builder
+        .bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .unwrap();
+    let listener = socket.get_file_descriptor();
+    assert_eq!(get_option(listener, SOL_SOCKET, SO_REUSEADDR), 1);
+    assert_eq!(get_option(listener, SOL_SOCKET, SO_REUSEPORT), 1);
+    assert!(get_option(listener, IPPROTO_TCP, TCP_DEFER_ACCEPT) >= 5);
+    socket.start_listening().unwrap();
+
+    // Port reuse lets a second socket listen alongside the first.
+    let port = socket.get_local_address().unwrap().port();
+    builder
+        .bind(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+        .unwrap()
+        .start_listening()
+        .unwrap();
+    assert!(
+        SocketBuilder::new()
+            .bind(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+            .is_err()
+    );
+
+    // Deferred accept holds the connection back until the client sends something.
+    let mut client = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+    std::io::Write::write_all(&mut client, b"GET").unwrap();
+    let accepted = (0..50)
+        .find_map(|_| match socket.accept_connection() {
+            Ok(accepted) => Some(accepted),
+            Err(SocketAcceptError::AcceptFailed(_)) => {
+                thread::sleep(Duration::from_millis(20));
+                None
+            }
+            Err(err) => panic!("{:?}", err),
+        })
+        .expect("Connection never arrived.");
+    let descriptor = accepted.descriptor;
+    assert_eq!(get_option(descriptor, IPPROTO_TCP, TCP_NODELAY), 1);
+    assert_eq!(get_option(descriptor, SOL_SOCKET, SO_KEEPALIVE), 1);
+    assert_eq!(get_option(descriptor, IPPROTO_TCP, TCP_KEEPIDLE), 30);
+    assert_eq!(get_option(descriptor, IPPROTO_TCP, TCP_KEEPINTVL), 10);
+    assert_eq!(get_option(descriptor, IPPROTO_TCP, TCP_KEEPCNT), 4);
+    assert!(get_option(descriptor, SOL_SOCKET, SO_RCVBUF) >= 64 * 1024);
+    unsafe { libc::close(descriptor as c_int) };
+}
+
+// The descriptors among `descriptors` that a program started from this process still has open.
+fn inherited(descriptors: &[usize]) -> Vec<usize> {
+    let output = Command::new("sh")
+        .args([
+            "-c",
+            "for fd; do [ -e /proc/$$/fd/$fd ] && echo $fd; done; true",
+            "sh",
+        ])
+        .args(descriptors.iter().map(usize::to_string))
+        .output()
+        .unwrap();
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| line.parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn close_on_exec() {
+    let mut socket = SocketBuilder::new()
+        .bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .unwrap();
+    socket.start_listening().unwrap();
+    let _client = TcpStream::connect(socket.get_local_address().unwrap()).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    let connection = socket.accept_connection().unwrap();
+    let adopted = Socket::from_raw_descriptor(
+        TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .into_raw_fd() as usize,
+    )
+    .unwrap();
+    assert_eq!(
+        inherited(&[
+            socket.get_file_descriptor(),
+            connection.descriptor,
+            adopted.get_file_descriptor(),
+        ]),
+        []
+    );
+    unsafe { libc::close(connection.descriptor as c_int) };
+
+    let mut builder = SocketBuilder::new();
+    builder.set_inheritable(true);
+    let socket = builder
+        .bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .unwrap();
+    assert_eq!(
+        inherited(&[socket.get_file_descriptor()]),
+        [socket.get_file_descriptor()]
+    );
+}
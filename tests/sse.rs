@@ -0,0 +1,301 @@
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use http_server::{
+    router::BaseRouter,
+    server::HTTPServer,
+    socket::Socket,
+    sse::{Event, EventChannel, EventSource, EventStream},
+};
+
+struct TestServer {
+    address: SocketAddr,
+    streams: Arc<Mutex<Vec<EventStream>>>,
+}
+
+// Serves /events, greeting every client and keeping its stream for the test to push to, and
+// /channel, subscribing clients to the given channel.
+fn start_server(keep_alive_interval: Option<Duration>, channel: EventChannel) -> TestServer {
+    let socket = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+    let address = socket.get_local_address().unwrap();
+    let streams = Arc::new(Mutex::new(Vec::new()));
+    let handler_streams = Arc::clone(&streams);
+    thread::spawn(move || {
+        let mut events = EventSource::new(move |request, stream| {
+            if request.get_query_parameters().contains_key("deny") {
+                return false;
+            }
+            stream.send_data("hello");
+            handler_streams.lock().unwrap().push(stream.clone());
+            true
+        });
+        events.set_keep_alive_interval(keep_alive_interval);
+        events.set_retry(Some(Duration::from_secs(3)));
+        let mut subscriptions = EventSource::from_channel(channel);
+        subscriptions.set_keep_alive_interval(keep_alive_interval);
+        subscriptions.set_max_queued_bytes(64 * 1024);
+        let mut router = BaseRouter::new();
+        router.register_handler_from_path(events, "/events");
+        router.register_handler_from_path(subscriptions, "/channel");
+        let mut server = HTTPServer::new(socket, router);
+        server.run();
+    });
+    TestServer { address, streams }
+}
+
+fn connect(address: SocketAddr) -> TcpStream {
+    let stream = (0..50)
+        .find_map(|_| {
+            TcpStream::connect(address)
+                .inspect_err(|_| thread::sleep(Duration::from_millis(20)))
+                .ok()
+        })
+        .expect("Server never accepted a connection.");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+fn read_line(stream: &mut TcpStream) -> String {
+    let mut line = Vec::new();
+    while !line.ends_with(b"\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        line.push(byte[0]);
+    }
+    String::from_utf8(line).unwrap()
+}
+
+fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        head.push_str(&read_line(stream));
+    }
+    head
+}
+
+fn open(address: SocketAddr, path: &str, headers: &str) -> TcpStream {
+    let mut stream = connect(address);
+    stream
+        .write_all(
+            format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+                path, headers
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    let head = read_head(&mut stream);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    stream
+}
+
+// Reads one chunk of the response body, empty for the last one.
+fn read_chunk(stream: &mut TcpStream) -> String {
+    let size = read_line(stream);
+    let size = usize::from_str_radix(size.trim_end(), 16).unwrap();
+    let mut chunk = vec![0; size + 2];
+    stream.read_exact(&mut chunk).unwrap();
+    assert!(chunk.ends_with(b"\r\n"));
+    chunk.truncate(size);
+    String::from_utf8(chunk).unwrap()
+}
+
+// Reads chunks until they add up to whole events.
+fn read_events(stream: &mut TcpStream) -> String {
+    let mut events = read_chunk(stream);
+    while !events.ends_with("\n\n") {
+        events.push_str(&read_chunk(stream));
+    }
+    events
+}
+
+fn wait_for(condition: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "Timed out waiting."
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn serialization() {
+    let mut event = Event::new("first\nsecond\r\nthird");
+    event.set_id(Some("7\n".to_string()));
+    event.set_event(Some("update".to_string()));
+    event.set_retry(Some(Duration::from_millis(1500)));
+    assert_eq!(
+        event.serialize(),
+        "event: update\nid: 7\nretry: 1500\ndata: first\ndata: second\ndata: third\n\n"
+    );
+    assert_eq!(Event::new("").serialize(), "data: \n\n");
+}
+
+#[test]
+fn event_stream() {
+    let server = start_server(None, EventChannel::new(0));
+    let mut stream = connect(server.address);
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let head = read_head(&mut stream).to_ascii_lowercase();
+    assert!(head.starts_with("http/1.1 200 ok\r\n"), "{}", head);
+    assert!(
+        head.contains("\r\ncontent-type: text/event-stream\r\n"),
+        "{}",
+        head
+    );
+    assert!(head.contains("\r\ncache-control: no-cache\r\n"), "{}", head);
+    assert!(
+        head.contains("\r\ntransfer-encoding: chunked\r\n"),
+        "{}",
+        head
+    );
+    assert!(!head.contains("content-length"), "{}", head);
+    assert_eq!(read_events(&mut stream), "retry: 3000\n\ndata: hello\n\n");
+
+    // Events pushed from another thread go out as they are sent.
+    wait_for(|| server.streams.lock().unwrap().len() == 1);
+    let events = server.streams.lock().unwrap()[0].clone();
+    let mut event = Event::new("one\ntwo");
+    event.set_id(Some("1".to_string()));
+    event.set_event(Some("tick".to_string()));
+    assert!(events.send(&event));
+    assert_eq!(
+        read_events(&mut stream),
+        "event: tick\nid: 1\ndata: one\ndata: two\n\n"
+    );
+    assert!(events.send_comment("still here"));
+    assert_eq!(read_events(&mut stream), ":still here\n\n");
+
+    // Closing the stream ends the response, and the connection takes the next request.
+    events.close();
+    assert!(events.is_closed());
+    assert!(!events.send_data("too late"));
+    assert_eq!(read_chunk(&mut stream), "");
+    stream
+        .write_all(b"GET /events?deny HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let head = read_head(&mut stream);
+    assert!(head.starts_with("HTTP/1.1 403"), "{}", head);
+}
+
+#[test]
+fn rejections() {
+    let server = start_server(None, EventChannel::new(0));
+    let rejected = |request: &str| {
+        let mut stream = connect(server.address);
+        stream.write_all(request.as_bytes()).unwrap();
+        read_head(&mut stream)
+    };
+    let post = rejected("POST /events HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n");
+    assert!(post.starts_with("HTTP/1.1 405"), "{}", post);
+    assert!(post.contains("Allow: GET\r\n"), "{}", post);
+    // Without chunked encoding there is no way to send an open-ended response.
+    let old = rejected("GET /events HTTP/1.0\r\n\r\n");
+    assert!(old.contains(" 505 "), "{}", old);
+    let denied = rejected("GET /events?deny HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(denied.starts_with("HTTP/1.1 403"), "{}", denied);
+    assert!(server.streams.lock().unwrap().is_empty());
+}
+
+#[test]
+fn keep_alive_comments() {
+    let server = start_server(Some(Duration::from_millis(50)), EventChannel::new(0));
+    let mut stream = open(server.address, "/events", "");
+    assert_eq!(read_events(&mut stream), "retry: 3000\n\ndata: hello\n\n");
+    let start = Instant::now();
+    assert_eq!(read_events(&mut stream), ":\n\n");
+    assert!(start.elapsed() >= Duration::from_millis(40));
+    assert_eq!(read_events(&mut stream), ":\n\n");
+
+    // A client that went away is noticed by the next comment failing to go out.
+    drop(stream);
+    wait_for(|| server.streams.lock().unwrap()[0].is_closed());
+}
+
+#[test]
+fn channel_replay() {
+    let channel = EventChannel::new(2);
+    for data in ["a", "b", "c"] {
+        channel.publish(Event::new(data));
+    }
+    let server = start_server(Some(Duration::from_millis(50)), channel.clone());
+
+    // Only the events after the one the client last saw are replayed.
+    let mut resumed = open(server.address, "/channel", "Last-Event-ID: 2\r\n");
+    assert_eq!(read_events(&mut resumed), "id: 3\ndata: c\n\n");
+    // Clients that are new, or last saw an event that is no longer kept, start from here.
+    let mut fresh = open(server.address, "/channel", "");
+    let mut forgotten = open(server.address, "/channel", "Last-Event-ID: 1\r\n");
+    wait_for(|| channel.get_subscriber_count() == 3);
+
+    let mut named = Event::new("d");
+    named.set_id(Some("custom".to_string()));
+    assert_eq!(channel.publish(named), "custom");
+    assert_eq!(channel.publish(Event::new("e")), "4");
+    for stream in [&mut resumed, &mut fresh, &mut forgotten] {
+        let mut events = String::new();
+        while !events.contains("data: e") {
+            events.push_str(&read_events(stream));
+        }
+        // Keep-alive comments may come in between.
+        assert_eq!(
+            events.replace(":\n\n", ""),
+            "id: custom\ndata: d\n\nid: 4\ndata: e\n\n"
+        );
+    }
+
+    drop(fresh);
+    wait_for(|| channel.get_subscriber_count() == 2);
+}
+
+// Reads until the stream has carried `end`, returning everything read.
+fn read_until(stream: &mut TcpStream, end: &str) -> String {
+    let mut received = Vec::new();
+    let mut buffer = [0; 64 * 1024];
+    while !received.ends_with(end.as_bytes()) {
+        let count = stream.read(&mut buffer).unwrap();
+        assert!(count > 0, "Stream ended early.");
+        received.extend_from_slice(&buffer[..count]);
+    }
+    String::from_utf8(received).unwrap()
+}
+
+#[test]
+fn lagging_subscriber() {
+    let channel = EventChannel::new(0);
+    let server = start_server(None, channel.clone());
+    let mut reading = open(server.address, "/channel", "");
+    let mut stalled = open(server.address, "/channel", "");
+    wait_for(|| channel.get_subscriber_count() == 2);
+    let reader = thread::spawn(move || read_until(&mut reading, "data: last\n\n\r\n"));
+
+    // A client that reads nothing fills the socket buffers, then its queue, and is dropped.
+    let update = "u".repeat(1000);
+    let mut published = 0;
+    while channel.get_subscriber_count() == 2 && published < 100_000 {
+        channel.publish(Event::new(update.as_str()));
+        published += 1;
+        if published % 16 == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+    assert_eq!(channel.get_subscriber_count(), 1);
+
+    // The client that keeps up gets every event, and the other one's response ends.
+    channel.publish(Event::new("last"));
+    let received = reader.join().unwrap();
+    assert_eq!(received.matches(update.as_str()).count(), published);
+    let received = read_until(&mut stalled, "\n\n\r\n0\r\n\r\n");
+    assert!(!received.contains("data: last"));
+}
@@ -0,0 +1,392 @@
+mod common;
+
+use std::{
+    fs,
+    io::{Read, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddr, TcpStream},
+    path::PathBuf,
+    thread,
+    time::Duration,
+};
+
+use flate2::read::GzDecoder;
+use http_server::{
+    compression::CompressionConfig,
+    conditional::{EntityTag, Representation, Validators},
+    connection::Connection,
+    handler::Handler,
+    protocol::Protocol,
+    request::{Method, Request},
+    response::{Response, ResponseCode},
+    router::BaseRouter,
+    server::HTTPServer,
+    socket::Socket,
+    static_files::{DirectoryListing, StaticFileHandler},
+};
+
+// A document that can be replaced with PUT. Its entity-tags contain a comma.
+struct DocumentHandler {
+    document: Option<(u32, String)>,
+}
+
+fn document_etag(version: u32) -> EntityTag {
+    EntityTag::strong(&format!("rev,{}", version)).unwrap()
+}
+
+impl Handler for DocumentHandler {
+    fn representation(&mut self, _request: &Request) -> Representation {
+        match &self.document {
+            Some((version, _)) => {
+                Representation::Present(Validators::new(Some(document_etag(*version)), None))
+            }
+            None => Representation::Missing,
+        }
+    }
+
+    fn handle(&mut self, _connection: &mut Connection, request: &Request) -> Response {
+        if *request.get_method() == Method::Put {
+            let (code, version) = match &self.document {
+                Some((version, _)) => (ResponseCode::NoContent, version + 1),
+                None => (ResponseCode::Created, 1),
+            };
+            let content = String::from_utf8_lossy(request.get_body()).to_string();
+            self.document = Some((version, content));
+            let mut response = Response::new(code, Protocol::Http1_1);
+            response.set_etag(&document_etag(version));
+            return response;
+        }
+        let Some((version, content)) = &self.document else {
+            return Response::new(ResponseCode::NotFound, Protocol::Http1_1);
+        };
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response.set_etag(&document_etag(*version));
+        response.set_content(Some(content.clone()));
+        response
+    }
+}
+
+fn create_root() -> PathBuf {
+    let root = std::env::temp_dir().join(format!("http_server_static_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("share/nested")).unwrap();
+    fs::write(root.join("share/b.txt"), "bravo").unwrap();
+    fs::write(root.join("share/a.txt"), "a").unwrap();
+    fs::write(root.join("share/.hidden"), "secret").unwrap();
+    fs::write(root.join("share/large.txt"), "compressible ".repeat(200)).unwrap();
+    fs::write(
+        root.join("share/nested/huge.txt"),
+        "compressible ".repeat(40_000),
+    )
+    .unwrap();
+    fs::write(root.join("share/nested/artifact.bin"), artifact()).unwrap();
+    fs::write(root.join("outside.txt"), "outside").unwrap();
+    root
+}
+
+// Undoes the chunked encoding of a response body.
+fn dechunk(mut body: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .unwrap();
+        let size =
+            usize::from_str_radix(std::str::from_utf8(&body[..line_end]).unwrap(), 16).unwrap();
+        if size == 0 {
+            assert_eq!(&body[line_end..], b"\r\n\r\n");
+            return decoded;
+        }
+        decoded.extend(&body[line_end + 2..line_end + 2 + size]);
+        body = &body[line_end + 4 + size..];
+    }
+}
+
+fn gunzip(body: &[u8]) -> String {
+    let mut decompressed = String::new();
+    GzDecoder::new(body)
+        .read_to_string(&mut decompressed)
+        .unwrap();
+    decompressed
+}
+
+// Large enough to go out in several pieces.
+fn artifact() -> Vec<u8> {
+    (0..300_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+fn start_server(root: PathBuf) {
+    thread::spawn(move || {
+        let mut handler = StaticFileHandler::new(root.join("share")).unwrap();
+        handler.set_directory_listing(Some(DirectoryListing::new()));
+        let mut router = BaseRouter::new();
+        router.register_handler_from_path(handler, "/files/{*path}");
+        let mut server = HTTPServer::new(
+            Socket::new(common::port(), Ipv4Addr::new(127, 0, 0, 1)).unwrap(),
+            router,
+        );
+        server.set_compression(Some(CompressionConfig::new()));
+        server.run();
+    });
+}
+
+fn request(target: &str) -> String {
+    request_with_headers(target, "")
+}
+
+fn request_with_headers(target: &str, headers: &str) -> String {
+    String::from_utf8(request_bytes(target, headers)).unwrap()
+}
+
+fn request_bytes(target: &str, headers: &str) -> Vec<u8> {
+    common::send(
+        format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\n{}\r\n",
+            target, headers
+        )
+        .as_bytes(),
+    )
+}
+
+#[test]
+fn static_files() {
+    let root = create_root();
+    start_server(root.clone());
+
+    let file = request("/files/b.txt");
+    assert!(file.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(file.ends_with("\r\n\r\nbravo"));
+
+    let etag = file
+        .lines()
+        .find_map(|line| line.strip_prefix("ETag: "))
+        .unwrap()
+        .to_string();
+    let last_modified = file
+        .lines()
+        .find_map(|line| line.strip_prefix("Last-Modified: "))
+        .unwrap()
+        .to_string();
+    let not_modified =
+        request_with_headers("/files/b.txt", &format!("If-None-Match: {}\r\n", etag));
+    assert!(not_modified.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+    assert!(not_modified.ends_with("\r\n\r\n"));
+    let not_modified = request_with_headers(
+        "/files/b.txt",
+        &format!("If-Modified-Since: {}\r\n", last_modified),
+    );
+    assert!(not_modified.starts_with("HTTP/1.1 304"));
+    let failed = request_with_headers("/files/b.txt", "If-Match: \"stale\"\r\n");
+    assert!(failed.starts_with("HTTP/1.1 412"));
+    let stale_range = request_with_headers(
+        "/files/b.txt",
+        "Range: bytes=1-2\r\nIf-Range: \"stale\"\r\n",
+    );
+    assert!(stale_range.starts_with("HTTP/1.1 200"));
+
+    let partial = request_with_headers("/files/b.txt", "Range: bytes=1-2\r\n");
+    assert!(partial.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+    assert!(partial.contains("Content-Range: bytes 1-2/5\r\n"));
+    assert!(partial.ends_with("\r\n\r\nra"));
+
+    let suffix = request_with_headers("/files/b.txt", "Range: bytes=-3\r\n");
+    assert!(suffix.ends_with("\r\n\r\navo"));
+
+    let multipart = request_with_headers("/files/b.txt", "Range: bytes=0-0,4-\r\n");
+    assert!(multipart.contains("Content-Type: multipart/byteranges; boundary="));
+    assert!(multipart.contains("Content-Range: bytes 4-4/5\r\n\r\no\r\n"));
+    // Adjacent ranges are merged, in order.
+    let merged = request_with_headers("/files/b.txt", "Range: bytes=2-3,0-1\r\n");
+    assert!(merged.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+    assert!(merged.contains("Content-Range: bytes 0-3/5\r\n"));
+    assert!(merged.ends_with("\r\n\r\nbrav"));
+    let overlapping = request_with_headers("/files/b.txt", "Range: bytes=1-2,0-1\r\n");
+    assert!(overlapping.contains("Content-Range: bytes 0-2/5\r\n"));
+    assert!(overlapping.ends_with("\r\n\r\nbra"));
+    // Ranges that repeat the body get it once, whole.
+    let repeated = request_with_headers("/files/b.txt", "Range: bytes=0-,0-,-5\r\n");
+    assert!(repeated.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(repeated.contains("Content-Length: 5\r\n"));
+    assert!(repeated.ends_with("\r\n\r\nbravo"));
+
+    let unsatisfiable = request_with_headers("/files/b.txt", "Range: bytes=10-\r\n");
+    assert!(unsatisfiable.starts_with("HTTP/1.1 416"));
+    assert!(unsatisfiable.contains("Content-Range: bytes */5\r\n"));
+
+    let compressed = request_bytes(
+        "/files/large.txt",
+        "Accept-Encoding: br;q=1, gzip;q=0.8\r\n",
+    );
+    let (head, body) = common::split_response(&compressed);
+    assert!(head.contains("Content-Encoding: gzip\r\n"));
+    assert!(head.contains("Vary: Accept-Encoding\r\n"));
+    assert!(head.contains("ETag: W/\""));
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(!head.contains("Content-Length"));
+    assert_eq!(gunzip(&dechunk(&body)), "compressible ".repeat(200));
+    // HEAD gets the headers GET does.
+    let head_only = String::from_utf8(common::send(
+        b"HEAD /files/large.txt HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n",
+    ))
+    .unwrap();
+    assert!(head_only.ends_with("\r\n\r\n"));
+    assert_eq!(
+        head_only
+            .trim_end()
+            .lines()
+            .filter(|line| !line.starts_with("Date: "))
+            .collect::<Vec<_>>(),
+        head.trim_end()
+            .lines()
+            .filter(|line| !line.starts_with("Date: "))
+            .collect::<Vec<_>>()
+    );
+    // The weak tag the client was sent revalidates.
+    let weak_etag = head
+        .lines()
+        .find_map(|line| line.strip_prefix("ETag: "))
+        .unwrap();
+    let revalidated = request_with_headers(
+        "/files/large.txt",
+        &format!("Accept-Encoding: gzip\r\nIf-None-Match: {}\r\n", weak_etag),
+    );
+    assert!(revalidated.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+    assert!(revalidated.ends_with("\r\n\r\n"));
+    // A file many chunks long is compressed as it goes out.
+    let (head, body) = common::split_response(&request_bytes(
+        "/files/nested/huge.txt",
+        "Accept-Encoding: gzip\r\n",
+    ));
+    assert!(head.contains("Content-Encoding: gzip\r\n"));
+    assert_eq!(gunzip(&dechunk(&body)), "compressible ".repeat(40_000));
+    let identity = request_with_headers("/files/large.txt", "Accept-Encoding: gzip;q=0\r\n");
+    assert!(!identity.contains("Content-Encoding"));
+    assert!(identity.contains("Vary: Accept-Encoding\r\n"));
+
+    let (head, body) = common::split_response(&request_bytes("/files/nested/artifact.bin", ""));
+    assert!(head.contains("Content-Length: 300000\r\n"));
+    assert!(body == artifact());
+    let (head, body) = common::split_response(&request_bytes(
+        "/files/nested/artifact.bin",
+        "Range: bytes=65530-65545\r\n",
+    ));
+    assert!(head.starts_with("HTTP/1.1 206"));
+    assert_eq!(body, artifact()[65530..=65545]);
+    let (head, body) = common::split_response(&request_bytes(
+        "/files/nested/artifact.bin",
+        "Range: bytes=200000-,10-19\r\n",
+    ));
+    let boundary = head
+        .split_once("boundary=")
+        .unwrap()
+        .1
+        .split_once("\r\n")
+        .unwrap()
+        .0;
+    let mut expected = format!(
+        "\r\n--{0}\r\nContent-Type: application/octet-stream\r\nContent-Range: bytes 10-19/300000\r\n\r\n",
+        boundary
+    )
+    .into_bytes();
+    expected.extend(&artifact()[10..20]);
+    expected.extend(
+        format!(
+            "\r\n--{0}\r\nContent-Type: application/octet-stream\r\nContent-Range: bytes 200000-299999/300000\r\n\r\n",
+            boundary
+        )
+        .into_bytes(),
+    );
+    expected.extend(&artifact()[200_000..]);
+    expected.extend(format!("\r\n--{}--\r\n", boundary).into_bytes());
+    assert!(head.contains(&format!("Content-Length: {}\r\n", expected.len())));
+    assert!(body == expected);
+
+    let redirect = request("/files/nested");
+    assert!(redirect.starts_with("HTTP/1.1 301"));
+    assert!(redirect.contains("Location: /files/nested/\r\n"));
+
+    let json = request("/files/?format=json&sort=size&order=desc");
+    let body = json.split_once("\r\n\r\n").unwrap().1;
+    assert!(body.starts_with("{\"path\":\"/files/\",\"entries\":[{\"name\":\"nested\""));
+    assert!(body.find("b.txt").unwrap() < body.find("a.txt").unwrap());
+    assert!(!body.contains(".hidden"));
+
+    let html = request("/files/");
+    assert!(html.contains("<a href=\"a.txt\">a.txt</a>"));
+
+    assert!(request("/files/../outside.txt").starts_with("HTTP/1.1 403"));
+    assert!(request("/files/%2e%2e/outside.txt").starts_with("HTTP/1.1 403"));
+    assert!(request("/files/missing.txt").starts_with("HTTP/1.1 404"));
+
+    // An edit within the same second that keeps the size still changes the strong tag, so a
+    // resumed download can't splice the two versions together.
+    let etag = |response: &str| {
+        response
+            .lines()
+            .find_map(|line| line.strip_prefix("ETag: "))
+            .unwrap()
+            .to_string()
+    };
+    let before = etag(&request("/files/b.txt"));
+    thread::sleep(Duration::from_millis(20));
+    fs::write(root.join("share/b.txt"), "BRAVO").unwrap();
+    let after = request("/files/b.txt");
+    assert!(after.ends_with("\r\n\r\nBRAVO"));
+    assert_ne!(etag(&after), before);
+    let resumed = request_with_headers(
+        "/files/b.txt",
+        &format!("Range: bytes=2-\r\nIf-Range: {}\r\n", before),
+    );
+    assert!(resumed.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let _ = fs::remove_dir_all(root);
+}
+
+#[test]
+fn representation_preconditions() {
+    let socket = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+    let address = socket.get_local_address().unwrap();
+    thread::spawn(move || {
+        let mut router = BaseRouter::new();
+        router.register_handler_from_path(DocumentHandler { document: None }, "/document");
+        let mut server = HTTPServer::new(socket, router);
+        server.run();
+    });
+    let put = |content: &str, headers: &str| {
+        let mut stream = (0..50)
+            .find_map(|_| {
+                TcpStream::connect(address)
+                    .inspect_err(|_| thread::sleep(Duration::from_millis(20)))
+                    .ok()
+            })
+            .unwrap();
+        let request = format!(
+            "PUT /document HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n{}\r\n{}",
+            content.len(),
+            headers,
+            content
+        );
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    // `*` only matches a representation that exists.
+    let missing = put("first", "If-Match: *\r\n");
+    assert!(missing.starts_with("HTTP/1.1 412"), "{}", missing);
+    let created = put("first", "If-None-Match: *\r\n");
+    assert!(created.starts_with("HTTP/1.1 201"), "{}", created);
+    assert!(created.contains("ETag: \"rev,1\"\r\n"), "{}", created);
+
+    // An update only applies on top of the version the client last saw.
+    let updated = put("second", "If-Match: \"rev,1\"\r\n");
+    assert!(updated.starts_with("HTTP/1.1 204"), "{}", updated);
+    let stale = put("lost update", "If-Match: \"rev,1\"\r\n");
+    assert!(stale.starts_with("HTTP/1.1 412"), "{}", stale);
+    let listed = put("third", "If-Match: \"rev,0\", \"rev,2\"\r\n");
+    assert!(listed.starts_with("HTTP/1.1 204"), "{}", listed);
+    let exists = put("again", "If-None-Match: *\r\n");
+    assert!(exists.starts_with("HTTP/1.1 412"), "{}", exists);
+}
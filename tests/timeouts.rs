@@ -0,0 +1,194 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        Arc,
+        mpsc::{self, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use http_server::{
+    connection::Connection,
+    handler::Handler,
+    limits::RequestLimits,
+    protocol::Protocol,
+    request::Request,
+    response::{Response, ResponseCode},
+    router::BaseRouter,
+    server::HTTPServer,
+    socket::Socket,
+    timer::Timeouts,
+};
+
+struct DeferringHandler {
+    sender: Sender<u64>,
+}
+
+impl Handler for DeferringHandler {
+    fn handle(&mut self, connection: &mut Connection, _request: &Request) -> Response {
+        let sender = self.sender.clone();
+        let served = connection.get_requests_served();
+        connection
+            .get_scheduler()
+            .schedule_after(Duration::from_millis(50), move || {
+                let _ = sender.send(served);
+            });
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response.set_content(Some(format!("{}", served)));
+        response
+    }
+}
+
+fn read_until_closed(stream: &mut impl Read) -> (String, Duration) {
+    let started = Instant::now();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    (
+        String::from_utf8_lossy(&response).to_string(),
+        started.elapsed(),
+    )
+}
+
+#[test]
+fn timeouts() {
+    let (sender, deferred) = mpsc::channel();
+    thread::spawn(move || {
+        let mut router = BaseRouter::new();
+        router.register_handler_from_path(DeferringHandler { sender }, "/defer");
+        let mut limits = RequestLimits::new();
+        limits.set_header_read_timeout(None);
+        let mut timeouts = Timeouts::new();
+        timeouts.set_keep_alive(Some(Duration::from_millis(300)));
+        timeouts.set_read(Some(Duration::from_millis(300)));
+        let mut server = HTTPServer::new(
+            Socket::new(common::port(), Ipv4Addr::new(127, 0, 0, 1)).unwrap(),
+            router,
+        );
+        server.set_request_limits(limits);
+        server.set_timeouts(timeouts);
+        server.run();
+    });
+
+    // Two pipelined requests share the connection, which then closes once the keep-alive timeout
+    // passes without a third.
+    let mut stream = common::connect();
+    stream
+        .write_all(
+            b"GET /defer HTTP/1.1\r\nHost: localhost\r\n\r\nHEAD /defer HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .unwrap();
+    let (responses, elapsed) = read_until_closed(&mut stream);
+    assert_eq!(responses.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+    assert!(responses.contains("Content-Length: 1\r\n\r\n0HTTP/1.1"));
+    assert!(responses.ends_with("Content-Length: 1\r\n\r\n"));
+    assert!(elapsed >= Duration::from_millis(300));
+    assert!(elapsed < Duration::from_secs(5));
+
+    let mut received: Vec<u64> = (0..2)
+        .map(|_| deferred.recv_timeout(Duration::from_secs(5)).unwrap())
+        .collect();
+    received.sort();
+    assert_eq!(received, [0, 1]);
+
+    // Asking to close ends the connection straight after the response.
+    let mut stream = common::connect();
+    stream
+        .write_all(b"GET /defer HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let (response, elapsed) = read_until_closed(&mut stream);
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(elapsed < Duration::from_millis(300));
+
+    // A request that stops arriving part way through is answered with 408.
+    let mut stream = common::connect();
+    stream.write_all(b"GET /defer HTTP/1.1\r\nHo").unwrap();
+    let (response, _) = read_until_closed(&mut stream);
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+}
+
+#[test]
+fn scheduled_from_another_thread() {
+    let (sender, schedulers) = mpsc::channel();
+    thread::spawn(move || {
+        let socket = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)));
+        let mut server = HTTPServer::new(socket.unwrap(), BaseRouter::new());
+        sender.send(server.get_scheduler()).unwrap();
+        server.run();
+    });
+    let scheduler = schedulers.recv().unwrap();
+    // With no connections and nothing on the timer heap, the server is asleep until woken.
+    thread::sleep(Duration::from_millis(100));
+    let (sender, ran) = mpsc::channel();
+    let scheduled_at = Instant::now();
+    scheduler.schedule_after(Duration::from_millis(20), move || {
+        let _ = sender.send(Instant::now());
+    });
+    let ran_at = ran.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(ran_at - scheduled_at >= Duration::from_millis(20));
+    assert!(ran_at - scheduled_at < Duration::from_secs(1));
+}
+
+#[test]
+fn cancelled_work() {
+    let (sender, schedulers) = mpsc::channel();
+    thread::spawn(move || {
+        let socket = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)));
+        let mut server = HTTPServer::new(socket.unwrap(), BaseRouter::new());
+        sender.send(server.get_scheduler()).unwrap();
+        server.run();
+    });
+    let scheduler = schedulers.recv().unwrap();
+    let (sender, ran) = mpsc::channel();
+    let schedule = |name: &'static str, delay: u64| {
+        let sender = sender.clone();
+        scheduler.schedule_after(Duration::from_millis(delay), move || {
+            let _ = sender.send(name);
+        })
+    };
+
+    // Work is cancelled both before and after the event loop has taken it onto its heap.
+    let early = schedule("early", 100);
+    scheduler.cancel(early);
+    let late = schedule("late", 200);
+    let first = schedule("first", 20);
+    thread::sleep(Duration::from_millis(50));
+    scheduler.cancel(late);
+    schedule("last", 250);
+    // Cancelling work that already ran changes nothing.
+    scheduler.cancel(first);
+
+    let received: Vec<&str> = (0..2)
+        .map(|_| ran.recv_timeout(Duration::from_secs(5)).unwrap())
+        .collect();
+    assert_eq!(received, ["first", "last"]);
+    assert!(ran.recv_timeout(Duration::from_millis(100)).is_err());
+}
+
+#[test]
+fn cancelled_work_is_dropped() {
+    let (sender, schedulers) = mpsc::channel();
+    thread::spawn(move || {
+        let socket = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)));
+        let mut server = HTTPServer::new(socket.unwrap(), BaseRouter::new());
+        sender.send(server.get_scheduler()).unwrap();
+        server.run();
+    });
+    let scheduler = schedulers.recv().unwrap();
+
+    // What cancelled work captured is let go of well before its deadline.
+    let guard = Arc::new(());
+    let captured = Arc::clone(&guard);
+    let id = scheduler.schedule_after(Duration::from_secs(3600), move || drop(captured));
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(Arc::strong_count(&guard), 2);
+    scheduler.cancel(id);
+    let cancelled_at = Instant::now();
+    while Arc::strong_count(&guard) > 1 {
+        assert!(cancelled_at.elapsed() < Duration::from_secs(1));
+        thread::sleep(Duration::from_millis(5));
+    }
+}
@@ -0,0 +1,241 @@
+#![cfg(feature = "tls")]
+
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use http_server::{
+    connection::Connection,
+    handler::Handler,
+    hpack::{Decoder, Encoder},
+    http2::PREFACE,
+    protocol::Protocol,
+    request::Request,
+    response::{Response, ResponseCode},
+    router::BaseRouter,
+    server::{HTTPServer, ListenerId},
+    socket::Socket,
+    tls::{TlsConfig, TlsConfigError},
+};
+use rustls::{
+    ClientConfig, ClientConnection, RootCertStore, StreamOwned, crypto::ring,
+    pki_types::CertificateDer,
+};
+
+const LARGE_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+// Describes the TLS session the request came in over.
+struct TlsInfoHandler {}
+
+impl Handler for TlsInfoHandler {
+    fn handle(&mut self, connection: &mut Connection, _request: &Request) -> Response {
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        let content = connection.get_tls_info().map_or_else(
+            || "plaintext".to_string(),
+            |info| {
+                format!(
+                    "{} {} {:?}",
+                    info.get_server_name().unwrap_or("-"),
+                    String::from_utf8_lossy(info.get_alpn_protocol().unwrap_or(b"-")),
+                    info.get_protocol_version().unwrap()
+                )
+            },
+        );
+        response.set_content(Some(content));
+        response
+    }
+}
+
+struct LargeHandler {}
+
+impl Handler for LargeHandler {
+    fn handle(&mut self, _connection: &mut Connection, _request: &Request) -> Response {
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response.set_content(Some("x".repeat(LARGE_BODY_SIZE)));
+        response
+    }
+}
+
+// Starts a TLS server for "localhost" and returns its address and certificate.
+fn start_server() -> (SocketAddr, CertificateDer<'static>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let certificate = certified.cert.der().clone();
+    let tls = TlsConfig::from_pem(
+        certified.cert.pem().as_bytes(),
+        certified.key_pair.serialize_pem().as_bytes(),
+    )
+    .unwrap();
+    let socket = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+    let address = socket.get_local_address().unwrap();
+    thread::spawn(move || {
+        let mut router = BaseRouter::new();
+        router.register_handler_from_path(TlsInfoHandler {}, "/info");
+        router.register_handler_from_path(LargeHandler {}, "/large");
+        let mut server = HTTPServer::new(socket, router);
+        server.set_tls(ListenerId::default(), Some(tls));
+        server.run();
+    });
+    (address, certificate)
+}
+
+fn connect(address: SocketAddr) -> TcpStream {
+    let stream = (0..50)
+        .find_map(|_| {
+            TcpStream::connect(address)
+                .inspect_err(|_| thread::sleep(Duration::from_millis(20)))
+                .ok()
+        })
+        .expect("Server never accepted a connection.");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+fn client(
+    address: SocketAddr,
+    certificate: &CertificateDer<'static>,
+    alpn_protocols: &[&[u8]],
+) -> StreamOwned<ClientConnection, TcpStream> {
+    let mut roots = RootCertStore::empty();
+    roots.add(certificate.clone()).unwrap();
+    let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = alpn_protocols
+        .iter()
+        .map(|protocol| protocol.to_vec())
+        .collect();
+    let session = ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+    StreamOwned::new(session, connect(address))
+}
+
+fn read_response(stream: &mut impl Read) -> String {
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    response
+}
+
+#[test]
+fn tls() {
+    let (address, certificate) = start_server();
+
+    let mut stream = client(address, &certificate, &[b"http/1.1"]);
+    stream
+        .write_all(b"GET /info HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let response = read_response(&mut stream);
+    assert!(
+        response.ends_with("\r\n\r\nlocalhost http/1.1 TLSv1_3"),
+        "{}",
+        response
+    );
+
+    // Bodies larger than the socket buffers go out over several writes.
+    let mut stream = client(address, &certificate, &[b"http/1.1"]);
+    stream
+        .write_all(b"GET /large HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let response = read_response(&mut stream);
+    let body = response.split_once("\r\n\r\n").unwrap().1;
+    assert_eq!(body.len(), LARGE_BODY_SIZE);
+}
+
+#[test]
+fn keep_alive() {
+    let (address, certificate) = start_server();
+    let mut stream = client(address, &certificate, &[b"http/1.1"]);
+    for _ in 0..3 {
+        stream
+            .write_all(b"GET /info HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        while !String::from_utf8_lossy(&response).ends_with("TLSv1_3") {
+            let mut buffer = [0; 1024];
+            let count = stream.read(&mut buffer).unwrap();
+            assert_ne!(count, 0);
+            response.extend_from_slice(&buffer[..count]);
+        }
+    }
+}
+
+#[test]
+fn alpn_h2() {
+    let (address, certificate) = start_server();
+    let mut stream = client(address, &certificate, &[b"h2", b"http/1.1"]);
+    let block = Encoder::new().encode([
+        (&b":method"[..], &b"GET"[..]),
+        (b":scheme", b"https"),
+        (b":authority", b"localhost"),
+        (b":path", b"/info"),
+    ]);
+    let mut frames = PREFACE.to_vec();
+    // An empty SETTINGS frame, then HEADERS with END_STREAM and END_HEADERS on stream 1.
+    frames.extend([0, 0, 0, 0x4, 0, 0, 0, 0, 0]);
+    frames.extend(&(block.len() as u32).to_be_bytes()[1..]);
+    frames.extend([0x1, 0x5, 0, 0, 0, 1]);
+    frames.extend(block);
+    stream.write_all(&frames).unwrap();
+
+    let mut decoder = Decoder::new();
+    let mut status = None;
+    let mut body = Vec::new();
+    loop {
+        let mut header = [0; 9];
+        stream.read_exact(&mut header).unwrap();
+        let mut payload =
+            vec![0; u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize];
+        stream.read_exact(&mut payload).unwrap();
+        match header[3] {
+            0x1 => {
+                let fields = decoder.decode(&payload).unwrap();
+                status = fields
+                    .into_iter()
+                    .find(|(name, _)| name == b":status")
+                    .map(|(_, value)| value);
+            }
+            0x0 => body.extend(payload),
+            _ => continue,
+        }
+        if header[4] & 0x1 != 0 {
+            break;
+        }
+    }
+    assert_eq!(status.as_deref(), Some(&b"200"[..]));
+    assert_eq!(String::from_utf8(body).unwrap(), "localhost h2 TLSv1_3");
+}
+
+#[test]
+fn plaintext_refused() {
+    let (address, _) = start_server();
+    let mut stream = connect(address);
+    stream
+        .write_all(b"GET /info HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = Vec::new();
+    match stream.read_to_end(&mut response) {
+        Ok(_) => assert!(!response.starts_with(b"HTTP/1.1"), "{:?}", response),
+        Err(err) => assert_eq!(err.kind(), ErrorKind::ConnectionReset),
+    }
+}
+
+#[test]
+fn invalid_pem() {
+    assert!(matches!(
+        TlsConfig::from_pem(b"", b""),
+        Err(TlsConfigError::NoCertificates)
+    ));
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    assert!(matches!(
+        TlsConfig::from_pem(certified.cert.pem().as_bytes(), b""),
+        Err(TlsConfigError::InvalidKey(_))
+    ));
+}
@@ -0,0 +1,78 @@
+use http_server::{
+    date::HttpDate,
+    header::Header,
+    header_map::HeaderMap,
+    typed_header::{
+        Accept, AcceptEncoding, CacheControl, CacheDirective, ContentLength, ContentType, Date,
+        LastModified, TypedHeader,
+    },
+};
+
+#[test]
+fn typed_header() {
+    let mut headers = HeaderMap::new();
+    headers.append(Header::ContentLength, "42".to_string());
+    headers.append(
+        Header::ContentType,
+        "Text/HTML; Charset=\"UTF-8\"".to_string(),
+    );
+    headers.append(Header::Accept, "text/*;q=0.5, application/json".to_string());
+    headers.append(Header::Accept, "*/*;q=0.1".to_string());
+    headers.append(
+        Header::AcceptEncoding,
+        "deflate;q=0.5, gzip;q=0".to_string(),
+    );
+    headers.append(Header::CacheControl, "max-age=60, no-cache".to_string());
+    headers.append(
+        Header::LastModified,
+        "Sun, 06 Nov 1994 08:49:37 GMT".to_string(),
+    );
+
+    assert_eq!(
+        headers.get_typed::<ContentLength>(),
+        Some(Ok(ContentLength(42)))
+    );
+    let content_type = headers.get_typed::<ContentType>().unwrap().unwrap();
+    assert_eq!(content_type.mime, "text/html");
+    assert_eq!(content_type.charset.as_deref(), Some("utf-8"));
+    assert_eq!(content_type.format(), "text/html; charset=utf-8");
+
+    let accept = headers.get_typed::<Accept>().unwrap().unwrap();
+    assert_eq!(accept.0.len(), 3);
+    assert_eq!(
+        accept.preferred(&["text/plain", "application/json"]),
+        Some("application/json")
+    );
+    assert_eq!(accept.preferred(&["image/png"]), Some("image/png"));
+
+    let accept_encoding = headers.get_typed::<AcceptEncoding>().unwrap().unwrap();
+    assert_eq!(
+        accept_encoding.preferred(&["gzip", "deflate"]),
+        Some("deflate")
+    );
+
+    let cache_control = headers.get_typed::<CacheControl>().unwrap().unwrap();
+    assert_eq!(cache_control.max_age(), Some(60));
+    assert!(cache_control.contains(&CacheDirective::NoCache));
+
+    let LastModified(date) = headers.get_typed::<LastModified>().unwrap().unwrap();
+    assert_eq!(date, HttpDate::from_unix_seconds(784_111_777));
+    // Days past the end of the month don't exist, and February 29 only does in leap years.
+    assert!(HttpDate::parse("Sat, 31 Feb 2024 00:00:00 GMT").is_none());
+    assert!(HttpDate::parse("Thu, 31 Apr 2025 00:00:00 GMT").is_none());
+    assert!(HttpDate::parse("Sat, 29 Feb 2025 00:00:00 GMT").is_none());
+    assert!(HttpDate::parse("Tue, 29 Feb 2000 00:00:00 GMT").is_some());
+    assert!(HttpDate::parse("Thu, 29 Feb 2024 00:00:00 GMT").is_some());
+    assert!(HttpDate::parse("Thu, 01 Mar 2100 00:00:00 GMT").is_some());
+    assert!(HttpDate::parse("Mon, 29 Feb 2100 00:00:00 GMT").is_none());
+
+    assert!(headers.get_typed::<Date>().is_none());
+    headers.insert_typed(&ContentLength(7));
+    assert_eq!(headers.get(&Header::ContentLength), Some(&"7".to_string()));
+
+    headers.append(Header::ContentLength, "8".to_string());
+    let err = headers.get_typed::<ContentLength>().unwrap().unwrap_err();
+    assert_eq!(*err.get_header(), Header::ContentLength);
+    assert!(ContentLength::parse(&["-1"]).is_err());
+    assert!(ContentType::parse(&["text"]).is_err());
+}
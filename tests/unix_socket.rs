@@ -0,0 +1,106 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    net::Shutdown,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            fs::PermissionsExt,
+            net::{SocketAddr, UnixListener, UnixStream},
+        },
+    },
+    path::PathBuf,
+    thread,
+    time::Duration,
+};
+
+use http_server::{
+    connection::Connection,
+    handler::Handler,
+    protocol::Protocol,
+    request::Request,
+    response::{Response, ResponseCode},
+    router::BaseRouter,
+    server::HTTPServer,
+    socket::{Socket, SocketCreateError},
+};
+
+struct CredentialsHandler {}
+
+impl Handler for CredentialsHandler {
+    fn handle(&mut self, connection: &mut Connection, _request: &Request) -> Response {
+        let credentials = connection.get_peer_credentials().unwrap();
+        let mut response = Response::new(ResponseCode::Ok, Protocol::Http1_1);
+        response.set_content(Some(format!(
+            "{} {} {}",
+            credentials.pid, credentials.uid, credentials.gid
+        )));
+        response
+    }
+}
+
+fn serve(socket: Socket) {
+    thread::spawn(move || {
+        let mut router = BaseRouter::new();
+        router.register_handler_from_path(CredentialsHandler {}, "/credentials");
+        HTTPServer::new(socket, router).run();
+    });
+}
+
+fn request_credentials(connect: impl Fn() -> std::io::Result<UnixStream>) -> String {
+    let mut stream = (0..50)
+        .find_map(|_| {
+            connect()
+                .inspect_err(|_| thread::sleep(Duration::from_millis(20)))
+                .ok()
+        })
+        .expect("Server never accepted a connection.");
+    stream
+        .write_all(b"GET /credentials HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    response.rsplit("\r\n").next().unwrap().to_string()
+}
+
+#[test]
+fn unix_socket() {
+    let expected = format!(
+        "{} {} {}",
+        std::process::id(),
+        unsafe { libc::getuid() },
+        unsafe { libc::getgid() }
+    );
+    let path = PathBuf::from(format!("/tmp/http_server_test_{}.sock", std::process::id()));
+
+    // A listener that went away without removing its file leaves a stale socket behind.
+    drop(UnixListener::bind(&path).unwrap());
+    let socket = Socket::new_unix(&path, Some(0o660)).unwrap();
+    assert_eq!(socket.get_address_descriptor().get_family(), libc::AF_UNIX);
+    assert_eq!(
+        fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+        0o660
+    );
+    serve(socket);
+    assert_eq!(request_credentials(|| UnixStream::connect(&path)), expected);
+
+    // A socket that is still being served is left alone.
+    assert!(matches!(
+        Socket::new_unix(&path, None),
+        Err(SocketCreateError::BindingFailed(_))
+    ));
+    assert!(path.exists());
+    // The server thread never exits, so its socket file would outlive the test.
+    fs::remove_file(&path).unwrap();
+
+    let name = format!("http_server_test_{}", std::process::id());
+    serve(Socket::new_abstract(name.as_bytes()).unwrap());
+    let address = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+    assert_eq!(
+        request_credentials(|| UnixStream::connect_addr(&address)),
+        expected
+    );
+}
@@ -0,0 +1,405 @@
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use http_server::{
+    limits::RequestLimits,
+    router::BaseRouter,
+    server::HTTPServer,
+    socket::Socket,
+    websocket::{Message, WebSocket, WebSocketHandler, WebSocketUpgrade, accept_key},
+};
+
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+const HANDSHAKE: &str = "GET /socket HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+    Connection: keep-alive, Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+    Sec-WebSocket-Version: 13\r\n\r\n";
+
+// Echoes every data message, and keeps the sockets and close codes it saw for the test to check.
+struct EchoHandler {
+    sockets: Arc<Mutex<Vec<WebSocket>>>,
+    closes: Arc<Mutex<Vec<u16>>>,
+}
+
+impl WebSocketHandler for EchoHandler {
+    fn on_open(&mut self, socket: &WebSocket) {
+        self.sockets.lock().unwrap().push(socket.clone());
+    }
+
+    fn on_message(&mut self, socket: &WebSocket, message: Message) {
+        match message {
+            Message::Text(text) if text == "close" => {
+                socket.close(4000, "asked to");
+            }
+            Message::Text(_) | Message::Binary(_) => {
+                socket.send(message);
+            }
+            Message::Ping(_) | Message::Pong(_) => {}
+        }
+    }
+
+    fn on_close(&mut self, code: u16, _reason: &str) {
+        self.closes.lock().unwrap().push(code);
+    }
+}
+
+struct TestServer {
+    address: SocketAddr,
+    sockets: Arc<Mutex<Vec<WebSocket>>>,
+    closes: Arc<Mutex<Vec<u16>>>,
+}
+
+fn start_server(ping_interval: Option<Duration>) -> TestServer {
+    let socket = Socket::from_socket_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+    let address = socket.get_local_address().unwrap();
+    let sockets = Arc::new(Mutex::new(Vec::new()));
+    let closes = Arc::new(Mutex::new(Vec::new()));
+    let (handler_sockets, handler_closes) = (Arc::clone(&sockets), Arc::clone(&closes));
+    thread::spawn(move || {
+        let mut upgrade = WebSocketUpgrade::new(move |request| {
+            (!request.get_query_parameters().contains_key("deny")).then(|| EchoHandler {
+                sockets: Arc::clone(&handler_sockets),
+                closes: Arc::clone(&handler_closes),
+            })
+        });
+        upgrade.set_max_message_size(1024);
+        upgrade.set_max_queued_bytes(64 * 1024);
+        upgrade.set_ping_interval(ping_interval);
+        let mut router = BaseRouter::new();
+        router.register_handler_from_path(upgrade, "/socket");
+        let mut server = HTTPServer::new(socket, router);
+        // Small enough that a burst of frames takes several reads.
+        let mut limits = RequestLimits::new();
+        limits.set_max_header_bytes(4096);
+        limits.set_max_body_size(1024);
+        server.set_request_limits(limits);
+        server.run();
+    });
+    TestServer {
+        address,
+        sockets,
+        closes,
+    }
+}
+
+fn connect(address: SocketAddr) -> TcpStream {
+    let stream = (0..50)
+        .find_map(|_| {
+            TcpStream::connect(address)
+                .inspect_err(|_| thread::sleep(Duration::from_millis(20)))
+                .ok()
+        })
+        .expect("Server never accepted a connection.");
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+fn open(address: SocketAddr) -> TcpStream {
+    let mut stream = connect(address);
+    stream.write_all(HANDSHAKE.as_bytes()).unwrap();
+    let head = read_head(&mut stream);
+    assert!(
+        head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+        "{}",
+        head
+    );
+    stream
+}
+
+fn frame(fin: bool, opcode: u8, payload: &[u8], masked: bool) -> Vec<u8> {
+    let mut frame = vec![if fin { 0x80 | opcode } else { opcode }];
+    let mask_bit = if masked { 0x80 } else { 0 };
+    match payload.len() {
+        length @ 0..=125 => frame.push(mask_bit | length as u8),
+        length => {
+            frame.push(mask_bit | 126);
+            frame.extend((length as u16).to_be_bytes());
+        }
+    }
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    if masked {
+        frame.extend(mask);
+    }
+    frame.extend(
+        payload
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(byte, mask)| if masked { byte ^ mask } else { *byte }),
+    );
+    frame
+}
+
+fn send(stream: &mut TcpStream, opcode: u8, payload: &[u8]) {
+    stream
+        .write_all(&frame(true, opcode, payload, true))
+        .unwrap();
+}
+
+fn receive(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut header = [0; 2];
+    stream.read_exact(&mut header).unwrap();
+    assert_eq!(header[1] & 0x80, 0, "Server frames must not be masked.");
+    let length = match header[1] {
+        126 => {
+            let mut length = [0; 2];
+            stream.read_exact(&mut length).unwrap();
+            u16::from_be_bytes(length) as usize
+        }
+        length => length as usize,
+    };
+    let mut payload = vec![0; length];
+    stream.read_exact(&mut payload).unwrap();
+    (header[0], payload)
+}
+
+fn close_code(payload: &[u8]) -> u16 {
+    u16::from_be_bytes([payload[0], payload[1]])
+}
+
+fn assert_closed(stream: &mut TcpStream) {
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty(), "{:?}", rest);
+}
+
+fn wait_for(condition: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "Timed out waiting."
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn handshake() {
+    // The example from RFC 6455 section 1.3.
+    assert_eq!(
+        accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+    let server = start_server(None);
+    let mut stream = connect(server.address);
+    stream.write_all(HANDSHAKE.as_bytes()).unwrap();
+    let head = read_head(&mut stream).to_ascii_lowercase();
+    assert!(head.contains("\r\nupgrade: websocket\r\n"), "{}", head);
+    assert!(head.contains("\r\nconnection: upgrade\r\n"), "{}", head);
+    assert!(
+        head.contains("\r\nsec-websocket-accept: s3pplmbitxaq9kygzzhzrbk+xoo=\r\n"),
+        "{}",
+        head
+    );
+    assert!(!head.contains("content-length"), "{}", head);
+
+    let rejected = |request: &str| {
+        let mut stream = connect(server.address);
+        stream.write_all(request.as_bytes()).unwrap();
+        read_head(&mut stream)
+    };
+    let plain = rejected("GET /socket HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(plain.starts_with("HTTP/1.1 426"), "{}", plain);
+    assert!(plain.contains("Upgrade: websocket\r\n"), "{}", plain);
+    let version = rejected(&HANDSHAKE.replace("Version: 13", "Version: 8"));
+    assert!(version.starts_with("HTTP/1.1 426"), "{}", version);
+    assert!(
+        version.contains("Sec-WebSocket-Version: 13\r\n"),
+        "{}",
+        version
+    );
+    let key = rejected(&HANDSHAKE.replace("dGhlIHNhbXBsZSBub25jZQ==", "short"));
+    assert!(key.starts_with("HTTP/1.1 400"), "{}", key);
+    // Sets bits past the end of a 16-byte key.
+    let key = rejected(&HANDSHAKE.replace("dGhlIHNhbXBsZSBub25jZQ==", "dGhlIHNhbXBsZSBub25jZR=="));
+    assert!(key.starts_with("HTTP/1.1 400"), "{}", key);
+    let denied = rejected(&HANDSHAKE.replace("/socket", "/socket?deny"));
+    assert!(denied.starts_with("HTTP/1.1 403"), "{}", denied);
+    let post = rejected(&HANDSHAKE.replace("GET", "POST"));
+    assert!(post.starts_with("HTTP/1.1 405"), "{}", post);
+}
+
+#[test]
+fn messages() {
+    let server = start_server(None);
+    let mut stream = open(server.address);
+
+    send(&mut stream, TEXT, "héllo".as_bytes());
+    assert_eq!(
+        receive(&mut stream),
+        (0x80 | TEXT, "héllo".as_bytes().to_vec())
+    );
+    send(&mut stream, BINARY, &[0, 1, 2, 255]);
+    assert_eq!(receive(&mut stream), (0x80 | BINARY, vec![0, 1, 2, 255]));
+    // 200 bytes needs the 16-bit length.
+    send(&mut stream, BINARY, &[7; 200]);
+    assert_eq!(receive(&mut stream), (0x80 | BINARY, vec![7; 200]));
+
+    // Control frames may come between the fragments of a message.
+    stream
+        .write_all(&frame(false, TEXT, b"frag", true))
+        .unwrap();
+    send(&mut stream, PING, b"are you there");
+    stream.write_all(&frame(false, 0, b"men", true)).unwrap();
+    stream.write_all(&frame(true, 0, b"ted", true)).unwrap();
+    assert_eq!(
+        receive(&mut stream),
+        (0x80 | PONG, b"are you there".to_vec())
+    );
+    assert_eq!(receive(&mut stream), (0x80 | TEXT, b"fragmented".to_vec()));
+
+    send(
+        &mut stream,
+        CLOSE,
+        &[&1000u16.to_be_bytes()[..], b"bye"].concat(),
+    );
+    let (opcode, payload) = receive(&mut stream);
+    assert_eq!((opcode, close_code(&payload)), (0x80 | CLOSE, 1000));
+    assert_closed(&mut stream);
+    wait_for(|| *server.closes.lock().unwrap() == [1000]);
+}
+
+// Far more frames than one wakeup reads are all answered, over several.
+#[test]
+fn frame_flood() {
+    const PINGS: u64 = 5000;
+    let server = start_server(None);
+    let mut stream = open(server.address);
+    let pings: Vec<u8> = (0..PINGS)
+        .flat_map(|index| frame(true, PING, &index.to_be_bytes(), true))
+        .collect();
+    let mut writer = stream.try_clone().unwrap();
+    let sender = thread::spawn(move || writer.write_all(&pings).unwrap());
+    for index in 0..PINGS {
+        assert_eq!(
+            receive(&mut stream),
+            (0x80 | PONG, index.to_be_bytes().to_vec())
+        );
+    }
+    sender.join().unwrap();
+    send(&mut stream, TEXT, b"still here");
+    assert_eq!(receive(&mut stream), (0x80 | TEXT, b"still here".to_vec()));
+}
+
+#[test]
+fn protocol_errors() {
+    let server = start_server(None);
+    let cases: [(Vec<u8>, u16); 6] = [
+        (frame(true, TEXT, b"unmasked", false), 1002),
+        (frame(true, TEXT, &[0xff, 0xfe], true), 1007),
+        (frame(true, BINARY, &[0; 2000], true), 1009),
+        (frame(true, 0, b"no message to continue", true), 1002),
+        (frame(false, PING, b"fragmented control", true), 1002),
+        (frame(true, 0x3, b"reserved opcode", true), 1002),
+    ];
+    for (bytes, code) in cases {
+        let mut stream = open(server.address);
+        stream.write_all(&bytes).unwrap();
+        let (opcode, payload) = receive(&mut stream);
+        assert_eq!((opcode, close_code(&payload)), (0x80 | CLOSE, code));
+        assert_closed(&mut stream);
+    }
+    wait_for(|| server.closes.lock().unwrap().len() == 6);
+    assert_eq!(
+        *server.closes.lock().unwrap(),
+        [1002, 1007, 1009, 1002, 1002, 1002]
+    );
+}
+
+#[test]
+fn push() {
+    let server = start_server(None);
+    let mut stream = open(server.address);
+    wait_for(|| server.sockets.lock().unwrap().len() == 1);
+    let socket = server.sockets.lock().unwrap()[0].clone();
+
+    // Messages pushed from another thread go out without waiting for the client.
+    let sent = Instant::now();
+    thread::spawn(move || socket.send_text("update"))
+        .join()
+        .unwrap();
+    assert_eq!(receive(&mut stream), (0x80 | TEXT, b"update".to_vec()));
+    assert!(sent.elapsed() < Duration::from_secs(1));
+
+    // The server starts the closing handshake here, and closes once it's answered.
+    send(&mut stream, TEXT, b"close");
+    let (opcode, payload) = receive(&mut stream);
+    assert_eq!((opcode, close_code(&payload)), (0x80 | CLOSE, 4000));
+    assert_eq!(&payload[2..], b"asked to");
+    send(&mut stream, CLOSE, &payload);
+    assert_closed(&mut stream);
+    wait_for(|| *server.closes.lock().unwrap() == [4000]);
+    assert!(server.sockets.lock().unwrap()[0].is_closed());
+    assert!(!server.sockets.lock().unwrap()[0].send_text("too late"));
+}
+
+#[test]
+fn slow_client() {
+    let server = start_server(None);
+    let mut stream = open(server.address);
+    wait_for(|| server.sockets.lock().unwrap().len() == 1);
+    let socket = server.sockets.lock().unwrap()[0].clone();
+
+    // A client that reads nothing fills the socket buffers, then the queue, and is closed.
+    let update = "u".repeat(1000);
+    let sent = (0..100_000)
+        .take_while(|_| socket.send_text(update.as_str()))
+        .count();
+    assert!(sent < 100_000);
+    assert!(socket.is_closed());
+
+    // Everything queued before the limit still arrives, ahead of the close.
+    let mut received = 0;
+    let payload = loop {
+        match receive(&mut stream) {
+            (opcode, payload) if opcode == 0x80 | TEXT => {
+                assert_eq!(payload, update.as_bytes());
+                received += 1;
+            }
+            (opcode, payload) => {
+                assert_eq!(opcode, 0x80 | CLOSE);
+                break payload;
+            }
+        }
+    };
+    assert_eq!(received, sent);
+    assert_eq!(close_code(&payload), 1008);
+    send(&mut stream, CLOSE, &payload);
+    assert_closed(&mut stream);
+    wait_for(|| *server.closes.lock().unwrap() == [1008]);
+}
+
+#[test]
+fn keep_alive_pings() {
+    let server = start_server(Some(Duration::from_millis(100)));
+    let mut stream = open(server.address);
+    let (opcode, payload) = receive(&mut stream);
+    assert_eq!((opcode, payload), (0x80 | PING, Vec::new()));
+    send(&mut stream, PONG, &[]);
+    assert_eq!(receive(&mut stream).0, 0x80 | PING);
+
+    // A client that stops answering is dropped.
+    assert_closed(&mut stream);
+    wait_for(|| *server.closes.lock().unwrap() == [1006]);
+}